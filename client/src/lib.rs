@@ -0,0 +1,153 @@
+//! Typed async client for the search server's HTTP API.
+//!
+//! Requests and responses are the [`core::api`] types the server itself serializes, so the
+//! two cannot drift apart. The search methods mirror the server crate's in-process `Searcher`:
+//! `search(q, k, opts)`, `search_params`, `search_batch`.
+
+use core::api::{DocParams, DocResponse, DocTermsParams, DocTermsResponse, ListDocsParams, ListDocsResponse, SearchParams, SearchResponse, SimilarParams};
+use core::DocId;
+use reqwest::{IntoUrl, Method, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub use core::api;
+
+/// Why a request failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent, or the response could not be read or decoded.
+    Http(reqwest::Error),
+    /// The server answered with an error status; `message` is the response body.
+    Status { status: StatusCode, message: String },
+    /// The base URL isn't an `http` or `https` URL.
+    BaseUrl(Url),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request failed: {e}"),
+            Error::Status { status, message } => write!(f, "server returned {status}: {message}"),
+            Error::BaseUrl(url) => write!(f, "base URL must be http or https, got {url}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Status { .. } | Error::BaseUrl(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self { Error::Http(e) }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A server at one base URL. Cheap to clone; clones share the connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: Url,
+}
+
+impl Client {
+    /// Client for the server at `base_url`, e.g. `http://localhost:8080`. A path in the URL
+    /// (a reverse-proxy prefix) is kept in front of every route.
+    pub fn new(base_url: impl IntoUrl) -> Result<Self> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Like [`Client::new`], with a configured `reqwest` client (timeouts, proxies, headers).
+    /// Fails with [`Error::BaseUrl`] unless `base_url` is `http` or `https`.
+    pub fn with_http_client(base_url: impl IntoUrl, http: reqwest::Client) -> Result<Self> {
+        let base = base_url.into_url()?;
+        if !matches!(base.scheme(), "http" | "https") || base.cannot_be_a_base() {
+            return Err(Error::BaseUrl(base));
+        }
+        Ok(Self { http, base })
+    }
+
+    /// `GET /health`: `Ok` when the server answers 200.
+    pub async fn health(&self) -> Result<()> {
+        send(self.request(Method::GET, &["health"])).await?;
+        Ok(())
+    }
+
+    /// The top `k` hits for `query`; the other search options come from `opts` (its `q` and `k` are ignored).
+    pub async fn search(&self, query: &str, k: usize, opts: SearchParams) -> Result<SearchResponse> {
+        self.search_params(SearchParams { q: query.to_string(), k, ..opts }).await
+    }
+
+    /// `POST /search` with every option in `params`.
+    pub async fn search_params(&self, params: SearchParams) -> Result<SearchResponse> {
+        json(self.request(Method::POST, &["search"]).json(&params)).await
+    }
+
+    /// `POST /search/batch`: several searches against one index snapshot, answered in order.
+    pub async fn search_batch(&self, batch: Vec<SearchParams>) -> Result<Vec<SearchResponse>> {
+        #[derive(Serialize)]
+        struct Batch {
+            queries: Vec<SearchParams>,
+        }
+        json(self.request(Method::POST, &["search", "batch"]).json(&Batch { queries: batch })).await
+    }
+
+    /// `GET /doc/{doc_id}`; `None` when there is no such document.
+    pub async fn get_doc(&self, doc_id: DocId, params: &DocParams) -> Result<Option<DocResponse>> {
+        found(json(self.request(Method::GET, &["doc", &doc_id.to_string()]).query(params)).await)
+    }
+
+    /// `GET /doc/by-external/{external_id}`: look a document up by its input `id`.
+    pub async fn get_doc_by_external(&self, external_id: &str, params: &DocParams) -> Result<Option<DocResponse>> {
+        found(json(self.request(Method::GET, &["doc", "by-external", external_id]).query(params)).await)
+    }
+
+    /// `GET /doc/{doc_id}/terms`: the document's highest-weight body terms (needs term vectors
+    /// or stored text on the server).
+    pub async fn doc_terms(&self, doc_id: DocId, params: &DocTermsParams) -> Result<DocTermsResponse> {
+        json(self.request(Method::GET, &["doc", &doc_id.to_string(), "terms"]).query(params)).await
+    }
+
+    /// `GET /similar/{doc_id}`: documents like this one.
+    pub async fn similar(&self, doc_id: DocId, params: &SimilarParams) -> Result<SearchResponse> {
+        json(self.request(Method::GET, &["similar", &doc_id.to_string()]).query(params)).await
+    }
+
+    /// `GET /docs`: one page of documents in doc id order.
+    pub async fn list_docs(&self, params: &ListDocsParams) -> Result<ListDocsResponse> {
+        json(self.request(Method::GET, &["docs"]).query(params)).await
+    }
+
+    /// A request to the route `segments` under the base URL; each segment is percent-encoded.
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.base.clone();
+        url.path_segments_mut().expect("the base URL was checked to be http(s)").pop_if_empty().extend(segments);
+        self.http.request(method, url)
+    }
+}
+
+async fn send(request: RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() { return Ok(response); }
+    let message = response.text().await.unwrap_or_default();
+    Err(Error::Status { status, message })
+}
+
+async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    Ok(send(request).await?.json().await?)
+}
+
+/// Turn a 404 into `None`.
+fn found<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(Error::Status { status: StatusCode::NOT_FOUND, .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
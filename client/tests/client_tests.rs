@@ -0,0 +1,102 @@
+use client::api::{DocParams, ListDocsParams, SearchParams, SimilarParams};
+use client::{Client, Error};
+use std::fs;
+use tempfile::{tempdir, TempDir};
+
+// `#[tokio::test]` expands to `::core` paths, which this workspace's `core` crate shadows,
+// so each test drives its own runtime.
+fn run(test: impl std::future::Future<Output = ()>) {
+    tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap().block_on(test)
+}
+
+/// Build a three-document index and serve it on a free local port.
+async fn spawn_server() -> (TempDir, Client) {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Alpha","body":"Rust makes systems programming safe."}"#, "\n",
+        r#"{"id":"b/1","title":"Beta","body":"Gardening tips for spring."}"#, "\n",
+        r#"{"id":"c","title":"Gamma","body":"Rust and gardening: safe systems for tomatoes."}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app_with_config(index_dir, server::ServerConfig::default()).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (dir, Client::new(format!("http://{addr}")).unwrap())
+}
+
+#[test]
+fn base_urls_must_be_http_or_https() {
+    for base in ["mailto:search@example.com", "data:text/plain,hi", "ftp://example.com/"] {
+        assert!(Client::new(base).is_err(), "{base}");
+    }
+    assert!(matches!(Client::new("ftp://example.com/"), Err(Error::BaseUrl(_))));
+    assert!(Client::new("https://example.com/search/").is_ok());
+}
+
+#[test]
+fn search_returns_typed_hits() {
+    run(async {
+        let (_dir, client) = spawn_server().await;
+        client.health().await.unwrap();
+
+        let response = client.search("rust", 10, SearchParams::default()).await.unwrap();
+        assert_eq!(response.query, "rust");
+        assert_eq!(response.total_hits, 2);
+        let ids: Vec<&str> = response.results.iter().map(|hit| hit.external_id.as_str()).collect();
+        assert!(ids.contains(&"a") && ids.contains(&"c"), "{ids:?}");
+        assert!(response.results[0].snippet.as_deref().unwrap().contains("<em>"));
+
+        // Options travel as the same struct the server reads; fields a hit_fields list leaves out default
+        let opts = SearchParams { hit_fields: Some("external_id".into()), matched_fields: true, fields: Some("title,body".into()), ..Default::default() };
+        let response = client.search("gamma", 10, opts).await.unwrap();
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(response.results[0].external_id, "c");
+        assert_eq!(response.results[0].snippet, None);
+        assert_eq!(response.results[0].matched_fields.as_deref(), Some(&["title".into()][..]));
+
+        let batch = client.search_batch(vec![
+            SearchParams { q: "gardening".into(), k: 1, ..Default::default() },
+            SearchParams { q: "nothing".into(), k: 1, ..Default::default() },
+        ]).await.unwrap();
+        assert_eq!(batch.iter().map(|r| r.total_hits).collect::<Vec<_>>(), vec![2, 0]);
+        assert_eq!(batch[0].results.len(), 1);
+    });
+}
+
+#[test]
+fn documents_by_id_and_external_id() {
+    run(async {
+        let (_dir, client) = spawn_server().await;
+        let doc = client.get_doc_by_external("b/1", &DocParams::default()).await.unwrap().unwrap();
+        assert_eq!(doc.title, "Beta");
+        assert_eq!(doc.text.as_deref(), Some("Gardening tips for spring."));
+
+        let prefix = client.get_doc(doc.doc_id, &DocParams { max_chars: Some(9) }).await.unwrap().unwrap();
+        assert_eq!((prefix.text.as_deref(), prefix.truncated), (Some("Gardening"), Some(true)));
+        assert!(client.get_doc(999, &DocParams::default()).await.unwrap().is_none());
+
+        let page = client.list_docs(&ListDocsParams { offset: 1, limit: 10 }).await.unwrap();
+        assert_eq!((page.total, page.docs.len()), (3, 2));
+
+        let similar = client.similar(doc.doc_id, &SimilarParams { k: 5, terms: 10 }).await.unwrap();
+        assert_eq!(similar.results.iter().map(|hit| hit.external_id.as_str()).collect::<Vec<_>>(), vec!["c"]);
+    });
+}
+
+#[test]
+fn error_statuses_carry_the_server_message() {
+    run(async {
+        let (_dir, client) = spawn_server().await;
+        let bad = SearchParams { fields: Some("nope".into()), ..Default::default() };
+        match client.search("rust", 10, bad).await {
+            Err(Error::Status { status, message }) => {
+                assert_eq!(status, 400);
+                assert!(message.contains("nope"), "{message}");
+            }
+            other => panic!("expected a 400, got {other:?}"),
+        }
+    });
+}
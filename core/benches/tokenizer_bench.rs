@@ -1,10 +1,43 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use core::tokenizer::tokenize;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::collections::HashMap;
+use core::tokenizer::{tokenize, tokenize_iter_with, tokenize_with, Normalization, StemLanguage, TokenizerConfig};
 
 fn bench_tokenize(c: &mut Criterion) {
-    let text = include_str!("../README.md");
+    let text = include_str!("../../README.md");
     c.bench_function("tokenize_readme", |b| b.iter(|| tokenize(text)));
 }
 
-criterion_group!(benches, bench_tokenize);
+/// Mixed-language web text (Latin, Cyrillic, Greek, CJK, Arabic; ligatures and full-width forms for NFKC),
+/// repeated so each iteration is long enough for stable timings.
+fn bench_tokenize_multilingual(c: &mut Criterion) {
+    let text = include_str!("fixtures/multilingual.txt").repeat(8);
+    let mut group = c.benchmark_group("tokenize_multilingual");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    for (normalization, norm_name) in [(Normalization::Nfkc, "nfkc"), (Normalization::None, "none")] {
+        for (stemmer, stem_name) in [(StemLanguage::English, "stem"), (StemLanguage::None, "nostem")] {
+            let config = TokenizerConfig { normalization, stemmer, ..Default::default() };
+            group.bench_with_input(BenchmarkId::new(norm_name, stem_name), &config, |b, config| b.iter(|| tokenize_with(&text, config)));
+        }
+    }
+    group.finish();
+}
+
+/// Term frequencies the way the indexer counts them: from a collected token vector (the
+/// old path) versus straight off [`tokenize_iter_with`].
+fn bench_tf_counts(c: &mut Criterion) {
+    let text = include_str!("fixtures/multilingual.txt").repeat(8);
+    let config = TokenizerConfig::default();
+    let count = |tokens: &mut dyn Iterator<Item = (String, usize)>| {
+        let mut tf: HashMap<String, u32> = HashMap::new();
+        for (term, _) in tokens { *tf.entry(term).or_insert(0) += 1; }
+        tf
+    };
+    let mut group = c.benchmark_group("tf_counts");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("collect", |b| b.iter(|| count(&mut tokenize_with(&text, &config).into_iter())));
+    group.bench_function("iter", |b| b.iter(|| count(&mut tokenize_iter_with(&text, &config))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_tokenize, bench_tokenize_multilingual, bench_tf_counts);
 criterion_main!(benches);
@@ -0,0 +1,439 @@
+//! Request and response types of the HTTP API, shared by the server and the `client` crate so
+//! both sides serialize the same shapes.
+
+use crate::{DocId, TermId};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// How query terms combine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Documents matching any term, ranked by score.
+    #[default]
+    Or,
+    /// Only documents matching every term.
+    And,
+}
+
+/// What to show when no query term occurs in a document's text (`snippet_fallback`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetFallback {
+    /// No snippet.
+    None,
+    /// The start of the text.
+    #[default]
+    Lead,
+    /// The stored meta description, or the lead when there is none.
+    Meta,
+}
+
+/// Where `min_score` cuts the ranked hits (`min_score_mode`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MinScoreMode {
+    /// Before paging: `total_hits` and every page count only hits above the bar.
+    #[default]
+    BeforePaging,
+    /// Only the returned page: `total_hits` counts every match, and pages keep their boundaries.
+    PageOnly,
+}
+
+/// A highlighted region of a returned snippet, in chars: `start..end` covers the matched
+/// (escaped) text between the highlight tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Search request: the `GET /search` query string or the `POST /search` JSON body.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    #[serde(default = "default_k")] 
+    pub k: usize,
+    /// Number of ranked hits to skip before returning `k`.
+    #[serde(default)]
+    pub offset: usize,
+    /// `or` (default): any query term may match; `and`: every term must match.
+    #[serde(default)]
+    pub mode: Mode,
+    /// `field:term` (or plain body `term`) conditions every hit must satisfy; they don't affect scores.
+    /// `facet:value` matches a `--facet-fields` value exactly (case-insensitively).
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// One more filter, for `GET /search?filter=category:rust`.
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Per-field score multipliers overriding the defaults, e.g. `{"title": 3.0}`.
+    #[serde(default)]
+    pub boosts: HashMap<String, f32>,
+    /// Comma-separated fields to score against: `body` (default), `title` and/or `url`.
+    /// `field:term` words in `q` search only that field regardless.
+    #[serde(default)]
+    pub fields: Option<String>,
+    /// Comma-separated `SearchHit` fields to return, e.g. `title,score` (default all). Omitting
+    /// `snippet` and `matches` also skips reading stored text.
+    #[serde(default)]
+    pub hit_fields: Option<String>,
+    /// Include corpus size and matched/unmatched query terms in the response.
+    #[serde(default)]
+    pub debug: bool,
+    /// Include a per-phase `timings` breakdown in the response.
+    #[serde(default)]
+    pub profile: bool,
+    /// Report this doc's score, rank and per-term contributions in `doc_debug`, even when it is
+    /// outside the returned page (or not a hit at all).
+    #[serde(default)]
+    pub debug_doc: Option<DocId>,
+    /// Match body words exactly as written (case, accents, no stemming); needs `--preserve-case`.
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// With an empty `q`, list every document (newest first) instead of returning nothing.
+    #[serde(default)]
+    pub browse: bool,
+    /// Snippet when no query term occurs in the text: `lead` (default), `meta` or `none`.
+    #[serde(default)]
+    pub snippet_fallback: SnippetFallback,
+    /// Fields snippets may come from (`body`, `title`, `description`; comma-separated, default
+    /// `body`). The one showing the most query words wins, and hits say which in `snippet_field`.
+    #[serde(default)]
+    pub snippet_fields: Option<String>,
+    /// Add each hit's highlighted regions as `matches: [{start, end}]` char offsets into `snippet`.
+    #[serde(default)]
+    pub highlight_spans: bool,
+    /// Add each hit's `matched_fields`: the fields (`title`, `body`, `url`) a query term matched in.
+    #[serde(default)]
+    pub matched_fields: bool,
+    /// Divide every returned score by the top hit's score, so the best match scores 1.0.
+    #[serde(default)]
+    pub normalize_scores: bool,
+    /// Drop hits scoring below this (compared after `normalize_scores`); where depends on `min_score_mode`.
+    #[serde(default)]
+    pub min_score: Option<f32>,
+    /// Whether `min_score` applies before paging (default) or only to the returned page.
+    #[serde(default)]
+    pub min_score_mode: MinScoreMode,
+    /// Collapse hits whose stored text is nearly identical to a higher-ranked hit's.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Keep only the best hit of each (case- and whitespace-normalized) title.
+    #[serde(default)]
+    pub dedup_titles: bool,
+    /// Boost hits whose stored text holds the query words close together.
+    #[serde(default)]
+    pub proximity: bool,
+    /// With `proximity=true`, how many extra positions may separate the query words (default 10).
+    #[serde(default)]
+    pub slop: Option<usize>,
+    /// With `proximity=true`, the score bonus for an exact phrase (default 0.5, i.e. ×1.5);
+    /// looser windows get proportionally less.
+    #[serde(default)]
+    pub proximity_boost: Option<f32>,
+    /// Markup inserted before each highlighted term in snippets (default `<em>`).
+    #[serde(default)]
+    pub highlight_pre: Option<String>,
+    /// Markup inserted after each highlighted term in snippets (default `</em>`).
+    #[serde(default)]
+    pub highlight_post: Option<String>,
+    /// Highlight query words only where they are whole words (default true); `false` also marks
+    /// them inside longer words. Wildcard words highlight as prefixes either way.
+    #[serde(default)]
+    pub whole_word: Option<bool>,
+    /// Divide body weights by each document's norm (default true). `false` scores by raw tf-idf,
+    /// which favours long documents; it needs an index built with `--store-doc-norms`.
+    #[serde(default)]
+    pub normalize_docs: Option<bool>,
+}
+fn default_k() -> usize { 10 }
+fn default_complete() -> bool { true }
+
+/// `POST /search/batch` body.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchSearchParams {
+    /// Searches to run, each with the same options as a `POST /search` body.
+    pub queries: Vec<SearchParams>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarParams {
+    #[serde(default = "default_k")]
+    pub k: usize,
+    /// How many of the source doc's highest-weight terms form the query.
+    #[serde(default = "default_seed_terms")]
+    pub terms: usize,
+}
+fn default_seed_terms() -> usize { 10 }
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct SearchResponse {
+    pub query: String,
+    pub took_ms: u128, // deprecated, kept for backward compatibility
+    pub took_s: f64,
+    pub total_hits: usize,
+    pub results: Vec<SearchHit>,
+    /// Number of documents in the index (debug only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corpus_size: Option<u32>,
+    /// Query tokens found in the dictionary of at least one searched field (debug only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_terms: Option<Vec<String>>,
+    /// Query tokens absent from every searched field: always under debug, otherwise only when
+    /// there are some. `or` ignores them; with `mode=and` they leave no hits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unmatched_terms: Option<Vec<String>>,
+    /// How the query was tokenized and weighted per field (debug only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<ParsedQuery>,
+    /// Set when some postings could not be loaded, so results may be missing matches.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub degraded: bool,
+    /// `false` when some index data could not be read; the results are what could be, and
+    /// `warnings` says what was missing.
+    #[serde(default = "default_complete")]
+    pub complete: bool,
+    /// One line per failure: a term whose postings are missing or corrupt, a hit whose stored
+    /// text could not be read.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Set when the requested `k` exceeded the server's `MAX_K` and was lowered to it.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub k_clamped: bool,
+    /// Where the time went (`profile=true`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+    /// How the `debug_doc` document scored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc_debug: Option<DocDebug>,
+    /// Near-duplicate hits dropped by `dedup=true` (already left out of `total_hits`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates_collapsed: Option<usize>,
+    /// Hits dropped by `dedup_titles=true` for repeating a better hit's title (left out of `total_hits`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub titles_collapsed: Option<usize>,
+}
+
+/// Why one document scored what it did (`debug_doc=<id>`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocDebug {
+    pub doc_id: DocId,
+    pub external_id: String,
+    /// Sum of `contributions`, before `normalize_scores`.
+    pub score: f32,
+    /// 1-based position among all hits; `null` if the doc is not a hit (no term matched, or
+    /// `mode=and` or a filter removed it).
+    pub rank: Option<usize>,
+    pub contributions: Vec<TermContribution>,
+}
+
+/// One query term's share of a document's score: `query_weight * doc_weight * boost`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermContribution {
+    pub field: Cow<'static, str>,
+    pub term: String,
+    pub query_weight: f32,
+    pub doc_weight: f32,
+    pub boost: f32,
+    pub contribution: f32,
+}
+
+/// Seconds spent in each search phase (`profile=true`). Postings loads are timed separately
+/// from the scoring loop that consumes them; `scoring_s` also covers `mode=and`, filters and the sort.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy)]
+pub struct Timings {
+    pub tokenize_s: f64,
+    pub query_weights_s: f64,
+    pub postings_s: f64,
+    pub scoring_s: f64,
+    pub snippets_s: f64,
+}
+
+/// The query as the scorer saw it (`debug=true`).
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ParsedQuery {
+    pub raw: String,
+    pub fields: Vec<ParsedField>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ParsedField {
+    pub field: Cow<'static, str>,
+    pub boost: f32,
+    pub terms: Vec<ParsedTerm>,
+}
+
+/// One query token in one field; the id, df and weight are absent when the field's dictionary lacks it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ParsedTerm {
+    pub term: String,
+    pub term_id: Option<TermId>,
+    pub df: Option<u32>,
+    /// Normalized query weight.
+    pub weight: Option<f32>,
+    /// The `prefix*` word this term was expanded from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expanded_from: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocParams {
+    /// Return at most this many characters of text and set `truncated`.
+    #[serde(default)]
+    pub max_chars: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocTermsParams {
+    /// Return at most this many terms (capped at 1000).
+    #[serde(default = "default_doc_terms_limit")]
+    pub limit: usize,
+}
+fn default_doc_terms_limit() -> usize { 20 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocTerm {
+    pub term: String,
+    pub term_id: TermId,
+    /// tf-idf weight as stored in the term's postings: normalized, unless the index was built
+    /// with `--store-doc-norms`.
+    pub weight: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocTermsResponse {
+    pub doc_id: u32,
+    /// Distinct body terms in the document.
+    pub total: usize,
+    /// Highest weight first.
+    pub terms: Vec<DocTerm>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListDocsParams {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_list_limit")]
+    pub limit: usize,
+}
+fn default_list_limit() -> usize { 50 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocSummary {
+    pub doc_id: u32,
+    pub external_id: String,
+    pub title: String,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListDocsResponse {
+    pub total: usize,
+    pub offset: usize,
+    pub docs: Vec<DocSummary>,
+}
+
+/// One ranked document. Deserializing fills the fields a `hit_fields` list left out with defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchHit {
+    pub doc_id: u32,
+    /// The document's input `id`, for correlating hits with the source system.
+    pub external_id: String,
+    pub score: f32,
+    pub title: String,
+    pub url: Option<String>,
+    pub snippet: Option<String>,
+    /// Highlighted regions of `snippet`, with `highlight_spans=true`.
+    pub matches: Option<Vec<MatchSpan>>,
+    /// Fields a query term matched in, in title, body, url order, with `matched_fields=true`.
+    pub matched_fields: Option<Vec<Cow<'static, str>>>,
+    /// The field `snippet` was taken from, when `snippet_fields` was given.
+    pub snippet_field: Option<Cow<'static, str>>,
+    /// Which of the above are serialized.
+    #[serde(skip)]
+    pub fields: HitFields,
+}
+
+impl Serialize for SearchHit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        if self.fields.has(HitFields::DOC_ID) { map.serialize_entry("doc_id", &self.doc_id)?; }
+        if self.fields.has(HitFields::EXTERNAL_ID) { map.serialize_entry("external_id", &self.external_id)?; }
+        if self.fields.has(HitFields::SCORE) { map.serialize_entry("score", &self.score)?; }
+        if self.fields.has(HitFields::TITLE) { map.serialize_entry("title", &self.title)?; }
+        if self.fields.has(HitFields::URL) { map.serialize_entry("url", &self.url)?; }
+        if self.fields.has(HitFields::SNIPPET) { map.serialize_entry("snippet", &self.snippet)?; }
+        if let Some(matches) = self.matches.as_ref().filter(|_| self.fields.has(HitFields::MATCHES)) {
+            map.serialize_entry("matches", matches)?;
+        }
+        if let Some(matched_fields) = &self.matched_fields { map.serialize_entry("matched_fields", matched_fields)?; }
+        if let Some(field) = self.snippet_field.as_ref().filter(|_| self.fields.has(HitFields::SNIPPET)) { map.serialize_entry("snippet_field", field)?; }
+        map.end()
+    }
+}
+
+/// The `SearchHit` fields a search returns (`hit_fields=title,score`); all of them by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HitFields(u8);
+
+impl HitFields {
+    pub const DOC_ID: u8 = 1 << 0;
+    pub const EXTERNAL_ID: u8 = 1 << 1;
+    pub const SCORE: u8 = 1 << 2;
+    pub const TITLE: u8 = 1 << 3;
+    pub const URL: u8 = 1 << 4;
+    pub const SNIPPET: u8 = 1 << 5;
+    pub const MATCHES: u8 = 1 << 6;
+    const NAMES: [(&'static str, u8); 7] = [
+        ("doc_id", Self::DOC_ID), ("external_id", Self::EXTERNAL_ID), ("score", Self::SCORE), ("title", Self::TITLE),
+        ("url", Self::URL), ("snippet", Self::SNIPPET), ("matches", Self::MATCHES),
+    ];
+
+    pub fn all() -> Self { Self(u8::MAX) }
+
+    pub fn has(self, field: u8) -> bool { self.0 & field != 0 }
+
+    /// Parse a comma-separated list of field names; empty or absent means all fields.
+    pub fn parse(spec: Option<&str>) -> Result<Self, String> {
+        let Some(spec) = spec.filter(|s| !s.trim().is_empty()) else { return Ok(Self::all()) };
+        let mut bits = 0;
+        for name in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (_, bit) = Self::NAMES.iter().find(|(n, _)| *n == name)
+                .ok_or_else(|| format!("unknown hit field '{name}' (expected doc_id, external_id, score, title, url, snippet or matches)"))?;
+            bits |= bit;
+        }
+        Ok(Self(bits))
+    }
+
+    /// Whether any returned field needs the document text.
+    pub fn needs_snippet(self) -> bool { self.has(Self::SNIPPET) || self.has(Self::MATCHES) }
+}
+
+impl Default for HitFields {
+    fn default() -> Self { Self::all() }
+}
+
+
+/// `GET /doc/{id}` and `GET /doc/by-external/{id}`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocResponse {
+    pub doc_id: DocId,
+    pub external_id: String,
+    pub title: String,
+    pub url: Option<String>,
+    /// Whether the index stores text for this document.
+    pub text_available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// With `max_chars`, whether `text` was cut.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    /// Stored texts hold only the first this many bytes of each body (`--snippet-store-bytes`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stored_text_limit: Option<usize>,
+}
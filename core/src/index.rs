@@ -1,5 +1,7 @@
+use crate::tokenizer::{tokenize_with, TokenizerConfig};
+use crate::weighting::Weighting;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 pub type TermId = u32;
 pub type DocId = u32;
@@ -9,8 +11,12 @@ pub struct DocMeta {
     pub external_id: String,
     pub title: String,
     pub url: Option<String>,
-    /// Relative path to the stored full text for snippet extraction, e.g., texts/{doc_id}.txt
+    /// Relative path to the stored full text for snippet extraction, e.g., texts/{doc_id}.txt,
+    /// or [`crate::persist::PACKED_TEXTS_FILE`] when the text is in the packed file
     pub text_path: Option<String>,
+    /// Values of the input `meta` keys selected with `--facet-fields`, for `field:value` filters
+    /// (format version 3+).
+    pub facets: BTreeMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,4 +36,43 @@ pub struct InvertedIndex {
 
 impl InvertedIndex {
     pub fn new() -> Self { Self::default() }
+
+    /// Build an in-memory index over short texts (e.g. titles) using the same
+    /// normalized TF-IDF weighting as the on-disk index. `docs` is left empty.
+    pub fn from_texts<'a, I>(texts: I, config: &TokenizerConfig, weighting: Weighting) -> Self
+    where
+        I: IntoIterator<Item = (DocId, &'a str)>,
+    {
+        let mut index = Self::new();
+        let mut tf_per_doc: Vec<(DocId, HashMap<TermId, u32>)> = Vec::new();
+        for (doc_id, text) in texts {
+            index.num_docs += 1;
+            let mut tf: HashMap<TermId, u32> = HashMap::new();
+            for (term, _pos) in tokenize_with(text, config) {
+                let next_id = index.dictionary.len() as TermId;
+                let tid = *index.dictionary.entry(term).or_insert(next_id);
+                if tid == next_id { index.df.push(0); }
+                *tf.entry(tid).or_insert(0) += 1;
+            }
+            for tid in tf.keys() { index.df[*tid as usize] += 1; }
+            tf_per_doc.push((doc_id, tf));
+        }
+
+        for (doc_id, tf) in tf_per_doc {
+            let max_tf = tf.values().copied().max().unwrap_or(0);
+            let weights: Vec<(TermId, f32)> = tf
+                .into_iter()
+                .map(|(tid, tf_raw)| (tid, weighting.weight(tf_raw, max_tf, index.num_docs, index.df[tid as usize])))
+                .collect();
+            let mut norm = weights.iter().map(|(_, w)| w * w).sum::<f32>().sqrt();
+            if norm == 0.0 { norm = 1.0; }
+            for (tid, w) in weights {
+                index.postings.entry(tid).or_default().push(Posting { doc_id, weight: w / norm });
+            }
+        }
+        for plist in index.postings.values_mut() {
+            plist.sort_by_key(|p| p.doc_id);
+        }
+        index
+    }
 }
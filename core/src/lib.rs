@@ -1,5 +1,10 @@
+pub mod api;
 pub mod tokenizer;
 pub mod index;
 pub mod persist;
+pub mod migrate;
+pub mod postings;
+pub mod verify;
+pub mod weighting;
 
 pub use index::*;
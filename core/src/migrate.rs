@@ -0,0 +1,133 @@
+//! In-place upgrades of an index written in an older `MetaFile::version`.
+//!
+//! Each [`Step`] turns one version into the next: it rewrites only what changed between them
+//! and then records the new version in `meta.json`. Migrating across several versions runs the
+//! steps in order, so every step only ever sees the version it was written for.
+
+use crate::persist::{check_index_files, load_docs, load_meta, replace_docs, save_meta, IndexPaths, MetaFile, CASE_CHANNEL_VERSION, DOC_FACETS_VERSION, DOC_NORMS_VERSION, INDEX_VERSION, POSTINGS_FANOUT_VERSION};
+use anyhow::{bail, Context, Result};
+
+/// One version-to-version transformation.
+#[derive(Debug)]
+pub struct Step {
+    pub from: u32,
+    pub to: u32,
+    /// What the step rewrites, for reports.
+    pub description: &'static str,
+    apply: fn(&IndexPaths, &mut MetaFile) -> Result<()>,
+}
+
+const STEPS: [Step; 4] = [
+    Step { from: 1, to: CASE_CHANNEL_VERSION, description: "record that the index has no case-sensitive channel", apply: v1_to_v2 },
+    Step { from: CASE_CHANNEL_VERSION, to: DOC_FACETS_VERSION, description: "rewrite docs.bin with an empty facet map per document", apply: v2_to_v3 },
+    Step { from: DOC_FACETS_VERSION, to: POSTINGS_FANOUT_VERSION, description: "record that the postings are in one flat directory", apply: v3_to_v4 },
+    Step { from: POSTINGS_FANOUT_VERSION, to: DOC_NORMS_VERSION, description: "record that the postings weights are already normalized", apply: v4_to_v5 },
+];
+
+/// Version 1 had no case channel; version 2 only adds the flag saying whether one exists.
+fn v1_to_v2(_paths: &IndexPaths, meta: &mut MetaFile) -> Result<()> {
+    meta.case_sensitive = false;
+    Ok(())
+}
+
+/// Version 3 stores `DocMeta::facets` in every `docs.bin` entry.
+fn v2_to_v3(paths: &IndexPaths, meta: &mut MetaFile) -> Result<()> {
+    let docs = load_docs(paths, meta.version).context("reading docs.bin")?;
+    replace_docs(paths, &docs).context("writing docs.bin")
+}
+
+/// Version 3 indexes always use the flat `postings/` layout; version 4 records the fanout.
+fn v3_to_v4(_paths: &IndexPaths, meta: &mut MetaFile) -> Result<()> {
+    meta.postings_fanout = 0;
+    Ok(())
+}
+
+/// Version 4 postings always hold normalized weights; version 5 records whether they do.
+fn v4_to_v5(_paths: &IndexPaths, meta: &mut MetaFile) -> Result<()> {
+    meta.doc_norms = false;
+    Ok(())
+}
+
+/// Upgrade the index at `paths` to version `to` (at most [`INDEX_VERSION`]) and return the steps
+/// applied, none if it is already there. Downgrades are refused.
+pub fn migrate(paths: &IndexPaths, to: u32) -> Result<Vec<&'static Step>> {
+    if to > INDEX_VERSION {
+        bail!("can't migrate to version {to}: the newest format is version {INDEX_VERSION}");
+    }
+    check_index_files(paths)?;
+    let mut meta = load_meta(paths).context("reading meta.json")?;
+    if to < meta.version {
+        bail!("the index is version {} and can't be downgraded to {to}; rebuild it with an older indexer instead", meta.version);
+    }
+    let mut applied = Vec::new();
+    while meta.version < to {
+        let Some(step) = STEPS.iter().find(|s| s.from == meta.version) else {
+            bail!("no migration from version {}; rebuild the index", meta.version);
+        };
+        (step.apply)(paths, &mut meta).with_context(|| format!("migrating version {} to {}", step.from, step.to))?;
+        // Recorded after the step's files are in place, so meta.json never claims an unfinished step
+        meta.version = step.to;
+        save_meta(paths, &meta)?;
+        tracing::info!(from = step.from, to = step.to, step = step.description, "migrated index");
+        applied.push(step);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::{load_index_header, save_dictionary};
+    use crate::DocId;
+    use serde::Serialize;
+    use std::collections::{BTreeMap, HashMap};
+
+    /// A version 1 index: legacy `docs.bin` entries without facets.
+    fn legacy_index(name: &str) -> IndexPaths {
+        #[derive(Serialize)]
+        struct V1<'a> { external_id: &'a str, title: &'a str, url: Option<&'a str>, text_path: Option<&'a str> }
+        let dir = std::env::temp_dir().join(format!("migrate-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = IndexPaths::new(&dir);
+        let legacy: BTreeMap<DocId, V1> = [(0, V1 { external_id: "a", title: "A", url: Some("https://a.example"), text_path: Some("texts/0.txt") })].into();
+        std::fs::write(dir.join("docs.bin"), bincode::serialize(&legacy).unwrap()).unwrap();
+        save_dictionary(&paths, &(HashMap::from([("rust".to_string(), 0)]), vec![1])).unwrap();
+        save_meta(&paths, &MetaFile { num_docs: 1, version: 1, ..Default::default() }).unwrap();
+        paths
+    }
+
+    #[test]
+    fn each_step_upgrades_one_version() {
+        let paths = legacy_index("steps");
+        let applied = migrate(&paths, CASE_CHANNEL_VERSION).unwrap();
+        assert_eq!(applied.iter().map(|s| (s.from, s.to)).collect::<Vec<_>>(), vec![(1, 2)]);
+        assert_eq!(load_meta(&paths).unwrap().version, CASE_CHANNEL_VERSION);
+
+        let applied = migrate(&paths, INDEX_VERSION).unwrap();
+        assert_eq!(applied.iter().map(|s| (s.from, s.to)).collect::<Vec<_>>(), vec![(2, 3), (3, 4), (4, 5)]);
+        let (_, _, docs, meta, _) = load_index_header(&paths).unwrap();
+        assert_eq!(meta.version, INDEX_VERSION);
+        assert_eq!((docs[&0].external_id.as_str(), docs[&0].url.as_deref()), ("a", Some("https://a.example")));
+        assert!(docs[&0].facets.is_empty());
+        // Already current: nothing to do
+        assert!(migrate(&paths, INDEX_VERSION).unwrap().is_empty());
+        std::fs::remove_dir_all(&paths.root).ok();
+    }
+
+    #[test]
+    fn downgrades_and_unknown_versions_are_refused() {
+        let paths = legacy_index("refused");
+        assert!(migrate(&paths, INDEX_VERSION + 1).is_err());
+        migrate(&paths, INDEX_VERSION).unwrap();
+        let err = migrate(&paths, 1).unwrap_err().to_string();
+        assert!(err.contains("can't be downgraded"), "{err}");
+
+        // An index from a newer indexer isn't loaded as if this build understood it
+        let mut meta = load_meta(&paths).unwrap();
+        meta.version = INDEX_VERSION + 1;
+        save_meta(&paths, &meta).unwrap();
+        let err = load_index_header(&paths).unwrap_err().to_string();
+        assert!(err.contains("reads at most version"), "{err}");
+        std::fs::remove_dir_all(&paths.root).ok();
+    }
+}
@@ -1,38 +1,264 @@
-use crate::{DocId, DocMeta, InvertedIndex, Posting, TermId};
-use anyhow::Result;
+use crate::tokenizer::TokenizerConfig;
+use crate::weighting::Weighting;
+use crate::{DocId, DocMeta, Posting, TermId};
+use anyhow::{bail, Context, Result};
 use bincode;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{create_dir_all, File};
-use std::io::{Read, Write};
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct MetaFile {
     pub num_docs: u32,
     pub created_at: String,
     pub version: u32,
+    /// Tokenizer settings used at index time; queries must be tokenized the same way.
+    #[serde(default)]
+    pub tokenizer: TokenizerConfig,
+    /// Pivoted length normalization blend used for doc norms (0 = plain cosine).
+    #[serde(default)]
+    pub pivot: f32,
+    /// A case-preserved term channel exists under `case/` (format version 2+).
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Input `meta` keys stored per document as filterable facets (format version 3+).
+    #[serde(default)]
+    pub facet_fields: Vec<String>,
+    /// Input `meta` keys whose text was indexed with the body (`--index-meta`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub index_meta: Vec<String>,
+    /// Stored texts hold at most this many leading bytes of each body (`--snippet-store-bytes`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet_store_bytes: Option<usize>,
+    /// Postings are spread over this many `postings/` subdirectories; 0 keeps them in one flat
+    /// directory (format version 4+).
+    #[serde(default)]
+    pub postings_fanout: u32,
+    /// tf and idf schemes of the postings weights, applied to queries too.
+    #[serde(default)]
+    pub weighting: Weighting,
+    /// Posting lists keep at most this many highest-weight documents (`--max-postings-per-term`).
+    /// `df` still counts every document, so idf is that of the full list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_postings_per_term: Option<usize>,
+    /// Body terms whose posting list was cut to `max_postings_per_term`.
+    #[serde(default)]
+    pub capped_terms: usize,
+    /// Body postings and term vectors hold un-normalized tf-idf, and each document's norm is in
+    /// `doc_norms.bin` for query time (`--store-doc-norms`). Otherwise weights are already divided by
+    /// it (format version 5+).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub doc_norms: bool,
+}
+
+/// First `MetaFile::version` that can carry a case-sensitive channel.
+pub const CASE_CHANNEL_VERSION: u32 = 2;
+/// First `MetaFile::version` whose `docs.bin` entries carry `DocMeta::facets`.
+pub const DOC_FACETS_VERSION: u32 = 3;
+/// First `MetaFile::version` that can carry `MetaFile::postings_fanout`.
+pub const POSTINGS_FANOUT_VERSION: u32 = 4;
+/// First `MetaFile::version` that can carry `MetaFile::doc_norms`.
+pub const DOC_NORMS_VERSION: u32 = 5;
+/// Format version written by the indexer, and the newest one this build can read.
+pub const INDEX_VERSION: u32 = DOC_NORMS_VERSION;
+
+/// `DocMeta` as stored before [`DOC_FACETS_VERSION`].
+#[derive(Deserialize)]
+struct LegacyDocMeta {
+    external_id: String,
+    title: String,
+    url: Option<String>,
+    text_path: Option<String>,
+}
+
+impl From<LegacyDocMeta> for DocMeta {
+    fn from(m: LegacyDocMeta) -> Self {
+        Self { external_id: m.external_id, title: m.title, url: m.url, text_path: m.text_path, facets: Default::default() }
+    }
 }
 
 pub struct IndexPaths {
     pub root: PathBuf,
+    /// `MetaFile::postings_fanout` of the index at `root`.
+    pub postings_fanout: u32,
 }
 
 impl IndexPaths {
     pub fn new<P: AsRef<Path>>(root: P) -> Self {
-        Self { root: root.as_ref().to_path_buf() }
+        Self { root: root.as_ref().to_path_buf(), postings_fanout: 0 }
     }
+    /// Locate postings files in `fanout` subdirectories, as recorded in the index's `meta.json`.
+    pub fn with_postings_fanout(self, fanout: u32) -> Self { Self { postings_fanout: fanout, ..self } }
+    /// Paths of the case-sensitive term channel: its own dictionary and postings under `case/`.
+    pub fn case_channel(&self) -> IndexPaths { IndexPaths::new(self.root.join("case")).with_postings_fanout(self.postings_fanout) }
     fn dictionary(&self) -> PathBuf { self.root.join("dictionary.bin") }
     fn docs(&self) -> PathBuf { self.root.join("docs.bin") }
     fn meta(&self) -> PathBuf { self.root.join("meta.json") }
     fn postings_dir(&self) -> PathBuf { self.root.join("postings") }
+    /// `postings/{id:08}.postings.bin`, or with a fanout `postings/{id % fanout}/{id:08}.postings.bin`
+    /// (the shard zero-padded to the width of `fanout - 1`).
+    pub fn postings_file(&self, term_id: TermId) -> PathBuf {
+        let name = format!("{term_id:08}.postings.bin");
+        match self.postings_fanout {
+            0 | 1 => self.postings_dir().join(name),
+            fanout => {
+                let width = (fanout - 1).to_string().len();
+                self.postings_dir().join(format!("{:0width$}", term_id % fanout)).join(name)
+            }
+        }
+    }
     fn doc_id_map(&self) -> PathBuf { self.root.join("doc_id_map.bin") }
+    fn texts_dat(&self) -> PathBuf { self.root.join(PACKED_TEXTS_FILE) }
+    fn texts_idx(&self) -> PathBuf { self.root.join("texts.idx") }
+    fn descriptions(&self) -> PathBuf { self.root.join("descriptions.bin") }
+    fn timestamps(&self) -> PathBuf { self.root.join("timestamps.bin") }
+    fn doc_norms(&self) -> PathBuf { self.root.join("doc_norms.bin") }
+    fn weight_bases(&self) -> PathBuf { self.root.join(WEIGHT_BASES_FILE) }
+    fn term_vectors_dat(&self) -> PathBuf { self.root.join("term_vectors.dat") }
+    fn term_vectors_idx(&self) -> PathBuf { self.root.join("term_vectors.idx") }
+}
+
+/// `DocMeta.text_path` of documents whose text lives in the packed `texts.dat`.
+pub const PACKED_TEXTS_FILE: &str = "texts.dat";
+
+/// Appends one record per document to a `.dat` file; [`PackedWriter::finish`] writes the
+/// `(offset, len)` table indexed by doc_id to the matching `.idx`.
+struct PackedWriter {
+    dat: BufWriter<File>,
+    offsets: Vec<(u64, u64)>,
+    pos: u64,
+}
+
+impl PackedWriter {
+    fn create(root: &Path, dat: PathBuf) -> Result<Self> {
+        create_dir_all(root)?;
+        Ok(Self { dat: BufWriter::new(File::create(dat)?), offsets: Vec::new(), pos: 0 })
+    }
+
+    fn append(&mut self, doc_id: DocId, bytes: &[u8]) -> Result<()> {
+        let idx = doc_id as usize;
+        if self.offsets.len() <= idx { self.offsets.resize(idx + 1, (0, 0)); }
+        self.dat.write_all(bytes)?;
+        self.offsets[idx] = (self.pos, bytes.len() as u64);
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self, idx: PathBuf) -> Result<()> {
+        self.dat.flush()?;
+        let mut f = File::create(idx)?;
+        f.write_all(&bincode::serialize(&self.offsets)?)?;
+        Ok(())
+    }
+}
+
+/// Read-only, memory-mapped view of a [`PackedWriter`] file, so reads touch only the pages they need.
+struct PackedReader {
+    /// `None` when every record is empty (a zero-length file cannot be mapped).
+    data: Option<memmap2::Mmap>,
+    offsets: Vec<(u64, u64)>,
+}
+
+impl PackedReader {
+    /// Open the packed file, or `Ok(None)` if its offset table doesn't exist.
+    fn open(idx: PathBuf, dat: PathBuf) -> Result<Option<Self>> {
+        if !idx.exists() { return Ok(None); }
+        let offsets = bincode::deserialize(&std::fs::read(idx)?)?;
+        let file = File::open(dat)?;
+        // SAFETY: index files are written once by the indexer and never modified in place.
+        let data = if file.metadata()?.len() == 0 { None } else { Some(unsafe { memmap2::Mmap::map(&file)? }) };
+        Ok(Some(Self { data, offsets }))
+    }
+
+    fn get(&self, doc_id: DocId) -> Option<&[u8]> {
+        let &(offset, len) = self.offsets.get(doc_id as usize)?;
+        match &self.data {
+            Some(data) => data.get(offset as usize..(offset + len) as usize),
+            None => Some(&[]),
+        }
+    }
+}
+
+/// Appends document texts to `texts.dat`; [`PackedTextWriter::finish`] writes the
+/// `(offset, len)` table indexed by doc_id to `texts.idx`.
+pub struct PackedTextWriter(PackedWriter);
+
+impl PackedTextWriter {
+    pub fn create(paths: &IndexPaths) -> Result<Self> {
+        Ok(Self(PackedWriter::create(&paths.root, paths.texts_dat())?))
+    }
+
+    pub fn append(&mut self, doc_id: DocId, text: &str) -> Result<()> {
+        self.0.append(doc_id, text.as_bytes())
+    }
+
+    pub fn finish(self, paths: &IndexPaths) -> Result<()> {
+        self.0.finish(paths.texts_idx())
+    }
+}
+
+/// Read-only view of `texts.dat`, memory-mapped so snippet reads touch only the pages they need.
+pub struct PackedTexts(PackedReader);
+
+impl PackedTexts {
+    /// Open the packed texts, or `Ok(None)` if the index stores texts as separate files.
+    pub fn open(paths: &IndexPaths) -> Result<Option<Self>> {
+        Ok(PackedReader::open(paths.texts_idx(), paths.texts_dat())?.map(Self))
+    }
+
+    /// Text of `doc_id`, if it was packed and is valid UTF-8.
+    pub fn get(&self, doc_id: DocId) -> Option<&str> {
+        std::str::from_utf8(self.0.get(doc_id)?).ok()
+    }
+}
+
+/// One document's body terms with their stored (normalized tf-idf) weights, highest first.
+pub type TermVector = Vec<(TermId, f32)>;
+
+/// Writes `term_vectors.dat` / `term_vectors.idx` (`--store-term-vectors`).
+pub struct TermVectorWriter(PackedWriter);
+
+impl TermVectorWriter {
+    pub fn create(paths: &IndexPaths) -> Result<Self> {
+        Ok(Self(PackedWriter::create(&paths.root, paths.term_vectors_dat())?))
+    }
+
+    pub fn append(&mut self, doc_id: DocId, vector: &TermVector) -> Result<()> {
+        self.0.append(doc_id, &bincode::serialize(vector)?)
+    }
+
+    pub fn finish(self, paths: &IndexPaths) -> Result<()> {
+        self.0.finish(paths.term_vectors_idx())
+    }
+}
+
+/// Memory-mapped per-document term vectors, for indexes built with `--store-term-vectors`.
+pub struct TermVectors(PackedReader);
+
+impl TermVectors {
+    /// Open the term vectors, or `Ok(None)` if the index has none.
+    pub fn open(paths: &IndexPaths) -> Result<Option<Self>> {
+        Ok(PackedReader::open(paths.term_vectors_idx(), paths.term_vectors_dat())?.map(Self))
+    }
+
+    /// Term vector of `doc_id`; `None` past the last document or if the entry is corrupt.
+    pub fn get(&self, doc_id: DocId) -> Option<TermVector> {
+        bincode::deserialize(self.0.get(doc_id)?).ok()
+    }
+}
+
+/// Key-ordered view of a map. Bincode encodes it exactly like the `HashMap` (length, then
+/// entries), so files load the same way but no longer depend on hash iteration order.
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> BTreeMap<&K, &V> {
+    map.iter().collect()
 }
 
 pub fn save_dictionary(paths: &IndexPaths, dict: &(HashMap<String, TermId>, Vec<u32>)) -> Result<()> {
     create_dir_all(&paths.root)?;
     let mut f = File::create(paths.dictionary())?;
-    let bytes = bincode::serialize(dict)?;
+    let bytes = bincode::serialize(&(sorted(&dict.0), &dict.1))?;
     f.write_all(&bytes)?;
     Ok(())
 }
@@ -47,23 +273,36 @@ pub fn load_dictionary(paths: &IndexPaths) -> Result<(HashMap<String, TermId>, V
 
 pub fn save_docs(paths: &IndexPaths, docs: &HashMap<DocId, DocMeta>) -> Result<()> {
     let mut f = File::create(paths.docs())?;
-    let bytes = bincode::serialize(docs)?;
+    let bytes = bincode::serialize(&sorted(docs))?;
     f.write_all(&bytes)?;
     Ok(())
 }
 
-pub fn load_docs(paths: &IndexPaths) -> Result<HashMap<DocId, DocMeta>> {
+/// Replace `docs.bin` with `docs` in the current format: written beside it, then renamed over it,
+/// so a failure leaves the old file intact.
+pub fn replace_docs(paths: &IndexPaths, docs: &HashMap<DocId, DocMeta>) -> Result<()> {
+    let tmp = paths.root.join("docs.bin.tmp");
+    std::fs::write(&tmp, bincode::serialize(&sorted(docs))?)?;
+    std::fs::rename(&tmp, paths.docs())?;
+    Ok(())
+}
+
+/// Load `docs.bin` as written by an index of format `version`.
+pub fn load_docs(paths: &IndexPaths, version: u32) -> Result<HashMap<DocId, DocMeta>> {
     let mut f = File::open(paths.docs())?;
     let mut buf = Vec::new();
     f.read_to_end(&mut buf)?;
+    if version < DOC_FACETS_VERSION {
+        let legacy: HashMap<DocId, LegacyDocMeta> = bincode::deserialize(&buf)?;
+        return Ok(legacy.into_iter().map(|(id, m)| (id, m.into())).collect());
+    }
     let docs = bincode::deserialize(&buf)?;
     Ok(docs)
 }
 
 pub fn save_postings_for_term(paths: &IndexPaths, term_id: TermId, postings: &Vec<Posting>) -> Result<()> {
-    let dir = paths.postings_dir();
-    create_dir_all(&dir)?;
-    let file = dir.join(format!("{term_id:08}.postings.bin"));
+    let file = paths.postings_file(term_id);
+    if let Some(dir) = file.parent() { create_dir_all(dir)?; }
     let mut f = File::create(file)?;
     let bytes = bincode::serialize(postings)?;
     f.write_all(&bytes)?;
@@ -71,7 +310,7 @@ pub fn save_postings_for_term(paths: &IndexPaths, term_id: TermId, postings: &Ve
 }
 
 pub fn load_postings_for_term(paths: &IndexPaths, term_id: TermId) -> Result<Vec<Posting>> {
-    let file = paths.postings_dir().join(format!("{term_id:08}.postings.bin"));
+    let file = paths.postings_file(term_id);
     let mut f = File::open(file)?;
     let mut buf = Vec::new();
     f.read_to_end(&mut buf)?;
@@ -97,11 +336,77 @@ pub fn load_meta(paths: &IndexPaths) -> Result<MetaFile> {
 
 pub fn save_doc_id_map(paths: &IndexPaths, map: &HashMap<String, DocId>) -> Result<()> {
     let mut f = File::create(paths.doc_id_map())?;
-    let bytes = bincode::serialize(map)?;
+    let bytes = bincode::serialize(&sorted(map))?;
     f.write_all(&bytes)?;
     Ok(())
 }
 
+/// Meta descriptions by doc_id, used as a snippet fallback.
+pub fn save_descriptions(paths: &IndexPaths, descriptions: &HashMap<DocId, String>) -> Result<()> {
+    let mut f = File::create(paths.descriptions())?;
+    f.write_all(&bincode::serialize(&sorted(descriptions))?)?;
+    Ok(())
+}
+
+/// Meta descriptions by doc_id; empty for indexes built without any.
+pub fn load_descriptions(paths: &IndexPaths) -> Result<HashMap<DocId, String>> {
+    let path = paths.descriptions();
+    if !path.exists() { return Ok(HashMap::new()); }
+    Ok(bincode::deserialize(&std::fs::read(path)?)?)
+}
+
+/// Document dates (Unix seconds) by doc_id, for browsing newest first.
+pub fn save_timestamps(paths: &IndexPaths, timestamps: &HashMap<DocId, i64>) -> Result<()> {
+    let mut f = File::create(paths.timestamps())?;
+    f.write_all(&bincode::serialize(&sorted(timestamps))?)?;
+    Ok(())
+}
+
+/// Document dates by doc_id; empty for indexes built without any.
+pub fn load_timestamps(paths: &IndexPaths) -> Result<HashMap<DocId, i64>> {
+    let path = paths.timestamps();
+    if !path.exists() { return Ok(HashMap::new()); }
+    Ok(bincode::deserialize(&std::fs::read(path)?)?)
+}
+
+/// Body vector norm (after `pivot` blending) of every document, indexed by doc_id.
+pub fn save_doc_norms(paths: &IndexPaths, norms: &[f32]) -> Result<()> {
+    let mut f = File::create(paths.doc_norms())?;
+    f.write_all(&bincode::serialize(norms)?)?;
+    Ok(())
+}
+
+pub fn load_doc_norms(paths: &IndexPaths) -> Result<Vec<f32>> {
+    Ok(bincode::deserialize(&std::fs::read(paths.doc_norms())?)?)
+}
+
+/// `weight_bases.bin`: statistics of documents whose body weights predate the current df.
+pub const WEIGHT_BASES_FILE: &str = "weight_bases.bin";
+
+/// The `N` and df that a range of documents' body postings were weighted with. A server commit
+/// that appends documents without reweighting records one for the documents it leaves stale, so
+/// a later reweight pass can rescale their weights to the current df.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightBasis {
+    pub docs: std::ops::Range<DocId>,
+    pub num_docs: u32,
+    /// df by term id at the time; terms added later aren't in these documents.
+    pub df: Vec<u32>,
+}
+
+/// Write the stale document ranges, in ascending doc id order.
+pub fn save_weight_bases(paths: &IndexPaths, bases: &[WeightBasis]) -> Result<()> {
+    std::fs::write(paths.weight_bases(), bincode::serialize(bases)?)?;
+    Ok(())
+}
+
+/// Stale document ranges; empty when every document is weighted with the current df.
+pub fn load_weight_bases(paths: &IndexPaths) -> Result<Vec<WeightBasis>> {
+    let path = paths.weight_bases();
+    if !path.exists() { return Ok(Vec::new()); }
+    Ok(bincode::deserialize(&std::fs::read(path)?)?)
+}
+
 pub fn load_doc_id_map(paths: &IndexPaths) -> Result<HashMap<String, DocId>> {
     let mut f = File::open(paths.doc_id_map())?;
     let mut buf = Vec::new();
@@ -110,10 +415,111 @@ pub fn load_doc_id_map(paths: &IndexPaths) -> Result<HashMap<String, DocId>> {
     Ok(map)
 }
 
-/// Load only the header structures required to search: dictionary, df, docs, meta.
-pub fn load_index_header(paths: &IndexPaths) -> Result<(HashMap<String, TermId>, Vec<u32>, HashMap<DocId, DocMeta>, MetaFile)> {
-    let (dict, df) = load_dictionary(paths)?;
-    let docs = load_docs(paths)?;
-    let meta = load_meta(paths)?;
-    Ok((dict, df, docs, meta))
+/// Header structures required to search: dictionary, df, docs, meta, and the doc norms of
+/// indexes built with `--store-doc-norms`.
+pub type IndexHeader = (HashMap<String, TermId>, Vec<u32>, HashMap<DocId, DocMeta>, MetaFile, Option<Vec<f32>>);
+
+/// Load only the header structures required to search: dictionary, df, docs, meta and doc norms.
+/// Fails if a header file is missing (see [`check_index_files`]), the index is newer than
+/// [`INDEX_VERSION`], the dictionary and df disagree (see [`check_dictionary`]) or there isn't
+/// one norm per document.
+pub fn load_index_header(paths: &IndexPaths) -> Result<IndexHeader> {
+    check_index_files(paths)?;
+    let meta = load_meta(paths).with_context(|| format!("reading {}", paths.meta().display()))?;
+    // A newer format may store weights or paths this build would silently misread
+    if meta.version > INDEX_VERSION {
+        bail!("the index is format version {} but this build reads at most version {INDEX_VERSION}; upgrade, or rebuild the index with this indexer", meta.version);
+    }
+    let (dict, df) = load_dictionary(paths).with_context(|| format!("reading {}", paths.dictionary().display()))?;
+    check_dictionary(&dict, &df, meta.num_docs)?;
+    let docs = load_docs(paths, meta.version).with_context(|| format!("reading {}", paths.docs().display()))?;
+    let doc_norms = match meta.doc_norms {
+        true => {
+            let norms = load_doc_norms(paths).with_context(|| format!("reading {}", paths.doc_norms().display()))?;
+            if norms.len() != meta.num_docs as usize {
+                bail!("doc_norms.bin has {} norms but the index has {} documents", norms.len(), meta.num_docs);
+            }
+            Some(norms)
+        }
+        false => None,
+    };
+    Ok((dict, df, docs, meta, doc_norms))
+}
+
+/// Check that the index directory and its header files exist, naming what is missing and
+/// how to (re)build it. `meta.json` is written last, so a build that stopped part-way lacks it.
+pub fn check_index_files(paths: &IndexPaths) -> Result<()> {
+    let root = paths.root.display();
+    if !paths.root.is_dir() {
+        bail!("index directory {root} does not exist; build one with `indexer build --input <docs.jsonl> --output {root}`");
+    }
+    let required = [(paths.meta(), "index metadata"), (paths.dictionary(), "term dictionary"), (paths.docs(), "document table")];
+    let missing: Vec<String> = required.iter().filter(|(path, _)| !path.exists())
+        .map(|(path, what)| format!("{} ({what})", path.file_name().unwrap_or_default().to_string_lossy()))
+        .collect();
+    if missing.len() == required.len() {
+        bail!("{root} is not an index (no meta.json, dictionary.bin or docs.bin); build one with `indexer build --input <docs.jsonl> --output {root}`");
+    }
+    if !missing.is_empty() {
+        bail!("index at {root} is incomplete, missing {}; the build may have been interrupted. Rebuild it with `indexer build --input <docs.jsonl> --output {root}`", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Check that `df` has exactly one entry per dictionary term, that term ids are distinct indexes
+/// into it and that no df exceeds `num_docs`. A mismatch means the dictionary, df and postings were
+/// not written by the same build, and query idf would silently come from the wrong counts.
+pub fn check_dictionary(dict: &HashMap<String, TermId>, df: &[u32], num_docs: u32) -> Result<()> {
+    if df.len() != dict.len() {
+        bail!("dictionary has {} terms but df has {} entries", dict.len(), df.len());
+    }
+    let mut seen = vec![false; df.len()];
+    for (term, &tid) in dict {
+        match seen.get_mut(tid as usize) {
+            None => bail!("term {term:?} has id {tid}, outside df ({} entries)", df.len()),
+            Some(true) => bail!("term id {tid} is used by more than one term (one is {term:?})"),
+            Some(slot) => *slot = true,
+        }
+    }
+    if let Some((tid, d)) = df.iter().enumerate().find(|(_, &d)| d > num_docs) {
+        bail!("df of term id {tid} is {d}, more than the index's {num_docs} documents");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn docs_from_before_facets_still_load() {
+        #[derive(Serialize)]
+        struct V1<'a> { external_id: &'a str, title: &'a str, url: Option<&'a str>, text_path: Option<&'a str> }
+        let dir = std::env::temp_dir().join(format!("persist-legacy-docs-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let paths = IndexPaths::new(&dir);
+        let legacy: BTreeMap<DocId, V1> = [(7, V1 { external_id: "a", title: "A", url: None, text_path: Some("texts/7.txt") })].into();
+        std::fs::write(paths.docs(), bincode::serialize(&legacy).unwrap()).unwrap();
+
+        let docs = load_docs(&paths, CASE_CHANNEL_VERSION).unwrap();
+        assert_eq!(docs[&7].external_id, "a");
+        assert!(docs[&7].facets.is_empty());
+
+        save_docs(&paths, &docs).unwrap();
+        assert_eq!(load_docs(&paths, INDEX_VERSION).unwrap()[&7].text_path.as_deref(), Some("texts/7.txt"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn dictionary_and_df_must_agree() {
+        let dict: HashMap<String, TermId> = [("a".to_string(), 0), ("b".to_string(), 1)].into();
+        assert!(check_dictionary(&dict, &[2, 1], 2).is_ok());
+        let short = check_dictionary(&dict, &[2], 2).unwrap_err().to_string();
+        assert!(short.contains("2 terms but df has 1"), "{short}");
+        let gap: HashMap<String, TermId> = [("a".to_string(), 0), ("b".to_string(), 2)].into();
+        assert!(check_dictionary(&gap, &[1, 1], 2).unwrap_err().to_string().contains("outside df"));
+        let dup: HashMap<String, TermId> = [("a".to_string(), 1), ("b".to_string(), 1)].into();
+        assert!(check_dictionary(&dup, &[1, 1], 2).unwrap_err().to_string().contains("more than one term"));
+        assert!(check_dictionary(&dict, &[3, 1], 2).unwrap_err().to_string().contains("more than the index's 2"));
+    }
 }
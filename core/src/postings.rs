@@ -0,0 +1,55 @@
+//! Set operations over postings lists, which are sorted by `doc_id` with no duplicates.
+
+use crate::{DocId, Posting};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Doc ids present in every list, ascending. Walks the shortest list and gallops through the
+/// others, so a rare term intersected with common ones costs about `short * log(long)`.
+/// No lists yields no docs.
+pub fn intersect_postings(lists: &[Vec<Posting>]) -> Vec<DocId> {
+    let mut by_len: Vec<&[Posting]> = lists.iter().map(Vec::as_slice).collect();
+    by_len.sort_by_key(|list| list.len());
+    let Some((shortest, rest)) = by_len.split_first() else { return Vec::new() };
+    let mut cursors = vec![0usize; rest.len()];
+    let mut out = Vec::new();
+    'candidates: for doc_id in shortest.iter().map(|p| p.doc_id) {
+        for (list, cursor) in rest.iter().zip(cursors.iter_mut()) {
+            *cursor = gallop(list, *cursor, doc_id);
+            match list.get(*cursor) {
+                Some(p) if p.doc_id == doc_id => {}
+                Some(_) => continue 'candidates,
+                None => break 'candidates,
+            }
+        }
+        out.push(doc_id);
+    }
+    out
+}
+
+/// Doc ids present in any list, ascending and distinct (a k-way merge).
+pub fn union_postings(lists: &[Vec<Posting>]) -> Vec<DocId> {
+    let mut heads: BinaryHeap<Reverse<(DocId, usize, usize)>> = lists
+        .iter()
+        .enumerate()
+        .filter_map(|(i, list)| Some(Reverse((list.first()?.doc_id, i, 0))))
+        .collect();
+    let mut out: Vec<DocId> = Vec::new();
+    while let Some(Reverse((doc_id, i, pos))) = heads.pop() {
+        if out.last() != Some(&doc_id) { out.push(doc_id); }
+        if let Some(next) = lists[i].get(pos + 1) { heads.push(Reverse((next.doc_id, i, pos + 1))); }
+    }
+    out
+}
+
+/// First index at or after `from` whose doc id is `>= target`: probe 1, 2, 4, ... ahead, then
+/// binary search the last gap.
+fn gallop(list: &[Posting], from: usize, target: DocId) -> usize {
+    if list.get(from).is_none_or(|p| p.doc_id >= target) { return from; }
+    // Invariant: list[from + bound / 2] < target
+    let mut bound = 1;
+    while from + bound < list.len() && list[from + bound].doc_id < target { bound *= 2; }
+    let start = from + bound / 2;
+    let end = (from + bound + 1).min(list.len());
+    start + list[start..end].partition_point(|p| p.doc_id < target)
+}
@@ -1,12 +1,17 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
 use unicode_normalization::UnicodeNormalization;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::ops::Range;
 
 lazy_static! {
-    static ref RE: Regex = Regex::new(r"(?u)\p{L}[\p{L}\p{N}_']*").expect("valid regex");
-    static ref STEMMER: Stemmer = Stemmer::create(Algorithm::English);
+    static ref RE: Regex = Regex::new(r"(?u)[\p{L}\p{N}][\p{L}\p{N}_']*").expect("valid regex");
+    /// Words may continue through combining marks, which decomposed (NFD/NFKD) text keeps
+    /// separate: `nai\u{308}ve` is one word.
+    static ref RE_DECOMPOSED: Regex = Regex::new(r"(?u)[\p{L}\p{N}][\p{L}\p{M}\p{N}_']*").expect("valid regex");
     static ref STOPWORDS: HashSet<&'static str> = {
         let words: &[&str] = &[
             "a","about","above","after","again","against","all","am","an","and","any","are","aren't","as","at",
@@ -26,19 +31,398 @@ lazy_static! {
         ];
         words.iter().copied().collect()
     };
+    /// Settings of [`tokenize_iter`], whose iterator borrows them.
+    static ref DEFAULT_CONFIG: TokenizerConfig = TokenizerConfig::default();
 }
 
-fn is_stopword(token: &str) -> bool { STOPWORDS.contains(token) }
+/// Marks a raw (unstemmed) form emitted with `raw_forms`: `=unwrap_or_else`. Words never
+/// start with it, so raw forms can't collide with stems in the dictionary.
+pub const RAW_FORM_PREFIX: char = '=';
 
-/// Tokenize text into (term, position) using NFKC normalization, lowercase, stopword removal, and stemming.
+/// Unicode normalization applied to text before it is split into words.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    None,
+    Nfc,
+    Nfd,
+    #[default]
+    Nfkc,
+    Nfkd,
+}
+
+impl std::str::FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(Normalization::None),
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            "nfkc" => Ok(Normalization::Nfkc),
+            "nfkd" => Ok(Normalization::Nfkd),
+            other => Err(format!("unknown normalization form '{other}' (expected nfc, nfd, nfkc, nfkd or none)")),
+        }
+    }
+}
+
+impl Normalization {
+    /// Word pattern for text in this form.
+    fn word_regex(self) -> &'static Regex {
+        match self {
+            Normalization::Nfd | Normalization::Nfkd => &RE_DECOMPOSED,
+            _ => &RE,
+        }
+    }
+
+    fn apply(self, text: &str, out: &mut String) {
+        match self {
+            Normalization::None => out.push_str(text),
+            Normalization::Nfc => out.extend(text.nfc()),
+            Normalization::Nfd => out.extend(text.nfd()),
+            Normalization::Nfkc => out.extend(text.nfkc()),
+            Normalization::Nfkd => out.extend(text.nfkd()),
+        }
+    }
+}
+
+/// Snowball stemmer language, or `none` to index words as written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StemLanguage {
+    None,
+    Arabic,
+    Danish,
+    Dutch,
+    #[default]
+    English,
+    Finnish,
+    French,
+    German,
+    Greek,
+    Hungarian,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Romanian,
+    Russian,
+    Spanish,
+    Swedish,
+    Tamil,
+    Turkish,
+}
+
+impl StemLanguage {
+    fn algorithm(self) -> Option<Algorithm> {
+        Some(match self {
+            StemLanguage::None => return None,
+            StemLanguage::Arabic => Algorithm::Arabic,
+            StemLanguage::Danish => Algorithm::Danish,
+            StemLanguage::Dutch => Algorithm::Dutch,
+            StemLanguage::English => Algorithm::English,
+            StemLanguage::Finnish => Algorithm::Finnish,
+            StemLanguage::French => Algorithm::French,
+            StemLanguage::German => Algorithm::German,
+            StemLanguage::Greek => Algorithm::Greek,
+            StemLanguage::Hungarian => Algorithm::Hungarian,
+            StemLanguage::Italian => Algorithm::Italian,
+            StemLanguage::Norwegian => Algorithm::Norwegian,
+            StemLanguage::Portuguese => Algorithm::Portuguese,
+            StemLanguage::Romanian => Algorithm::Romanian,
+            StemLanguage::Russian => Algorithm::Russian,
+            StemLanguage::Spanish => Algorithm::Spanish,
+            StemLanguage::Swedish => Algorithm::Swedish,
+            StemLanguage::Tamil => Algorithm::Tamil,
+            StemLanguage::Turkish => Algorithm::Turkish,
+        })
+    }
+}
+
+/// Character n-gram lengths emitted for each token, in addition to the token itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NgramRange {
+    pub min: usize,
+    pub max: usize,
+}
+
+/// Tokenizer settings. Recorded in the index's `meta.json` so the server
+/// tokenizes queries exactly as the indexer tokenized documents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenizerConfig {
+    /// Unicode normalization form applied before splitting into words.
+    pub normalization: Normalization,
+    /// Lowercase text before splitting into words.
+    pub lowercase: bool,
+    /// Stopwords to drop (compared lowercased); `None` uses the built-in English list.
+    pub stopwords: Option<BTreeSet<String>>,
+    /// Stemmer applied to each token.
+    pub stemmer: StemLanguage,
+    /// Also emit character n-grams of each token, at the token's position.
+    pub ngrams: Option<NgramRange>,
+    /// Drop words shorter than this many characters (measured after normalization, before stemming).
+    pub min_token_len: usize,
+    /// Drop words longer than this many characters (measured after normalization, before stemming).
+    pub max_token_len: usize,
+    /// Strip a trailing possessive `'s` or `'` (runner's -> runner, runners' -> runners).
+    pub strip_possessives: bool,
+    /// Keep tokens made only of digits (years, ids). When false they are dropped.
+    pub index_numbers: bool,
+    /// Keep case and accents and skip stemming, for exact (`HashMap` != `hashmap`) matching.
+    /// Set for the index's case-sensitive channel; the analyzer recorded in `meta.json` leaves it off.
+    pub preserve_case: bool,
+    /// Also emit the folded, lowercased word prefixed with [`RAW_FORM_PREFIX`] when stemming changed it,
+    /// at the same position, so exact spellings (`unwrap_or_else`) score above other inflections.
+    pub raw_forms: bool,
+    /// Keep words joined by hyphens (`real-time`, `state-of-the-art`) as one compound token,
+    /// at the position of its first part, instead of splitting them.
+    pub keep_hyphenated: bool,
+    /// With `keep_hyphenated`, also emit each part at its own position, so `time` still
+    /// matches `real-time`.
+    pub hyphenated_parts: bool,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self {
+            normalization: Normalization::Nfkc,
+            lowercase: true,
+            stopwords: None,
+            stemmer: StemLanguage::English,
+            ngrams: None,
+            min_token_len: 1,
+            max_token_len: 64,
+            strip_possessives: false,
+            index_numbers: true,
+            preserve_case: false,
+            raw_forms: false,
+            keep_hyphenated: false,
+            hyphenated_parts: false,
+        }
+    }
+}
+
+impl TokenizerConfig {
+    fn is_stopword(&self, token: &str) -> bool {
+        match &self.stopwords {
+            Some(words) => words.contains(token),
+            None => STOPWORDS.contains(token),
+        }
+    }
+
+    fn lowercases(&self) -> bool { self.lowercase && !self.preserve_case }
+
+    fn normalize(&self, text: &str) -> String {
+        let mut normalized = String::with_capacity(text.len());
+        self.normalization.apply(text, &mut normalized);
+        if self.lowercases() { normalized.to_lowercase() } else { normalized }
+    }
+
+    /// Push `term` and, with `ngrams` set, its character n-grams; with `raw_forms`, then push
+    /// the unstemmed `word` if it differs.
+    fn emit(&self, word: String, term: String, mut push: impl FnMut(String)) {
+        if let Some(NgramRange { min, max }) = self.ngrams {
+            let chars: Vec<char> = term.chars().collect();
+            for n in min.max(1)..=max.min(chars.len().saturating_sub(1)) {
+                for gram in chars.windows(n) { push(gram.iter().collect()); }
+            }
+        }
+        let raw = (self.raw_forms && word != term).then(|| format!("{RAW_FORM_PREFIX}{word}"));
+        push(term);
+        if let Some(raw) = raw { push(raw); }
+    }
+}
+
+/// Strip combining marks so accented forms match their unaccented spelling (café -> cafe).
+fn fold_diacritics(token: &str) -> String {
+    token.nfd().filter(|c| !is_combining_mark(*c)).collect()
+}
+
+/// Fold, drop stopwords, numbers and out-of-range lengths, and stem one normalized word
+/// (with `preserve_case`: only drop, on the word as written). Returns the folded word and its stem.
+fn analyze(word: &str, config: &TokenizerConfig) -> Option<(String, String)> {
+    let mut token = if config.preserve_case { word.to_string() } else { fold_diacritics(word) };
+    if config.strip_possessives {
+        let stripped = token.strip_suffix("'s").or_else(|| token.strip_suffix('\'')).unwrap_or(&token);
+        token = stripped.to_string();
+    }
+    if !config.index_numbers && token.chars().all(char::is_numeric) { return None; }
+    let len = token.chars().count();
+    if len < config.min_token_len || len > config.max_token_len { return None; }
+    let is_stopword = if config.lowercases() { config.is_stopword(&token) } else { config.is_stopword(&token.to_lowercase()) };
+    if is_stopword { return None; }
+    match config.stemmer.algorithm().filter(|_| !config.preserve_case) {
+        Some(algorithm) => {
+            let stem = Stemmer::create(algorithm).stem(&token).into_owned();
+            Some((token, stem))
+        }
+        None => Some((token.clone(), token)),
+    }
+}
+
+/// A word of normalized text. Its position counts word-regex matches, so with `keep_hyphenated`
+/// a compound takes its first part's position and its `parts` keep the positions they have
+/// when split; the words after it are numbered the same either way.
+struct Word {
+    pos: usize,
+    range: Range<usize>,
+    /// The hyphen-joined pieces of a compound (empty for a plain word).
+    parts: Vec<(usize, Range<usize>)>,
+}
+
+/// Resumable walk over the words of normalized text, joining hyphenated runs when
+/// `keep_hyphenated` is set. It holds offsets rather than a borrow, so [`TokenIter`] can keep
+/// it next to the normalized text it owns.
+#[derive(Debug, Default)]
+struct WordCursor {
+    /// Byte offset in the normalized text where the next regex search starts.
+    offset: usize,
+    /// Position of the next regex match.
+    pos: usize,
+    /// A match read ahead to see whether it continued a compound, and it did not.
+    peeked: Option<(usize, Range<usize>)>,
+}
+
+impl WordCursor {
+    fn next_match(&mut self, normalized: &str, config: &TokenizerConfig) -> Option<(usize, Range<usize>)> {
+        if let Some(peeked) = self.peeked.take() { return Some(peeked); }
+        // `find_at` looks behind `offset`, so matching resumes exactly as `find_iter` would
+        let mat = config.normalization.word_regex().find_at(normalized, self.offset)?;
+        self.offset = mat.end();
+        self.pos += 1;
+        Some((self.pos - 1, mat.range()))
+    }
+
+    fn next_word(&mut self, normalized: &str, config: &TokenizerConfig) -> Option<Word> {
+        let (pos, range) = self.next_match(normalized, config)?;
+        let mut word = Word { pos, range, parts: Vec::new() };
+        if config.keep_hyphenated {
+            while let Some((pos, range)) = self.next_match(normalized, config) {
+                if !matches!(&normalized[word.range.end..range.start], "-" | "\u{2010}") {
+                    self.peeked = Some((pos, range));
+                    break;
+                }
+                if word.parts.is_empty() { word.parts.push((word.pos, word.range.clone())); }
+                word.range.end = range.end;
+                word.parts.push((pos, range));
+            }
+        }
+        Some(word)
+    }
+}
+
+/// Split normalized text into words, joining hyphenated runs when `keep_hyphenated` is set.
+fn words(normalized: &str, config: &TokenizerConfig) -> Vec<Word> {
+    let mut cursor = WordCursor::default();
+    std::iter::from_fn(|| cursor.next_word(normalized, config)).collect()
+}
+
+/// Analyze `word` (and, with `hyphenated_parts`, its parts) and push each resulting term with
+/// its position and the normalized text range it came from.
+fn emit_word(normalized: &str, word: &Word, config: &TokenizerConfig, mut push: impl FnMut(String, usize, &Range<usize>)) {
+    if let Some((folded, stem)) = analyze(&normalized[word.range.clone()], config) {
+        config.emit(folded, stem, |term| push(term, word.pos, &word.range));
+    }
+    if config.hyphenated_parts {
+        for (pos, range) in &word.parts {
+            if let Some((folded, stem)) = analyze(&normalized[range.clone()], config) {
+                config.emit(folded, stem, |term| push(term, *pos, range));
+            }
+        }
+    }
+}
+
+/// Tokenize text into (term, position) using NFKC normalization, lowercase, diacritic folding, stopword removal, and stemming.
+/// N-grams, when configured, share their token's position.
 pub fn tokenize(text: &str) -> Vec<(String, usize)> {
-    let normalized = text.nfkc().collect::<String>().to_lowercase();
+    tokenize_with(text, &TokenizerConfig::default())
+}
+
+/// [`tokenize`] with explicit settings.
+pub fn tokenize_with(text: &str, config: &TokenizerConfig) -> Vec<(String, usize)> {
+    tokenize_iter_with(text, config).collect()
+}
+
+/// Like [`tokenize`], but yields the tokens one word at a time instead of collecting them,
+/// for callers that only fold over them (term counts, term sets).
+pub fn tokenize_iter(text: &str) -> TokenIter<'static> {
+    tokenize_iter_with(text, &DEFAULT_CONFIG)
+}
+
+/// [`tokenize_iter`] with explicit settings. The text is normalized up front; analysis
+/// (stemming, n-grams) runs lazily as tokens are pulled.
+pub fn tokenize_iter_with<'c>(text: &str, config: &'c TokenizerConfig) -> TokenIter<'c> {
+    TokenIter { config, normalized: config.normalize(text), cursor: WordCursor::default(), pending: VecDeque::new() }
+}
+
+/// Iterator returned by [`tokenize_iter`] and [`tokenize_iter_with`].
+#[derive(Debug)]
+pub struct TokenIter<'c> {
+    config: &'c TokenizerConfig,
+    normalized: String,
+    cursor: WordCursor,
+    /// Terms of the current word not yet yielded (n-grams, raw forms, compound parts).
+    pending: VecDeque<(String, usize)>,
+}
+
+impl Iterator for TokenIter<'_> {
+    type Item = (String, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let word = self.cursor.next_word(&self.normalized, self.config)?;
+            let pending = &mut self.pending;
+            emit_word(&self.normalized, &word, self.config, |term, pos, _| pending.push_back((term, pos)));
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// A token with its byte span in the original (pre-normalization) text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub term: String,
+    /// Ordinal position among all words, counting stopwords, as in [`tokenize`].
+    pub position: usize,
+    pub span: Range<usize>,
+}
+
+/// Like [`tokenize`], but also returns each token's byte range in `text`.
+///
+/// Normalization can change lengths (e.g. the `ﬁ` ligature becomes `fi`), so the
+/// text is normalized one base character (plus trailing combining marks) at a time
+/// and each normalized piece remembers the original bytes it came from.
+pub fn tokenize_spans(text: &str) -> Vec<Token> {
+    tokenize_spans_with(text, &TokenizerConfig::default())
+}
+
+/// [`tokenize_spans`] with explicit settings.
+pub fn tokenize_spans_with(text: &str, config: &TokenizerConfig) -> Vec<Token> {
+    // (start in normalized text, source range in original text), one entry per cluster
+    let mut clusters: Vec<(usize, Range<usize>)> = Vec::new();
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, next)) = chars.peek() {
+            if !is_combining_mark(next) { break; }
+            end = i + next.len_utf8();
+            chars.next();
+        }
+        clusters.push((normalized.len(), start..end));
+        normalized.push_str(&config.normalize(&text[start..end]));
+    }
+    let source_of = |norm_byte: usize| {
+        let idx = clusters.partition_point(|(s, _)| *s <= norm_byte).saturating_sub(1);
+        clusters[idx].1.clone()
+    };
+
     let mut tokens = Vec::new();
-    for (pos, mat) in RE.find_iter(&normalized).enumerate() {
-        let token = mat.as_str();
-        if is_stopword(token) { continue; }
-        let stem = STEMMER.stem(token).to_string();
-        tokens.push((stem, pos));
+    for word in words(&normalized, config) {
+        emit_word(&normalized, &word, config, |term, position, range| {
+            let span = source_of(range.start).start..source_of(range.end - 1).end;
+            tokens.push(Token { term, position, span });
+        });
     }
     tokens
 }
@@ -0,0 +1,63 @@
+//! Whole-index consistency check (`indexer verify`), run before a new build is put live.
+//!
+//! Loading an index only reads its header; postings are read lazily per query, so a truncated or
+//! mismatched postings file would otherwise only surface as `degraded` searches. Verifying reads
+//! every postings file of every channel and checks it against the dictionary and document table.
+
+use crate::persist::{check_dictionary, load_dictionary, load_index_header, load_postings_for_term, IndexPaths, PackedTexts, TermVectors};
+use crate::{DocId, DocMeta, TermId};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// What a successful verification looked at.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub num_docs: u32,
+    pub terms: usize,
+    pub postings: usize,
+}
+
+/// Check the index at `paths`: header files, dictionary against df, every postings file (it
+/// loads, names only known documents, has finite weights and as many entries as the term's df,
+/// or the `max_postings_per_term` cap) and, when present, the doc norms (positive and finite), case channel,
+/// packed texts and term vectors.
+pub fn verify_index(paths: &IndexPaths) -> Result<VerifyReport> {
+    let (dictionary, df, docs, meta, doc_norms) = load_index_header(paths)?;
+    if docs.len() != meta.num_docs as usize {
+        bail!("meta.json counts {} documents but docs.bin has {}", meta.num_docs, docs.len());
+    }
+    let paths = IndexPaths::new(&paths.root).with_postings_fanout(meta.postings_fanout);
+    if let Some((doc_id, norm)) = doc_norms.iter().flatten().enumerate().find(|(_, norm)| !(norm.is_finite() && **norm > 0.0)) {
+        bail!("doc_norms.bin gives doc {doc_id} the norm {norm}");
+    }
+    let mut report = VerifyReport { num_docs: meta.num_docs, ..Default::default() };
+    verify_postings(&paths, &dictionary, &df, &docs, meta.max_postings_per_term, &mut report).context("body postings")?;
+    if meta.case_sensitive {
+        let case = paths.case_channel();
+        let (dictionary, df) = load_dictionary(&case).context("reading case/dictionary.bin")?;
+        check_dictionary(&dictionary, &df, meta.num_docs).context("case-sensitive channel")?;
+        verify_postings(&case, &dictionary, &df, &docs, meta.max_postings_per_term, &mut report).context("case-sensitive postings")?;
+    }
+    PackedTexts::open(&paths).context("opening packed texts")?;
+    TermVectors::open(&paths).context("opening term vectors")?;
+    Ok(report)
+}
+
+fn verify_postings(paths: &IndexPaths, dictionary: &HashMap<String, TermId>, df: &[u32], docs: &HashMap<DocId, DocMeta>, cap: Option<usize>, report: &mut VerifyReport) -> Result<()> {
+    for (term, &tid) in dictionary {
+        let postings = load_postings_for_term(paths, tid).with_context(|| format!("reading postings of {term:?} (term {tid})"))?;
+        let expected = cap.map_or(df[tid as usize] as usize, |cap| (df[tid as usize] as usize).min(cap));
+        if postings.len() != expected {
+            bail!("term {term:?} has {} postings but its df is {} (expected {expected})", postings.len(), df[tid as usize]);
+        }
+        if let Some(p) = postings.iter().find(|p| !docs.contains_key(&p.doc_id)) {
+            bail!("postings of {term:?} name doc {}, which is not in docs.bin", p.doc_id);
+        }
+        if let Some(p) = postings.iter().find(|p| !p.weight.is_finite()) {
+            bail!("postings of {term:?} give doc {} the weight {}", p.doc_id, p.weight);
+        }
+        report.terms += 1;
+        report.postings += postings.len();
+    }
+    Ok(())
+}
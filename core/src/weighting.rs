@@ -0,0 +1,106 @@
+//! Term-frequency and inverse-document-frequency variants of tf-idf.
+//!
+//! A term's weight in a document is `tf * idf`, divided by the document's L2 norm. Queries are
+//! weighted with the same pair of schemes, which the indexer records in `meta.json`.
+
+use serde::{Deserialize, Serialize};
+
+/// How a term's raw count `tf` in a document (or query) becomes its tf factor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TfScheme {
+    /// `1 + ln(tf)`
+    #[default]
+    Log,
+    /// `tf`
+    Raw,
+    /// `1` for any occurrence
+    Boolean,
+    /// `0.5 + 0.5 * tf / max_tf`, where `max_tf` is the count of the document's most frequent term
+    Augmented,
+}
+
+impl std::str::FromStr for TfScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "log" => Ok(TfScheme::Log),
+            "raw" => Ok(TfScheme::Raw),
+            "boolean" => Ok(TfScheme::Boolean),
+            "augmented" => Ok(TfScheme::Augmented),
+            other => Err(format!("unknown tf scheme '{other}' (expected log, raw, boolean or augmented)")),
+        }
+    }
+}
+
+impl TfScheme {
+    /// The tf factor of a term occurring `tf` times where the most frequent term occurs `max_tf` times.
+    pub fn weight(self, tf: u32, max_tf: u32) -> f32 {
+        if tf == 0 { return 0.0; }
+        match self {
+            TfScheme::Log => 1.0 + (tf as f32).ln(),
+            TfScheme::Raw => tf as f32,
+            TfScheme::Boolean => 1.0,
+            TfScheme::Augmented => 0.5 + 0.5 * tf as f32 / max_tf.max(tf) as f32,
+        }
+    }
+}
+
+/// How a term's document frequency `df` among `N` documents becomes its idf factor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdfScheme {
+    /// `ln(N / df)`: zero for a term in every document
+    #[default]
+    Standard,
+    /// `ln(1 + N / df)`: never zero, so ubiquitous terms still count a little
+    Smooth,
+    /// `max(0, ln((N - df) / df))`: zero for terms in half the documents or more
+    Probabilistic,
+    /// `1`: tf alone
+    None,
+}
+
+impl std::str::FromStr for IdfScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "standard" => Ok(IdfScheme::Standard),
+            "smooth" => Ok(IdfScheme::Smooth),
+            "probabilistic" => Ok(IdfScheme::Probabilistic),
+            "none" => Ok(IdfScheme::None),
+            other => Err(format!("unknown idf scheme '{other}' (expected standard, smooth, probabilistic or none)")),
+        }
+    }
+}
+
+impl IdfScheme {
+    /// The idf factor of a term in `df` of `num_docs` documents (both floored at 1).
+    pub fn weight(self, num_docs: u32, df: u32) -> f32 {
+        let n = num_docs.max(1) as f32;
+        let df = df.max(1) as f32;
+        match self {
+            IdfScheme::Standard => (n / df).ln(),
+            IdfScheme::Smooth => (1.0 + n / df).ln(),
+            IdfScheme::Probabilistic => ((n - df).max(0.0) / df).ln().max(0.0),
+            IdfScheme::None => 1.0,
+        }
+    }
+}
+
+/// The tf and idf schemes of an index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Weighting {
+    pub tf: TfScheme,
+    pub idf: IdfScheme,
+}
+
+impl Weighting {
+    /// Unnormalized `tf * idf` weight.
+    pub fn weight(&self, tf: u32, max_tf: u32, num_docs: u32, df: u32) -> f32 {
+        self.tf.weight(tf, max_tf) * self.idf.weight(num_docs, df)
+    }
+}
@@ -0,0 +1,55 @@
+use core::postings::{intersect_postings, union_postings};
+use core::{DocId, Posting};
+use std::collections::BTreeSet;
+
+fn list(ids: &[DocId]) -> Vec<Posting> {
+    ids.iter().map(|&doc_id| Posting { doc_id, weight: 1.0 }).collect()
+}
+
+#[test]
+fn intersection_and_union_of_small_lists() {
+    let lists = [list(&[1, 3, 5, 7, 9]), list(&[3, 4, 5, 9, 10]), list(&[0, 3, 9])];
+    assert_eq!(intersect_postings(&lists), vec![3, 9]);
+    assert_eq!(union_postings(&lists), vec![0, 1, 3, 4, 5, 7, 9, 10]);
+
+    assert!(intersect_postings(&[]).is_empty());
+    assert!(union_postings(&[]).is_empty());
+    assert!(intersect_postings(&[list(&[1, 2]), list(&[])]).is_empty());
+    assert_eq!(union_postings(&[list(&[]), list(&[2])]), vec![2]);
+    assert_eq!(intersect_postings(&[list(&[4, 8])]), vec![4, 8]);
+}
+
+/// xorshift64*: a small deterministic generator, so failures reproduce from the printed seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 { self.next() % n }
+}
+
+/// A sorted, distinct list whose density and range vary, so some cases are sparse against dense.
+fn random_list(rng: &mut Rng) -> BTreeSet<DocId> {
+    let universe = 1 + rng.below(2_000) as DocId;
+    let len = rng.below(universe as u64 + 1) as usize;
+    (0..len).map(|_| rng.below(universe as u64) as DocId).collect()
+}
+
+#[test]
+fn intersection_and_union_match_a_set_reference() {
+    for seed in 1..=500u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let sets: Vec<BTreeSet<DocId>> = (0..1 + rng.below(5)).map(|_| random_list(&mut rng)).collect();
+        let lists: Vec<Vec<Posting>> = sets.iter().map(|s| list(&s.iter().copied().collect::<Vec<_>>())).collect();
+
+        let expected_and: Vec<DocId> = sets[0].iter().copied().filter(|d| sets.iter().all(|s| s.contains(d))).collect();
+        let expected_or: Vec<DocId> = sets.iter().flatten().copied().collect::<BTreeSet<_>>().into_iter().collect();
+        assert_eq!(intersect_postings(&lists), expected_and, "intersection, seed {seed}");
+        assert_eq!(union_postings(&lists), expected_or, "union, seed {seed}");
+    }
+}
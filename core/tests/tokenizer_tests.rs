@@ -1,4 +1,4 @@
-use core::tokenizer::tokenize;
+use core::tokenizer::{tokenize, tokenize_iter, tokenize_iter_with, tokenize_spans, tokenize_spans_with, tokenize_with, NgramRange, Normalization, StemLanguage, TokenizerConfig, RAW_FORM_PREFIX};
 
 #[test]
 fn it_normalizes_and_stems() {
@@ -16,4 +16,141 @@ fn it_filters_stopwords() {
     let words: Vec<String> = toks.into_iter().map(|(w, _)| w).collect();
     assert!(!words.contains(&"the".to_string()));
     assert!(!words.contains(&"and".to_string()));
-}
\ No newline at end of file
+}
+
+#[test]
+fn spans_point_into_original_text() {
+    let text = "The \u{FB01}le Caf\u{65}\u{301} runs";
+    let toks = tokenize_spans(text);
+    let found: Vec<(&str, &str)> = toks.iter().map(|t| (t.term.as_str(), &text[t.span.clone()])).collect();
+    assert_eq!(found, vec![("file", "\u{FB01}le"), ("cafe", "Caf\u{65}\u{301}"), ("run", "runs")]);
+    // Stems and positions agree with `tokenize`
+    let plain: Vec<(String, usize)> = toks.into_iter().map(|t| (t.term, t.position)).collect();
+    assert_eq!(plain, tokenize(text));
+}
+
+#[test]
+fn it_filters_by_token_length() {
+    let config = TokenizerConfig { min_token_len: 2, max_token_len: 8, ..Default::default() };
+    let words: Vec<String> = tokenize_with("x marks supercalifragilistic spot", &config).into_iter().map(|(w, _)| w).collect();
+    assert_eq!(words, vec!["mark".to_string(), "spot".to_string()]);
+    // Defaults keep single letters
+    assert!(tokenize("x marks").iter().any(|(w, _)| w == "x"));
+}
+
+#[test]
+fn it_strips_possessives_when_enabled() {
+    let config = TokenizerConfig { strip_possessives: true, ..Default::default() };
+    let words: Vec<String> = tokenize_with("the runner's shoes", &config).into_iter().map(|(w, _)| w).collect();
+    assert_eq!(words, vec!["runner".to_string(), "shoe".to_string()]);
+}
+
+#[test]
+fn it_drops_numbers_when_disabled() {
+    assert!(tokenize("released in 2024").iter().any(|(w, _)| w == "2024"));
+    let config = TokenizerConfig { index_numbers: false, ..Default::default() };
+    let words: Vec<String> = tokenize_with("released in 2024 for x86", &config).into_iter().map(|(w, _)| w).collect();
+    assert_eq!(words, vec!["releas".to_string(), "x86".to_string()]);
+}
+
+#[test]
+fn it_keeps_case_and_skips_stemming_when_preserving_case() {
+    let config = TokenizerConfig { preserve_case: true, ..Default::default() };
+    let words: Vec<String> = tokenize_with("The HashMap Running café", &config).into_iter().map(|(w, _)| w).collect();
+    assert_eq!(words, vec!["HashMap".to_string(), "Running".to_string(), "café".to_string()]);
+}
+
+#[test]
+fn analyzer_options_swap_stemmer_stopwords_and_add_ngrams() {
+    let words = |text: &str, config: &TokenizerConfig| -> Vec<String> { tokenize_with(text, config).into_iter().map(|(w, _)| w).collect() };
+
+    let unstemmed = TokenizerConfig { stemmer: StemLanguage::None, ..Default::default() };
+    assert_eq!(words("the Running dogs", &unstemmed), vec!["running", "dogs"]);
+
+    let custom_stops = TokenizerConfig { stopwords: Some(["dogs".to_string()].into()), ..Default::default() };
+    assert_eq!(words("the dogs bark", &custom_stops), vec!["the", "bark"]);
+
+    let french = TokenizerConfig { stemmer: StemLanguage::French, stopwords: Some(Default::default()), ..Default::default() };
+    assert_eq!(words("continuellement", &french), vec!["continuel"]);
+
+    let grams = TokenizerConfig { ngrams: Some(NgramRange { min: 2, max: 3 }), stemmer: StemLanguage::None, ..Default::default() };
+    let tokens = tokenize_with("a rust", &grams);
+    let at_rust: Vec<&str> = tokens.iter().filter(|(_, p)| *p == 1).map(|(w, _)| w.as_str()).collect();
+    assert_eq!(at_rust, vec!["ru", "us", "st", "rus", "ust", "rust"]);
+    assert_eq!(tokenize_spans_with("a rust", &grams).len(), tokens.len());
+
+    let cased = TokenizerConfig { lowercase: false, stemmer: StemLanguage::None, ..Default::default() };
+    assert_eq!(words("The Rust", &cased), vec!["Rust"]);
+}
+
+#[test]
+fn normalization_form_decides_whether_compatibility_forms_collapse() {
+    let words = |form: Normalization| -> Vec<String> {
+        let config = TokenizerConfig { normalization: form, ..Default::default() };
+        tokenize_with("port ８０８０ ﬁle", &config).into_iter().map(|(w, _)| w).collect()
+    };
+    // NFKC (the default) folds the full-width digits and the ligature
+    assert_eq!(words(Normalization::Nfkc), vec!["port", "8080", "file"]);
+    assert_eq!(words(Normalization::Nfkc), tokenize("port ８０８０ ﬁle").into_iter().map(|(w, _)| w).collect::<Vec<_>>());
+    // NFC and no normalization keep them as written
+    assert_eq!(words(Normalization::Nfc), vec!["port", "８０８０", "ﬁle"]);
+    assert_eq!(words(Normalization::None), vec!["port", "８０８０", "ﬁle"]);
+    // Decomposed forms keep a word whole across its combining marks
+    let nfd = TokenizerConfig { normalization: Normalization::Nfd, ..Default::default() };
+    assert_eq!(tokenize_with("naïve café", &nfd).into_iter().map(|(w, _)| w).collect::<Vec<_>>(), vec!["naiv", "cafe"]);
+    let spans = tokenize_spans_with("naïve café", &nfd);
+    assert_eq!(spans.iter().map(|t| &"naïve café"[t.span.clone()]).collect::<Vec<_>>(), vec!["naïve", "café"]);
+
+    assert_eq!("NFKD".parse::<Normalization>(), Ok(Normalization::Nfkd));
+    assert!("nfx".parse::<Normalization>().is_err());
+}
+
+#[test]
+fn raw_forms_follow_their_stem_only_when_stemming_changed_the_word() {
+    let config = TokenizerConfig { raw_forms: true, ..Default::default() };
+    let tokens = tokenize_with("Calls unwrap_or_else fast", &config);
+    let expected = [("call", 0), ("=calls", 0), ("unwrap_or_els", 1), ("=unwrap_or_else", 1), ("fast", 2)];
+    assert_eq!(tokens, expected.map(|(w, p)| (w.to_string(), p)));
+    // Spans line up with the token list, raw forms included
+    let spans = tokenize_spans_with("Calls unwrap_or_else fast", &config);
+    assert_eq!(spans.iter().map(|t| t.term.as_str()).collect::<Vec<_>>(), expected.map(|(w, _)| w));
+    assert!(tokenize("unwrap_or_else").iter().all(|(w, _)| !w.starts_with(RAW_FORM_PREFIX)));
+}
+
+#[test]
+fn keep_hyphenated_emits_compounds_as_one_token() {
+    let terms = |text: &str, config: &TokenizerConfig| tokenize_with(text, config).into_iter().map(|(w, _)| w).collect::<Vec<_>>();
+    let keep = TokenizerConfig { keep_hyphenated: true, stemmer: StemLanguage::None, ..Default::default() };
+    assert_eq!(terms("real-time", &keep), vec!["real-time"]);
+    assert_eq!(terms("real-time", &TokenizerConfig { stemmer: StemLanguage::None, ..Default::default() }), vec!["real", "time"]);
+    // A dash with spaces around it is punctuation, not a compound
+    assert_eq!(terms("real - time", &keep), vec!["real", "time"]);
+
+    // Parts keep their own positions, and the words after the compound are numbered as if it were split
+    let parts = TokenizerConfig { hyphenated_parts: true, ..keep.clone() };
+    let expected = [("state-of-the-art", 0), ("state", 0), ("art", 3), ("search", 4)];
+    assert_eq!(tokenize_with("state-of-the-art search", &parts), expected.map(|(w, p)| (w.to_string(), p)));
+    let spans = tokenize_spans_with("State-of-the-art search", &parts);
+    assert_eq!(spans.iter().map(|t| &"State-of-the-art search"[t.span.clone()]).collect::<Vec<_>>(), vec!["State-of-the-art", "State", "art", "search"]);
+}
+
+#[test]
+fn tokenize_iter_yields_what_tokenize_collects() {
+    let text = "The runner's real-time café: Ｆｕｌｌ-width ﬁles, unwrap_or_else() and 2024 again.";
+    assert_eq!(tokenize_iter(text).collect::<Vec<_>>(), tokenize(text));
+    let configs = [
+        TokenizerConfig { raw_forms: true, ..Default::default() },
+        TokenizerConfig { ngrams: Some(NgramRange { min: 2, max: 3 }), ..Default::default() },
+        TokenizerConfig { keep_hyphenated: true, hyphenated_parts: true, ..Default::default() },
+        TokenizerConfig { normalization: Normalization::Nfd, preserve_case: true, ..Default::default() },
+    ];
+    for config in &configs {
+        let streamed: Vec<_> = tokenize_iter_with(text, config).collect();
+        assert_eq!(streamed, tokenize_with(text, config), "{config:?}");
+        // tokenize_spans_with walks the words without the iterator
+        assert_eq!(streamed, tokenize_spans_with(text, config).into_iter().map(|t| (t.term, t.position)).collect::<Vec<_>>(), "{config:?}");
+    }
+    // Lazy: taking a prefix gives the same prefix
+    let ngrams = &configs[1];
+    assert_eq!(tokenize_iter_with(text, ngrams).take(5).collect::<Vec<_>>(), tokenize_with(text, ngrams)[..5]);
+}
@@ -0,0 +1,79 @@
+use core::tokenizer::{StemLanguage, TokenizerConfig};
+use core::weighting::{IdfScheme, TfScheme, Weighting};
+use core::{DocId, InvertedIndex};
+
+const TF_SCHEMES: [TfScheme; 4] = [TfScheme::Log, TfScheme::Raw, TfScheme::Boolean, TfScheme::Augmented];
+const IDF_SCHEMES: [IdfScheme; 4] = [IdfScheme::Standard, IdfScheme::Smooth, IdfScheme::Probabilistic, IdfScheme::None];
+
+/// Four docs: "zebra" in 2 (max tf 3), "lynx" in 2, "otter" in 2, "puma" in 1.
+const CORPUS: [&str; 4] = ["zebra zebra zebra lynx", "zebra otter", "otter otter lynx", "puma"];
+
+/// The documented formulas, written out independently of `core::weighting`.
+fn expected_tf(scheme: TfScheme, tf: f32, max_tf: f32) -> f32 {
+    match scheme {
+        TfScheme::Log => 1.0 + tf.ln(),
+        TfScheme::Raw => tf,
+        TfScheme::Boolean => 1.0,
+        TfScheme::Augmented => 0.5 + 0.5 * tf / max_tf,
+    }
+}
+
+fn expected_idf(scheme: IdfScheme, n: f32, df: f32) -> f32 {
+    match scheme {
+        IdfScheme::Standard => (n / df).ln(),
+        IdfScheme::Smooth => (1.0 + n / df).ln(),
+        IdfScheme::Probabilistic => ((n - df) / df).ln().max(0.0),
+        IdfScheme::None => 1.0,
+    }
+}
+
+/// Normalized weights of `doc` computed by hand from its term counts.
+fn expected_doc_weights(weighting: Weighting, doc: &str) -> Vec<(String, f32)> {
+    let mut counts: Vec<(String, u32)> = Vec::new();
+    for word in doc.split_whitespace() {
+        match counts.iter_mut().find(|(w, _)| w == word) {
+            Some((_, c)) => *c += 1,
+            None => counts.push((word.to_string(), 1)),
+        }
+    }
+    let max_tf = counts.iter().map(|(_, c)| *c).max().unwrap() as f32;
+    let raw: Vec<(String, f32)> = counts
+        .into_iter()
+        .map(|(word, tf)| {
+            let df = CORPUS.iter().filter(|d| d.split_whitespace().any(|w| w == word)).count() as f32;
+            let w = expected_tf(weighting.tf, tf as f32, max_tf) * expected_idf(weighting.idf, CORPUS.len() as f32, df);
+            (word, w)
+        })
+        .collect();
+    let norm = raw.iter().map(|(_, w)| w * w).sum::<f32>().sqrt();
+    let norm = if norm == 0.0 { 1.0 } else { norm };
+    raw.into_iter().map(|(word, w)| (word, w / norm)).collect()
+}
+
+#[test]
+fn every_scheme_combination_matches_its_formula() {
+    let config = TokenizerConfig { stemmer: StemLanguage::None, ..Default::default() };
+    for tf in TF_SCHEMES {
+        for idf in IDF_SCHEMES {
+            let weighting = Weighting { tf, idf };
+            let index = InvertedIndex::from_texts(CORPUS.iter().enumerate().map(|(i, t)| (i as DocId, *t)), &config, weighting);
+            for (doc_id, text) in CORPUS.iter().enumerate() {
+                for (word, want) in expected_doc_weights(weighting, text) {
+                    let tid = index.dictionary[&word];
+                    let got = index.postings[&tid].iter().find(|p| p.doc_id == doc_id as DocId).unwrap().weight;
+                    assert!((got - want).abs() < 1e-5, "{weighting:?}: doc {doc_id} term {word}: got {got}, want {want}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn schemes_parse_from_their_flag_names() {
+    assert_eq!("augmented".parse::<TfScheme>(), Ok(TfScheme::Augmented));
+    assert_eq!(" Boolean ".parse::<TfScheme>(), Ok(TfScheme::Boolean));
+    assert_eq!("probabilistic".parse::<IdfScheme>(), Ok(IdfScheme::Probabilistic));
+    assert_eq!("none".parse::<IdfScheme>(), Ok(IdfScheme::None));
+    assert!("bm25".parse::<IdfScheme>().is_err());
+    assert_eq!(Weighting::default(), Weighting { tf: TfScheme::Log, idf: IdfScheme::Standard });
+}
@@ -1,25 +1,30 @@
 use anyhow::{anyhow, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use parking_lot::RwLock;
 use reqwest::{header, Client, Url};
 use scraper::{Html, Selector};
 use serde::Serialize;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use time::format_description::well_known::Rfc3339;
 use sha1::{Sha1, Digest};
+use flate2::{write::GzEncoder, Compression};
 
 #[derive(Parser, Debug)]
 #[command(name = "crawler")]
 #[command(about = "Crawl the web to JSONL, respecting robots.txt")]
 struct Cli {
-    /// Path to a file with seed URLs (one per line)
-    #[arg(long)]
-    seeds: String,
+    /// File with seed URLs, one per line (repeatable); `*` and `?` in a path match several files
+    #[arg(long, required = true)]
+    seeds: Vec<String>,
+    /// Seed files list bare hostnames: each line seeds `https://<host>/`
+    #[arg(long, default_value_t = false)]
+    seeds_from_host_list: bool,
     /// Output JSONL file path
     #[arg(long, default_value = "./sample_data/crawl.jsonl")]
     output: String,
@@ -29,18 +34,226 @@ struct Cli {
     /// Maximum pages to crawl per host (politeness)
     #[arg(long, default_value_t = 10)]
     max_per_host: usize,
-    /// Concurrency (number of workers)
-    #[arg(long, default_value_t = 16)]
-    concurrency: usize,
-    /// Request timeout seconds
-    #[arg(long, default_value_t = 12)]
-    timeout_secs: u64,
+    /// Preset for --concurrency, --max-concurrent-per-host, --min-host-delay-ms and --timeout-secs;
+    /// those flags override it
+    #[arg(long, value_enum)]
+    politeness: Option<Politeness>,
+    /// Concurrency (number of workers) [default: 16, or the --politeness preset's]
+    #[arg(long)]
+    concurrency: Option<usize>,
+    /// Request timeout seconds [default: 12, or the --politeness preset's]
+    #[arg(long)]
+    timeout_secs: Option<u64>,
     /// User-Agent string to use for robots.txt and crawling
     #[arg(long, default_value = "search-engine-rs-bot/0.1 (+https://example.com/bot)")]
     user_agent: String,
     /// If true, only follow links that remain on the same host as the page
     #[arg(long, default_value_t = true)]
     same_host_only: bool,
+    /// Roll output into numbered shards (crawl-00001.jsonl, ...) of at most this many docs
+    #[arg(long)]
+    shard_size: Option<usize>,
+    /// Gzip-compress output files (adds .gz)
+    #[arg(long, default_value_t = false)]
+    gzip: bool,
+    /// Maximum simultaneous in-flight requests to any one host [default: unlimited, or the --politeness preset's]
+    #[arg(long)]
+    max_concurrent_per_host: Option<usize>,
+    /// Wait at least this long before each request to a host, even without a robots.txt Crawl-delay
+    /// [default: 0, or the --politeness preset's]
+    #[arg(long)]
+    min_host_delay_ms: Option<u64>,
+    /// Write a JSONL log of every URL's outcome (fetched, robots-disallowed, non-html, ...)
+    #[arg(long)]
+    crawl_log: Option<String>,
+    /// Extra request header as "Name: Value" (repeatable); also sent for robots.txt
+    #[arg(long = "header")]
+    headers: Vec<String>,
+    /// Accept-Language header value, e.g. en-US
+    #[arg(long)]
+    accept_language: Option<String>,
+    /// Fetch only the seed URLs: don't follow links (and ignore --max-per-host)
+    #[arg(long, default_value_t = false)]
+    no_follow: bool,
+    /// Refetch a host's robots.txt once its cached copy is this old; 0 keeps it for the whole crawl
+    #[arg(long, default_value_t = 3600)]
+    robots_ttl_secs: u64,
+    /// Flush output (and the crawl log) after this many emitted docs; 0 flushes only on exit
+    #[arg(long, default_value_t = 100)]
+    flush_every: usize,
+    /// Also flush buffered output at least this often while docs are being emitted; 0 disables
+    #[arg(long, default_value_t = 5)]
+    flush_interval_secs: u64,
+    /// fsync output files on every periodic flush
+    #[arg(long, default_value_t = false)]
+    fsync: bool,
+    /// Emit noindex pages and follow nofollow pages' links (robots meta tag / X-Robots-Tag)
+    #[arg(long, default_value_t = false)]
+    ignore_meta_robots: bool,
+    /// Follow at most this many redirects per URL; 0 treats every redirect as an HTTP error
+    #[arg(long, default_value_t = 5)]
+    max_redirects: usize,
+    /// Add each doc's redirect chain (the URLs that led to it) to the output as `redirects`
+    #[arg(long, default_value_t = false)]
+    record_redirects: bool,
+    /// Don't copy `<meta name="description">` and `<meta name="keywords">` into each doc's `meta`
+    #[arg(long, default_value_t = false)]
+    no_page_meta: bool,
+    /// Re-queue a URL answered with 429 Too Many Requests up to this many times; 0 drops it
+    #[arg(long, default_value_t = 0)]
+    retry_429: u32,
+    /// Cap on the extra per-host delay added after 429 and 5xx responses, in ms; 0 disables adaptive slowdown
+    #[arg(long, default_value_t = 60_000)]
+    max_adaptive_delay_ms: u64,
+    /// The adaptive delay halves every this many seconds
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    adaptive_delay_half_life_secs: u64,
+}
+
+/// `--politeness` presets: how hard the crawl may push each host.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Politeness {
+    Conservative,
+    Balanced,
+    Aggressive,
+}
+
+/// Request pacing, from a `--politeness` preset (or the defaults) plus explicit flags.
+#[derive(Debug, Clone, Copy)]
+struct PolitenessSettings {
+    concurrency: usize,
+    max_concurrent_per_host: Option<usize>,
+    min_host_delay_ms: u64,
+    timeout_secs: u64,
+}
+
+impl PolitenessSettings {
+    /// Without a preset: the flags' historical defaults.
+    const DEFAULT: Self = Self { concurrency: 16, max_concurrent_per_host: None, min_host_delay_ms: 0, timeout_secs: 12 };
+
+    fn preset(politeness: Option<Politeness>) -> Self {
+        match politeness {
+            None => Self::DEFAULT,
+            Some(Politeness::Conservative) => Self { concurrency: 4, max_concurrent_per_host: Some(1), min_host_delay_ms: 1000, timeout_secs: 30 },
+            Some(Politeness::Balanced) => Self { concurrency: 16, max_concurrent_per_host: Some(2), min_host_delay_ms: 250, timeout_secs: 15 },
+            Some(Politeness::Aggressive) => Self { concurrency: 64, max_concurrent_per_host: Some(8), min_host_delay_ms: 0, timeout_secs: 10 },
+        }
+    }
+
+    /// The preset with any explicitly given flags taking precedence.
+    fn from_args(args: &Cli) -> Self {
+        let preset = Self::preset(args.politeness);
+        Self {
+            concurrency: args.concurrency.unwrap_or(preset.concurrency),
+            max_concurrent_per_host: args.max_concurrent_per_host.or(preset.max_concurrent_per_host),
+            min_host_delay_ms: args.min_host_delay_ms.unwrap_or(preset.min_host_delay_ms),
+            timeout_secs: args.timeout_secs.unwrap_or(preset.timeout_secs),
+        }
+    }
+}
+
+/// Read every `--seeds` file (expanding globs) into one frontier, dropping blank lines, `#`
+/// comments, lines that aren't URLs (or hostnames, with `host_list`) and repeats of an earlier seed.
+fn load_seeds(patterns: &[String], host_list: bool) -> Result<VecDeque<Url>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let matched = expand_glob(pattern)?;
+        if matched.is_empty() { return Err(anyhow!("--seeds {pattern:?} matches no files")); }
+        files.extend(matched);
+    }
+    let mut frontier = VecDeque::new();
+    let mut seen = HashSet::new();
+    let (mut invalid, mut duplicates) = (0usize, 0usize);
+    for file in &files {
+        let reader = BufReader::new(File::open(file).map_err(|e| anyhow!("--seeds {}: {e}", file.display()))?);
+        for line in reader.lines() {
+            let s = line?.trim().to_string();
+            if s.is_empty() || s.starts_with('#') { continue; }
+            let u = if host_list {
+                // A bare host may carry a port, which `Url::parse` would take for a scheme
+                if s.contains('/') { Err(url::ParseError::InvalidDomainCharacter) } else { Url::parse(&format!("https://{s}/")) }
+            } else {
+                Url::parse(&s).or_else(|_| Url::parse(&format!("https://{}", s)))
+            };
+            match u {
+                Ok(u) if seen.insert(norm(&u)) => frontier.push_back(u),
+                Ok(_) => duplicates += 1,
+                Err(_) => invalid += 1,
+            }
+        }
+    }
+    eprintln!("crawler: seed_files={} unique_seeds={} duplicate_seeds={duplicates} invalid_seeds={invalid}", files.len(), frontier.len());
+    Ok(frontier)
+}
+
+/// The files `pattern` names: itself when it has no `*` or `?`, otherwise every match in sorted
+/// order. Wildcards match within one path component (`*` never crosses a `/`).
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let is_glob = |s: &str| s.contains(['*', '?']);
+    if !is_glob(pattern) { return Ok(vec![PathBuf::from(pattern)]); }
+    let mut paths = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !is_glob(&part) {
+            paths.iter_mut().for_each(|p| p.push(component));
+            continue;
+        }
+        let mut next = Vec::new();
+        for dir in &paths {
+            let Ok(entries) = fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) else { continue };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                // Like shells, a wildcard doesn't match hidden files unless the pattern starts with '.'
+                let name = name.to_string_lossy();
+                if name.starts_with('.') && !part.starts_with('.') { continue; }
+                if wildcard_match(&part, &name) { next.push(dir.join(&*name)); }
+            }
+        }
+        paths = next;
+    }
+    paths.retain(|p| p.is_file());
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and `?` any one character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    // Position of the last `*` and the name position it was tried at, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Parse `--header` and `--accept-language` into a header map, failing on malformed entries.
+fn default_headers(headers: &[String], accept_language: Option<&str>) -> Result<header::HeaderMap> {
+    let mut map = header::HeaderMap::new();
+    for h in headers {
+        let (name, value) = h.split_once(':').ok_or_else(|| anyhow!("invalid --header {h:?}: expected \"Name: Value\""))?;
+        let name = header::HeaderName::from_bytes(name.trim().as_bytes()).map_err(|e| anyhow!("invalid --header name in {h:?}: {e}"))?;
+        let value = header::HeaderValue::from_str(value.trim()).map_err(|e| anyhow!("invalid --header value in {h:?}: {e}"))?;
+        map.append(name, value);
+    }
+    if let Some(lang) = accept_language {
+        let value = header::HeaderValue::from_str(lang).map_err(|e| anyhow!("invalid --accept-language {lang:?}: {e}"))?;
+        map.insert(header::ACCEPT_LANGUAGE, value);
+    }
+    Ok(map)
 }
 
 #[derive(Debug, Clone)]
@@ -49,11 +262,214 @@ struct Robots {
     allows: Vec<String>,
     disallows: Vec<String>,
     crawl_delay_ms: Option<u64>,
+    /// Slowdown earned by 429 and 5xx responses; survives robots.txt refetches.
+    adaptive: AdaptiveDelay,
+    /// Validators from the robots.txt response, sent back on refetch.
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Robots {
+    /// No rules: everything allowed.
+    fn empty() -> Self {
+        Self { fetched_at: Instant::now(), allows: Vec::new(), disallows: Vec::new(), crawl_delay_ms: None, adaptive: AdaptiveDelay::default(), etag: None, last_modified: None }
+    }
+}
+
+/// Extra delay before each request to a host that answered 429 or 5xx, halving every half-life.
+#[derive(Debug, Clone, Copy, Default)]
+struct AdaptiveDelay {
+    delay_ms: u64,
+    /// When `delay_ms` was last raised.
+    raised_at: Option<Instant>,
+}
+
+impl AdaptiveDelay {
+    /// Slowdown after a first 429 or 5xx without `Retry-After`.
+    const INITIAL_MS: u64 = 1000;
+
+    /// Whether a response with `status` means the host wants us to slow down.
+    fn backs_off(status: reqwest::StatusCode) -> bool {
+        status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+
+    /// The delay now, after decaying since it was last raised.
+    fn current_ms(&self, half_life: Duration) -> u64 {
+        let Some(raised_at) = self.raised_at else { return 0 };
+        let halvings = raised_at.elapsed().as_secs_f64() / half_life.as_secs_f64();
+        (self.delay_ms as f64 * 0.5f64.powf(halvings)).round() as u64
+    }
+
+    /// Double the current delay, to at least `INITIAL_MS` and the server's `Retry-After`, at most `max_ms`.
+    fn raise(&mut self, retry_after_ms: Option<u64>, half_life: Duration, max_ms: u64) {
+        let delay_ms = (self.current_ms(half_life) * 2).max(Self::INITIAL_MS).max(retry_after_ms.unwrap_or(0)).min(max_ms);
+        *self = Self { delay_ms, raised_at: Some(Instant::now()) };
+    }
+}
+
+/// Why a URL was or wasn't emitted.
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+enum Outcome {
+    Fetched,
+    RobotsDisallowed,
+    NonHtml { content_type: String },
+    TooLarge { bytes: usize },
+    HttpError { status: u16 },
+    FetchError { error: String },
+    DedupSkipped,
+    HostLimit,
+    /// Fetched, but the page opted out with a `noindex` robots meta tag or `X-Robots-Tag`.
+    MetaNoindex,
+    /// Redirected to another host while `--same-host-only` is set.
+    OffHostRedirect { location: String },
+    TooManyRedirects { max: usize },
+    /// 429 Too Many Requests; `requeued` if the URL went back into the frontier (`--retry-429`).
+    RateLimited { retry_after_ms: Option<u64>, requeued: bool },
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Fetched => "fetched",
+            Outcome::RobotsDisallowed => "robots_disallowed",
+            Outcome::NonHtml { .. } => "non_html",
+            Outcome::TooLarge { .. } => "too_large",
+            Outcome::HttpError { .. } => "http_error",
+            Outcome::FetchError { .. } => "fetch_error",
+            Outcome::DedupSkipped => "dedup_skipped",
+            Outcome::HostLimit => "host_limit",
+            Outcome::MetaNoindex => "meta_noindex",
+            Outcome::OffHostRedirect { .. } => "off_host_redirect",
+            Outcome::TooManyRedirects { .. } => "too_many_redirects",
+            Outcome::RateLimited { .. } => "rate_limited",
+        }
+    }
+}
+
+/// What a worker produced for one URL.
+struct WorkerResult {
+    url: Url,
+    outcome: Outcome,
+    /// Redirects followed from `url`, in order; the last entry is where the page was fetched.
+    redirects: Vec<Url>,
+    /// Extracted (normalized final url, title, body, meta tags) when the page was fetched.
+    doc: Option<(String, String, String, PageMeta)>,
+    links: Vec<Url>,
+}
+
+impl WorkerResult {
+    fn skipped(url: &Url, outcome: Outcome) -> Self {
+        Self { url: url.clone(), outcome, redirects: vec![], doc: None, links: vec![] }
+    }
+
+    /// The URL the response actually came from.
+    fn final_url(&self) -> &Url {
+        self.redirects.last().unwrap_or(&self.url)
+    }
+}
+
+#[derive(Serialize)]
+struct LogRecord<'a> {
+    url: &'a str,
+    #[serde(flatten)]
+    outcome: &'a Outcome,
+    timestamp: String,
+}
+
+/// Per-outcome tallies plus the optional `--crawl-log` sidecar.
+struct CrawlLog {
+    writer: Option<BufWriter<File>>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl CrawlLog {
+    fn create(path: Option<&str>) -> Result<Self> {
+        let writer = path.map(|p| File::create(p).map(BufWriter::new)).transpose()?;
+        Ok(Self { writer, counts: BTreeMap::new() })
+    }
+
+    fn record(&mut self, url: &str, outcome: &Outcome) {
+        *self.counts.entry(outcome.name()).or_insert(0) += 1;
+        if let Some(w) = self.writer.as_mut() {
+            let ts = time::OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+            serde_json::to_writer(&mut *w, &LogRecord { url, outcome, timestamp: ts }).ok();
+            w.write_all(b"\n").ok();
+        }
+    }
+
+    fn summary(&self) -> String {
+        self.counts.iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>().join(" ")
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(w) = self.writer.as_mut() { w.flush()?; }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(mut w) = self.writer { w.flush()?; }
+        Ok(())
+    }
 }
 
 #[derive(Default)]
 struct Seen { urls: HashSet<String>, per_host: HashMap<String, usize> }
 
+/// URLs waiting to be fetched, queued per host. URLs come out in the order they went in, except
+/// that a parked host's (one at `--max-concurrent-per-host`) are passed over without being looked
+/// at until it is unparked.
+#[derive(Default)]
+struct Frontier {
+    /// Each host's URLs with their arrival number; hostless URLs queue under ""
+    queues: HashMap<String, VecDeque<(u64, Url)>>,
+    /// The first URL of every unparked host, by arrival number
+    ready: BTreeMap<u64, String>,
+    parked: HashSet<String>,
+    next_seq: u64,
+    len: usize,
+}
+
+impl Frontier {
+    fn host_key(url: &Url) -> String { url.host_str().unwrap_or_default().to_string() }
+
+    fn push_back(&mut self, url: Url) {
+        let (seq, host) = (self.next_seq, Self::host_key(&url));
+        self.next_seq += 1;
+        self.len += 1;
+        let queue = self.queues.entry(host.clone()).or_default();
+        if queue.is_empty() && !self.parked.contains(&host) { self.ready.insert(seq, host); }
+        queue.push_back((seq, url));
+    }
+
+    /// The oldest URL whose host isn't parked.
+    fn pop_front(&mut self) -> Option<Url> {
+        let (_, host) = self.ready.pop_first()?;
+        let queue = self.queues.get_mut(&host).expect("ready hosts have a queue");
+        let (_, url) = queue.pop_front().expect("ready queues are non-empty");
+        match queue.front() {
+            Some(&(next, _)) => { self.ready.insert(next, host); }
+            None => { self.queues.remove(&host); }
+        }
+        self.len -= 1;
+        Some(url)
+    }
+
+    /// Hold back `host`'s URLs until [`Frontier::unpark`].
+    fn park(&mut self, host: &str) {
+        if !self.parked.insert(host.to_string()) { return; }
+        if let Some(&(seq, _)) = self.queues.get(host).and_then(VecDeque::front) { self.ready.remove(&seq); }
+    }
+
+    fn unpark(&mut self, host: &str) {
+        if !self.parked.remove(host) { return; }
+        if let Some(&(seq, _)) = self.queues.get(host).and_then(VecDeque::front) { self.ready.insert(seq, host.to_string()); }
+    }
+
+    fn len(&self) -> usize { self.len }
+    fn is_empty(&self) -> bool { self.len == 0 }
+}
+
 #[derive(Serialize)]
 struct OutDoc<'a> {
     id: String,
@@ -61,140 +477,337 @@ struct OutDoc<'a> {
     body: &'a str,
     url: &'a str,
     timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirects: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "PageMeta::is_empty")]
+    meta: PageMeta,
+}
+
+/// The page's `<meta name="description">` and `<meta name="keywords">`, written as the doc's `meta`.
+#[derive(Debug, Default, Serialize)]
+struct PageMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<String>,
+}
+
+impl PageMeta {
+    /// Collect the tags from `(name, content)` pairs; the first non-empty description wins.
+    fn extract<'a>(tags: impl IntoIterator<Item = (String, &'a str)>) -> Self {
+        let mut meta = Self::default();
+        for (name, content) in tags {
+            match name.as_str() {
+                "description" if meta.description.is_none() => {
+                    let text = content.split_whitespace().collect::<Vec<_>>().join(" ");
+                    meta.description = (!text.is_empty()).then_some(text);
+                }
+                "keywords" => meta.keywords.extend(content.split(',').map(str::trim).filter(|k| !k.is_empty()).map(str::to_string)),
+                _ => {}
+            }
+        }
+        meta
+    }
+
+    fn is_empty(&self) -> bool { self.description.is_none() && self.keywords.is_empty() }
+}
+
+enum Sink {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+/// JSONL output, optionally rolled into numbered shards and gzip-compressed.
+struct Output {
+    base: PathBuf,
+    shard_size: Option<usize>,
+    gzip: bool,
+    sink: Sink,
+    shard: usize,
+    in_shard: usize,
+    /// Records written since the last flush.
+    unflushed: usize,
+}
+
+impl Output {
+    fn create(base: &str, shard_size: Option<usize>, gzip: bool) -> Result<Self> {
+        let base = PathBuf::from(base);
+        let shard_size = shard_size.filter(|n| *n > 0);
+        let sink = Self::open_sink(&Self::path_for(&base, shard_size.map(|_| 1), gzip), gzip)?;
+        Ok(Self { base, shard_size, gzip, sink, shard: 1, in_shard: 0, unflushed: 0 })
+    }
+
+    /// `dir/crawl.jsonl` -> `dir/crawl-00001.jsonl[.gz]` when sharding, else `dir/crawl.jsonl[.gz]`.
+    fn path_for(base: &Path, shard: Option<usize>, gzip: bool) -> PathBuf {
+        let name = base.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| "crawl.jsonl".into());
+        let name = name.strip_suffix(".gz").unwrap_or(&name).to_string();
+        let mut name = match shard {
+            Some(n) => {
+                let (stem, ext) = name.rsplit_once('.').unwrap_or((&name, "jsonl"));
+                format!("{stem}-{n:05}.{ext}")
+            }
+            None => name,
+        };
+        if gzip { name.push_str(".gz"); }
+        base.with_file_name(name)
+    }
+
+    fn open_sink(path: &Path, gzip: bool) -> Result<Sink> {
+        let w = BufWriter::new(File::create(path)?);
+        Ok(if gzip { Sink::Gzip(GzEncoder::new(w, Compression::default())) } else { Sink::Plain(w) })
+    }
+
+    fn write_record<T: Serialize>(&mut self, rec: &T) -> Result<()> {
+        if let Some(limit) = self.shard_size {
+            if self.in_shard >= limit {
+                self.shard += 1;
+                self.in_shard = 0;
+                let next = Self::open_sink(&Self::path_for(&self.base, Some(self.shard), self.gzip), self.gzip)?;
+                let prev = std::mem::replace(&mut self.sink, next);
+                Self::close_sink(prev)?;
+            }
+        }
+        let w: &mut dyn Write = match &mut self.sink { Sink::Plain(w) => w, Sink::Gzip(w) => w };
+        serde_json::to_writer(&mut *w, rec)?;
+        w.write_all(b"\n")?;
+        self.in_shard += 1;
+        self.unflushed += 1;
+        Ok(())
+    }
+
+    /// Push buffered records to the OS (a gzip sync point when compressing), optionally fsyncing.
+    fn flush(&mut self, fsync: bool) -> Result<()> {
+        let file = match &mut self.sink {
+            Sink::Plain(w) => { w.flush()?; w.get_ref() }
+            Sink::Gzip(w) => { w.flush()?; w.get_ref().get_ref() }
+        };
+        if fsync { file.sync_data()?; }
+        self.unflushed = 0;
+        Ok(())
+    }
+
+    fn close_sink(sink: Sink) -> Result<()> {
+        match sink {
+            Sink::Plain(mut w) => w.flush()?,
+            Sink::Gzip(w) => w.finish()?.flush()?,
+        }
+        Ok(())
+    }
+
+    /// Flush and close the current (possibly partial) shard.
+    fn finish(self) -> Result<()> { Self::close_sink(self.sink) }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
+    let pacing = PolitenessSettings::from_args(&args);
     if let Some(dir) = std::path::Path::new(&args.output).parent() {
         fs::create_dir_all(dir).ok();
     }
 
+    // Pages follow redirects by hand (see `fetch_page`); robots.txt lookups let reqwest follow them
+    let headers = default_headers(&args.headers, args.accept_language.as_deref())?;
     let client = Client::builder()
         .user_agent(args.user_agent.clone())
+        .default_headers(headers.clone())
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(pacing.timeout_secs))
+        .build()?;
+    let robots_client = Client::builder()
+        .user_agent(args.user_agent.clone())
+        .default_headers(headers)
         .redirect(reqwest::redirect::Policy::limited(5))
-        .timeout(Duration::from_secs(args.timeout_secs))
+        .timeout(Duration::from_secs(pacing.timeout_secs))
         .build()?;
 
-    // Load seeds
-    let mut frontier: VecDeque<Url> = VecDeque::new();
-    for line in BufReader::new(File::open(&args.seeds)?).lines() {
-        let s = line?.trim().to_string();
-        if s.is_empty() || s.starts_with('#') { continue; }
-        let u = Url::parse(&s).or_else(|_| Url::parse(&format!("https://{}", s)));
-        if let Ok(u) = u { frontier.push_back(u); }
-    }
-    if frontier.is_empty() { return Err(anyhow!("no valid seeds")); }
+    let seeds = load_seeds(&args.seeds, args.seeds_from_host_list)?;
+    if seeds.is_empty() { return Err(anyhow!("no valid seeds")); }
+    let mut frontier = Frontier::default();
+    seeds.into_iter().for_each(|u| frontier.push_back(u));
     eprintln!(
-        "crawler: seeds_loaded={} max_docs={} concurrency={} same_host_only={} max_per_host={} output={}",
-        frontier.len(), args.max_docs, args.concurrency, args.same_host_only, args.max_per_host, args.output
+        "crawler: seeds_loaded={} max_docs={} concurrency={} max_concurrent_per_host={:?} min_host_delay_ms={} timeout_secs={} same_host_only={} max_per_host={} no_follow={} output={}",
+        frontier.len(), args.max_docs, pacing.concurrency, pacing.max_concurrent_per_host, pacing.min_host_delay_ms, pacing.timeout_secs, args.same_host_only, args.max_per_host, args.no_follow, args.output
     );
 
-    let mut out = BufWriter::new(File::create(&args.output)?);
+    let mut out = Output::create(&args.output, args.shard_size, args.gzip)?;
     let robots_cache: Arc<RwLock<HashMap<String, Robots>>> = Arc::new(RwLock::new(HashMap::new()));
     let mut seen = Seen::default();
+    let mut log = CrawlLog::create(args.crawl_log.as_deref())?;
 
     let sel_title = Selector::parse("title").unwrap();
     let sel_body = Selector::parse("body").unwrap();
     let sel_a = Selector::parse("a").unwrap();
+    let sel_meta = Selector::parse("meta[name][content]").unwrap();
+    let bot_name = product_token(&args.user_agent);
+    let robots_ttl = (args.robots_ttl_secs > 0).then(|| Duration::from_secs(args.robots_ttl_secs));
 
     let mut emitted = 0usize;
-    let mut inflight: Vec<tokio::task::JoinHandle<(Option<(String,String,String)>, Vec<Url>)>> = Vec::new();
+    let mut last_flush = Instant::now();
+    let flush_interval = (args.flush_interval_secs > 0).then(|| Duration::from_secs(args.flush_interval_secs));
+    let mut inflight: tokio::task::JoinSet<WorkerResult> = tokio::task::JoinSet::new();
+    // The host each worker is fetching from, for `inflight_per_host` once it's done
+    let mut inflight_hosts: HashMap<tokio::task::Id, Option<String>> = HashMap::new();
+    let mut inflight_per_host: HashMap<String, usize> = HashMap::new();
+    // 429 re-queues so far, per normalized URL
+    let mut retries: HashMap<String, u32> = HashMap::new();
+    let adaptive = AdaptiveRules { max_ms: args.max_adaptive_delay_ms, half_life: Duration::from_secs(args.adaptive_delay_half_life_secs) };
 
     while emitted < args.max_docs && (!frontier.is_empty() || !inflight.is_empty()) {
-        // Fill workers
-        while inflight.len() < args.concurrency && !frontier.is_empty() && emitted + inflight.len() < args.max_docs {
-            let url = frontier.pop_front().unwrap();
+        // Fill workers; hosts at their concurrency cap are parked in the frontier until a worker finishes
+        while inflight.len() < pacing.concurrency && emitted + inflight.len() < args.max_docs {
+            let Some(url) = frontier.pop_front() else { break };
             let url_key = norm(&url);
-            if seen.urls.contains(&url_key) { continue; }
+            if seen.urls.contains(&url_key) { log.record(url.as_str(), &Outcome::DedupSkipped); continue; }
+            let host = url.host_str().map(|h| h.to_string());
             seen.urls.insert(url_key.clone());
-            if let Some(h) = url.host_str() {
+            if let Some(h) = url.host_str().filter(|_| !args.no_follow) {
                 let cnt = *seen.per_host.get(h).unwrap_or(&0);
-                if cnt >= args.max_per_host { continue; }
+                if cnt >= args.max_per_host { log.record(url.as_str(), &Outcome::HostLimit); continue; }
                 *seen.per_host.entry(h.to_string()).or_insert(0) = cnt + 1;
             }
 
             let client_c = client.clone();
+            let robots_client_c = robots_client.clone();
             let robots_c = robots_cache.clone();
             let ua = args.user_agent.clone();
             let tsel = sel_title.clone();
             let bsel = sel_body.clone();
             let asel = sel_a.clone();
+            let msel = sel_meta.clone();
+            let follow = !args.no_follow;
+            let bot = bot_name.clone();
+            let ignore_meta_robots = args.ignore_meta_robots;
+            let page_meta = !args.no_page_meta;
+            let redirect_rules = RedirectRules { max: args.max_redirects, same_host_only: args.same_host_only };
 
-            let handle = tokio::spawn(async move {
-                if !allowed(&client_c, &robots_c, &url, &ua).await.unwrap_or(false) {
-                    return (None, vec![]);
-                }
-                if let Some(delay) = robots_delay(&robots_c, &url) { sleep(Duration::from_millis(delay)).await; }
-
-                match client_c.get(url.clone()).send().await {
-                    Ok(resp) => {
-                        if !resp.status().is_success() { return (None, vec![]); }
+            let handle = inflight.spawn(async move {
+                let fetcher = Fetcher { client: &client_c, robots_client: &robots_client_c, robots: &robots_c, ua: &ua, robots_ttl, adaptive, min_delay_ms: pacing.min_host_delay_ms };
+                match fetcher.fetch_page(&url, redirect_rules).await {
+                    Err(outcome) => WorkerResult::skipped(&url, outcome),
+                    Ok((redirects, resp)) => {
+                        let skipped = |outcome| WorkerResult { url: url.clone(), outcome, redirects: redirects.clone(), doc: None, links: vec![] };
+                        if !resp.status().is_success() { return skipped(Outcome::HttpError { status: resp.status().as_u16() }); }
                         if let Some(ct) = resp.headers().get(header::CONTENT_TYPE) {
-                            if let Ok(v) = ct.to_str() { if !v.starts_with("text/html") { return (None, vec![]); } }
+                            if let Ok(v) = ct.to_str() { if !v.starts_with("text/html") { return skipped(Outcome::NonHtml { content_type: v.to_string() }); } }
                         }
-                        let bytes = match resp.bytes().await { Ok(b)=>b, Err(_)=>return (None, vec![]) };
-                        if bytes.len() > 2*1024*1024 { return (None, vec![]); }
+                        let header_directives: Vec<String> = resp.headers().get_all("x-robots-tag").iter().filter_map(|v| v.to_str().ok()).map(str::to_string).collect();
+                        let bytes = match resp.bytes().await { Ok(b)=>b, Err(e)=>return skipped(Outcome::FetchError { error: e.to_string() }) };
+                        if bytes.len() > 2*1024*1024 { return skipped(Outcome::TooLarge { bytes: bytes.len() }); }
+                        let page_url = redirects.last().unwrap_or(&url).clone();
                         let body = String::from_utf8_lossy(&bytes).to_string();
 
                         let doc = Html::parse_document(&body);
                         let title = doc.select(&tsel).next().map(|n| n.text().collect::<String>()).unwrap_or_default();
                         let text = doc.select(&bsel).next().map(|n| n.text().collect::<String>()).unwrap_or_default();
+                        let robots = if ignore_meta_robots { MetaRobots::default() } else {
+                            let tags = doc.select(&msel).filter_map(|m| Some((m.value().attr("name")?.to_lowercase(), m.value().attr("content")?)));
+                            MetaRobots::from_page(&header_directives, tags, &bot)
+                        };
 
                         let mut links = Vec::new();
-                        for a in doc.select(&asel) {
+                        for a in doc.select(&asel).filter(|_| follow && !robots.nofollow) {
                             if let Some(h) = a.value().attr("href") {
-                                if let Ok(u) = Url::parse(h).or_else(|_| url.join(h)) {
+                                if let Ok(u) = Url::parse(h).or_else(|_| page_url.join(h)) {
                                     if u.scheme().starts_with("http") { links.push(u); }
                                 }
                             }
                         }
-                        (Some((norm(&url), title.trim().to_string(), text.trim().to_string())), links)
+                        if robots.noindex { return WorkerResult { url, outcome: Outcome::MetaNoindex, redirects, doc: None, links }; }
+                        let meta = if page_meta {
+                            PageMeta::extract(doc.select(&msel).filter_map(|m| Some((m.value().attr("name")?.to_lowercase(), m.value().attr("content")?))))
+                        } else {
+                            PageMeta::default()
+                        };
+                        let doc = Some((norm(&page_url), title.trim().to_string(), text.trim().to_string(), meta));
+                        WorkerResult { url, outcome: Outcome::Fetched, redirects, doc, links }
                     }
-                    Err(_) => (None, vec![])
                 }
             });
-            inflight.push(handle);
+            if let Some(h) = &host {
+                let n = inflight_per_host.entry(h.clone()).or_insert(0);
+                *n += 1;
+                if pacing.max_concurrent_per_host.is_some_and(|cap| *n >= cap) { frontier.park(h); }
+            }
+            inflight_hosts.insert(handle.id(), host);
         }
 
         if inflight.is_empty() { break; }
 
-        let mut i = 0;
-        while i < inflight.len() {
-            if inflight[i].is_finished() {
-                let h = inflight.swap_remove(i);
-                if let Ok((doc, links)) = h.await {
-                    for l in links {
-                        if args.same_host_only {
-                            if l.host_str() != doc.as_ref().and_then(|(u,_,_)| Url::parse(u).ok()).as_ref().and_then(|uu| uu.host_str()) { continue; }
-                        }
-                        frontier.push_back(l);
-                    }
-                    if let Some((u, t, b)) = doc {
-                        let mut hasher = Sha1::new();
-                        hasher.update(u.as_bytes());
-                        let id = format!("{:x}", hasher.finalize());
-                        let ts = time::OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
-                        let rec = OutDoc { id, title: &t, body: &b, url: &u, timestamp: ts };
-                        serde_json::to_writer(&mut out, &rec).ok();
-                        out.write_all(b"\n").ok();
-                        emitted += 1;
-                        if emitted % 100 == 0 {
-                            eprintln!(
-                                "progress: emitted={} visited={} frontier={}",
-                                emitted,
-                                seen.urls.len(),
-                                frontier.len()
-                            );
-                        }
-                    }
+        // Sleep until a worker finishes (or a time-based flush is due), then take every finished one
+        let flush_at = flush_interval.filter(|_| out.unflushed > 0).map(|d| last_flush + d);
+        let first = match flush_at {
+            Some(at) => tokio::time::timeout_at(at.into(), inflight.join_next_with_id()).await.ok().flatten(),
+            None => inflight.join_next_with_id().await,
+        };
+        for joined in std::iter::successors(first, |_| inflight.try_join_next_with_id()) {
+            let id = match &joined { Ok((id, _)) => *id, Err(e) => e.id() };
+            if let Some(host) = inflight_hosts.remove(&id).flatten() {
+                if let Some(n) = inflight_per_host.get_mut(&host) { *n = n.saturating_sub(1); }
+                frontier.unpark(&host);
+            }
+            let Ok((_, mut res)) = joined else { continue };
+            // A redirect target reached from two URLs (http and https, with and without a
+            // trailing slash, ...) is emitted once, under the id of the final URL
+            let final_key = norm(res.final_url());
+            if !res.redirects.is_empty() && !seen.urls.insert(final_key) && res.doc.is_some() {
+                res.outcome = Outcome::DedupSkipped;
+                res.doc = None;
+            }
+            if let Outcome::RateLimited { requeued, .. } = &mut res.outcome {
+                let url_key = norm(&res.url);
+                let attempts = retries.entry(url_key.clone()).or_insert(0);
+                if *attempts < args.retry_429 {
+                    // Forget the visit so the URL is fetched again (after the host's raised delay)
+                    *attempts += 1;
+                    *requeued = true;
+                    seen.urls.remove(&url_key);
+                    if let Some(n) = res.url.host_str().and_then(|h| seen.per_host.get_mut(h)) { *n = n.saturating_sub(1); }
+                    frontier.push_back(res.url.clone());
                 }
-            } else {
-                i += 1;
             }
+            log.record(res.url.as_str(), &res.outcome);
+            let page_host = res.final_url().host_str().map(str::to_string);
+            for l in res.links {
+                if args.same_host_only && l.host_str() != page_host.as_deref() { continue; }
+                frontier.push_back(l);
+            }
+            if let Some((u, t, b, meta)) = res.doc {
+                let mut hasher = Sha1::new();
+                hasher.update(u.as_bytes());
+                let id = format!("{:x}", hasher.finalize());
+                let ts = time::OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+                let redirects = (args.record_redirects && !res.redirects.is_empty()).then(|| {
+                    std::iter::once(&res.url).chain(&res.redirects[..res.redirects.len() - 1]).map(|r| r.to_string()).collect()
+                });
+                let rec = OutDoc { id, title: &t, body: &b, url: &u, timestamp: ts, redirects, meta };
+                // As with a failed flush, a crawl that can't write its output stops rather than count lost pages
+                out.write_record(&rec)?;
+                emitted += 1;
+                if emitted.is_multiple_of(100) {
+                    eprintln!(
+                        "progress: emitted={} visited={} frontier={}",
+                        emitted,
+                        seen.urls.len(),
+                        frontier.len()
+                    );
+                }
+            }
+        }
+
+        // Bound what a crash or kill can lose: the writers' buffers are otherwise only flushed on exit
+        let count_due = args.flush_every > 0 && out.unflushed >= args.flush_every;
+        let time_due = out.unflushed > 0 && flush_interval.is_some_and(|d| last_flush.elapsed() >= d);
+        if count_due || time_due {
+            out.flush(args.fsync)?;
+            log.flush()?;
+            last_flush = Instant::now();
         }
     }
 
+    out.finish()?;
+    eprintln!("outcomes: {}", log.summary());
+    log.finish()?;
     eprintln!(
         "done: emitted={} visited={} frontier={} -> {}",
         emitted,
@@ -205,66 +818,235 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Page-level robots directives from `<meta name="robots">` and `X-Robots-Tag`.
+#[derive(Debug, Default, Clone, Copy)]
+struct MetaRobots {
+    noindex: bool,
+    nofollow: bool,
+}
+
+impl MetaRobots {
+    /// Combine directive lists like `noindex, nofollow`. Values scoped to a user agent
+    /// (`otherbot: noindex`) only apply when the agent is `bot`.
+    fn parse<'a>(values: impl IntoIterator<Item = &'a str>, bot: &str) -> Self {
+        let mut robots = Self::default();
+        for value in values {
+            let directives = match value.split_once(':') {
+                Some((agent, rest)) if agent.trim().eq_ignore_ascii_case(bot) => rest,
+                Some(_) => continue,
+                None => value,
+            };
+            for d in directives.split(',').map(|d| d.trim().to_lowercase()) {
+                match d.as_str() {
+                    "noindex" => robots.noindex = true,
+                    "nofollow" => robots.nofollow = true,
+                    "none" => { robots.noindex = true; robots.nofollow = true; }
+                    _ => {}
+                }
+            }
+        }
+        robots
+    }
+
+    /// Directives from `X-Robots-Tag` `headers` and the meta tags among `tags` (lowercased
+    /// name, content) named `robots` or `bot`.
+    fn from_page<'a>(headers: &'a [String], tags: impl IntoIterator<Item = (String, &'a str)>, bot: &str) -> Self {
+        let meta = tags.into_iter().filter(|(name, _)| name == "robots" || name == bot).map(|(_, content)| content);
+        Self::parse(headers.iter().map(String::as_str).chain(meta), bot)
+    }
+}
+
 fn norm(u: &Url) -> String { let mut s = u.clone(); s.set_fragment(None); s.to_string() }
 
-fn parse_robots(txt: &str) -> Robots {
-    // minimal parser for the '*' group
-    let mut active = false;
-    let mut allows = Vec::new();
-    let mut disallows = Vec::new();
-    let mut crawl_delay_ms: Option<u64> = None;
+/// "search-engine-rs-bot/0.1 (+https://...)" -> "search-engine-rs-bot", the name robots rules address.
+fn product_token(user_agent: &str) -> String {
+    user_agent.split(['/', ' ']).next().unwrap_or_default().to_lowercase()
+}
+
+/// Parse the rules that apply to `bot` (a [`product_token`]): the group(s) whose
+/// `User-agent` is the longest match for our name, else the `*` group(s). An agent matches
+/// the whole name, case-insensitively (RFC 9309), or a leading `-` part of it: `zapbot`
+/// matches `zapbot-news`, but `bot` and `zap` don't.
+fn parse_robots(txt: &str, bot: &str) -> Robots {
+    // One group per run of User-agent lines; groups naming the same agent are merged below
+    let mut groups: Vec<(Vec<String>, Robots)> = Vec::new();
+    let mut in_agents = false;
     for line in txt.lines() {
-        let l = line.trim();
-        if l.is_empty() || l.starts_with('#') { continue; }
-        if let Some((k, v)) = l.split_once(':') {
-            let key = k.trim().to_lowercase();
-            let val = v.trim();
-            match key.as_str() {
-                "user-agent" => { active = val == "*"; }
-                "allow" if active => allows.push(val.to_string()),
-                "disallow" if active => disallows.push(val.to_string()),
-                "crawl-delay" if active => {
-                    if let Ok(n) = val.parse::<f64>() { crawl_delay_ms = Some((n * 1000.0) as u64); }
+        let l = line.split('#').next().unwrap_or_default().trim();
+        if l.is_empty() { continue; }
+        let Some((k, v)) = l.split_once(':') else { continue };
+        let key = k.trim().to_lowercase();
+        let val = v.trim();
+        if key == "user-agent" {
+            if !in_agents { groups.push((Vec::new(), Robots::empty())); }
+            in_agents = true;
+            if let Some((agents, _)) = groups.last_mut() { agents.push(val.to_lowercase()); }
+            continue;
+        }
+        in_agents = false;
+        let Some((_, rules)) = groups.last_mut() else { continue };
+        match key.as_str() {
+            // An empty rule matches nothing ("Disallow:" allows everything)
+            "allow" | "disallow" if val.is_empty() => {}
+            "allow" => rules.allows.push(val.to_string()),
+            "disallow" => rules.disallows.push(val.to_string()),
+            "crawl-delay" => {
+                if let Ok(n) = val.parse::<f64>() { rules.crawl_delay_ms = Some((n * 1000.0) as u64); }
+            }
+            _ => {}
+        }
+    }
+
+    let specificity = |agent: &str| -> Option<usize> {
+        let named = !agent.is_empty() && bot.strip_prefix(agent).is_some_and(|rest| rest.is_empty() || rest.starts_with('-'));
+        if agent == "*" { Some(0) } else if named { Some(agent.len()) } else { None }
+    };
+    let best = groups.iter().flat_map(|(agents, _)| agents.iter().filter_map(|a| specificity(a))).max();
+    let mut robots = Robots::empty();
+    let Some(best) = best else { return robots };
+    for (_, rules) in groups.into_iter().filter(|(agents, _)| agents.iter().any(|a| specificity(a) == Some(best))) {
+        robots.allows.extend(rules.allows);
+        robots.disallows.extend(rules.disallows);
+        robots.crawl_delay_ms = robots.crawl_delay_ms.or(rules.crawl_delay_ms);
+    }
+    robots
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RedirectRules {
+    max: usize,
+    same_host_only: bool,
+}
+
+/// `--max-adaptive-delay-ms` and `--adaptive-delay-half-life-secs`.
+#[derive(Debug, Clone, Copy)]
+struct AdaptiveRules {
+    max_ms: u64,
+    half_life: Duration,
+}
+
+/// What a worker needs to fetch pages politely.
+struct Fetcher<'a> {
+    client: &'a Client,
+    robots_client: &'a Client,
+    robots: &'a Arc<RwLock<HashMap<String, Robots>>>,
+    ua: &'a str,
+    robots_ttl: Option<Duration>,
+    adaptive: AdaptiveRules,
+    /// `--min-host-delay-ms`: floor under the robots.txt and adaptive delays.
+    min_delay_ms: u64,
+}
+
+impl Fetcher<'_> {
+    /// GET `url`, following redirects by hand so every hop is checked against robots.txt (and
+    /// its crawl delay), `--same-host-only` and `--max-redirects`. Returns the redirect targets
+    /// in order with the final response, which may itself be a redirect without a `Location`.
+    /// A 429 or 5xx slows down later requests to that host; a 429 is returned as `RateLimited`.
+    async fn fetch_page(&self, url: &Url, rules: RedirectRules) -> std::result::Result<(Vec<Url>, reqwest::Response), Outcome> {
+        let mut redirects: Vec<Url> = Vec::new();
+        let mut current = url.clone();
+        loop {
+            if !allowed(self.robots_client, self.robots, &current, self.ua, self.robots_ttl).await.unwrap_or(false) {
+                return Err(Outcome::RobotsDisallowed);
+            }
+            let delay = host_delay(self.robots, &current, self.adaptive.half_life).max(self.min_delay_ms);
+            if delay > 0 { sleep(Duration::from_millis(delay)).await; }
+            let resp = self.client.get(current.clone()).send().await.map_err(|e| Outcome::FetchError { error: e.to_string() })?;
+            if AdaptiveDelay::backs_off(resp.status()) {
+                let retry_after_ms = retry_after_ms(&resp);
+                if let Some(host) = current.host_str().filter(|_| self.adaptive.max_ms > 0) {
+                    if let Some(robots) = self.robots.write().get_mut(host) { robots.adaptive.raise(retry_after_ms, self.adaptive.half_life, self.adaptive.max_ms); }
+                }
+                if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    return Err(Outcome::RateLimited { retry_after_ms, requeued: false });
                 }
-                _ => {}
             }
+            if !resp.status().is_redirection() { return Ok((redirects, resp)); }
+            let next = resp.headers().get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|loc| current.join(loc).ok());
+            let Some(next) = next else { return Ok((redirects, resp)) };
+            if redirects.len() >= rules.max {
+                if rules.max == 0 { return Ok((redirects, resp)); }
+                return Err(Outcome::TooManyRedirects { max: rules.max });
+            }
+            if !next.scheme().starts_with("http") {
+                return Err(Outcome::FetchError { error: format!("redirect to unsupported url {next}") });
+            }
+            if rules.same_host_only && next.host_str() != url.host_str() {
+                return Err(Outcome::OffHostRedirect { location: next.to_string() });
+            }
+            redirects.push(next.clone());
+            current = next;
         }
     }
-    Robots { fetched_at: Instant::now(), allows, disallows, crawl_delay_ms }
 }
 
-async fn allowed(client: &Client, cache: &Arc<RwLock<HashMap<String, Robots>>>, url: &Url, ua: &str) -> Result<bool> {
+/// Check `url` against its host's robots.txt, fetching it on first use and again once the
+/// cached copy is older than `ttl`.
+async fn allowed(client: &Client, cache: &Arc<RwLock<HashMap<String, Robots>>>, url: &Url, ua: &str, ttl: Option<Duration>) -> Result<bool> {
     let host = match url.host_str() { Some(h) => h.to_string(), None => return Ok(false) };
-    let rules_opt = { let c = cache.read(); c.get(&host).cloned() };
-    let rules = if let Some(r) = rules_opt { r } else {
-        let robots_url = format!("{}://{}/robots.txt", url.scheme(), host);
-        let txt = match client
-            .get(&robots_url)
-            .header(header::USER_AGENT, ua)
-            .send()
-            .await
-        {
-            Ok(resp) if resp.status().is_success() => resp.text().await.unwrap_or_default(),
-            _ => String::new(),
-        };
-        let parsed = parse_robots(&txt);
-        { let mut c = cache.write(); c.insert(host.clone(), parsed.clone()); }
-        parsed
+    let cached = { let c = cache.read(); c.get(&host).cloned() };
+    let rules = match cached {
+        Some(r) if ttl.is_none_or(|ttl| r.fetched_at.elapsed() < ttl) => r,
+        cached => {
+            let adaptive = cached.as_ref().map(|r| r.adaptive).unwrap_or_default();
+            let refreshed = Robots { adaptive, ..fetch_robots(client, url, &host, ua, cached.as_ref()).await };
+            { let mut c = cache.write(); c.insert(host.clone(), refreshed.clone()); }
+            refreshed
+        }
     };
     Ok(path_allowed(url.path(), &rules))
 }
 
-fn robots_delay(cache: &Arc<RwLock<HashMap<String, Robots>>>, url: &Url) -> Option<u64> {
-    let host = url.host_str()?;
-    cache.read().get(host).and_then(|r| r.crawl_delay_ms)
+/// Fetch and parse robots.txt, conditionally when `previous` has validators. A 304, a
+/// failed request or a server error keeps `previous` (restamped) if there is one; a
+/// missing robots.txt or a first-time failure allows everything.
+async fn fetch_robots(client: &Client, url: &Url, host: &str, ua: &str, previous: Option<&Robots>) -> Robots {
+    let robots_url = format!("{}://{}/robots.txt", url.scheme(), host);
+    let mut req = client.get(&robots_url).header(header::USER_AGENT, ua);
+    if let Some(prev) = previous {
+        if let Some(etag) = &prev.etag { req = req.header(header::IF_NONE_MATCH, etag); }
+        if let Some(lm) = &prev.last_modified { req = req.header(header::IF_MODIFIED_SINCE, lm); }
+    }
+    let keep_previous = || previous.map(|p| Robots { fetched_at: Instant::now(), ..p.clone() });
+    match req.send().await {
+        Ok(resp) if resp.status().is_success() => {
+            let validator = |name| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+            let (etag, last_modified) = (validator(header::ETAG), validator(header::LAST_MODIFIED));
+            match resp.text().await {
+                Ok(txt) => Robots { etag, last_modified, ..parse_robots(&txt, &product_token(ua)) },
+                Err(_) => keep_previous().unwrap_or_else(Robots::empty),
+            }
+        }
+        Ok(resp) if resp.status() == reqwest::StatusCode::NOT_MODIFIED || resp.status().is_server_error() => keep_previous().unwrap_or_else(Robots::empty),
+        Ok(_) => Robots::empty(),
+        Err(_) => keep_previous().unwrap_or_else(Robots::empty),
+    }
+}
+
+/// Milliseconds to wait before requesting `url`: the larger of the host's robots.txt
+/// `Crawl-delay` and its current adaptive delay.
+fn host_delay(cache: &Arc<RwLock<HashMap<String, Robots>>>, url: &Url, half_life: Duration) -> u64 {
+    let Some(host) = url.host_str() else { return 0 };
+    cache.read().get(host).map_or(0, |r| r.crawl_delay_ms.unwrap_or(0).max(r.adaptive.current_ms(half_life)))
+}
+
+/// A `Retry-After: <seconds>` header in ms (the HTTP-date form is ignored).
+fn retry_after_ms(resp: &reqwest::Response) -> Option<u64> {
+    let secs: u64 = resp.headers().get(header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(secs.saturating_mul(1000))
 }
 
 fn path_allowed(path: &str, rules: &Robots) -> bool {
     // basic rule precedence: longest matching Allow vs Disallow
     let mut best_allow: Option<&str> = None;
     let mut best_dis: Option<&str> = None;
-    for a in &rules.allows { if path.starts_with(a) { if best_allow.map_or(true, |p| a.len() > p.len()) { best_allow = Some(a); } } }
-    for d in &rules.disallows { if d == "/" { best_dis = Some(d); continue; } if path.starts_with(d) { if best_dis.map_or(true, |p| d.len() > p.len()) { best_dis = Some(d); } } }
+    for a in &rules.allows { if path.starts_with(a) && best_allow.is_none_or(|p| a.len() > p.len()) { best_allow = Some(a); } }
+    for d in &rules.disallows {
+        if d == "/" { best_dis = Some(d); continue; }
+        if path.starts_with(d) && best_dis.is_none_or(|p| d.len() > p.len()) { best_dis = Some(d); }
+    }
     match (best_allow, best_dis) {
         (Some(a), Some(d)) => a.len() >= d.len(),
         (Some(_), None) => true,
@@ -272,3 +1054,241 @@ fn path_allowed(path: &str, rules: &Robots) -> bool {
         (None, None) => true,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "\
+User-agent: *
+Disallow: /private
+Crawl-delay: 5
+
+User-agent: zapbot
+Disallow: /zap-only
+Allow: /zap-only/public
+
+User-agent: zapbot-news
+User-agent: otherbot
+Disallow: /news-only
+Crawl-delay: 2
+
+# A second group for the same agent is merged with the first
+User-agent: zapbot-news
+Disallow: /more
+";
+
+    #[test]
+    fn robots_groups_prefer_the_longest_matching_agent() {
+        assert_eq!(product_token("ZapBot-News/0.1 (+https://example.com/bot)"), "zapbot-news");
+
+        // Exact match, merged across both of its groups
+        let robots = parse_robots(ROBOTS, "zapbot-news");
+        assert_eq!(robots.disallows, vec!["/news-only", "/more"]);
+        assert_eq!(robots.crawl_delay_ms, Some(2000));
+
+        // `zapbot` is the longest agent the name starts with
+        let robots = parse_robots(ROBOTS, "zapbot-images");
+        assert_eq!((robots.allows, robots.disallows), (vec!["/zap-only/public".to_string()], vec!["/zap-only".to_string()]));
+        assert_eq!(robots.crawl_delay_ms, None);
+
+        // Nothing names us, so the `*` group applies
+        let robots = parse_robots(ROBOTS, "somebot");
+        assert_eq!(robots.disallows, vec!["/private"]);
+        assert_eq!(robots.crawl_delay_ms, Some(5000));
+
+        // Agents inside the name, or cutting a word of it short, don't name us
+        let robots = parse_robots("User-agent: *\nDisallow: /private\n\nUser-agent: bot\nUser-agent: news\nUser-agent: zap\nDisallow: /\n", "zapbot-news");
+        assert_eq!(robots.disallows, vec!["/private"]);
+
+        // No `*` group and no match: everything is allowed
+        let robots = parse_robots("User-agent: otherbot\nDisallow: /\n", "somebot");
+        assert!(robots.disallows.is_empty() && path_allowed("/anything", &robots));
+    }
+
+    #[test]
+    fn longest_allow_or_disallow_wins() {
+        let robots = parse_robots("User-agent: *\nDisallow: /docs\nAllow: /docs/public\nDisallow: /docs/public/drafts\nDisallow:\n", "zapbot");
+        assert!(path_allowed("/", &robots));
+        assert!(!path_allowed("/docs/secret", &robots));
+        assert!(path_allowed("/docs/public/page", &robots));
+        assert!(!path_allowed("/docs/public/drafts/1", &robots));
+
+        // An Allow as long as the Disallow wins the tie
+        let robots = parse_robots("User-agent: *\nDisallow: /a\nAllow: /a\n", "zapbot");
+        assert!(path_allowed("/a/b", &robots));
+
+        // `Disallow: /` blocks everything no Allow matches
+        let robots = parse_robots("User-agent: *\nDisallow: /\nAllow: /open\n", "zapbot");
+        assert!(!path_allowed("/closed", &robots));
+        assert!(path_allowed("/open/x", &robots));
+    }
+
+    #[test]
+    fn wildcards_match_within_a_name() {
+        assert!(wildcard_match("*.txt", "seeds.txt"));
+        assert!(wildcard_match("seeds-??.txt", "seeds-01.txt"));
+        assert!(wildcard_match("*-*.txt", "a-b-c.txt"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("seeds-??.txt", "seeds-1.txt"));
+        assert!(!wildcard_match("*.txt", "seeds.txt.bak"));
+        assert!(!wildcard_match("a*c", "abd"));
+    }
+
+    #[test]
+    fn seed_globs_expand_in_order_and_drop_repeats_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("lists");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("b.txt"), "https://example.org/\nhttps://example.com/#top\n").unwrap();
+        fs::write(sub.join("a.txt"), "# comment\n\nhttps://example.com/\nnot a url at all\n").unwrap();
+        fs::write(sub.join(".hidden.txt"), "https://hidden.example/\n").unwrap();
+        fs::write(sub.join("notes.md"), "https://example.net/\n").unwrap();
+
+        let pattern = format!("{}/lists/*.txt", dir.path().display());
+        assert_eq!(expand_glob(&pattern).unwrap(), vec![sub.join("a.txt"), sub.join("b.txt")]);
+        assert_eq!(expand_glob(&format!("{}/l?sts/?.txt", dir.path().display())).unwrap().len(), 2);
+        assert!(expand_glob(&format!("{}/lists/*.csv", dir.path().display())).unwrap().is_empty());
+
+        // The fragment-only difference in b.txt is a repeat of a.txt's seed
+        let seeds: Vec<String> = load_seeds(&[pattern], false).unwrap().iter().map(Url::to_string).collect();
+        assert_eq!(seeds, vec!["https://example.com/", "https://example.org/"]);
+        assert!(load_seeds(&[format!("{}/lists/*.csv", dir.path().display())], false).is_err());
+    }
+
+    /// `MetaRobots` for a page with `head` markup and `X-Robots-Tag` `headers`, as zapbot.
+    fn meta_robots(head: &str, headers: &[&str]) -> MetaRobots {
+        let doc = Html::parse_document(&format!("<html><head>{head}</head><body></body></html>"));
+        let sel = Selector::parse("meta").unwrap();
+        let tags = doc.select(&sel).filter_map(|m| Some((m.value().attr("name")?.to_lowercase(), m.value().attr("content")?)));
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        MetaRobots::from_page(&headers, tags, "zapbot")
+    }
+
+    #[test]
+    fn meta_robots_combine_tags_and_headers() {
+        let flags = |r: MetaRobots| (r.noindex, r.nofollow);
+        assert_eq!(flags(meta_robots("", &[])), (false, false));
+        assert_eq!(flags(meta_robots(r#"<meta name="robots" content="noindex">"#, &[])), (true, false));
+        assert_eq!(flags(meta_robots(r#"<meta name="ROBOTS" content=" NoFollow , NOARCHIVE">"#, &[])), (false, true));
+        assert_eq!(flags(meta_robots(r#"<meta name="robots" content="none">"#, &[])), (true, true));
+
+        // Directives add up across several tags and the header
+        assert_eq!(flags(meta_robots(r#"<meta name="robots" content="noindex"><meta name="ZapBot" content="nofollow">"#, &[])), (true, true));
+        assert_eq!(flags(meta_robots(r#"<meta name="robots" content="nofollow">"#, &["noindex"])), (true, true));
+
+        // Tags and header values addressed to another crawler don't apply
+        assert_eq!(flags(meta_robots(r#"<meta name="googlebot" content="noindex"><meta name="description" content="noindex">"#, &[])), (false, false));
+        assert_eq!(flags(meta_robots("", &["otherbot: noindex", "ZapBot: nofollow"])), (false, true));
+    }
+
+    #[test]
+    fn adaptive_delay_grows_decays_and_clamps() {
+        let half_life = Duration::from_secs(10);
+        assert!(AdaptiveDelay::backs_off(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(AdaptiveDelay::backs_off(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert!(AdaptiveDelay::backs_off(reqwest::StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!AdaptiveDelay::backs_off(reqwest::StatusCode::NOT_FOUND));
+        assert!(!AdaptiveDelay::backs_off(reqwest::StatusCode::OK));
+
+        // Starts at INITIAL_MS, then doubles with each response, up to the cap. A long half-life
+        // keeps the decay between raises from showing.
+        let mut delay = AdaptiveDelay::default();
+        assert_eq!(delay.current_ms(half_life), 0);
+        let hour = Duration::from_secs(3600);
+        let steps: Vec<u64> = (0..5).map(|_| { delay.raise(None, hour, 10_000); delay.delay_ms }).collect();
+        assert_eq!(steps, vec![1000, 2000, 4000, 8000, 10_000]);
+
+        // Retry-After raises it further, still under the cap
+        let mut delay = AdaptiveDelay::default();
+        delay.raise(Some(5000), half_life, 60_000);
+        assert_eq!(delay.delay_ms, 5000);
+        delay.raise(Some(120_000), half_life, 60_000);
+        assert_eq!(delay.delay_ms, 60_000);
+        // The cap wins over INITIAL_MS too
+        let mut delay = AdaptiveDelay::default();
+        delay.raise(None, half_life, 300);
+        assert_eq!(delay.delay_ms, 300);
+
+        // Halves every half-life, and grows from the decayed value
+        let mut delay = AdaptiveDelay { delay_ms: 8000, raised_at: Some(Instant::now() - Duration::from_secs(20)) };
+        assert!((1990..=2000).contains(&delay.current_ms(half_life)), "{}", delay.current_ms(half_life));
+        delay.raise(None, half_life, 60_000);
+        assert!((3980..=4000).contains(&delay.delay_ms), "{}", delay.delay_ms);
+        let long_ago = AdaptiveDelay { delay_ms: 8000, raised_at: Some(Instant::now() - Duration::from_secs(600)) };
+        assert_eq!(long_ago.current_ms(half_life), 0);
+    }
+
+    #[test]
+    fn output_paths_name_shards_and_compression() {
+        let base = Path::new("out/crawl.jsonl");
+        assert_eq!(Output::path_for(base, None, false), Path::new("out/crawl.jsonl"));
+        assert_eq!(Output::path_for(base, None, true), Path::new("out/crawl.jsonl.gz"));
+        assert_eq!(Output::path_for(base, Some(3), false), Path::new("out/crawl-00003.jsonl"));
+        assert_eq!(Output::path_for(Path::new("out/crawl.jsonl.gz"), Some(12), true), Path::new("out/crawl-00012.jsonl.gz"));
+        assert_eq!(Output::path_for(Path::new("pages"), Some(1), false), Path::new("pages-00001.jsonl"));
+    }
+
+    #[test]
+    fn the_last_partial_shard_is_complete_after_finish() {
+        let read = |path: &Path, gzip: bool| -> Vec<u64> {
+            let file = File::open(path).unwrap();
+            let reader: Box<dyn BufRead> = if gzip { Box::new(BufReader::new(flate2::read::GzDecoder::new(file))) } else { Box::new(BufReader::new(file)) };
+            reader.lines().map(|l| serde_json::from_str::<serde_json::Value>(&l.unwrap()).unwrap()["n"].as_u64().unwrap()).collect()
+        };
+        for gzip in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let base = dir.path().join("crawl.jsonl");
+            // As when max_docs (7) runs out partway through the third shard of 3
+            let mut out = Output::create(&base.to_string_lossy(), Some(3), gzip).unwrap();
+            for n in 0..7 { out.write_record(&serde_json::json!({ "n": n })).unwrap(); }
+            out.finish().unwrap();
+
+            let shards: Vec<Vec<u64>> = (1..=3).map(|i| read(&Output::path_for(&base, Some(i), gzip), gzip)).collect();
+            assert_eq!(shards, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]], "gzip={gzip}");
+            assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3, "gzip={gzip}");
+        }
+
+        // Without sharding everything goes to the one file
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("crawl.jsonl");
+        let mut out = Output::create(&base.to_string_lossy(), None, false).unwrap();
+        for n in 0..4 { out.write_record(&serde_json::json!({ "n": n })).unwrap(); }
+        out.finish().unwrap();
+        assert_eq!(read(&base, false), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn frontier_passes_over_parked_hosts_in_arrival_order() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        let mut frontier = Frontier::default();
+        for u in ["https://a.test/1", "https://a.test/2", "https://b.test/1", "https://a.test/3", "https://c.test/1"] {
+            frontier.push_back(url(u));
+        }
+        assert_eq!(frontier.len(), 5);
+        assert_eq!(frontier.pop_front(), Some(url("https://a.test/1")));
+
+        // With a.test parked its URLs wait, and the rest keep their order
+        frontier.park("a.test");
+        frontier.push_back(url("https://a.test/4"));
+        frontier.push_back(url("https://b.test/2"));
+        assert_eq!(frontier.pop_front(), Some(url("https://b.test/1")));
+        assert_eq!(frontier.pop_front(), Some(url("https://c.test/1")));
+        assert_eq!(frontier.pop_front(), Some(url("https://b.test/2")));
+        assert_eq!(frontier.pop_front(), None);
+        assert_eq!(frontier.len(), 3);
+
+        // Unparked, a.test's URLs come out in the order they went in
+        frontier.unpark("a.test");
+        let rest: Vec<Url> = std::iter::from_fn(|| frontier.pop_front()).collect();
+        assert_eq!(rest, vec![url("https://a.test/2"), url("https://a.test/3"), url("https://a.test/4")]);
+        assert!(frontier.is_empty());
+
+        // Parking a host with nothing queued holds back what arrives later
+        frontier.park("d.test");
+        frontier.push_back(url("https://d.test/1"));
+        assert_eq!(frontier.pop_front(), None);
+        frontier.unpark("d.test");
+        assert_eq!(frontier.pop_front(), Some(url("https://d.test/1")));
+    }
+}
@@ -0,0 +1,137 @@
+//! `indexer export`: dump an index's postings as one row per (term, document) for offline
+//! analysis, without parsing the binary index files.
+
+use anyhow::{Context, Result};
+use core::persist::{check_index_files, load_dictionary, load_meta, load_postings_for_term, IndexPaths};
+use core::{DocId, Posting};
+use serde::Serialize;
+use std::io::Write;
+
+/// Output format of `indexer export`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Comma-separated values with a header row
+    Csv,
+    /// Apache Parquet (needs the `parquet` feature, on by default)
+    Parquet,
+}
+
+/// One exported posting. Every row of a term repeats its `df`, so rows stand alone.
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    term: &'a str,
+    df: u32,
+    doc_id: DocId,
+    weight: f32,
+}
+
+/// Counts for the summary line.
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub terms: usize,
+    pub rows: usize,
+}
+
+/// Write the body postings of the index at `index_dir` to `out` as `term, df, doc_id, weight`
+/// rows, terms in lexicographic order and each term's postings in stored order.
+pub fn export_postings<W: Write + Send>(index_dir: &str, format: ExportFormat, out: W) -> Result<ExportReport> {
+    let paths = IndexPaths::new(index_dir);
+    check_index_files(&paths)?;
+    let meta = load_meta(&paths).context("reading meta.json")?;
+    let paths = paths.with_postings_fanout(meta.postings_fanout);
+    let (dictionary, df) = load_dictionary(&paths).context("reading dictionary.bin")?;
+    let mut terms: Vec<(&str, u32)> = dictionary.iter().map(|(term, &tid)| (term.as_str(), tid)).collect();
+    terms.sort_unstable();
+    let postings = terms.iter().map(|&(term, tid)| {
+        let postings = load_postings_for_term(&paths, tid).with_context(|| format!("reading postings of {term:?} (term {tid})"))?;
+        Ok((term, df.get(tid as usize).copied().unwrap_or(0), postings))
+    });
+    let mut report = ExportReport { terms: terms.len(), rows: 0 };
+    match format {
+        ExportFormat::Jsonl => write_jsonl(postings, out, &mut report)?,
+        ExportFormat::Csv => write_csv(postings, out, &mut report)?,
+        ExportFormat::Parquet => write_parquet(postings, out, &mut report)?,
+    }
+    Ok(report)
+}
+
+fn write_jsonl<'a>(postings: impl Iterator<Item = Result<(&'a str, u32, Vec<Posting>)>>, out: impl Write, report: &mut ExportReport) -> Result<()> {
+    let mut out = std::io::BufWriter::new(out);
+    for entry in postings {
+        let (term, df, postings) = entry?;
+        for p in &postings {
+            serde_json::to_writer(&mut out, &Row { term, df, doc_id: p.doc_id, weight: p.weight })?;
+            out.write_all(b"\n")?;
+        }
+        report.rows += postings.len();
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_csv<'a>(postings: impl Iterator<Item = Result<(&'a str, u32, Vec<Posting>)>>, out: impl Write, report: &mut ExportReport) -> Result<()> {
+    let mut out = csv::WriterBuilder::new().has_headers(false).from_writer(out);
+    // Written up front, not from the first row, so an index without terms still yields a loadable file
+    out.write_record(["term", "df", "doc_id", "weight"])?;
+    for entry in postings {
+        let (term, df, postings) = entry?;
+        for p in &postings {
+            out.serialize(Row { term, df, doc_id: p.doc_id, weight: p.weight })?;
+        }
+        report.rows += postings.len();
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet<'a, W: Write + Send>(postings: impl Iterator<Item = Result<(&'a str, u32, Vec<Posting>)>>, out: W, report: &mut ExportReport) -> Result<()> {
+    use arrow_array::{ArrayRef, Float32Array, RecordBatch, StringArray, UInt32Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    /// Rows buffered per record batch (and so at most per row group).
+    const BATCH_ROWS: usize = 64 * 1024;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("term", DataType::Utf8, false),
+        Field::new("df", DataType::UInt32, false),
+        Field::new("doc_id", DataType::UInt32, false),
+        Field::new("weight", DataType::Float32, false),
+    ]));
+    let mut writer = ArrowWriter::try_new(out, schema.clone(), None)?;
+    let (mut terms, mut dfs, mut doc_ids, mut weights) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut flush = |terms: &mut Vec<&str>, dfs: &mut Vec<u32>, doc_ids: &mut Vec<DocId>, weights: &mut Vec<f32>| -> Result<()> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(std::mem::take(terms))),
+            Arc::new(UInt32Array::from(std::mem::take(dfs))),
+            Arc::new(UInt32Array::from(std::mem::take(doc_ids))),
+            Arc::new(Float32Array::from(std::mem::take(weights))),
+        ];
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+        Ok(())
+    };
+    for entry in postings {
+        let (term, df, postings) = entry?;
+        for p in &postings {
+            terms.push(term);
+            dfs.push(df);
+            doc_ids.push(p.doc_id);
+            weights.push(p.weight);
+            if terms.len() == BATCH_ROWS { flush(&mut terms, &mut dfs, &mut doc_ids, &mut weights)?; }
+        }
+        report.rows += postings.len();
+    }
+    if !terms.is_empty() { flush(&mut terms, &mut dfs, &mut doc_ids, &mut weights)?; }
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet<'a, W: Write + Send>(_postings: impl Iterator<Item = Result<(&'a str, u32, Vec<Posting>)>>, _out: W, _report: &mut ExportReport) -> Result<()> {
+    anyhow::bail!("this indexer was built without the `parquet` feature; use --format jsonl or csv, or rebuild with it enabled")
+}
@@ -0,0 +1,811 @@
+//! Index building: ingest JSON/JSONL documents and write the on-disk index.
+//!
+//! Used by the `indexer` CLI and by the server's admin reindex endpoint.
+
+pub mod export;
+pub mod swap;
+
+use anyhow::{bail, Context, Result};
+use core::persist::{save_descriptions, save_dictionary, save_doc_id_map, save_doc_norms, save_docs, save_meta, save_postings_for_term, save_timestamps, IndexPaths, MetaFile, PackedTextWriter, TermVector, TermVectorWriter, INDEX_VERSION, PACKED_TEXTS_FILE};
+use core::tokenizer::{tokenize_iter_with, NgramRange, Normalization, StemLanguage, TokenizerConfig, RAW_FORM_PREFIX};
+use core::weighting::Weighting;
+use core::{DocId, DocMeta, Posting, TermId};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Debug, Deserialize)]
+struct InputDoc {
+    id: String,
+    title: String,
+    body: String,
+    url: Option<String>,
+    /// RFC 3339 date (as written by the crawler), stored for browsing newest first
+    timestamp: Option<String>,
+    /// Free-form page metadata; a string `description` is stored for snippet fallback
+    #[serde(default)]
+    meta: Option<serde_json::Value>,
+    /// Terms from an external analysis pipeline, indexed exactly as given instead of tokenizing `body`
+    #[serde(default)]
+    tokens: Option<Vec<String>>,
+}
+
+/// Running state accumulated while ingesting documents.
+#[derive(Default)]
+struct Accumulators {
+    next_doc_id: DocId,
+    terms: TermChannel,
+    /// Case-preserved, unstemmed terms, with `preserve_case`
+    case_terms: Option<TermChannel>,
+    docs: HashMap<DocId, DocMeta>,
+    doc_id_map: HashMap<String, DocId>,
+    skipped_records: u32,
+    truncated_docs: Vec<String>,
+    /// Indexed tokens over all documents
+    total_tokens: u64,
+    /// Documents indexed from their `tokens` rather than the tokenizer
+    pretokenized_docs: u32,
+    descriptions: HashMap<DocId, String>,
+    timestamps: HashMap<DocId, i64>,
+    packed_texts: Option<PackedTextWriter>,
+}
+
+/// Dictionary, document frequencies and raw `(doc_id, tf)` postings of one term space.
+#[derive(Default)]
+struct TermChannel {
+    next_term_id: TermId,
+    dictionary: HashMap<String, TermId>,
+    df: Vec<u32>,
+    postings_raw: HashMap<TermId, Vec<(DocId, u32)>>,
+    /// Count of each document's most frequent term, indexed by doc_id (for augmented tf)
+    max_tf: Vec<u32>,
+}
+
+impl TermChannel {
+    /// Count the terms of one document. Returns how many tokens it had.
+    fn add_doc(&mut self, doc_id: DocId, tokens: impl IntoIterator<Item = (String, usize)>) -> usize {
+        let mut tf_counts: HashMap<TermId, u32> = HashMap::new();
+        let mut seen_in_doc: HashSet<TermId> = HashSet::new();
+        let mut count = 0;
+        for (term, _pos) in tokens {
+            count += 1;
+            let tid = *self.dictionary.entry(term).or_insert_with(|| {
+                let id = self.next_term_id;
+                self.next_term_id += 1;
+                // ensure df vec capacity
+                if self.df.len() <= id as usize { self.df.resize(id as usize + 1, 0); }
+                id
+            });
+            *tf_counts.entry(tid).or_insert(0) += 1;
+            if !seen_in_doc.contains(&tid) {
+                self.df[tid as usize] += 1;
+                seen_in_doc.insert(tid);
+            }
+        }
+
+        if self.max_tf.len() <= doc_id as usize { self.max_tf.resize(doc_id as usize + 1, 0); }
+        self.max_tf[doc_id as usize] = tf_counts.values().copied().max().unwrap_or(0);
+        for (tid, tf_raw) in tf_counts.into_iter() {
+            self.postings_raw.entry(tid).or_default().push((doc_id, tf_raw));
+        }
+        count
+    }
+
+    /// Compute normalized TF-IDF postings and write them with the dictionary under `paths`,
+    /// plus each document's term vector when `term_vectors` is set. With `doc_norms`, postings and
+    /// term vectors keep the raw tf-idf and the norms go to `doc_norms.bin` instead.
+    fn write(self, num_docs: u32, paths: &IndexPaths, ctx: &BuildContext, term_vectors: bool, doc_norms_file: bool) -> Result<()> {
+        let TermChannel { next_term_id, mut dictionary, mut df, postings_raw, max_tf } = self;
+        let opts = ctx.opts;
+        // Ensure df length matches highest term id + 1
+        df.resize(next_term_id as usize, 0);
+        let mut postings_raw: Vec<(TermId, Vec<(DocId, u32)>)> = postings_raw.into_iter().collect();
+        if opts.sort_terms { sort_term_ids(&mut dictionary, &mut df, &mut postings_raw); }
+        // Term order fixes the float summation order of the norms below
+        postings_raw.sort_unstable_by_key(|(term_id, _)| *term_id);
+
+        let mut doc_norms: Vec<f32> = vec![0.0; num_docs as usize];
+        // First pass: compute tfidf and accumulate norms
+        let num_terms = postings_raw.len();
+        for (i, (term_id, plist)) in postings_raw.iter_mut().enumerate() {
+            ctx.pass_progress(1, i, num_terms);
+            let idf = opts.weighting.idf.weight(num_docs, df[*term_id as usize]);
+            for (doc_id, tf_raw) in plist.iter_mut() {
+                let tf = opts.weighting.tf.weight(*tf_raw, max_tf.get(*doc_id as usize).copied().unwrap_or(0));
+                let tfidf = tf * idf;
+                doc_norms[*doc_id as usize] += tfidf * tfidf;
+                // temporarily store tfidf back in tf_raw slot by casting via bits (will convert in second pass)
+                *tf_raw = f32_to_u32(tfidf);
+            }
+        }
+        for dn in doc_norms.iter_mut() {
+            *dn = dn.sqrt();
+        }
+        if opts.pivot > 0.0 {
+            let non_empty: Vec<f32> = doc_norms.iter().copied().filter(|dn| *dn > 0.0).collect();
+            let avg = non_empty.iter().sum::<f32>() / non_empty.len().max(1) as f32;
+            for dn in doc_norms.iter_mut() {
+                *dn = (1.0 - opts.pivot) * *dn + opts.pivot * avg;
+            }
+        }
+        for dn in doc_norms.iter_mut() {
+            if *dn == 0.0 { *dn = 1.0; }
+        }
+
+        // Second pass: create normalized postings and persist per term
+        let mut vectors: Vec<TermVector> = if term_vectors { vec![Vec::new(); num_docs as usize] } else { Vec::new() };
+        for (i, (term_id, plist)) in postings_raw.into_iter().enumerate() {
+            ctx.pass_progress(2, i, num_terms);
+            let mut out_postings: Vec<Posting> = Vec::with_capacity(plist.len());
+            for (doc_id, tfidf_bits) in plist.into_iter() {
+                let tfidf = u32_to_f32(tfidf_bits);
+                let weight = if doc_norms_file { tfidf } else { tfidf / doc_norms[doc_id as usize] };
+                out_postings.push(Posting { doc_id, weight });
+                if term_vectors { vectors[doc_id as usize].push((term_id, weight)); }
+            }
+            if let Some(cap) = opts.max_postings_per_term.filter(|&cap| out_postings.len() > cap) {
+                // Rank by normalized weight either way, so the cap keeps the same documents
+                let normalized = |p: &Posting| if doc_norms_file { p.weight / doc_norms[p.doc_id as usize] } else { p.weight };
+                out_postings.select_nth_unstable_by(cap - 1, |a, b| normalized(b).total_cmp(&normalized(a)).then(a.doc_id.cmp(&b.doc_id)));
+                out_postings.truncate(cap);
+            }
+            // Sort by doc_id per spec
+            out_postings.sort_by_key(|p| p.doc_id);
+            save_postings_for_term(paths, term_id, &out_postings)?;
+        }
+        if term_vectors {
+            let mut writer = TermVectorWriter::create(paths)?;
+            for (doc_id, mut vector) in vectors.into_iter().enumerate() {
+                vector.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+                writer.append(doc_id as DocId, &vector)?;
+            }
+            writer.finish(paths)?;
+        }
+        if doc_norms_file { save_doc_norms(paths, &doc_norms)?; }
+        save_dictionary(paths, &(dictionary, df))
+    }
+}
+
+/// Check a `--pivot` value: a blend fraction in `[0, 1]`.
+pub fn parse_pivot(s: &str) -> Result<f32> {
+    let pivot: f32 = s.trim().parse()?;
+    if !(0.0..=1.0).contains(&pivot) { bail!("pivot must be between 0 and 1, got {pivot}"); }
+    Ok(pivot)
+}
+
+/// Input keys accepted as targets of a [`FieldMap`].
+const INPUT_FIELDS: [&str; 7] = ["id", "title", "body", "url", "timestamp", "meta", "tokens"];
+
+/// Renames input keys before deserialization, e.g. `headline` -> `title`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldMap(Vec<(String, String)>);
+
+impl FieldMap {
+    /// Parse `title=headline,body=content,url=link` (target=source pairs).
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut pairs = Vec::new();
+        for pair in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let Some((target, source)) = pair.split_once('=') else { bail!("expected target=source, got '{pair}'") };
+            let (target, source) = (target.trim(), source.trim());
+            if !INPUT_FIELDS.contains(&target) {
+                bail!("unknown field '{target}' (expected one of {})", INPUT_FIELDS.join(", "));
+            }
+            if source.is_empty() { bail!("empty source key for '{target}'"); }
+            pairs.push((target.to_string(), source.to_string()));
+        }
+        Ok(Self(pairs))
+    }
+
+    /// Move each mapped source key to its target name. Documents that already use
+    /// the target name are left alone.
+    fn apply(&self, value: &mut serde_json::Value) {
+        let serde_json::Value::Object(obj) = value else { return };
+        for (target, source) in &self.0 {
+            if obj.contains_key(target) { continue; }
+            if let Some(v) = obj.remove(source) { obj.insert(target.clone(), v); }
+        }
+    }
+}
+
+/// An `--analyzer` file: the tokenization pipeline in TOML. Omitted keys keep the
+/// defaults, so an empty file is today's analyzer.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AnalyzerFile {
+    normalization: Normalization,
+    lowercase: bool,
+    /// Inline stopword list; `[]` disables stopwords
+    stopwords: Option<Vec<String>>,
+    /// One stopword per line (`#` comments allowed), relative to the analyzer file
+    stopwords_file: Option<PathBuf>,
+    stemmer: StemLanguage,
+    min_token_len: usize,
+    max_token_len: usize,
+    strip_possessives: bool,
+    index_numbers: bool,
+    ngrams: Option<NgramRange>,
+    keep_hyphenated: bool,
+    hyphenated_parts: bool,
+}
+
+impl Default for AnalyzerFile {
+    fn default() -> Self {
+        let t = TokenizerConfig::default();
+        Self {
+            normalization: t.normalization,
+            lowercase: t.lowercase,
+            stopwords: None,
+            stopwords_file: None,
+            stemmer: t.stemmer,
+            min_token_len: t.min_token_len,
+            max_token_len: t.max_token_len,
+            strip_possessives: t.strip_possessives,
+            index_numbers: t.index_numbers,
+            ngrams: t.ngrams,
+            keep_hyphenated: t.keep_hyphenated,
+            hyphenated_parts: t.hyphenated_parts,
+        }
+    }
+}
+
+/// Read an `--analyzer` TOML file into the tokenizer config recorded in `meta.json`.
+/// A stopword file is read here, so the index carries the words themselves.
+pub fn load_analyzer(path: &Path) -> Result<TokenizerConfig> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading analyzer {}", path.display()))?;
+    let file: AnalyzerFile = toml::from_str(&text).with_context(|| format!("parsing analyzer {}", path.display()))?;
+    if file.min_token_len > file.max_token_len {
+        bail!("min_token_len ({}) exceeds max_token_len ({})", file.min_token_len, file.max_token_len);
+    }
+    if file.hyphenated_parts && !file.keep_hyphenated {
+        bail!("hyphenated_parts needs keep_hyphenated = true");
+    }
+    if let Some(NgramRange { min, max }) = file.ngrams {
+        if min == 0 || min > max { bail!("ngrams needs 1 <= min <= max, got min={min} max={max}"); }
+    }
+    let lowercase = |w: &str| w.trim().to_lowercase();
+    let stopwords: Option<BTreeSet<String>> = match (file.stopwords, file.stopwords_file) {
+        (Some(_), Some(_)) => bail!("set either stopwords or stopwords_file, not both"),
+        (Some(words), None) => Some(words.iter().map(|w| lowercase(w)).filter(|w| !w.is_empty()).collect()),
+        (None, Some(rel)) => {
+            let stop_path = path.parent().unwrap_or(Path::new(".")).join(rel);
+            let words = fs::read_to_string(&stop_path).with_context(|| format!("reading stopwords {}", stop_path.display()))?;
+            Some(words.lines().map(lowercase).filter(|w| !w.is_empty() && !w.starts_with('#')).collect())
+        }
+        (None, None) => None,
+    };
+    Ok(TokenizerConfig {
+        normalization: file.normalization,
+        lowercase: file.lowercase,
+        stopwords,
+        stemmer: file.stemmer,
+        ngrams: file.ngrams,
+        min_token_len: file.min_token_len,
+        max_token_len: file.max_token_len,
+        strip_possessives: file.strip_possessives,
+        index_numbers: file.index_numbers,
+        preserve_case: false,
+        raw_forms: false,
+        keep_hyphenated: file.keep_hyphenated,
+        hyphenated_parts: file.hyphenated_parts,
+    })
+}
+
+/// What to do with an input record that is not valid JSON or lacks required fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnParseError {
+    /// Log the location and error, count it in the report, and continue
+    #[default]
+    Skip,
+    /// Abort the build
+    Fail,
+}
+
+/// What happens to control characters (NUL, escape, C1 codes, ...) in document bodies before they
+/// are indexed and stored. Tab, newline and carriage return are always kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ControlChars {
+    /// Remove them
+    #[default]
+    Strip,
+    /// Replace each with a space
+    Space,
+    /// Keep them as they are
+    Keep,
+}
+
+impl ControlChars {
+    /// `text` with its control characters handled; borrowed when there are none to change.
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        let is_control = |c: char| c.is_control() && !matches!(c, '\t' | '\n' | '\r');
+        if self == ControlChars::Keep || !text.contains(is_control) { return Cow::Borrowed(text); }
+        let replacement = if self == ControlChars::Space { " " } else { "" };
+        Cow::Owned(text.replace(is_control, replacement))
+    }
+}
+
+/// Where document text for snippets and `/doc` comes from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TextStorage {
+    /// Append every body to one `texts.dat` with a `texts.idx` offset table
+    #[default]
+    Packed,
+    /// Copy each body to `texts/{doc_id}.txt` inside the index
+    Files,
+    /// Store no text; `DocMeta.text_path` is `None`
+    None,
+    /// Point at existing files `{dir}/{external_id}.txt`; nothing is written
+    External(PathBuf),
+}
+
+/// Build-time options. `Default` matches `indexer build` with no optional flags.
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// tf and idf schemes for postings weights; recorded in meta.json so queries match
+    pub weighting: Weighting,
+    /// Pivoted length normalization: blend each doc norm toward the average norm by this
+    /// fraction (0 = plain cosine, 1 = same norm for every doc). Reduces the short-doc bias.
+    pub pivot: f32,
+    /// Tokenizer settings, recorded in `meta.json` for query-time use
+    pub tokenizer: TokenizerConfig,
+    /// Input key renames applied to every document before parsing
+    pub field_map: FieldMap,
+    pub on_parse_error: OnParseError,
+    pub control_chars: ControlChars,
+    pub text_storage: TextStorage,
+    /// Log progress every this many documents (and terms, in the TF-IDF passes); 0 disables
+    pub progress_every: u32,
+    /// Also index case-preserved, unstemmed body terms under `case/` for case-sensitive search
+    pub preserve_case: bool,
+    /// Ingest and tokenize only: report statistics without writing anything to the output
+    pub dry_run: bool,
+    /// Renumber terms in lexical order after ingestion, so term ids don't depend on input order
+    pub sort_terms: bool,
+    /// Index only the first N tokens of each body, counted after stopword and length filtering
+    pub max_doc_tokens: Option<usize>,
+    /// Input `meta` keys stored in `DocMeta::facets` for `key:value` filters
+    pub facet_fields: Vec<String>,
+    /// Input `meta` keys (e.g. `description`, `keywords`) whose text is indexed as part of the body
+    pub index_meta: Vec<String>,
+    /// Write each document's body terms and weights to `term_vectors.dat` for `/doc/{id}/terms`
+    pub store_term_vectors: bool,
+    /// Store only the first N bytes of each body (cut at a char boundary) for snippets and `/doc`;
+    /// the whole body is still indexed
+    pub snippet_store_bytes: Option<usize>,
+    /// Spread postings files over this many `postings/` subdirectories (0 = one flat directory)
+    pub postings_fanout: u32,
+    /// Keep only the N highest-weight postings of each term; documents past the cap can't be found by it
+    pub max_postings_per_term: Option<usize>,
+    /// Write un-normalized body weights plus `doc_norms.bin`, so the server normalizes at query time
+    pub store_doc_norms: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            weighting: Weighting::default(),
+            pivot: 0.0,
+            tokenizer: TokenizerConfig::default(),
+            field_map: FieldMap::default(),
+            on_parse_error: OnParseError::default(),
+            control_chars: ControlChars::default(),
+            text_storage: TextStorage::default(),
+            progress_every: 10_000,
+            max_doc_tokens: None,
+            sort_terms: false,
+            dry_run: false,
+            preserve_case: false,
+            facet_fields: Vec::new(),
+            index_meta: Vec::new(),
+            store_term_vectors: false,
+            snippet_store_bytes: None,
+            postings_fanout: 0,
+            max_postings_per_term: None,
+            store_doc_norms: false,
+        }
+    }
+}
+
+/// Names `--facet-fields` can't use: filters already read them as document fields.
+const RESERVED_FACETS: [&str; 4] = ["title", "body", "url", "body_case"];
+
+/// Check `--facet-fields` names: lowercase, non-empty, distinct and not a document field.
+pub fn parse_facet_field(s: &str) -> Result<String> {
+    let name = s.trim().to_lowercase();
+    if name.is_empty() || name.contains(char::is_whitespace) || name.contains(':') {
+        bail!("invalid facet field '{s}'");
+    }
+    if RESERVED_FACETS.contains(&name.as_str()) { bail!("'{name}' is a document field and can't be a facet"); }
+    Ok(name)
+}
+
+/// Facet values of `meta[key]`: a string, number or bool, or an array of them.
+fn facet_values(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(s) => vec![s.trim().to_string()],
+        serde_json::Value::Number(n) => vec![n.to_string()],
+        serde_json::Value::Bool(b) => vec![b.to_string()],
+        serde_json::Value::Array(items) => items.iter().filter(|v| !v.is_array()).flat_map(facet_values).collect(),
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .filter(|v| !v.is_empty())
+    .collect()
+}
+
+/// Per-build references threaded through ingestion.
+struct BuildContext<'a> {
+    out_paths: &'a IndexPaths,
+    opts: &'a BuildOptions,
+    on_doc: &'a dyn Fn(u32),
+    started: Instant,
+}
+
+impl BuildContext<'_> {
+    /// True every `progress_every` items.
+    fn progress_due(&self, count: usize) -> bool {
+        self.opts.progress_every > 0 && count > 0 && count.is_multiple_of(self.opts.progress_every as usize)
+    }
+
+    /// Report one more ingested document to the caller and, periodically, the log.
+    fn doc_ingested(&self, acc: &Accumulators) {
+        (self.on_doc)(acc.next_doc_id);
+        if self.progress_due(acc.next_doc_id as usize) {
+            let elapsed_s = self.started.elapsed().as_secs_f64();
+            let docs_per_s = (acc.next_doc_id as f64 / elapsed_s.max(1e-9)).round();
+            tracing::info!(docs = acc.next_doc_id, terms = acc.terms.dictionary.len(), skipped = acc.skipped_records, elapsed_s, docs_per_s, "ingest progress");
+        }
+    }
+
+    fn pass_progress(&self, pass: u8, done: usize, total: usize) {
+        if self.progress_due(done) {
+            tracing::info!(pass, terms_done = done, terms_total = total, elapsed_s = self.started.elapsed().as_secs_f64(), "tf-idf progress");
+        }
+    }
+}
+
+/// Summary of a finished build.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildReport {
+    pub num_docs: u32,
+    pub num_terms: usize,
+    /// Dictionary terms that are `--index-raw-forms` spellings (included in `num_terms`)
+    pub raw_form_terms: usize,
+    /// Input records skipped because they failed to parse
+    pub skipped_records: u32,
+    /// Mean indexed tokens per document
+    pub avg_doc_tokens: f64,
+    /// External ids of documents cut off at `max_doc_tokens`
+    pub truncated_docs: Vec<String>,
+    /// Documents indexed from a supplied `tokens` list instead of the tokenizer
+    pub pretokenized_docs: u32,
+    /// Body terms whose posting list exceeds `max_postings_per_term` and is cut to it
+    pub capped_terms: usize,
+    /// Body postings dropped by the cap
+    pub dropped_postings: u64,
+}
+
+/// `input` that reads JSONL from standard input.
+pub const STDIN_INPUT: &str = "-";
+
+/// Build the index at `output` from `input` (a JSON/JSONL file, a directory of them, or
+/// [`STDIN_INPUT`] for JSONL on stdin).
+pub fn build_index(input: &str, output: &str, opts: &BuildOptions) -> Result<BuildReport> {
+    build_index_with_progress(input, output, opts, &|_| {})
+}
+
+/// Like [`build_index`], calling `on_doc` with the running document count after each ingested document.
+pub fn build_index_with_progress(input: &str, output: &str, opts: &BuildOptions, on_doc: &dyn Fn(u32)) -> Result<BuildReport> {
+    let _span = tracing::info_span!("build_index", input, output).entered();
+    if input == STDIN_INPUT {
+        // Same per-line path as a .jsonl file; nothing is buffered beyond one line
+        return build_with(output, opts, on_doc, |acc, ctx| read_jsonl(std::io::stdin().lock(), "<stdin>", acc, ctx));
+    }
+    let input_path = Path::new(input);
+    let mut files: Vec<PathBuf> = Vec::new();
+    if input_path.is_dir() {
+        for entry in WalkDir::new(input_path).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+            let p = entry.path();
+            if p.is_file() {
+                if let Some(ext) = p.extension().and_then(|s| s.to_str()) {
+                    if matches!(ext, "json" | "jsonl") {
+                        files.push(p.to_path_buf());
+                    }
+                }
+            }
+        }
+    } else if input_path.is_file() {
+        files.push(input_path.to_path_buf());
+    }
+    build_with(output, opts, on_doc, |acc, ctx| {
+        for file in files {
+            if file.extension().and_then(|s| s.to_str()) == Some("jsonl") {
+                index_jsonl(&file, acc, ctx)?;
+            } else {
+                index_json(&file, acc, ctx)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Build the index at `output` from JSONL read from `reader`, as [`build_index`] does for
+/// [`STDIN_INPUT`]; `source` names the input in parse errors.
+pub fn build_index_from_reader(reader: impl BufRead, source: &str, output: &str, opts: &BuildOptions) -> Result<BuildReport> {
+    let _span = tracing::info_span!("build_index", input = source, output).entered();
+    build_with(output, opts, &|_| {}, |acc, ctx| read_jsonl(reader, source, acc, ctx))
+}
+
+/// Set up the output, let `ingest` feed every document through `ingest_doc`, then compute and
+/// write the index.
+fn build_with(output: &str, opts: &BuildOptions, on_doc: &dyn Fn(u32), ingest: impl FnOnce(&mut Accumulators, &BuildContext) -> Result<()>) -> Result<BuildReport> {
+    let out_paths = IndexPaths::new(output).with_postings_fanout(opts.postings_fanout);
+    let mut acc = Accumulators { case_terms: opts.preserve_case.then(TermChannel::default), ..Default::default() };
+    if !opts.dry_run {
+        fs::create_dir_all(&out_paths.root)?;
+        match opts.text_storage {
+            TextStorage::Files => fs::create_dir_all(out_paths.root.join("texts"))?,
+            TextStorage::Packed => acc.packed_texts = Some(PackedTextWriter::create(&out_paths)?),
+            TextStorage::None | TextStorage::External(_) => {}
+        }
+    }
+    let ctx = BuildContext { out_paths: &out_paths, opts, on_doc, started: Instant::now() };
+    ingest(&mut acc, &ctx)?;
+
+    let Accumulators { next_doc_id, terms, case_terms, docs, doc_id_map, skipped_records, truncated_docs, total_tokens, pretokenized_docs, descriptions, timestamps, packed_texts } = acc;
+    if let Some(writer) = packed_texts { writer.finish(&out_paths)?; }
+    let num_docs = next_doc_id;
+    let num_terms = terms.dictionary.len();
+    let raw_form_terms = terms.dictionary.keys().filter(|t| t.starts_with(RAW_FORM_PREFIX)).count();
+    if opts.tokenizer.raw_forms {
+        let increase_pct = 100.0 * raw_form_terms as f64 / (num_terms - raw_form_terms).max(1) as f64;
+        tracing::info!(raw_form_terms, increase_pct, "raw forms added to the dictionary");
+    }
+    let avg_doc_tokens = total_tokens as f64 / num_docs.max(1) as f64;
+    tracing::info!(num_docs, num_terms, skipped_records, truncated_docs = truncated_docs.len(), pretokenized_docs, avg_doc_tokens, elapsed_s = ctx.started.elapsed().as_secs_f64(), "ingested documents");
+    // A term's posting list has one entry per document containing it, so df says what the cap will cut
+    let (capped_terms, dropped_postings) = match opts.max_postings_per_term {
+        Some(cap) => terms.df.iter().filter(|&&d| d as usize > cap).fold((0, 0), |(n, dropped), &d| (n + 1, dropped + (d as usize - cap) as u64)),
+        None => (0, 0),
+    };
+    if capped_terms > 0 { tracing::info!(capped_terms, dropped_postings, "posting lists capped"); }
+    if opts.dry_run {
+        return Ok(BuildReport { num_docs, num_terms, raw_form_terms, skipped_records, avg_doc_tokens, truncated_docs, pretokenized_docs, capped_terms, dropped_postings });
+    }
+
+    terms.write(num_docs, &out_paths, &ctx, opts.store_term_vectors, opts.store_doc_norms)?;
+    if let Some(case_terms) = case_terms {
+        tracing::info!(num_terms = case_terms.dictionary.len(), "writing case-sensitive terms");
+        case_terms.write(num_docs, &out_paths.case_channel(), &ctx, false, false)?;
+    }
+
+    // Persist docs, doc_id_map, meta
+    save_docs(&out_paths, &docs)?;
+    save_doc_id_map(&out_paths, &doc_id_map)?;
+    if !descriptions.is_empty() { save_descriptions(&out_paths, &descriptions)?; }
+    if !timestamps.is_empty() { save_timestamps(&out_paths, &timestamps)?; }
+    let meta = MetaFile {
+        num_docs,
+        created_at: time::OffsetDateTime::now_utc().format(&time::format_description::well_known::Rfc3339).unwrap_or_else(|_| "".into()),
+        version: INDEX_VERSION,
+        tokenizer: opts.tokenizer.clone(),
+        pivot: opts.pivot,
+        case_sensitive: opts.preserve_case,
+        facet_fields: opts.facet_fields.clone(),
+        index_meta: opts.index_meta.clone(),
+        snippet_store_bytes: opts.snippet_store_bytes,
+        postings_fanout: opts.postings_fanout,
+        weighting: opts.weighting,
+        max_postings_per_term: opts.max_postings_per_term,
+        capped_terms,
+        doc_norms: opts.store_doc_norms,
+    };
+    save_meta(&out_paths, &meta)?;
+
+    tracing::info!(output, elapsed_s = ctx.started.elapsed().as_secs_f64(), "index build complete");
+    Ok(BuildReport { num_docs, num_terms, raw_form_terms, skipped_records, avg_doc_tokens, truncated_docs, pretokenized_docs, capped_terms, dropped_postings })
+}
+
+fn index_jsonl(file: &Path, acc: &mut Accumulators, ctx: &BuildContext) -> Result<()> {
+    let f = File::open(file)?;
+    read_jsonl(BufReader::new(f), &file.display().to_string(), acc, ctx)
+}
+
+/// Ingest one JSON document per line from `reader`; `source` names it in parse errors. Bytes
+/// that aren't UTF-8 become U+FFFD rather than failing the build.
+fn read_jsonl(reader: impl BufRead, source: &str, acc: &mut Accumulators, ctx: &BuildContext) -> Result<()> {
+    for (line_no, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() { continue; }
+        let parsed = serde_json::from_str(&line).map_err(anyhow::Error::from).and_then(|v| parse_doc(v, ctx));
+        let Some(doc) = handle_parse_error(parsed, acc, ctx, || format!("{}:{}", source, line_no + 1))? else { continue };
+        ingest_doc(doc, acc, ctx)?;
+        ctx.doc_ingested(acc);
+    }
+    Ok(())
+}
+
+fn index_json(file: &Path, acc: &mut Accumulators, ctx: &BuildContext) -> Result<()> {
+    let json: serde_json::Value = serde_json::from_str(&String::from_utf8_lossy(&fs::read(file)?))?;
+    match json {
+        serde_json::Value::Array(arr) => {
+            for (i, v) in arr.into_iter().enumerate() {
+                let parsed = parse_doc(v, ctx);
+                let Some(doc) = handle_parse_error(parsed, acc, ctx, || format!("{}: document #{}", file.display(), i + 1))? else { continue };
+                ingest_doc(doc, acc, ctx)?;
+                ctx.doc_ingested(acc);
+            }
+        }
+        serde_json::Value::Object(_) => {
+            let parsed = parse_doc(json, ctx);
+            let Some(doc) = handle_parse_error(parsed, acc, ctx, || file.display().to_string())? else { return Ok(()) };
+            ingest_doc(doc, acc, ctx)?;
+            ctx.doc_ingested(acc);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Apply the field map and deserialize one input document.
+fn parse_doc(mut value: serde_json::Value, ctx: &BuildContext) -> Result<InputDoc> {
+    ctx.opts.field_map.apply(&mut value);
+    let doc: InputDoc = serde_json::from_value(value)?;
+    if let TextStorage::External(dir) = &ctx.opts.text_storage { external_text_path(dir, &doc.id)?; }
+    Ok(doc)
+}
+
+/// `{dir}/{id}.txt`, for `--text-dir`. Fails for ids that would name a file outside `dir`: empty,
+/// or containing `/`, `\`, `..` or NUL.
+fn external_text_path(dir: &Path, id: &str) -> Result<PathBuf> {
+    if id.is_empty() || id.contains(['/', '\\', '\0']) || id.contains("..") {
+        bail!("document id {id:?} can't name a file under the text dir");
+    }
+    let dir = std::path::absolute(dir)?;
+    let path = dir.join(format!("{id}.txt"));
+    // Also rules out ids the platform reads as a root or prefix, like `C:x` on Windows
+    if path.parent() != Some(dir.as_path()) {
+        bail!("document id {id:?} resolves outside the text dir");
+    }
+    Ok(path)
+}
+
+/// Apply `--on-parse-error`: fail with `location` attached, or log, count and return `None`.
+fn handle_parse_error(parsed: Result<InputDoc>, acc: &mut Accumulators, ctx: &BuildContext, location: impl FnOnce() -> String) -> Result<Option<InputDoc>> {
+    match parsed {
+        Ok(doc) => Ok(Some(doc)),
+        Err(e) if ctx.opts.on_parse_error == OnParseError::Skip => {
+            tracing::warn!(location = %location(), error = %e, "skipping malformed record");
+            acc.skipped_records += 1;
+            Ok(None)
+        }
+        Err(e) => Err(e.context(location())),
+    }
+}
+
+fn ingest_doc(doc: InputDoc, acc: &mut Accumulators, ctx: &BuildContext) -> Result<()> {
+    let doc_id = acc.next_doc_id;
+    acc.next_doc_id += 1;
+    acc.doc_id_map.insert(doc.id.clone(), doc_id);
+
+    // Indexed and stored text alike, so snippets show what was matched
+    let text = ctx.opts.control_chars.apply(&doc.body);
+    // Tokenize body (unless the input brings its own terms) and compute term frequencies
+    let supplied = doc.tokens.as_ref().map(|terms| {
+        terms.iter().filter(|t| !t.is_empty()).enumerate().map(|(pos, t)| (t.clone(), pos)).collect::<Vec<_>>()
+    });
+    if supplied.is_some() { acc.pretokenized_docs += 1; }
+    // Meta text goes after the (possibly truncated) body, so `--max-doc-tokens` never drops it
+    let meta_texts: Vec<String> = match doc.meta.as_ref() {
+        Some(meta) => ctx.opts.index_meta.iter().filter_map(|key| meta.get(key)).flat_map(facet_values).filter(|v| !v.is_empty()).collect(),
+        None => Vec::new(),
+    };
+    if let Some(case_terms) = acc.case_terms.as_mut() {
+        // Supplied terms are already exactly as the pipeline wants them matched
+        let case_config = TokenizerConfig { preserve_case: true, ..ctx.opts.tokenizer.clone() };
+        let body: Box<dyn Iterator<Item = (String, usize)>> = match supplied.clone() {
+            Some(tokens) => Box::new(tokens.into_iter()),
+            None => Box::new(tokenize_iter_with(&text, &case_config)),
+        };
+        add_doc_tokens(case_terms, doc_id, body, &meta_texts, &case_config, ctx.opts.max_doc_tokens);
+    }
+    let body: Box<dyn Iterator<Item = (String, usize)>> = match supplied {
+        Some(tokens) => Box::new(tokens.into_iter()),
+        None => Box::new(tokenize_iter_with(&text, &ctx.opts.tokenizer)),
+    };
+    let (indexed, truncated) = add_doc_tokens(&mut acc.terms, doc_id, body, &meta_texts, &ctx.opts.tokenizer, ctx.opts.max_doc_tokens);
+    acc.total_tokens += indexed as u64;
+    // The stored text stays whole; only indexing is bounded
+    if let (Some(tokens), Some(max)) = (truncated, ctx.opts.max_doc_tokens) {
+        tracing::debug!(id = %doc.id, tokens, max, "truncating document");
+        acc.truncated_docs.push(doc.id.clone());
+    }
+
+    // Write (or locate) text for snippet extraction
+    let stored = match ctx.opts.snippet_store_bytes {
+        Some(max) => text_head(&text, max),
+        None => &text,
+    };
+    let text_path = match &ctx.opts.text_storage {
+        _ if ctx.opts.dry_run => None,
+        TextStorage::Packed => {
+            if let Some(writer) = acc.packed_texts.as_mut() { writer.append(doc_id, stored)?; }
+            Some(PACKED_TEXTS_FILE.to_string())
+        }
+        TextStorage::Files => {
+            let text_rel = format!("texts/{}.txt", doc_id);
+            fs::write(ctx.out_paths.root.join(&text_rel), stored)?;
+            Some(text_rel)
+        }
+        TextStorage::None => None,
+        // Absolute, so the server's `root.join(text_path)` resolves outside the index
+        TextStorage::External(dir) => Some(external_text_path(dir, &doc.id)?.to_string_lossy().to_string()),
+    };
+
+    let description = doc.meta.as_ref().and_then(|m| m.get("description")?.as_str()).map(str::trim).filter(|d| !d.is_empty());
+    if let Some(description) = description { acc.descriptions.insert(doc_id, description.to_string()); }
+    let timestamp = doc.timestamp.as_deref().and_then(|ts| time::OffsetDateTime::parse(ts.trim(), &time::format_description::well_known::Rfc3339).ok());
+    if let Some(ts) = timestamp { acc.timestamps.insert(doc_id, ts.unix_timestamp()); }
+
+    let facets: BTreeMap<String, Vec<String>> = match doc.meta.as_ref() {
+        Some(meta) => ctx.opts.facet_fields.iter()
+            .filter_map(|key| Some((key.clone(), facet_values(meta.get(key)?))))
+            .filter(|(_, values)| !values.is_empty())
+            .collect(),
+        None => BTreeMap::new(),
+    };
+
+    acc.docs.insert(doc_id, DocMeta { external_id: doc.id, title: doc.title, url: doc.url, text_path, facets });
+    Ok(())
+}
+
+/// Count `body`, cut to `max` tokens, and then each of `texts` into `channel` as one document,
+/// without collecting the tokens. Each text starts one position past the previous token so
+/// adjacent values never look like consecutive words. Returns how many tokens were indexed and,
+/// when the body was cut, how many it had.
+fn add_doc_tokens(channel: &mut TermChannel, doc_id: DocId, mut body: impl Iterator<Item = (String, usize)>, texts: &[String], config: &TokenizerConfig, max: Option<usize>) -> (usize, Option<usize>) {
+    let last = Cell::new(None::<usize>);
+    let meta = texts.iter().flat_map(|text| {
+        // Runs once the body and earlier texts are used up, so `last` is final by then
+        let start = last.get().map_or(0, |pos| pos + 2);
+        tokenize_iter_with(text, config).map(move |(term, pos)| (term, start + pos))
+    });
+    let tokens = body.by_ref().take(max.unwrap_or(usize::MAX)).chain(meta).inspect(|&(_, pos)| last.set(Some(pos)));
+    let indexed = channel.add_doc(doc_id, tokens);
+    let truncated = max.filter(|_| body.next().is_some()).map(|max| max + 1 + body.count());
+    (indexed, truncated)
+}
+
+/// The first `max_bytes` of `text`, cut back to a char boundary.
+fn text_head(text: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) { end -= 1; }
+    &text[..end]
+}
+
+/// Renumber terms so ids follow lexical order, remapping `df` and postings to match.
+fn sort_term_ids(dictionary: &mut HashMap<String, TermId>, df: &mut Vec<u32>, postings_raw: &mut [(TermId, Vec<(DocId, u32)>)]) {
+    let mut terms: Vec<(&String, &mut TermId)> = dictionary.iter_mut().collect();
+    terms.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    let mut new_id = vec![0 as TermId; df.len()];
+    let mut new_df = vec![0u32; df.len()];
+    for (i, (_, tid)) in terms.into_iter().enumerate() {
+        new_id[*tid as usize] = i as TermId;
+        new_df[i] = df[*tid as usize];
+        *tid = i as TermId;
+    }
+    *df = new_df;
+    for (tid, _) in postings_raw.iter_mut() { *tid = new_id[*tid as usize]; }
+}
+
+#[inline]
+fn f32_to_u32(f: f32) -> u32 { f.to_bits() }
+#[inline]
+fn u32_to_f32(u: u32) -> f32 { f32::from_bits(u) }
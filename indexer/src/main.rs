@@ -1,27 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use core::persist::{save_dictionary, save_doc_id_map, save_docs, save_meta, save_postings_for_term, IndexPaths, MetaFile};
-use core::tokenizer::tokenize;
-use core::{DocId, DocMeta, Posting, TermId};
-use serde::Deserialize;
+use core::migrate::migrate;
+use core::verify::verify_index;
+use core::persist::{IndexPaths, INDEX_VERSION};
+use core::tokenizer::{Normalization, TokenizerConfig};
+use core::weighting::{IdfScheme, TfScheme, Weighting};
+use indexer::export::{export_postings, ExportFormat};
+use indexer::swap;
+use indexer::{build_index, load_analyzer, parse_facet_field, parse_pivot, BuildOptions, ControlChars, FieldMap, OnParseError, TextStorage};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use tracing_subscriber::{EnvFilter, fmt};
-use walkdir::WalkDir;
-
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
-use std::path::{Path, PathBuf};
-
-#[derive(Debug, Deserialize)]
-struct InputDoc {
-    id: String,
-    title: String,
-    body: String,
-    url: Option<String>,
-    timestamp: Option<String>,
-    #[serde(default)]
-    meta: Option<serde_json::Value>,
-}
 
 #[derive(Parser)]
 #[command(name = "indexer")] 
@@ -32,196 +21,211 @@ struct Cli {
 }
 
 #[derive(Subcommand)]
+// Parsed once per run, so `Build`'s many flags don't need boxing
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Build the index from input JSON/JSONL files or a directory
     Build {
-        /// Input path (file or directory)
+        /// Input path (file or directory), or `-` to read JSONL from stdin
         #[arg(long)]
         input: String,
         /// Output index directory
-        #[arg(long)]
-        output: String,
-        /// Use smoothed IDF = ln(1 + N/df) instead of ln(N/df)
+        #[arg(long, required_unless_present = "dry_run")]
+        output: Option<String>,
+        /// Parse and tokenize the input, print statistics as JSON and write nothing
         #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        /// Term frequency scheme: log (1+ln tf), raw (tf), boolean (1) or augmented (0.5+0.5*tf/max_tf)
+        #[arg(long, default_value = "log")]
+        tf_scheme: TfScheme,
+        /// IDF scheme: standard (ln N/df), smooth (ln(1+N/df)), probabilistic (ln((N-df)/df), floored at 0) or none (1)
+        #[arg(long, default_value = "standard")]
+        idf_scheme: IdfScheme,
+        /// Shorthand for `--idf-scheme smooth`
+        #[arg(long, default_value_t = false, conflicts_with = "idf_scheme")]
         smoothed_idf: bool,
+        /// Pivoted length normalization: blend doc norms toward the average by this fraction (0-1)
+        #[arg(long, default_value_t = 0.0, value_parser = parse_pivot)]
+        pivot: f32,
+        /// Log progress every N documents (and N terms in the TF-IDF passes); 0 disables
+        #[arg(long, default_value_t = BuildOptions::default().progress_every)]
+        progress_every: u32,
+        /// Also index case-sensitive, unstemmed body terms (searched with `case_sensitive=true`)
+        #[arg(long, default_value_t = false)]
+        preserve_case: bool,
+        /// Assign term ids in sorted order so the same input always yields identical index files
+        #[arg(long, default_value_t = false)]
+        sort_terms: bool,
+        /// Index at most N tokens per document (counted after stopword and length filtering)
+        #[arg(long)]
+        max_doc_tokens: Option<NonZeroUsize>,
+        /// Tokenizer pipeline from a TOML file (normalization, lowercase, stopwords, stemmer, lengths, ngrams)
+        #[arg(long, conflicts_with_all = ["normalization", "min_token_len", "max_token_len", "strip_possessives", "no_index_numbers", "keep_hyphenated"])]
+        analyzer: Option<PathBuf>,
+        /// Unicode normalization form applied before tokenizing: nfc, nfd, nfkc, nfkd or none
+        #[arg(long, default_value = "nfkc")]
+        normalization: Normalization,
+        /// Drop tokens shorter than this many characters
+        #[arg(long, default_value_t = TokenizerConfig::default().min_token_len)]
+        min_token_len: usize,
+        /// Drop tokens longer than this many characters
+        #[arg(long, default_value_t = TokenizerConfig::default().max_token_len)]
+        max_token_len: usize,
+        /// Strip trailing possessive 's / ' before stemming
+        #[arg(long, default_value_t = false)]
+        strip_possessives: bool,
+        /// Drop tokens made only of digits (years, ids)
+        #[arg(long, default_value_t = false)]
+        no_index_numbers: bool,
+        /// Index hyphenated words (`real-time`) as one token instead of splitting them
+        #[arg(long, default_value_t = false)]
+        keep_hyphenated: bool,
+        /// With --keep-hyphenated, also index each part of the compound
+        #[arg(long, default_value_t = false, requires = "keep_hyphenated")]
+        hyphenated_parts: bool,
+        /// Also index each word's unstemmed lowercase form when stemming changes it, so exact spellings rank higher
+        #[arg(long, default_value_t = false)]
+        index_raw_forms: bool,
+        /// Rename input keys, e.g. `title=headline,body=content,url=link`
+        #[arg(long, value_parser = FieldMap::parse)]
+        field_map: Option<FieldMap>,
+        /// What to do with malformed input records
+        #[arg(long, value_enum, default_value_t = OnParseError::Skip)]
+        on_parse_error: OnParseError,
+        /// What to do with control characters (NUL, escape, ...) in bodies; tab and newlines are always kept
+        #[arg(long, value_enum, default_value_t = ControlChars::Strip)]
+        control_chars: ControlChars,
+        /// Store texts as one `texts/{doc_id}.txt` file per document instead of the packed `texts.dat`
+        #[arg(long, default_value_t = false)]
+        per_file_texts: bool,
+        /// Input `meta` keys to store per document for `key:value` filters, e.g. `category,author`
+        #[arg(long, value_delimiter = ',', value_parser = parse_facet_field)]
+        facet_fields: Vec<String>,
+        /// Input `meta` keys whose text is also indexed as body text, e.g. `description,keywords`
+        #[arg(long, value_delimiter = ',')]
+        index_meta: Vec<String>,
+        /// Store each document's terms and tf-idf weights (served by `/doc/{id}/terms`)
+        #[arg(long, default_value_t = false)]
+        store_term_vectors: bool,
+        /// Don't copy document bodies into the index (no snippets or `/doc` text)
+        #[arg(long, default_value_t = false, conflicts_with = "text_dir")]
+        no_store_text: bool,
+        /// Use existing text files `<DIR>/<id>.txt` instead of copying bodies into the index
+        #[arg(long)]
+        text_dir: Option<String>,
+        /// Store only the first N bytes of each body for snippets and `/doc` (the full body is still indexed)
+        #[arg(long, conflicts_with_all = ["no_store_text", "text_dir"])]
+        snippet_store_bytes: Option<NonZeroUsize>,
+        /// Spread postings over N subdirectories (`postings/{term_id % N}/`) to keep directories small; 0 = flat
+        #[arg(long, default_value_t = 0)]
+        postings_fanout: u32,
+        /// Keep only the N highest-weight documents of each term's posting list (documents past the cap aren't found by that term)
+        #[arg(long)]
+        max_postings_per_term: Option<NonZeroUsize>,
+        /// Keep raw tf-idf in the body postings and write per-document norms to `doc_norms.bin`, so the server
+        /// normalizes at query time (and `normalize_docs=false` can skip it)
+        #[arg(long, default_value_t = false)]
+        store_doc_norms: bool,
+        /// After building, verify the new index and atomically repoint this symlink at it (then SIGHUP the server)
+        #[arg(long, conflicts_with = "dry_run")]
+        swap_symlink: Option<PathBuf>,
+    },
+    /// Upgrade an index written by an older indexer to a newer format, in place
+    Migrate {
+        /// Index directory to upgrade
+        #[arg(long)]
+        index: String,
+        /// Target format version (default: the newest)
+        #[arg(long, default_value_t = INDEX_VERSION)]
+        to: u32,
+    },
+    /// Check that every file of an index loads and agrees with the others
+    Verify {
+        /// Index directory to check
+        #[arg(long)]
+        index: String,
+    },
+    /// Dump the body postings as (term, df, doc_id, weight) rows for offline analysis
+    Export {
+        /// Index directory to read
+        #[arg(long)]
+        index: String,
+        /// Output file; standard output if omitted or `-`
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// jsonl, csv or parquet
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
     },
 }
 
 fn main() -> Result<()> {
-    fmt().with_env_filter(EnvFilter::from_default_env()).init();
+    // Progress and summaries are logged at info level, so show them unless RUST_LOG says otherwise
+    fmt().with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))).init();
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Build { input, output, smoothed_idf } => {
-            build_index(&input, &output, smoothed_idf)
-        }
-    }
-}
-
-fn build_index(input: &str, output: &str, smoothed_idf: bool) -> Result<()> {
-    let input_path = Path::new(input);
-    let out_paths = IndexPaths::new(output);
-    fs::create_dir_all(&out_paths.root)?;
-    fs::create_dir_all(out_paths.root.join("texts"))?;
-
-    // Accumulators
-    let mut next_doc_id: DocId = 0;
-    let mut next_term_id: TermId = 0;
-    let mut dictionary: HashMap<String, TermId> = HashMap::new();
-    let mut df: Vec<u32> = Vec::new();
-    let mut postings_raw: HashMap<TermId, Vec<(DocId, u32)>> = HashMap::new();
-    let mut docs: HashMap<DocId, DocMeta> = HashMap::new();
-    let mut doc_id_map: HashMap<String, DocId> = HashMap::new();
-
-    let mut files: Vec<PathBuf> = Vec::new();
-    if input_path.is_dir() {
-        for entry in WalkDir::new(input_path).into_iter().filter_map(|e| e.ok()) {
-            let p = entry.path();
-            if p.is_file() {
-                if let Some(ext) = p.extension().and_then(|s| s.to_str()) {
-                    if matches!(ext, "json" | "jsonl") {
-                        files.push(p.to_path_buf());
-                    }
+        Commands::Build { input, output, dry_run, tf_scheme, idf_scheme, smoothed_idf, pivot, progress_every, preserve_case, sort_terms, max_doc_tokens, analyzer, normalization, min_token_len, max_token_len, strip_possessives, no_index_numbers, keep_hyphenated, hyphenated_parts, index_raw_forms, field_map, facet_fields, index_meta, store_term_vectors, on_parse_error, control_chars, per_file_texts, no_store_text, text_dir, snippet_store_bytes, postings_fanout, max_postings_per_term, store_doc_norms, swap_symlink } => {
+            let mut tokenizer = match analyzer {
+                Some(path) => load_analyzer(&path)?,
+                None => TokenizerConfig { normalization, min_token_len, max_token_len, strip_possessives, index_numbers: !no_index_numbers, keep_hyphenated, hyphenated_parts, ..Default::default() },
+            };
+            tokenizer.raw_forms = index_raw_forms;
+            let text_storage = match (no_store_text, text_dir) {
+                (true, _) => TextStorage::None,
+                (false, Some(dir)) => TextStorage::External(dir.into()),
+                (false, None) if per_file_texts => TextStorage::Files,
+                (false, None) => TextStorage::Packed,
+            };
+            let weighting = Weighting { tf: tf_scheme, idf: if smoothed_idf { IdfScheme::Smooth } else { idf_scheme } };
+            let opts = BuildOptions { weighting, pivot, progress_every, preserve_case, sort_terms, max_doc_tokens: max_doc_tokens.map(NonZeroUsize::get), dry_run, tokenizer, field_map: field_map.unwrap_or_default(), facet_fields, index_meta, store_term_vectors, snippet_store_bytes: snippet_store_bytes.map(NonZeroUsize::get), postings_fanout, max_postings_per_term: max_postings_per_term.map(NonZeroUsize::get), store_doc_norms, on_parse_error, control_chars, text_storage };
+            let output = output.unwrap_or_default();
+            if let Some(link) = &swap_symlink { swap::check_swap_target(link, output.as_ref())?; }
+            let report = build_index(&input, &output, &opts)?;
+            if dry_run { println!("{}", serde_json::to_string_pretty(&report)?); }
+            if report.skipped_records > 0 {
+                eprintln!("skipped {} malformed record(s); see the warnings above for their locations", report.skipped_records);
+            }
+            if !report.truncated_docs.is_empty() {
+                eprintln!("truncated {} document(s) to {} tokens", report.truncated_docs.len(), max_doc_tokens.map_or(0, NonZeroUsize::get));
+            }
+            if report.capped_terms > 0 {
+                eprintln!("capped {} posting list(s), dropping {} posting(s)", report.capped_terms, report.dropped_postings);
+            }
+            if let Some(link) = swap_symlink {
+                let checked = verify_index(&IndexPaths::new(&output)).with_context(|| format!("verifying {output}; {} was not changed", link.display()))?;
+                let previous = swap::swap_symlink(&link, output.as_ref())?;
+                println!("verified {} terms, {} postings; {} -> {output}", checked.terms, checked.postings, link.display());
+                if let Some(previous) = previous {
+                    println!("previous index {} is still on disk; remove it once the server has reloaded (SIGHUP)", previous.display());
                 }
             }
+            Ok(())
         }
-    } else if input_path.is_file() {
-        files.push(input_path.to_path_buf());
-    }
-
-    for file in files {
-        if file.extension().and_then(|s| s.to_str()) == Some("jsonl") {
-            index_jsonl(&file, &mut next_doc_id, &mut next_term_id, &mut dictionary, &mut df, &mut postings_raw, &mut docs, &mut doc_id_map, &out_paths)?;
-        } else {
-            index_json(&file, &mut next_doc_id, &mut next_term_id, &mut dictionary, &mut df, &mut postings_raw, &mut docs, &mut doc_id_map, &out_paths)?;
-        }
-    }
-
-    let num_docs = next_doc_id as u32;
-    tracing::info!(num_docs, num_terms = dictionary.len(), "ingested documents");
-
-    // Compute TF-IDF and normalize
-    let n = num_docs.max(1);
-    // Ensure df length matches highest term id + 1
-    df.resize(next_term_id as usize, 0);
-
-    let mut doc_norms: Vec<f32> = vec![0.0; num_docs as usize];
-    // First pass: compute tfidf and accumulate norms
-    for (term_id, plist) in postings_raw.iter_mut() {
-        let df_t = df[*term_id as usize].max(1);
-        let idf = if smoothed_idf { (1.0 + (n as f32) / (df_t as f32)).ln() } else { ((n as f32) / (df_t as f32)).ln() };
-        for (doc_id, tf_raw) in plist.iter_mut() {
-            let tf = if *tf_raw > 0 { 1.0 + (*tf_raw as f32).ln() } else { 0.0 };
-            let tfidf = tf * idf;
-            doc_norms[*doc_id as usize] += tfidf * tfidf;
-            // temporarily store tfidf back in tf_raw slot by casting via bits (will convert in second pass)
-            *tf_raw = f32_to_u32(tfidf);
-        }
-    }
-    for dn in doc_norms.iter_mut() {
-        *dn = dn.sqrt();
-        if *dn == 0.0 { *dn = 1.0; }
-    }
-
-    // Second pass: create normalized postings and persist per term
-    for (term_id, plist) in postings_raw.into_iter() {
-        let mut out_postings: Vec<Posting> = Vec::with_capacity(plist.len());
-        for (doc_id, tfidf_bits) in plist.into_iter() {
-            let tfidf = u32_to_f32(tfidf_bits);
-            let norm = doc_norms[doc_id as usize];
-            let weight = tfidf / norm;
-            out_postings.push(Posting { doc_id, weight });
-        }
-        // Sort by doc_id per spec
-        out_postings.sort_by_key(|p| p.doc_id);
-        save_postings_for_term(&out_paths, term_id, &out_postings)?;
-    }
-
-    // Persist dictionary, docs, doc_id_map, meta
-    save_dictionary(&out_paths, &(dictionary.clone(), df.clone()))?;
-    save_docs(&out_paths, &docs)?;
-    save_doc_id_map(&out_paths, &doc_id_map)?;
-    let meta = MetaFile {
-        num_docs: n,
-        created_at: time::OffsetDateTime::now_utc().format(&time::format_description::well_known::Rfc3339).unwrap_or_else(|_| "".into()),
-        version: 1,
-    };
-    save_meta(&out_paths, &meta)?;
-
-    tracing::info!(output, "index build complete");
-    Ok(())
-}
-
-fn index_jsonl(file: &Path, next_doc_id: &mut DocId, next_term_id: &mut TermId, dictionary: &mut HashMap<String, TermId>, df: &mut Vec<u32>, postings_raw: &mut HashMap<TermId, Vec<(DocId, u32)>>, docs: &mut HashMap<DocId, DocMeta>, doc_id_map: &mut HashMap<String, DocId>, out_paths: &IndexPaths) -> Result<()> {
-    let f = File::open(file)?;
-    let reader = BufReader::new(f);
-    for line in reader.lines() {
-        let line = line?;
-        if line.trim().is_empty() { continue; }
-        let doc: InputDoc = serde_json::from_str(&line)?;
-        ingest_doc(doc, next_doc_id, next_term_id, dictionary, df, postings_raw, docs, doc_id_map, out_paths)?;
-    }
-    Ok(())
-}
-
-fn index_json(file: &Path, next_doc_id: &mut DocId, next_term_id: &mut TermId, dictionary: &mut HashMap<String, TermId>, df: &mut Vec<u32>, postings_raw: &mut HashMap<TermId, Vec<(DocId, u32)>>, docs: &mut HashMap<DocId, DocMeta>, doc_id_map: &mut HashMap<String, DocId>, out_paths: &IndexPaths) -> Result<()> {
-    let f = File::open(file)?;
-    let reader = BufReader::new(f);
-    let json: serde_json::Value = serde_json::from_reader(reader)?;
-    match json {
-        serde_json::Value::Array(arr) => {
-            for v in arr {
-                let doc: InputDoc = serde_json::from_value(v)?;
-                ingest_doc(doc, next_doc_id, next_term_id, dictionary, df, postings_raw, docs, doc_id_map, out_paths)?;
+        Commands::Migrate { index, to } => {
+            let steps = migrate(&IndexPaths::new(&index), to)?;
+            if steps.is_empty() { println!("{index} is already version {to}"); }
+            for step in steps {
+                println!("version {} -> {}: {}", step.from, step.to, step.description);
             }
+            Ok(())
         }
-        serde_json::Value::Object(_) => {
-            let doc: InputDoc = serde_json::from_value(json)?;
-            ingest_doc(doc, next_doc_id, next_term_id, dictionary, df, postings_raw, docs, doc_id_map, out_paths)?;
+        Commands::Verify { index } => {
+            let report = verify_index(&IndexPaths::new(&index))?;
+            println!("{index}: ok ({} documents, {} terms, {} postings)", report.num_docs, report.terms, report.postings);
+            Ok(())
         }
-        _ => {}
-    }
-    Ok(())
-}
-
-fn ingest_doc(doc: InputDoc, next_doc_id: &mut DocId, next_term_id: &mut TermId, dictionary: &mut HashMap<String, TermId>, df: &mut Vec<u32>, postings_raw: &mut HashMap<TermId, Vec<(DocId, u32)>>, docs: &mut HashMap<DocId, DocMeta>, doc_id_map: &mut HashMap<String, DocId>, out_paths: &IndexPaths) -> Result<()> {
-    let doc_id = *next_doc_id;
-    *next_doc_id += 1;
-    doc_id_map.insert(doc.id.clone(), doc_id);
-
-    // Tokenize body and compute term frequencies
-    let tokens = tokenize(&doc.body);
-    let mut tf_counts: HashMap<TermId, u32> = HashMap::new();
-    let mut seen_in_doc: HashSet<TermId> = HashSet::new();
-    for (term, _pos) in tokens {
-        let tid = *dictionary.entry(term).or_insert_with(|| {
-            let id = *next_term_id;
-            *next_term_id += 1;
-            // ensure df vec capacity
-            if df.len() <= id as usize { df.resize(id as usize + 1, 0); }
-            id
-        });
-        *tf_counts.entry(tid).or_insert(0) += 1;
-        if !seen_in_doc.contains(&tid) {
-            df[tid as usize] += 1;
-            seen_in_doc.insert(tid);
+        Commands::Export { index, output, format } => {
+            let report = match output.filter(|p| p.as_os_str() != "-") {
+                Some(path) => {
+                    let file = std::fs::File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+                    export_postings(&index, format, file)?
+                }
+                None => export_postings(&index, format, std::io::stdout())?,
+            };
+            // stderr, so the summary never mixes into an export written to stdout
+            eprintln!("exported {} rows for {} terms", report.rows, report.terms);
+            Ok(())
         }
     }
-
-    for (tid, tf_raw) in tf_counts.into_iter() {
-        postings_raw.entry(tid).or_default().push((doc_id, tf_raw));
-    }
-
-    // Write text for snippet extraction
-    let text_rel = format!("texts/{}.txt", doc_id);
-    let text_abs = out_paths.root.join(&text_rel);
-    fs::write(&text_abs, &doc.body)?;
-
-    docs.insert(doc_id, DocMeta { external_id: doc.id, title: doc.title, url: doc.url, text_path: Some(text_rel) });
-    Ok(())
 }
-
-#[inline]
-fn f32_to_u32(f: f32) -> u32 { f.to_bits() }
-#[inline]
-fn u32_to_f32(u: u32) -> f32 { f32::from_bits(u) }
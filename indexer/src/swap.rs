@@ -0,0 +1,45 @@
+//! `indexer build --swap-symlink`: make a freshly built and verified index live by pointing a
+//! symlink at it. The server opens `--index <link>`; a rename replaces the link in one step, so
+//! a reload (SIGHUP) or restart sees either the old directory or the new one, never a mix.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Refuse builds that would write into the directory `link` currently serves, or whose link
+/// path holds a real directory that a swap could not replace. Run before building.
+pub fn check_swap_target(link: &Path, output: &Path) -> Result<()> {
+    let Ok(meta) = fs::symlink_metadata(link) else { return Ok(()) };
+    if !meta.file_type().is_symlink() {
+        bail!("{} exists and is not a symlink; move it aside (e.g. `mv {0} {0}.v1`), point a symlink at it and re-run", link.display());
+    }
+    if let (Ok(live), Ok(out)) = (fs::canonicalize(link), fs::canonicalize(output)) {
+        if live == out {
+            bail!("{} is the directory {} serves; build into a new directory instead", output.display(), link.display());
+        }
+    }
+    Ok(())
+}
+
+/// Point `link` at `target` (made absolute) by renaming a temporary symlink over it. Returns
+/// the directory it pointed to before, which is left in place for rollback or cleanup.
+#[cfg(unix)]
+pub fn swap_symlink(link: &Path, target: &Path) -> Result<Option<PathBuf>> {
+    check_swap_target(link, target)?;
+    let target = fs::canonicalize(target).with_context(|| format!("resolving {}", target.display()))?;
+    let previous = fs::read_link(link).ok();
+    let name = link.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| "index".into());
+    let staging = link.with_file_name(format!(".{name}.swap-{}", std::process::id()));
+    fs::remove_file(&staging).ok();
+    std::os::unix::fs::symlink(&target, &staging).with_context(|| format!("creating symlink {}", staging.display()))?;
+    if let Err(e) = fs::rename(&staging, link) {
+        fs::remove_file(&staging).ok();
+        return Err(e).with_context(|| format!("replacing {}", link.display()));
+    }
+    Ok(previous)
+}
+
+#[cfg(not(unix))]
+pub fn swap_symlink(_link: &Path, _target: &Path) -> Result<Option<PathBuf>> {
+    bail!("--swap-symlink is only supported on Unix")
+}
@@ -0,0 +1,212 @@
+//! `build_index` over small inputs written to a temp dir, checked through the report and the
+//! files it writes.
+
+use indexer::{BuildOptions, BuildReport, OnParseError, TextStorage};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::tempdir;
+
+/// Write `lines` to `dir/docs.jsonl` and return its path.
+fn write_input(dir: &Path, lines: &[&str]) -> PathBuf {
+    let input = dir.join("docs.jsonl");
+    std::fs::write(&input, lines.iter().map(|l| format!("{l}\n")).collect::<String>()).unwrap();
+    input
+}
+
+/// Build `dir/index` from `lines`.
+fn build(dir: &Path, lines: &[&str], opts: &BuildOptions) -> anyhow::Result<BuildReport> {
+    let input = write_input(dir, lines);
+    indexer::build_index(&input.to_string_lossy(), &dir.join("index").to_string_lossy(), opts)
+}
+
+/// Log output captured while `f` runs.
+fn captured_logs<T>(f: impl FnOnce() -> T) -> (T, String) {
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+    let result = tracing::subscriber::with_default(subscriber, f);
+    let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    (result, logs)
+}
+
+const MALFORMED: [&str; 4] = [
+    r#"{"id":"a","title":"A","body":"first document"}"#,
+    r#"{"id":"b","title":"B","body":"truncated"#,
+    r#"{"id":"c","body":"no title"}"#,
+    r#"{"id":"d","title":"D","body":"last document"}"#,
+];
+
+#[test]
+fn malformed_records_are_skipped_with_their_line_numbers() {
+    let dir = tempdir().unwrap();
+    let (report, logs) = captured_logs(|| build(dir.path(), &MALFORMED, &BuildOptions::default()));
+    let report = report.unwrap();
+    assert_eq!((report.num_docs, report.skipped_records), (2, 2));
+    assert!(logs.contains("docs.jsonl:2") && logs.contains("docs.jsonl:3"), "{logs}");
+    assert!(!logs.contains("docs.jsonl:1") && !logs.contains("docs.jsonl:4"), "{logs}");
+
+    // The documents around them are indexed under consecutive ids
+    let map = core::persist::load_doc_id_map(&core::persist::IndexPaths::new(dir.path().join("index"))).unwrap();
+    assert_eq!((map["a"], map["d"]), (0, 1));
+}
+
+#[test]
+fn on_parse_error_fail_stops_at_the_first_malformed_line() {
+    let dir = tempdir().unwrap();
+    let opts = BuildOptions { on_parse_error: OnParseError::Fail, ..Default::default() };
+    let err = build(dir.path(), &MALFORMED, &opts).unwrap_err();
+    assert!(format!("{err:#}").contains("docs.jsonl:2"), "{err:#}");
+
+    // A clean input builds the same either way
+    let report = build(dir.path(), &[MALFORMED[0], MALFORMED[3]], &opts).unwrap();
+    assert_eq!((report.num_docs, report.skipped_records), (2, 0));
+}
+
+#[test]
+fn ids_that_escape_the_text_dir_are_malformed_records() {
+    let dir = tempdir().unwrap();
+    let lines = [
+        r#"{"id":"a","title":"A","body":"kept"}"#,
+        r#"{"id":"../secret","title":"B","body":"parent"}"#,
+        r#"{"id":"sub/doc","title":"C","body":"separator"}"#,
+        r#"{"id":"nul\u0000","title":"D","body":"nul"}"#,
+    ];
+    let opts = BuildOptions { text_storage: TextStorage::External(dir.path().join("texts")), ..Default::default() };
+    let (report, logs) = captured_logs(|| build(dir.path(), &lines, &opts));
+    let report = report.unwrap();
+    assert_eq!((report.num_docs, report.skipped_records), (1, 3));
+    assert!(logs.contains("docs.jsonl:2") && logs.contains("can't name a file under the text dir"), "{logs}");
+    let docs = core::persist::load_docs(&core::persist::IndexPaths::new(dir.path().join("index")), core::persist::INDEX_VERSION).unwrap();
+    assert_eq!(docs[&0].text_path.as_deref(), Some(&*std::path::absolute(dir.path().join("texts/a.txt")).unwrap().to_string_lossy()));
+
+    let opts = BuildOptions { on_parse_error: OnParseError::Fail, ..opts };
+    let err = build(dir.path(), &lines, &opts).unwrap_err();
+    assert!(format!("{err:#}").contains("docs.jsonl:2"), "{err:#}");
+}
+
+#[test]
+fn field_map_renames_input_keys() {
+    let dir = tempdir().unwrap();
+    let opts = BuildOptions { field_map: indexer::FieldMap::parse("title=headline,body=content,url=link").unwrap(), ..Default::default() };
+    let report = build(dir.path(), &[r#"{"id":"a","headline":"Walrus News","content":"tusks and flippers","link":"https://example.com/walrus"}"#], &opts).unwrap();
+    assert_eq!((report.num_docs, report.skipped_records), (1, 0));
+
+    let paths = core::persist::IndexPaths::new(dir.path().join("index"));
+    let meta = core::persist::load_meta(&paths).unwrap();
+    let doc = &core::persist::load_docs(&paths, meta.version).unwrap()[&0];
+    assert_eq!((doc.title.as_str(), doc.url.as_deref()), ("Walrus News", Some("https://example.com/walrus")));
+    let (dictionary, _) = core::persist::load_dictionary(&paths).unwrap();
+    assert!(dictionary.contains_key("tusk") && dictionary.contains_key("flipper"), "{dictionary:?}");
+}
+
+/// Every file under `root` by relative path, with `meta.json`'s `created_at` blanked.
+fn index_files(root: &Path) -> std::collections::BTreeMap<PathBuf, Vec<u8>> {
+    walkdir::WalkDir::new(root).into_iter().map(Result::unwrap).filter(|e| e.file_type().is_file()).map(|e| {
+        let rel = e.path().strip_prefix(root).unwrap().to_path_buf();
+        let mut bytes = std::fs::read(e.path()).unwrap();
+        if rel == Path::new("meta.json") {
+            let mut meta: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            meta["created_at"] = serde_json::Value::Null;
+            bytes = serde_json::to_vec(&meta).unwrap();
+        }
+        (rel, bytes)
+    }).collect()
+}
+
+#[test]
+fn sorted_term_ids_make_builds_byte_identical() {
+    let lines = [
+        r#"{"id":"a","title":"A","body":"zebras graze while yaks wander"}"#,
+        r#"{"id":"b","title":"B","body":"aardvarks dig and zebras watch"}"#,
+        r#"{"id":"c","title":"C","body":"moles dig tunnels"}"#,
+    ];
+    let opts = BuildOptions { sort_terms: true, store_term_vectors: true, ..Default::default() };
+    let dirs = (tempdir().unwrap(), tempdir().unwrap());
+    build(dirs.0.path(), &lines, &opts).unwrap();
+    build(dirs.1.path(), &lines, &opts).unwrap();
+    let (first, second) = (index_files(&dirs.0.path().join("index")), index_files(&dirs.1.path().join("index")));
+    assert!(first.len() > 3, "{:?}", first.keys());
+    assert_eq!(first.keys().collect::<Vec<_>>(), second.keys().collect::<Vec<_>>());
+    for (path, bytes) in &first {
+        assert!(bytes == &second[path], "{} differs between builds", path.display());
+    }
+
+    // Ids follow the terms' lexical order, whatever order the input introduced them in
+    let (dictionary, _) = core::persist::load_dictionary(&core::persist::IndexPaths::new(dirs.0.path().join("index"))).unwrap();
+    let mut by_id: Vec<(u32, &str)> = dictionary.iter().map(|(term, &tid)| (tid, term.as_str())).collect();
+    by_id.sort_unstable();
+    assert_eq!(by_id.iter().map(|&(tid, _)| tid).collect::<Vec<_>>(), (0..dictionary.len() as u32).collect::<Vec<_>>());
+    assert!(by_id.windows(2).all(|w| w[0].1 < w[1].1), "{by_id:?}");
+}
+
+#[test]
+fn max_doc_tokens_cuts_bodies_and_lists_them() {
+    let dir = tempdir().unwrap();
+    let lines = [
+        r#"{"id":"long","title":"L","body":"apple banana cherry damson elder fig"}"#,
+        r#"{"id":"exact","title":"E","body":"grape hazel iris"}"#,
+    ];
+    let report = build(dir.path(), &lines, &BuildOptions { max_doc_tokens: Some(3), ..Default::default() }).unwrap();
+    assert_eq!(report.truncated_docs, vec!["long".to_string()]);
+    assert_eq!(report.avg_doc_tokens, 3.0);
+
+    // Only the first three words of the long body are searchable
+    let (dictionary, _) = core::persist::load_dictionary(&core::persist::IndexPaths::new(dir.path().join("index"))).unwrap();
+    let mut terms: Vec<&str> = dictionary.keys().map(String::as_str).collect();
+    terms.sort_unstable();
+    assert_eq!(terms, vec!["appl", "banana", "cherri", "grape", "hazel", "iri"]);
+}
+
+#[test]
+fn dry_run_reports_statistics_and_writes_nothing() {
+    let dir = tempdir().unwrap();
+    let lines = [
+        r#"{"id":"a","title":"A","body":"otters float"}"#,
+        r#"{"id":"b","title":"B","body":"otters hold hands while sleeping"}"#,
+    ];
+    std::fs::create_dir(dir.path().join("index")).unwrap();
+    for text_storage in [indexer::TextStorage::Packed, indexer::TextStorage::Files] {
+        let opts = BuildOptions { dry_run: true, text_storage, preserve_case: true, store_term_vectors: true, ..Default::default() };
+        let report = build(dir.path(), &lines, &opts).unwrap();
+        assert_eq!((report.num_docs, report.num_terms), (2, 5));
+        // "while" is a stopword
+        assert_eq!(report.avg_doc_tokens, 3.0);
+        assert_eq!(std::fs::read_dir(dir.path().join("index")).unwrap().count(), 0);
+    }
+
+    // An output directory that doesn't exist isn't created either
+    let input = write_input(dir.path(), &lines);
+    let missing = dir.path().join("missing");
+    indexer::build_index(&input.to_string_lossy(), &missing.to_string_lossy(), &BuildOptions { dry_run: true, ..Default::default() }).unwrap();
+    assert!(!missing.exists());
+}
+
+#[test]
+fn reading_jsonl_from_a_reader_builds_the_same_index_as_the_file() {
+    let dir = tempdir().unwrap();
+    let lines = [
+        r#"{"id":"a","title":"A","body":"herons wade in the shallows"}"#,
+        "",
+        r#"{"id":"b","title":"B","body":"herons nest in colonies","url":"https://example.com/b"}"#,
+    ];
+    let input = write_input(dir.path(), &lines);
+    let opts = BuildOptions { sort_terms: true, ..Default::default() };
+    let from_file = indexer::build_index(&input.to_string_lossy(), &dir.path().join("file").to_string_lossy(), &opts).unwrap();
+    let reader = std::io::Cursor::new(std::fs::read(&input).unwrap());
+    let from_reader = indexer::build_index_from_reader(reader, "<stdin>", &dir.path().join("reader").to_string_lossy(), &opts).unwrap();
+    assert_eq!((from_reader.num_docs, from_reader.num_terms), (from_file.num_docs, from_file.num_terms));
+    assert_eq!(index_files(&dir.path().join("reader")), index_files(&dir.path().join("file")));
+
+    // Parse errors name the reader's source
+    let reader = std::io::Cursor::new("{\"id\":\"a\"\n");
+    let opts = BuildOptions { on_parse_error: OnParseError::Fail, ..Default::default() };
+    let err = indexer::build_index_from_reader(reader, "<stdin>", &dir.path().join("bad").to_string_lossy(), &opts).unwrap_err();
+    assert!(format!("{err:#}").contains("<stdin>:1"), "{err:#}");
+}
@@ -0,0 +1,94 @@
+//! `indexer export` round trips: an index built from a small input is exported in each format
+//! and read back, and every format must yield the same rows.
+
+use indexer::export::{export_postings, ExportFormat};
+use serde_json::Value;
+use std::path::Path;
+use tempfile::tempdir;
+
+/// `(term, df, doc_id, weight)`, as every format writes it.
+type Row = (String, u32, u32, f32);
+
+/// Build an index whose terms need CSV quoting: supplied `tokens` are indexed as given, so
+/// they can carry commas, quotes and newlines the tokenizer would split on.
+fn build(dir: &Path) -> String {
+    let input = dir.join("docs.jsonl");
+    let docs = [
+        serde_json::json!({ "id": "a", "title": "A", "body": "", "tokens": ["plain", "one,two", "say \"hi\"", "plain"] }),
+        serde_json::json!({ "id": "b", "title": "B", "body": "", "tokens": ["plain", "line\nbreak"] }),
+    ];
+    std::fs::write(&input, docs.iter().map(|d| format!("{d}\n")).collect::<String>()).unwrap();
+    let index_dir = dir.join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    index_dir
+}
+
+fn export(index_dir: &str, format: ExportFormat) -> Vec<u8> {
+    let mut out = Vec::new();
+    let report = export_postings(index_dir, format, &mut out).unwrap();
+    assert_eq!((report.terms, report.rows), (4, 5), "{format:?}");
+    out
+}
+
+fn jsonl_rows(bytes: &[u8]) -> Vec<Row> {
+    std::str::from_utf8(bytes).unwrap().lines().map(|line| {
+        let v: Value = serde_json::from_str(line).unwrap();
+        (v["term"].as_str().unwrap().to_string(), v["df"].as_u64().unwrap() as u32, v["doc_id"].as_u64().unwrap() as u32, v["weight"].as_f64().unwrap() as f32)
+    }).collect()
+}
+
+#[test]
+fn jsonl_lists_terms_in_order_with_their_postings() {
+    let dir = tempdir().unwrap();
+    let rows = jsonl_rows(&export(&build(dir.path()), ExportFormat::Jsonl));
+    let keys: Vec<(&str, u32, u32)> = rows.iter().map(|(t, df, id, _)| (t.as_str(), *df, *id)).collect();
+    assert_eq!(keys, vec![("line\nbreak", 1, 1), ("one,two", 1, 0), ("plain", 2, 0), ("plain", 2, 1), ("say \"hi\"", 1, 0)]);
+    // "plain" is in every document, so its idf and weight are zero
+    assert!(rows.iter().all(|(t, _, _, w)| (t == "plain") == (*w == 0.0)), "{rows:?}");
+}
+
+#[test]
+fn csv_quotes_terms_and_round_trips() {
+    let dir = tempdir().unwrap();
+    let index_dir = build(dir.path());
+    let bytes = export(&index_dir, ExportFormat::Csv);
+    let mut reader = csv::Reader::from_reader(bytes.as_slice());
+    assert_eq!(reader.headers().unwrap(), vec!["term", "df", "doc_id", "weight"]);
+    let rows: Vec<Row> = reader.deserialize().map(Result::unwrap).collect();
+    assert_eq!(rows, jsonl_rows(&export(&index_dir, ExportFormat::Jsonl)));
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.contains("\"one,two\"") && text.contains("\"say \"\"hi\"\"\""), "{text}");
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_round_trips() {
+    use arrow_array::{Float32Array, StringArray, UInt32Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let dir = tempdir().unwrap();
+    let index_dir = build(dir.path());
+    let path = dir.path().join("postings.parquet");
+    std::fs::write(&path, export(&index_dir, ExportFormat::Parquet)).unwrap();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap().build().unwrap();
+    let mut rows: Vec<Row> = Vec::new();
+    for batch in reader {
+        let batch = batch.unwrap();
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let (terms, dfs, doc_ids, weights) = (column("term"), column("df"), column("doc_id"), column("weight"));
+        let terms = terms.as_any().downcast_ref::<StringArray>().unwrap();
+        let dfs = dfs.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let doc_ids = doc_ids.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let weights = weights.as_any().downcast_ref::<Float32Array>().unwrap();
+        rows.extend((0..batch.num_rows()).map(|i| (terms.value(i).to_string(), dfs.value(i), doc_ids.value(i), weights.value(i))));
+    }
+    assert_eq!(rows, jsonl_rows(&export(&index_dir, ExportFormat::Jsonl)));
+}
+
+#[cfg(not(feature = "parquet"))]
+#[test]
+fn parquet_needs_the_feature() {
+    let dir = tempdir().unwrap();
+    let err = export_postings(&build(dir.path()), ExportFormat::Parquet, Vec::new()).unwrap_err();
+    assert!(err.to_string().contains("`parquet` feature"), "{err}");
+}
@@ -0,0 +1,540 @@
+//! Buffered documents into the served index (`POST /index/batch`, `POST /index/commit`).
+//!
+//! Body postings store `tf-idf / norm` with the idf of the df when they were weighted. Committing
+//! documents changes `N` and the df of their terms, so the weights already stored drift from what
+//! a full rebuild would write. A commit therefore has two steps:
+//!
+//! 1. Append (every commit, before it returns): the buffered documents are weighted with the
+//!    updated df and appended to the postings of their terms. This happens in a staging copy that
+//!    hard-links every file it doesn't rewrite, and is swapped in like a reindex. Earlier documents
+//!    keep their weights. The `N` and df they were weighted with go into `weight_bases.bin`, and
+//!    the commit reports (and without `reweight` logs) how far idf has drifted since.
+//! 2. Reweight (`reweight=true`, background): each stale document's weights are multiplied by
+//!    `idf_now / idf_then` per term and renormalized. That is exactly what a rebuild stores, since
+//!    the tf factor and the old norm cancel out. The pass reads every posting list twice: once to
+//!    sum the new norms, once to rewrite the lists. It checkpoints both phases in its staging
+//!    directory, so a pass that failed or was cut short by a restart resumes at the next
+//!    `reweight=true` commit instead of starting over.
+//!
+//! A posting weighted with an idf of zero (e.g. a term in every document under `standard` idf) kept
+//! no tf to rescale. It stays zero and is counted in `approximated_postings` until a full reindex.
+//! Indexes whose weights can't be rescaled per term (pivoted norms, norm files, case channel, term
+//! vectors, capped posting lists) don't take commits at all.
+
+use crate::reindex::{sibling, swap_in};
+use crate::{AppState, BufferedDoc, Field, LoadedIndex, ReindexState};
+use anyhow::Context;
+use axum::http::StatusCode;
+use core::persist::{load_doc_id_map, load_meta, load_postings_for_term, load_weight_bases, save_dictionary, save_doc_id_map, save_docs, save_meta, save_postings_for_term, save_weight_bases, IndexPaths, PackedTextWriter, WeightBasis, PACKED_TEXTS_FILE, WEIGHT_BASES_FILE};
+use core::tokenizer::tokenize_with;
+use core::{DocId, DocMeta, Posting, TermId};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// One document of a `/index/batch` body, with the indexer's input keys.
+#[derive(Debug, Deserialize)]
+pub struct BatchDoc {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CommitParams {
+    /// Rescale the weights left stale by this and earlier commits in a background pass
+    #[serde(default)]
+    pub reweight: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitPhase {
+    /// Appending the buffered documents
+    Append,
+    /// Reweight pass, summing the new norms of stale documents
+    Norms,
+    /// Reweight pass, rewriting the posting lists
+    Postings,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommitReport {
+    /// Buffered documents appended to the index.
+    pub committed: u32,
+    pub num_docs: u32,
+    /// Body terms first seen in the committed documents.
+    pub new_terms: usize,
+    /// Documents weighted with an older df than the index's, until a reweight pass.
+    pub stale_docs: u32,
+    /// Largest change of a body term's idf since stale documents were weighted.
+    pub max_idf_drift: f32,
+}
+
+/// Where a reweight pass picked up an interrupted one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ResumePoint {
+    pub phase: CommitPhase,
+    pub next_term: TermId,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommitStatus {
+    pub state: ReindexState,
+    /// Step running, or the one that failed.
+    pub phase: Option<CommitPhase>,
+    pub reweight: bool,
+    pub report: Option<CommitReport>,
+    /// Body terms the current reweight phase has done, of `num_terms`.
+    pub terms_done: TermId,
+    pub num_terms: TermId,
+    pub resumed_from: Option<ResumePoint>,
+    /// Stale postings left as they were because their idf was zero when weighted.
+    pub approximated_postings: u64,
+    /// Seconds since the current (or last) commit started.
+    pub elapsed_s: Option<f64>,
+    pub error: Option<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+/// Progress of a reweight pass, saved in its staging directory with the norms.
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    /// `created_at` and `num_docs` of the index being reweighted; a checkpoint of another is stale.
+    base: String,
+    #[serde(flatten)]
+    at: ResumePoint,
+    approximated_postings: u64,
+}
+
+const CHECKPOINT_FILE: &str = "reweight.json";
+/// By doc id: squared norms summed so far in the `norms` phase, final norms in `postings`.
+const NORMS_FILE: &str = "reweight_norms.bin";
+const CHECKPOINT_EVERY: TermId = 1024;
+
+/// Current commit status with `elapsed_s` filled in.
+pub fn status(state: &AppState) -> CommitStatus {
+    let mut status = state.commit.lock().clone();
+    status.elapsed_s = status.started.map(|t0| status.finished.unwrap_or_else(Instant::now).duration_since(t0).as_secs_f64());
+    status
+}
+
+/// Why `index` can't take commits: the build option that keeps its weights from being rescaled per term.
+fn unsupported(index: &LoadedIndex) -> Option<&'static str> {
+    if index.case_channel.is_some() { return Some("--preserve-case"); }
+    if index.term_vectors.is_some() { return Some("--store-term-vectors"); }
+    if index.doc_norms.is_some() { return Some("--store-doc-norms"); }
+    if index.max_postings_per_term.is_some() { return Some("--max-postings-per-term"); }
+    if index.pivot > 0.0 { return Some("--pivot"); }
+    None
+}
+
+fn check_supported(index: &LoadedIndex) -> Result<(), (StatusCode, String)> {
+    match unsupported(index) {
+        Some(flag) => Err((StatusCode::CONFLICT, format!("indexes built with {flag} can't take incremental commits; rebuild with /admin/reindex instead"))),
+        None => Ok(()),
+    }
+}
+
+/// Analyze `docs` and add them to the delta. Fails as a whole if an id is empty or already
+/// committed, buffered or repeated. Returns how many documents are buffered now.
+pub fn buffer(state: &AppState, docs: Vec<BatchDoc>) -> Result<u32, (StatusCode, String)> {
+    let tokenizer = state.index().tokenizer.clone();
+    let analyzed: Vec<BufferedDoc> = docs.into_iter().map(|doc| {
+        let terms = tokenize_with(&doc.body, &tokenizer).into_iter().map(|(term, _)| term).collect();
+        BufferedDoc { external_id: doc.id, title: doc.title, url: doc.url, body: doc.body, terms }
+    }).collect();
+
+    let served = state.index();
+    check_supported(&served)?;
+    let mut ids = HashSet::with_capacity(analyzed.len());
+    for doc in &analyzed {
+        if doc.external_id.trim().is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "document id must not be empty".into()));
+        }
+        if !ids.insert(doc.external_id.as_str()) {
+            return Err(already_indexed(&doc.external_id));
+        }
+    }
+    if let Some(id) = committed_id(&served, &ids) { return Err(already_indexed(id)); }
+
+    // The index read lock keeps a commit or reindex from swapping in an index these ids weren't
+    // checked against; the delta is only locked for the append itself
+    let current = state.index.read();
+    if !Arc::ptr_eq(&current, &served) {
+        check_supported(&current)?;
+        if let Some(id) = committed_id(&current, &ids) { return Err(already_indexed(id)); }
+    }
+    let mut delta = state.delta.write();
+    if let Some(id) = ids.iter().find(|id| delta.contains(id)) { return Err(already_indexed(id)); }
+    drop(ids);
+    // Copies only if a search still holds the current snapshot
+    let delta = Arc::make_mut(&mut delta);
+    for doc in analyzed { delta.add_doc(doc); }
+    Ok(delta.num_docs)
+}
+
+fn already_indexed(id: &str) -> (StatusCode, String) {
+    (StatusCode::CONFLICT, format!("document {id:?} is already indexed or buffered"))
+}
+
+/// One of `ids` that `index` already holds. Without a loaded id map the documents are scanned
+/// once for the whole batch.
+fn committed_id<'a>(index: &LoadedIndex, ids: &HashSet<&'a str>) -> Option<&'a str> {
+    match &index.doc_id_map {
+        Some(map) => ids.iter().copied().find(|id| map.contains_key(*id)),
+        None => index.docs.values().find_map(|meta| ids.get(meta.external_id.as_str()).copied()),
+    }
+}
+
+/// Append the buffered documents, then with `reweight` start the reweight pass on a background
+/// thread. Blocks for the append; answers 202 when a pass was started. Fails with 409 while a
+/// commit, reweight pass or reindex is running.
+pub fn commit(state: &AppState, params: CommitParams) -> Result<(StatusCode, CommitStatus), (StatusCode, String)> {
+    check_supported(&state.index())?;
+    {
+        let mut status = state.commit.lock();
+        if status.state == ReindexState::Running {
+            return Err((StatusCode::CONFLICT, "a commit or reweight pass is already running".into()));
+        }
+        if state.reindex.lock().state == ReindexState::Running {
+            return Err((StatusCode::CONFLICT, "a reindex is running".into()));
+        }
+        *status = CommitStatus { state: ReindexState::Running, phase: Some(CommitPhase::Append), reweight: params.reweight, started: Some(Instant::now()), ..Default::default() };
+    }
+
+    let report = match append(state) {
+        Ok(report) => report,
+        Err(e) => {
+            finish(state, Err(e));
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, status(state).error.unwrap_or_default()));
+        }
+    };
+    if report.committed > 0 && report.stale_docs > 0 && !params.reweight {
+        tracing::warn!(stale_docs = report.stale_docs, max_idf_drift = report.max_idf_drift, "committed without reweighting; scores of earlier documents drift until a reweight=true commit or a full reindex");
+    }
+    let stale = report.stale_docs > 0;
+    state.commit.lock().report = Some(report);
+    if !params.reweight || !stale {
+        finish(state, Ok(()));
+        return Ok((StatusCode::OK, status(state)));
+    }
+
+    let state_c = state.clone();
+    std::thread::spawn(move || {
+        let result = reweight(&state_c);
+        finish(&state_c, result);
+    });
+    Ok((StatusCode::ACCEPTED, status(state)))
+}
+
+fn finish(state: &AppState, result: anyhow::Result<()>) {
+    let mut status = state.commit.lock();
+    status.finished = Some(Instant::now());
+    match result {
+        Ok(()) => {
+            tracing::info!(report = ?status.report, reweight = status.reweight, "commit complete");
+            status.state = ReindexState::Succeeded;
+            status.phase = None;
+        }
+        Err(e) => {
+            tracing::error!(error = %e, phase = ?status.phase, "commit failed");
+            status.state = ReindexState::Failed;
+            status.error = Some(format!("{e:#}"));
+        }
+    }
+}
+
+/// The append step: a staging copy of the index with the buffered documents added, swapped in
+/// along with the documents buffered while it was written.
+fn append(state: &AppState) -> anyhow::Result<CommitReport> {
+    let current = state.index();
+    // The documents, not the segment: holding a snapshot of the segment would make every batch
+    // buffered during the commit copy it
+    let buffered: Vec<Arc<BufferedDoc>> = state.delta.read().docs.clone();
+    let mut bases = current.root.with(|root| load_weight_bases(&IndexPaths::new(root)))?;
+    if buffered.is_empty() {
+        return Ok(CommitReport { committed: 0, num_docs: current.num_docs, new_terms: 0, stale_docs: stale_docs(&bases), max_idf_drift: max_idf_drift(&current, &bases) });
+    }
+    let old_num_docs = current.num_docs;
+    let num_docs = old_num_docs + buffered.len() as u32;
+    // The documents weighted with the current df become stale once it changes
+    let exact_from = bases.last().map_or(0, |basis| basis.docs.end);
+    if exact_from < old_num_docs {
+        bases.push(WeightBasis { docs: exact_from..old_num_docs, num_docs: old_num_docs, df: current.df.clone() });
+    }
+
+    // Count every new document first, so all of them are weighted with the final df
+    let mut dictionary = current.dictionary.clone();
+    let mut df = current.df.clone();
+    let mut doc_tfs: Vec<HashMap<TermId, u32>> = Vec::with_capacity(buffered.len());
+    for doc in &buffered {
+        let mut tfs: HashMap<TermId, u32> = HashMap::new();
+        for term in &doc.terms {
+            let next = df.len() as TermId;
+            let tid = *dictionary.entry(term.clone()).or_insert(next);
+            if tid == next { df.push(0); }
+            *tfs.entry(tid).or_insert(0) += 1;
+        }
+        for &tid in tfs.keys() { df[tid as usize] += 1; }
+        doc_tfs.push(tfs);
+    }
+    let mut added: BTreeMap<TermId, Vec<Posting>> = BTreeMap::new();
+    for (i, tfs) in doc_tfs.iter().enumerate() {
+        let doc_id = old_num_docs + i as DocId;
+        let max_tf = tfs.values().copied().max().unwrap_or(0);
+        let mut weights: Vec<(TermId, f32)> = tfs.iter().map(|(&tid, &tf)| (tid, current.weighting.weight(tf, max_tf, num_docs, df[tid as usize]))).collect();
+        // Term order fixes the float summation order of the norm, as in the indexer
+        weights.sort_unstable_by_key(|(tid, _)| *tid);
+        let norm = weights.iter().map(|(_, w)| w * w).sum::<f32>().sqrt();
+        let norm = if norm == 0.0 { 1.0 } else { norm };
+        for (tid, weight) in weights { added.entry(tid).or_default().push(Posting { doc_id, weight: weight / norm }); }
+    }
+
+    let live = current.root.path();
+    let staging = sibling(&live, "commit");
+    if staging.exists() { fs::remove_dir_all(&staging)?; }
+    let rewritten = ["dictionary.bin", "docs.bin", "meta.json", "doc_id_map.bin", WEIGHT_BASES_FILE, PACKED_TEXTS_FILE, "texts.idx"];
+    current.root.with(|root| link_tree(root, &staging, &rewritten)).context("linking the index into staging")?;
+    let out = IndexPaths::new(&staging).with_postings_fanout(current.postings_fanout);
+
+    for (tid, mut postings) in added {
+        // New doc ids are past every committed one, so appending keeps the list sorted
+        let mut list = match (tid as usize) < current.df.len() {
+            true => current.root.with(|root| load_postings_for_term(&current.body_paths(root, Field::Body), tid))?,
+            false => Vec::new(),
+        };
+        list.append(&mut postings);
+        unlink(&out.postings_file(tid))?;
+        save_postings_for_term(&out, tid, &list)?;
+    }
+
+    let mut docs = current.docs.clone();
+    let mut packed = current.packed_texts.as_ref().map(|_| PackedTextWriter::create(&out)).transpose()?;
+    if let (Some(writer), Some(texts)) = (packed.as_mut(), current.packed_texts.as_ref()) {
+        for &doc_id in &current.doc_ids {
+            if let Some(text) = texts.get(doc_id) { writer.append(doc_id, text)?; }
+        }
+    }
+    let mut doc_id_map = match current.root.with(|root| root.join("doc_id_map.bin").exists()) {
+        true => Some(current.root.with(|root| load_doc_id_map(&IndexPaths::new(root)))?),
+        false => None,
+    };
+    for (i, doc) in buffered.iter().enumerate() {
+        let doc_id = old_num_docs + i as DocId;
+        let stored = match current.snippet_store_bytes {
+            Some(max) => text_head(&doc.body, max),
+            None => &doc.body,
+        };
+        let text_path = match packed.as_mut() {
+            Some(writer) => {
+                writer.append(doc_id, stored)?;
+                PACKED_TEXTS_FILE.to_string()
+            }
+            None => {
+                let rel = format!("texts/{doc_id}.txt");
+                fs::create_dir_all(staging.join("texts"))?;
+                fs::write(staging.join(&rel), stored)?;
+                rel
+            }
+        };
+        docs.insert(doc_id, DocMeta { external_id: doc.external_id.clone(), title: doc.title.clone(), url: doc.url.clone(), text_path: Some(text_path), facets: Default::default() });
+        if let Some(map) = doc_id_map.as_mut() { map.insert(doc.external_id.clone(), doc_id); }
+    }
+    if let Some(writer) = packed { writer.finish(&out)?; }
+    if let Some(map) = &doc_id_map { save_doc_id_map(&out, map)?; }
+    let new_terms = df.len() - current.df.len();
+    save_dictionary(&out, &(dictionary, df))?;
+    save_docs(&out, &docs)?;
+    save_weight_bases(&out, &bases)?;
+    let mut meta = current.root.with(|root| load_meta(&IndexPaths::new(root)))?;
+    meta.num_docs = num_docs;
+    meta.created_at = now_rfc3339();
+    save_meta(&out, &meta)?;
+
+    // Load before touching the live directory so a bad commit never replaces a good index
+    let loaded = LoadedIndex::load(&staging.to_string_lossy(), state.config.load_doc_id_map)?;
+    let report = CommitReport { committed: buffered.len() as u32, num_docs, new_terms, stale_docs: stale_docs(&bases), max_idf_drift: max_idf_drift(&loaded, &bases) };
+    let _served = swap_in(state, &staging, loaded)?;
+    let mut delta = state.delta.write();
+    *delta = Arc::new(delta.after(buffered.len()));
+    Ok(report)
+}
+
+/// The reweight pass over the served index, resuming a checkpoint of it if there is one.
+fn reweight(state: &AppState) -> anyhow::Result<()> {
+    let current = state.index();
+    let live = current.root.path();
+    let mut meta = current.root.with(|root| load_meta(&IndexPaths::new(root)))?;
+    let bases = current.root.with(|root| load_weight_bases(&IndexPaths::new(root)))?;
+    let base = format!("{}/{}", meta.created_at, meta.num_docs);
+    let staging = sibling(&live, "reweight");
+    let out = IndexPaths::new(&staging).with_postings_fanout(current.postings_fanout);
+    let num_terms = current.df.len() as TermId;
+
+    let resumed = read_checkpoint(&staging).filter(|checkpoint| checkpoint.base == base);
+    let (mut checkpoint, mut norms) = match resumed {
+        Some(checkpoint) => {
+            let norms: Vec<f32> = bincode::deserialize(&fs::read(staging.join(NORMS_FILE))?)?;
+            tracing::info!(phase = ?checkpoint.at.phase, next_term = checkpoint.at.next_term, "resuming reweight pass");
+            state.commit.lock().resumed_from = Some(checkpoint.at);
+            (checkpoint, norms)
+        }
+        None => {
+            if staging.exists() { fs::remove_dir_all(&staging)?; }
+            current.root.with(|root| link_tree(root, &staging, &["meta.json", WEIGHT_BASES_FILE])).context("linking the index into staging")?;
+            let checkpoint = Checkpoint { base, at: ResumePoint { phase: CommitPhase::Norms, next_term: 0 }, approximated_postings: 0 };
+            (checkpoint, vec![0.0; current.num_docs as usize])
+        }
+    };
+    state.commit.lock().num_terms = num_terms;
+
+    for phase in [CommitPhase::Norms, CommitPhase::Postings] {
+        if checkpoint.at.phase != phase { continue; }
+        state.commit.lock().phase = Some(phase);
+        for tid in checkpoint.at.next_term..num_terms {
+            let step = match phase {
+                CommitPhase::Postings => rewrite_postings(&current, &bases, tid, &norms, &out),
+                _ => sum_norms(&current, &bases, tid, &mut norms, &mut checkpoint.approximated_postings),
+            };
+            let done = tid + 1;
+            if let Err(e) = step {
+                checkpoint.at.next_term = tid;
+                save_checkpoint(&staging, &checkpoint, &norms)?;
+                return Err(e.context(format!("reweighting term {tid}")));
+            }
+            if done % CHECKPOINT_EVERY == 0 {
+                checkpoint.at.next_term = done;
+                save_checkpoint(&staging, &checkpoint, &norms)?;
+            }
+            let mut status = state.commit.lock();
+            status.terms_done = done;
+            status.approximated_postings = checkpoint.approximated_postings;
+        }
+        if phase == CommitPhase::Norms {
+            for norm in norms.iter_mut() { *norm = norm.sqrt(); }
+            checkpoint.at = ResumePoint { phase: CommitPhase::Postings, next_term: 0 };
+            save_checkpoint(&staging, &checkpoint, &norms)?;
+        }
+    }
+
+    meta.created_at = now_rfc3339();
+    save_meta(&out, &meta)?;
+    fs::remove_file(staging.join(CHECKPOINT_FILE))?;
+    fs::remove_file(staging.join(NORMS_FILE))?;
+    let loaded = LoadedIndex::load(&staging.to_string_lossy(), state.config.load_doc_id_map)?;
+    let _served = swap_in(state, &staging, loaded)?;
+    Ok(())
+}
+
+/// The basis `doc_id` was weighted with, if it is stale.
+fn basis_of(bases: &[WeightBasis], doc_id: DocId) -> Option<&WeightBasis> {
+    let i = bases.partition_point(|basis| basis.docs.end <= doc_id);
+    bases.get(i).filter(|basis| basis.docs.contains(&doc_id))
+}
+
+/// `idf_now / idf_then` of `tid` for documents weighted with `basis`; `None` if idf was zero then.
+fn idf_ratio(index: &LoadedIndex, basis: &WeightBasis, tid: TermId) -> Option<f32> {
+    let idf = index.weighting.idf;
+    let then = idf.weight(basis.num_docs, *basis.df.get(tid as usize)?);
+    (then != 0.0).then(|| idf.weight(index.num_docs, index.df[tid as usize]) / then)
+}
+
+/// Add the rescaled weights of `tid`'s stale postings to their documents' squared norms.
+fn sum_norms(index: &LoadedIndex, bases: &[WeightBasis], tid: TermId, norms: &mut [f32], approximated: &mut u64) -> anyhow::Result<()> {
+    let postings = index.root.with(|root| load_postings_for_term(&index.body_paths(root, Field::Body), tid))?;
+    for posting in postings {
+        let Some(basis) = basis_of(bases, posting.doc_id) else { continue };
+        match idf_ratio(index, basis, tid) {
+            Some(ratio) => norms[posting.doc_id as usize] += (posting.weight * ratio).powi(2),
+            None => *approximated += 1,
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite `tid`'s posting list into `out` with its stale weights rescaled and renormalized.
+/// Lists without stale postings keep their link to the served file.
+fn rewrite_postings(index: &LoadedIndex, bases: &[WeightBasis], tid: TermId, norms: &[f32], out: &IndexPaths) -> anyhow::Result<()> {
+    let postings = index.root.with(|root| load_postings_for_term(&index.body_paths(root, Field::Body), tid))?;
+    if !postings.iter().any(|posting| basis_of(bases, posting.doc_id).is_some()) { return Ok(()); }
+    let postings: Vec<Posting> = postings.into_iter().map(|posting| {
+        let norm = norms[posting.doc_id as usize];
+        match basis_of(bases, posting.doc_id).and_then(|basis| idf_ratio(index, basis, tid)) {
+            Some(ratio) if norm > 0.0 => Posting { weight: posting.weight * ratio / norm, ..posting },
+            _ => posting,
+        }
+    }).collect();
+    unlink(&out.postings_file(tid))?;
+    save_postings_for_term(out, tid, &postings)
+}
+
+fn read_checkpoint(staging: &Path) -> Option<Checkpoint> {
+    serde_json::from_slice(&fs::read(staging.join(CHECKPOINT_FILE)).ok()?).ok()
+}
+
+/// Save the norms, then the checkpoint that refers to them, each written beside and renamed over.
+fn save_checkpoint(staging: &Path, checkpoint: &Checkpoint, norms: &[f32]) -> anyhow::Result<()> {
+    for (name, bytes) in [(NORMS_FILE, bincode::serialize(norms)?), (CHECKPOINT_FILE, serde_json::to_vec(checkpoint)?)] {
+        let tmp = staging.join(format!("{name}.tmp"));
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, staging.join(name))?;
+    }
+    Ok(())
+}
+
+fn stale_docs(bases: &[WeightBasis]) -> u32 {
+    bases.iter().map(|basis| basis.docs.len() as u32).sum()
+}
+
+/// Largest `|idf_now - idf_then|` over the terms of every basis.
+fn max_idf_drift(index: &LoadedIndex, bases: &[WeightBasis]) -> f32 {
+    let idf = index.weighting.idf;
+    bases.iter()
+        .flat_map(|basis| basis.df.iter().enumerate().map(move |(tid, &then)| (idf.weight(index.num_docs, index.df[tid]) - idf.weight(basis.num_docs, then)).abs()))
+        .fold(0.0, f32::max)
+}
+
+/// Recreate `src` at `dst` with every file hard-linked, except the top-level files named in
+/// `skip`, which the caller writes fresh. A linked file is shared with the served index, so it
+/// must be unlinked before it is rewritten.
+fn link_tree(src: &Path, dst: &Path, skip: &[&str]) -> std::io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let to = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_tree(&entry.path(), &to, &[])?;
+        } else if !skip.iter().any(|name| entry.file_name() == *name) {
+            fs::hard_link(entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove `path` if it exists.
+fn unlink(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// At most the first `max_bytes` of `text`, cut back to a char boundary.
+fn text_head(text: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) { end -= 1; }
+    &text[..end]
+}
+
+fn now_rfc3339() -> String {
+    time::OffsetDateTime::now_utc().format(&time::format_description::well_known::Rfc3339).unwrap_or_default()
+}
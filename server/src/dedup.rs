@@ -0,0 +1,93 @@
+//! Query-time collapsing of near-identical hits (`dedup=true`).
+//!
+//! Crawls often hold the same content under several URLs (mirrors, paginated copies), and those
+//! pages score alike and crowd the top of the list. Hits are compared by the set of analyzed
+//! terms in their stored text: one whose set overlaps a higher-ranked kept hit's by at least
+//! [`SIMILARITY`] (Jaccard) is dropped in favour of it.
+//!
+//! `dedup_titles=true` is the cheap, title-only variant for crawl artifacts such as thousands of
+//! "Page Not Found" pages: only the best hit of each normalized title is kept.
+
+use crate::LoadedIndex;
+use core::tokenizer::tokenize_iter_with;
+use core::DocId;
+use std::collections::HashSet;
+
+/// Term-set Jaccard similarity at which two hits count as the same content.
+pub const SIMILARITY: f64 = 0.9;
+/// Hits examined per distinct hit wanted, bounding the text reads on very repetitive results.
+const SCAN_FACTOR: usize = 10;
+
+/// Drop near-duplicates from the best-ranked `scored` hits until `want` distinct ones are kept
+/// or `want * SCAN_FACTOR` have been examined; later hits stay as they are. Hits without stored
+/// text are never duplicates. Returns how many hits were dropped.
+pub fn collapse_duplicates(index: &LoadedIndex, scored: &mut Vec<(DocId, f32)>, want: usize, read_bytes: usize) -> usize {
+    let limit = want.saturating_mul(SCAN_FACTOR);
+    let mut kept: Vec<(DocId, f32)> = Vec::with_capacity(scored.len());
+    let mut signatures: Vec<HashSet<String>> = Vec::new();
+    let mut collapsed = 0;
+    for (i, hit) in scored.drain(..).enumerate() {
+        if i >= limit || kept.len() >= want {
+            kept.push(hit);
+            continue;
+        }
+        let signature: Option<HashSet<String>> = index.doc_text_head(hit.0, read_bytes)
+            .map(|text| tokenize_iter_with(&text, &index.tokenizer).map(|(term, _)| term).collect())
+            .filter(|terms: &HashSet<String>| !terms.is_empty());
+        if let Some(signature) = signature {
+            if signatures.iter().any(|seen| jaccard(seen, &signature) >= SIMILARITY) {
+                collapsed += 1;
+                continue;
+            }
+            signatures.push(signature);
+        }
+        kept.push(hit);
+    }
+    *scored = kept;
+    collapsed
+}
+
+/// Drop every hit whose normalized title (see [`title_key`]) a higher-ranked hit already has.
+/// Untitled hits are kept. Only metadata is read, so the whole list is scanned. Returns how many
+/// hits were dropped.
+pub fn collapse_same_titles(index: &LoadedIndex, scored: &mut Vec<(DocId, f32)>) -> usize {
+    let before = scored.len();
+    let mut seen: HashSet<String> = HashSet::new();
+    scored.retain(|(doc_id, _)| {
+        let Some(key) = index.docs.get(doc_id).map(|meta| title_key(&meta.title)).filter(|key| !key.is_empty()) else { return true };
+        seen.insert(key)
+    });
+    before - scored.len()
+}
+
+/// A title compared case-insensitively, with whitespace runs collapsed: `Page  not found ` and
+/// `page not found` are the same title.
+fn title_key(title: &str) -> String {
+    title.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>().join(" ")
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let shared = small.iter().filter(|term| large.contains(*term)).count();
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jaccard_of_term_sets() {
+        let set = |s: &str| s.split_whitespace().map(str::to_string).collect::<HashSet<_>>();
+        assert_eq!(jaccard(&set("a b c"), &set("a b c")), 1.0);
+        assert_eq!(jaccard(&set("a b c d"), &set("a b")), 0.5);
+        assert_eq!(jaccard(&set("a"), &set("b")), 0.0);
+    }
+
+    #[test]
+    fn title_keys_ignore_case_and_spacing() {
+        assert_eq!(title_key("  Page  Not\tFound "), title_key("page not found"));
+        assert_ne!(title_key("Login"), title_key("Log in"));
+        assert_eq!(title_key(" "), "");
+    }
+}
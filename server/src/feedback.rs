@@ -0,0 +1,104 @@
+//! Click feedback capture (`POST /feedback`).
+//!
+//! Handlers only push events onto a bounded channel; a background thread appends
+//! them to the JSONL file named by `FEEDBACK_LOG` and flushes whenever the queue
+//! drains, so a slow disk never adds latency to a request.
+
+use crate::metrics::Metrics;
+use crate::AppState;
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use core::tokenizer::tokenize_with;
+use core::DocId;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Events queued before new ones are rejected with 503.
+const QUEUE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Deserialize)]
+pub struct FeedbackRequest {
+    /// The query as the user typed it
+    pub q: String,
+    /// The clicked result
+    pub doc_id: DocId,
+    /// 1-based position of the result in the list the user saw
+    pub rank: u32,
+}
+
+/// One line of the feedback log.
+#[derive(Debug, Serialize)]
+struct FeedbackEvent {
+    /// Milliseconds since the Unix epoch
+    ts_ms: u64,
+    q: String,
+    /// `q` run through the index tokenizer, terms separated by single spaces
+    normalized_q: String,
+    doc_id: DocId,
+    /// External id of `doc_id`, which survives rebuilds that renumber documents
+    external_id: String,
+    rank: u32,
+}
+
+/// Sending half of the feedback queue; the writer thread exits once every clone is dropped.
+pub struct FeedbackLog {
+    tx: SyncSender<FeedbackEvent>,
+}
+
+impl FeedbackLog {
+    /// Open (or create) `path` for appending and start the writer thread.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("opening feedback log {}", path.display()))?;
+        let (tx, rx) = sync_channel::<FeedbackEvent>(QUEUE_CAPACITY);
+        let log_path = path.display().to_string();
+        std::thread::Builder::new().name("feedback-writer".into()).spawn(move || {
+            let mut out = BufWriter::new(file);
+            while let Ok(event) = rx.recv() {
+                let mut result = write_event(&mut out, &event);
+                while let Ok(event) = rx.try_recv() {
+                    result = result.and_then(|_| write_event(&mut out, &event));
+                }
+                if let Err(e) = result.and_then(|_| out.flush()) {
+                    tracing::warn!(path = %log_path, error = %e, "failed to write feedback log");
+                }
+            }
+        })?;
+        Ok(Self { tx })
+    }
+}
+
+fn write_event(out: &mut impl Write, event: &FeedbackEvent) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *out, event)?;
+    out.write_all(b"\n")
+}
+
+pub async fn feedback_handler(State(state): State<AppState>, Json(req): Json<FeedbackRequest>) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, String)> {
+    let Some(log) = state.feedback.as_ref() else {
+        return Err((StatusCode::NOT_FOUND, "feedback logging is disabled (set FEEDBACK_LOG)".into()));
+    };
+    if req.q.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "q must not be empty".into()));
+    }
+    let index = state.index();
+    let Some(external_id) = index.external_id(req.doc_id) else {
+        return Err((StatusCode::BAD_REQUEST, format!("unknown doc_id {}", req.doc_id)));
+    };
+    let normalized_q = tokenize_with(&req.q, &index.tokenizer).into_iter().map(|(t, _)| t).collect::<Vec<_>>().join(" ");
+    let ts_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let event = FeedbackEvent { ts_ms, q: req.q, normalized_q, doc_id: req.doc_id, external_id: external_id.to_string(), rank: req.rank };
+    match log.tx.try_send(event) {
+        Ok(()) => Ok((StatusCode::ACCEPTED, Json(serde_json::json!({ "status": "queued" })))),
+        Err(TrySendError::Full(_)) => {
+            Metrics::inc(&state.metrics.feedback_dropped_total);
+            Err((StatusCode::SERVICE_UNAVAILABLE, "feedback queue is full".into()))
+        }
+        Err(TrySendError::Disconnected(_)) => Err((StatusCode::SERVICE_UNAVAILABLE, "feedback writer stopped".into())),
+    }
+}
@@ -0,0 +1,156 @@
+//! Corpus statistics across the committed index and documents buffered on top of it.
+//!
+//! Query idf has to count every document a search can see, committed or buffered, or
+//! weights drift from the postings they are applied to. [`IndexState`] pairs one committed
+//! [`LoadedIndex`] snapshot with one [`DeltaSegment`] snapshot and answers df and `N` for
+//! both together, so the scorer never combines them by hand.
+
+use crate::{Field, LoadedIndex};
+use core::TermId;
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A document accepted by `/index/batch`, with its body analyzed by the served index's tokenizer.
+#[derive(Debug, Clone)]
+pub struct BufferedDoc {
+    pub external_id: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub body: String,
+    /// Analyzed body terms in order, repeats included.
+    pub terms: Vec<String>,
+}
+
+/// Documents accepted by `/index/batch` but not yet committed, in arrival order, with the body
+/// document frequencies they add. Body terms only; title and URL statistics stay those of the
+/// committed index until a commit adds the documents to it.
+#[derive(Debug, Default, Clone)]
+pub struct DeltaSegment {
+    pub num_docs: u32,
+    /// Buffered documents containing each analyzed body term.
+    pub df: HashMap<String, u32>,
+    /// Shared with the snapshots searches hold, so copying the segment never copies a body.
+    pub docs: Vec<Arc<BufferedDoc>>,
+    /// External ids of `docs`.
+    ids: HashSet<String>,
+}
+
+impl DeltaSegment {
+    /// Buffer one document and count its terms.
+    pub fn add_doc(&mut self, doc: impl Into<Arc<BufferedDoc>>) {
+        let doc = doc.into();
+        self.num_docs += 1;
+        self.ids.insert(doc.external_id.clone());
+        let distinct: HashSet<&str> = doc.terms.iter().map(String::as_str).collect();
+        for term in distinct {
+            *self.df.entry(term.to_string()).or_insert(0) += 1;
+        }
+        self.docs.push(doc);
+    }
+
+    pub fn is_empty(&self) -> bool { self.num_docs == 0 }
+
+    /// Whether `external_id` is already buffered.
+    pub fn contains(&self, external_id: &str) -> bool {
+        self.ids.contains(external_id)
+    }
+
+    /// The documents buffered after the first `n`, e.g. those that arrived while a commit of
+    /// the first `n` ran.
+    pub fn after(&self, n: usize) -> DeltaSegment {
+        let mut rest = DeltaSegment::default();
+        for doc in self.docs.iter().skip(n) { rest.add_doc(Arc::clone(doc)); }
+        rest
+    }
+}
+
+/// One consistent view for a search: a committed index snapshot plus the delta buffered on
+/// top of it. Dereferences to the committed [`LoadedIndex`] for everything but statistics.
+#[derive(Clone)]
+pub struct IndexState {
+    committed: Arc<LoadedIndex>,
+    delta: Arc<DeltaSegment>,
+}
+
+impl IndexState {
+    pub fn new(committed: Arc<LoadedIndex>, delta: Arc<DeltaSegment>) -> Self {
+        Self { committed, delta }
+    }
+
+    pub fn delta(&self) -> &DeltaSegment { &self.delta }
+
+    /// Dictionary, df and `N` of `field` as query weighting must see them.
+    pub fn effective_stats(&self, field: Field) -> FieldStats<'_> {
+        let (dictionary, committed_df, num_docs) = self.committed.field_stats(field);
+        if field == Field::Body {
+            FieldStats { dictionary, committed_df, delta_df: Some(&self.delta.df), num_docs: num_docs + self.delta.num_docs }
+        } else {
+            FieldStats { dictionary, committed_df, delta_df: None, num_docs }
+        }
+    }
+}
+
+impl Deref for IndexState {
+    type Target = LoadedIndex;
+
+    fn deref(&self) -> &LoadedIndex { &self.committed }
+}
+
+/// Statistics of one field: committed counts plus, for the body, the delta's.
+pub struct FieldStats<'a> {
+    pub dictionary: &'a HashMap<String, TermId>,
+    committed_df: &'a [u32],
+    delta_df: Option<&'a HashMap<String, u32>>,
+    /// Committed plus buffered documents.
+    pub num_docs: u32,
+}
+
+impl FieldStats<'_> {
+    /// Term id and combined df of `term`. Only terms in the committed dictionary have
+    /// postings to score, so others are `None` even if buffered documents contain them.
+    pub fn df(&self, term: &str) -> Option<(TermId, u32)> {
+        let tid = *self.dictionary.get(term)?;
+        let committed = self.committed_df.get(tid as usize).copied().unwrap_or(0);
+        let buffered = self.delta_df.and_then(|df| df.get(term)).copied().unwrap_or(0);
+        Some((tid, committed + buffered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(id: &str, terms: &[&str]) -> BufferedDoc {
+        BufferedDoc { external_id: id.into(), title: String::new(), url: None, body: terms.join(" "), terms: terms.iter().map(|t| t.to_string()).collect() }
+    }
+
+    #[test]
+    fn df_and_n_combine_committed_and_buffered_counts() {
+        let mut delta = DeltaSegment::default();
+        delta.add_doc(doc("a", &["rust", "rust", "tokio"]));
+        delta.add_doc(doc("b", &["rust"]));
+        assert_eq!(delta.num_docs, 2);
+        assert_eq!(delta.df["rust"], 2);
+
+        let dictionary: HashMap<String, TermId> = [("rust".to_string(), 0), ("async".to_string(), 1)].into();
+        let committed_df = [3, 1];
+        let body = FieldStats { dictionary: &dictionary, committed_df: &committed_df, delta_df: Some(&delta.df), num_docs: 10 + delta.num_docs };
+        assert_eq!(body.df("rust"), Some((0, 5)));
+        assert_eq!(body.df("async"), Some((1, 1)));
+        // Buffered-only terms have no committed postings yet
+        assert_eq!(body.df("tokio"), None);
+        assert_eq!(body.num_docs, 12);
+    }
+
+    #[test]
+    fn after_keeps_the_later_documents_and_their_counts() {
+        let mut delta = DeltaSegment::default();
+        delta.add_doc(doc("a", &["rust", "tokio"]));
+        delta.add_doc(doc("b", &["rust"]));
+        let rest = delta.after(1);
+        assert_eq!(rest.num_docs, 1);
+        assert_eq!(rest.df, HashMap::from([("rust".to_string(), 1)]));
+        assert!(rest.contains("b") && !rest.contains("a"));
+    }
+}
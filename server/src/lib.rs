@@ -1,224 +1,1337 @@
-use anyhow::Result;
-use axum::{extract::{Path, Query, State}, http::StatusCode, routing::{get, post}, Json, Router};
-use core::persist::{load_index_header, load_postings_for_term, IndexPaths};
-use core::tokenizer::tokenize;
-use core::{DocId, DocMeta, TermId};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use axum::{extract::{DefaultBodyLimit, Path, Query, Request, State}, http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode}, middleware::{self, Next}, response::{IntoResponse, Response}, routing::{get, post}, Json, Router};
+use core::persist::{check_dictionary, load_descriptions, load_dictionary, load_doc_id_map, load_index_header, CASE_CHANNEL_VERSION, load_timestamps, load_postings_for_term, IndexPaths, PackedTexts, TermVectors, PACKED_TEXTS_FILE};
+use core::tokenizer::{tokenize_with, TokenizerConfig, RAW_FORM_PREFIX};
+use core::weighting::Weighting;
+use core::{DocId, DocMeta, InvertedIndex, Posting, TermId};
+use parking_lot::{Mutex, RwLock};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer, AllowOrigin};
 
-#[derive(Deserialize)]
-pub struct SearchParams {
-    pub q: String,
-    #[serde(default = "default_k")] 
-    pub k: usize,
+mod commit;
+mod dedup;
+mod feedback;
+mod index_state;
+pub mod listen;
+pub mod metrics;
+mod proximity;
+pub mod query;
+mod reindex;
+pub mod snippet;
+use feedback::{feedback_handler, FeedbackLog};
+use metrics::Metrics;
+pub use query::Field;
+pub use query::Mode;
+use query::{parse_boosts, parse_fields, parse_query};
+use snippet::{best_match_snippet, lead_snippet, HighlightOptions, SnippetFallback, SnippetField};
+pub use commit::{BatchDoc, CommitParams, CommitPhase, CommitReport, CommitStatus, ResumePoint};
+pub use index_state::{BufferedDoc, DeltaSegment, FieldStats, IndexState};
+pub use reindex::{IndexRoot, ReindexRequest, ReindexState, ReindexStatus};
+
+pub use core::api::{
+    BatchSearchParams, DocDebug, DocParams, DocResponse, DocSummary, DocTerm, DocTermsParams, DocTermsResponse, HitFields, ListDocsParams, ListDocsResponse, MinScoreMode,
+    ParsedField, ParsedQuery, ParsedTerm, SearchHit, SearchParams, SearchResponse, SimilarParams, TermContribution, Timings,
+};
+
+/// Index data loaded from disk. Replaced as a whole when the index is rebuilt.
+pub struct LoadedIndex {
+    /// Directory the postings and per-file texts are read from, shared with other snapshots of it.
+    pub root: Arc<IndexRoot>,
+    pub dictionary: HashMap<String, TermId>,
+    /// Document frequency per term id. Loaded with `dictionary` from the same build (checked by
+    /// [`check_dictionary`]) and only ever swapped together with it and the postings, so query idf
+    /// always matches the postings being scored.
+    pub df: Vec<u32>,
+    pub docs: HashMap<DocId, DocMeta>,
+    /// Keys of `docs` in ascending order, for stable paging in `/docs`.
+    pub doc_ids: Vec<DocId>,
+    pub num_docs: u32,
+    /// Index format version from `meta.json`, sent as `X-Index-Version`.
+    pub version: u32,
+    /// Build time from `meta.json`, sent as `Last-Modified` on searches and `X-Index-Created-At`
+    /// on every response.
+    pub created_at: Option<SystemTime>,
+    /// Tokenizer settings the index was built with, applied to queries.
+    pub tokenizer: TokenizerConfig,
+    /// In-memory index over document titles for `fields=title` searches.
+    pub title_index: InvertedIndex,
+    /// In-memory index over document URLs for `fields=url` searches.
+    pub url_index: InvertedIndex,
+    /// Memory-mapped `texts.dat`, for indexes built with packed texts.
+    pub packed_texts: Option<PackedTexts>,
+    /// Meta descriptions from the input's `meta.description`, for `snippet_fallback=meta`.
+    pub descriptions: HashMap<DocId, String>,
+    /// Document dates (Unix seconds) from the input's `timestamp`, for `browse=true`.
+    pub timestamps: HashMap<DocId, i64>,
+    /// Dictionary and df of the case-sensitive body channel, for indexes built with `--preserve-case`.
+    pub case_channel: Option<(HashMap<String, TermId>, Vec<u32>)>,
+    /// Input `meta` keys stored as `DocMeta::facets` (`--facet-fields`).
+    pub facet_fields: Vec<String>,
+    /// Input `meta` keys indexed with the body (`--index-meta`), kept by reindexing.
+    pub index_meta: Vec<String>,
+    /// Per-document body terms and weights, for indexes built with `--store-term-vectors`.
+    pub term_vectors: Option<TermVectors>,
+    /// Stored texts are only each body's first N bytes (`--snippet-store-bytes`).
+    pub snippet_store_bytes: Option<usize>,
+    /// External (input) id -> doc id, from `doc_id_map.bin`; `None` if the index has no map or
+    /// `LOAD_DOC_ID_MAP=false`. The reverse direction is `DocMeta::external_id`.
+    pub doc_id_map: Option<HashMap<String, DocId>>,
+    /// Postings subdirectory fanout from `meta.json` (`--postings-fanout`).
+    pub postings_fanout: u32,
+    /// Posting lists were cut to this many entries at build time (`--max-postings-per-term`).
+    pub max_postings_per_term: Option<usize>,
+    /// tf and idf schemes the postings were weighted with (`--tf-scheme`, `--idf-scheme`).
+    pub weighting: Weighting,
+    /// Pivoted length normalization blend the body norms were computed with (`--pivot`).
+    pub pivot: f32,
+    /// Body norm of every document (by doc id) for indexes built with `--store-doc-norms`, whose
+    /// body postings hold un-normalized tf-idf. Searches divide by it unless `normalize_docs=false`.
+    pub doc_norms: Option<Vec<f32>>,
+}
+
+impl LoadedIndex {
+    /// Load the index header from `index_dir` and build the in-memory title index.
+    /// `doc_id_map.bin` is only read with `with_doc_id_map`.
+    pub fn load(index_dir: &str, with_doc_id_map: bool) -> Result<Self> {
+        // Postings and per-file texts are read lazily, so pin a symlinked index to the directory it
+        // names now: repointing the link (`--swap-symlink`) must not mix two builds before a reload.
+        let is_link = std::fs::symlink_metadata(index_dir).is_ok_and(|m| m.file_type().is_symlink());
+        let root = if is_link { std::fs::canonicalize(index_dir).with_context(|| format!("resolving {index_dir}"))? } else { PathBuf::from(index_dir) };
+        let index_paths = IndexPaths::new(&root);
+        let (dictionary, df, docs, meta, doc_norms) = load_index_header(&index_paths)?;
+        let title_index = InvertedIndex::from_texts(docs.iter().map(|(id, m)| (*id, m.title.as_str())), &meta.tokenizer, meta.weighting);
+        let url_index = InvertedIndex::from_texts(docs.iter().filter_map(|(id, m)| Some((*id, m.url.as_deref()?))), &meta.tokenizer, meta.weighting);
+        let packed_texts = PackedTexts::open(&index_paths)?;
+        let term_vectors = TermVectors::open(&index_paths)?;
+        let descriptions = load_descriptions(&index_paths)?;
+        let timestamps = load_timestamps(&index_paths)?;
+        let doc_id_map = if with_doc_id_map && index_paths.root.join("doc_id_map.bin").exists() { Some(load_doc_id_map(&index_paths)?) } else { None };
+        let case_channel = if meta.version >= CASE_CHANNEL_VERSION && meta.case_sensitive {
+            let (dictionary, df) = load_dictionary(&index_paths.case_channel())?;
+            check_dictionary(&dictionary, &df, meta.num_docs).context("case-sensitive channel")?;
+            Some((dictionary, df))
+        } else {
+            None
+        };
+        let created_at = time::OffsetDateTime::parse(&meta.created_at, &time::format_description::well_known::Rfc3339).ok().map(SystemTime::from);
+        let mut doc_ids: Vec<DocId> = docs.keys().copied().collect();
+        doc_ids.sort_unstable();
+        Ok(Self { root: Arc::new(IndexRoot::new(root)), dictionary, df, docs, doc_ids, num_docs: meta.num_docs, version: meta.version, created_at, tokenizer: meta.tokenizer, title_index, url_index, packed_texts, descriptions, timestamps, case_channel, facet_fields: meta.facet_fields, index_meta: meta.index_meta, term_vectors, snippet_store_bytes: meta.snippet_store_bytes, doc_id_map, postings_fanout: meta.postings_fanout, max_postings_per_term: meta.max_postings_per_term, weighting: meta.weighting, pivot: meta.pivot, doc_norms })
+    }
+
+    /// The in-memory index for a metadata field (`None` for the on-disk body).
+    fn field_index(&self, field: Field) -> Option<&InvertedIndex> {
+        match field {
+            Field::Title => Some(&self.title_index),
+            Field::Url => Some(&self.url_index),
+            Field::Body | Field::BodyCase => None,
+        }
+    }
+
+    /// Dictionary, document frequencies and corpus size of a field.
+    fn field_stats(&self, field: Field) -> (&HashMap<String, TermId>, &[u32], u32) {
+        match (self.field_index(field), &self.case_channel) {
+            (Some(mem), _) => (&mem.dictionary, &mem.df, mem.num_docs),
+            (None, Some((dictionary, df))) if field == Field::BodyCase => (dictionary, df, self.num_docs),
+            (None, _) => (&self.dictionary, &self.df, self.num_docs),
+        }
+    }
+
+    /// Body terms for `ids`, in the order given (ids missing from the dictionary are skipped).
+    /// Scans the dictionary once, since it is only indexed by term.
+    fn term_names(&self, ids: impl IntoIterator<Item = TermId>) -> Vec<(TermId, String)> {
+        let ids: Vec<TermId> = ids.into_iter().collect();
+        let mut names: HashMap<TermId, &str> = ids.iter().map(|&tid| (tid, "")).collect();
+        for (term, tid) in &self.dictionary {
+            if let Some(name) = names.get_mut(tid) { *name = term; }
+        }
+        ids.into_iter().filter(|tid| !names[tid].is_empty()).map(|tid| (tid, names[&tid].to_string())).collect()
+    }
+
+    /// The doc id indexed under input id `external_id`. Without a loaded `doc_id_map` this scans the docs.
+    pub fn resolve_external_id(&self, external_id: &str) -> Option<DocId> {
+        match &self.doc_id_map {
+            Some(map) => map.get(external_id).copied(),
+            None => self.docs.iter().find(|(_, meta)| meta.external_id == external_id).map(|(&doc_id, _)| doc_id),
+        }
+    }
+
+    /// The input id of `doc_id`.
+    pub fn external_id(&self, doc_id: DocId) -> Option<&str> {
+        self.docs.get(&doc_id).map(|meta| meta.external_id.as_str())
+    }
+
+    /// Tokenizer settings for query text searched in `field`.
+    fn field_tokenizer(&self, field: Field) -> Cow<'_, TokenizerConfig> {
+        match field {
+            Field::BodyCase => Cow::Owned(TokenizerConfig { preserve_case: true, ..self.tokenizer.clone() }),
+            _ => Cow::Borrowed(&self.tokenizer),
+        }
+    }
+
+    /// Up to `limit` terms of `field` starting with `prefix` (or equal to its stemmed form),
+    /// most frequent first.
+    fn prefix_terms(&self, field: Field, prefix: &str, limit: usize) -> Vec<String> {
+        let (dictionary, df, _) = self.field_stats(field);
+        let stemmed: Vec<String> = tokenize_with(prefix, &self.field_tokenizer(field)).into_iter().map(|(t, _)| t).collect();
+        let mut terms: Vec<(&String, u32)> = dictionary
+            .iter()
+            .filter(|(term, _)| term.starts_with(prefix) || stemmed.contains(term))
+            .map(|(term, &tid)| (term, df.get(tid as usize).copied().unwrap_or(0)))
+            .collect();
+        terms.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        terms.into_iter().take(limit).map(|(term, _)| term.clone()).collect()
+    }
+
+    /// Postings of `tid` in `field`. Body postings are read from disk and counted in `metrics`;
+    /// a missing or corrupt postings file is logged and returned as an error.
+    fn postings(&self, field: Field, tid: TermId, metrics: &Metrics) -> Result<Cow<'_, [Posting]>> {
+        let postings = self.read_postings(field, tid, metrics);
+        if let Err(e) = &postings {
+            Metrics::inc(&metrics.postings_load_errors_total);
+            tracing::warn!(term_id = tid, error = %e, "failed to load postings");
+        }
+        postings
+    }
+
+    /// [`Self::postings`] without counting or logging a failure, for callers that leave it to a
+    /// retry through [`Self::postings`].
+    fn read_postings(&self, field: Field, tid: TermId, metrics: &Metrics) -> Result<Cow<'_, [Posting]>> {
+        match self.field_index(field) {
+            Some(mem) => Ok(mem.postings.get(&tid).map_or(Cow::Borrowed(&[][..]), |p| Cow::Borrowed(p.as_slice()))),
+            None => {
+                let postings = self.root.with(|root| load_postings_for_term(&self.body_paths(root, field), tid))?;
+                Metrics::inc(&metrics.postings_files_read_total);
+                Ok(Cow::Owned(postings))
+            }
+        }
+    }
+
+    /// Where `Body` or `BodyCase` postings live under `root`.
+    fn body_paths(&self, root: &std::path::Path, field: Field) -> IndexPaths {
+        let paths = IndexPaths::new(root).with_postings_fanout(self.postings_fanout);
+        if field == Field::BodyCase { paths.case_channel() } else { paths }
+    }
+
+    /// Try to load up to `sample` body postings files spread evenly over the term ids.
+    /// Returns (failed, checked). Reads directly rather than through [`Self::postings`], so health
+    /// polling doesn't count toward the postings read and error metrics.
+    fn check_postings_sample(&self, sample: usize) -> (usize, usize) {
+        let num_terms = self.df.len();
+        if sample == 0 || num_terms == 0 { return (0, 0); }
+        let step = (num_terms / sample).max(1);
+        let mut failed = 0;
+        let mut checked = 0;
+        for tid in (0..num_terms).step_by(step).take(sample) {
+            checked += 1;
+            if self.root.with(|root| load_postings_for_term(&self.body_paths(root, Field::Body), tid as TermId)).is_err() { failed += 1; }
+        }
+        (failed, checked)
+    }
+
+    /// Where a document's stored text lives, if it has any and it can be read. A per-file text
+    /// is opened here, while the index directory can't move.
+    fn text_location(&self, doc_id: DocId) -> Option<TextLocation<'_>> {
+        let rel = self.docs.get(&doc_id)?.text_path.as_ref()?;
+        if rel == PACKED_TEXTS_FILE {
+            return self.packed_texts.as_ref()?.get(doc_id).map(TextLocation::Packed);
+        }
+        self.root.with(|root| std::fs::File::open(root.join(rel))).ok().map(TextLocation::File)
+    }
+
+    /// Full stored text of a document.
+    pub fn doc_text(&self, doc_id: DocId) -> Option<Cow<'_, str>> {
+        match self.text_location(doc_id)? {
+            TextLocation::Packed(text) => Some(Cow::Borrowed(text)),
+            // Text files the index doesn't own (`--text-dir`) may not be UTF-8
+            TextLocation::File(mut file) => {
+                use std::io::Read;
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).ok()?;
+                Some(Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()))
+            }
+        }
+    }
+
+    /// At most the first `max_bytes` of a document's text (all of it when `max_bytes` is 0),
+    /// cut back to a char boundary. Only that much of a per-file text is read.
+    pub fn doc_text_head(&self, doc_id: DocId, max_bytes: usize) -> Option<Cow<'_, str>> {
+        if max_bytes == 0 { return self.doc_text(doc_id); }
+        match self.text_location(doc_id)? {
+            TextLocation::Packed(text) => {
+                let mut end = max_bytes.min(text.len());
+                while !text.is_char_boundary(end) { end -= 1; }
+                Some(Cow::Borrowed(&text[..end]))
+            }
+            TextLocation::File(file) => {
+                use std::io::Read;
+                let mut buf = Vec::new();
+                file.take(max_bytes as u64).read_to_end(&mut buf).ok()?;
+                match String::from_utf8(buf) {
+                    Ok(text) => Some(Cow::Owned(text)),
+                    // The cap split a multi-byte char: drop its leading bytes
+                    Err(e) if e.utf8_error().error_len().is_none() => {
+                        let valid = e.utf8_error().valid_up_to();
+                        let mut bytes = e.into_bytes();
+                        bytes.truncate(valid);
+                        String::from_utf8(bytes).ok().map(Cow::Owned)
+                    }
+                    Err(e) => Some(Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned())),
+                }
+            }
+        }
+    }
+
+    /// At most `max_chars` characters of a document's text, and whether it was cut short.
+    pub fn doc_text_prefix(&self, doc_id: DocId, max_chars: usize) -> Option<(String, bool)> {
+        match self.text_location(doc_id)? {
+            TextLocation::Packed(text) => read_text_prefix(text.as_bytes(), max_chars).ok(),
+            TextLocation::File(file) => read_text_prefix(file, max_chars).ok(),
+        }
+    }
+}
+
+enum TextLocation<'a> {
+    /// Slice of the memory-mapped `texts.dat`.
+    Packed(&'a str),
+    /// A text file, already open.
+    File(std::fs::File),
+}
+
+/// Settings read from the environment at startup.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Token required in `X-ADMIN-TOKEN` for admin endpoints (`ADMIN_TOKEN`).
+    pub admin_token: Option<String>,
+    /// Largest `k` a search may request; larger values are clamped (`MAX_K`, default 100).
+    pub max_k: usize,
+    /// `Cache-Control: max-age` for `/search` responses (`SEARCH_CACHE_MAX_AGE`, default 60 seconds).
+    pub search_cache_max_age_s: u64,
+    /// Postings files `/health` tries to load (`HEALTH_SAMPLE_TERMS`, default 16; 0 disables the check).
+    pub health_sample_terms: usize,
+    /// Fraction of sampled postings that may fail before `/health` reports 503
+    /// (`HEALTH_MAX_FAILED_FRACTION`, default 0).
+    pub health_max_failed_fraction: f64,
+    /// Most dictionary terms a trailing-wildcard word (`program*`) expands to
+    /// (`MAX_WILDCARD_EXPANSIONS`, default 50).
+    pub max_wildcard_expansions: usize,
+    /// Bytes of each hit's stored text scanned for its snippet (`SNIPPET_MAX_READ_BYTES`,
+    /// default 256 KiB; 0 reads the whole text). Matches past the cap fall back to the lead.
+    pub snippet_max_read_bytes: usize,
+    /// JSONL file that `POST /feedback` click events are appended to (`FEEDBACK_LOG`); unset disables the endpoint.
+    pub feedback_log: Option<PathBuf>,
+    /// Load `doc_id_map.bin` for `/doc/by-external` lookups (`LOAD_DOC_ID_MAP`, default true). It holds every input
+    /// id, so large indexes may turn it off; lookups then scan the document metadata instead.
+    pub load_doc_id_map: bool,
+    /// Most searches in one `POST /search/batch` request (`MAX_BATCH_QUERIES`, default 64).
+    pub max_batch_queries: usize,
+    /// gzip/brotli-compress responses for clients that send `Accept-Encoding` (`COMPRESSION`, default true).
+    pub compression: bool,
+    /// Responses with a known length below this many bytes are sent uncompressed (`COMPRESSION_MIN_BYTES`, default 1024).
+    pub compression_min_bytes: u16,
+    /// Origins allowed to call the API from a browser (`CORS_ALLOW_ORIGIN`, comma-separated); empty allows any.
+    pub cors_allow_origins: Vec<HeaderValue>,
+    /// How long browsers may cache a preflight answer, sent as `Access-Control-Max-Age`
+    /// (`CORS_MAX_AGE`, seconds; unset sends none and browsers use their own short default).
+    pub cors_max_age_s: Option<u64>,
+    /// Response headers scripts may read, beyond the CORS-safelisted ones and the `X-Index-*`
+    /// headers, which are always exposed (`CORS_EXPOSE_HEADERS`, comma-separated).
+    pub cors_expose_headers: Vec<HeaderName>,
+    /// Largest request body the search, feedback and other public endpoints accept; larger ones get 413
+    /// (`MAX_BODY_BYTES`, default 1 MiB).
+    pub max_body_bytes: usize,
+    /// Largest request body of the admin ingest endpoints (`/index/batch`, `/index/commit`,
+    /// `/admin/reindex`), which carry documents (`MAX_INGEST_BODY_BYTES`, default 64 MiB).
+    pub max_ingest_body_bytes: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { admin_token: None, max_k: 100, search_cache_max_age_s: 60, health_sample_terms: 16, health_max_failed_fraction: 0.0, max_wildcard_expansions: 50, snippet_max_read_bytes: 256 * 1024, feedback_log: None, load_doc_id_map: true, max_batch_queries: 64, compression: true, compression_min_bytes: 1024, cors_allow_origins: Vec::new(), cors_max_age_s: None, cors_expose_headers: Vec::new(), max_body_bytes: 1024 * 1024, max_ingest_body_bytes: 64 * 1024 * 1024 }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            admin_token: std::env::var("ADMIN_TOKEN").ok(),
+            max_k: env_parse("MAX_K").filter(|&k| k > 0).unwrap_or(default.max_k),
+            search_cache_max_age_s: env_parse("SEARCH_CACHE_MAX_AGE").unwrap_or(default.search_cache_max_age_s),
+            health_sample_terms: env_parse("HEALTH_SAMPLE_TERMS").unwrap_or(default.health_sample_terms),
+            health_max_failed_fraction: env_parse("HEALTH_MAX_FAILED_FRACTION").unwrap_or(default.health_max_failed_fraction),
+            max_wildcard_expansions: env_parse("MAX_WILDCARD_EXPANSIONS").unwrap_or(default.max_wildcard_expansions),
+            snippet_max_read_bytes: env_parse("SNIPPET_MAX_READ_BYTES").unwrap_or(default.snippet_max_read_bytes),
+            feedback_log: std::env::var_os("FEEDBACK_LOG").filter(|p| !p.is_empty()).map(PathBuf::from),
+            load_doc_id_map: env_parse("LOAD_DOC_ID_MAP").unwrap_or(default.load_doc_id_map),
+            max_batch_queries: env_parse("MAX_BATCH_QUERIES").unwrap_or(default.max_batch_queries),
+            compression: env_parse("COMPRESSION").unwrap_or(default.compression),
+            compression_min_bytes: env_parse("COMPRESSION_MIN_BYTES").unwrap_or(default.compression_min_bytes),
+            cors_allow_origins: env_list("CORS_ALLOW_ORIGIN"),
+            cors_max_age_s: env_parse("CORS_MAX_AGE"),
+            cors_expose_headers: env_list("CORS_EXPOSE_HEADERS"),
+            max_body_bytes: env_parse("MAX_BODY_BYTES").unwrap_or(default.max_body_bytes),
+            max_ingest_body_bytes: env_parse("MAX_INGEST_BODY_BYTES").unwrap_or(default.max_ingest_body_bytes),
+        }
+    }
 }
-fn default_k() -> usize { 10 }
 
-#[derive(Serialize)]
-pub struct SearchResponse {
-    pub query: String,
-    pub took_ms: u128, // deprecated, kept for backward compatibility
-    pub took_s: f64,
-    pub total_hits: usize,
-    pub results: Vec<SearchHit>,
+/// Parse a comma-separated environment variable, warning about (and skipping) unparsable items.
+fn env_list<T: std::str::FromStr>(name: &str) -> Vec<T> {
+    let Ok(raw) = std::env::var(name) else { return Vec::new() };
+    raw.split(',').map(str::trim).filter(|item| !item.is_empty()).filter_map(|item| {
+        let parsed = item.parse().ok();
+        if parsed.is_none() { tracing::warn!(name, value = %item, "ignoring invalid environment value"); }
+        parsed
+    }).collect()
 }
 
-#[derive(Serialize)]
-pub struct SearchHit {
-    pub doc_id: u32,
-    pub score: f32,
-    pub title: String,
-    pub url: Option<String>,
-    pub snippet: Option<String>,
+/// Parse an environment variable, warning about (and ignoring) unparsable values.
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let raw = std::env::var(name).ok()?;
+    let parsed = raw.trim().parse().ok();
+    if parsed.is_none() { tracing::warn!(name, value = %raw, "ignoring invalid environment value"); }
+    parsed
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub index_paths_root: PathBuf,
-    pub dictionary: HashMap<String, TermId>,
-    pub df: Vec<u32>,
-    pub docs: HashMap<DocId, DocMeta>,
-    pub num_docs: u32,
-    pub admin_token: Option<String>,
+    /// The served index; handlers take a snapshot via [`AppState::index`].
+    pub index: Arc<RwLock<Arc<LoadedIndex>>>,
+    pub config: Arc<ServerConfig>,
+    pub reindex: Arc<Mutex<ReindexStatus>>,
+    /// Last `/index/commit` and its reweight pass. Locked before `reindex` when both are needed.
+    pub commit: Arc<Mutex<CommitStatus>>,
+    pub metrics: Arc<Metrics>,
+    /// The index path the server was started with; [`Searcher::reload`] loads it again, so a
+    /// repointed symlink takes effect.
+    pub index_dir: PathBuf,
+    /// Queue to the feedback log writer, when `FEEDBACK_LOG` is set.
+    pub feedback: Option<Arc<FeedbackLog>>,
+    /// Documents buffered since the last commit. Replaced (never mutated in place) while
+    /// holding the `index` write lock, so [`AppState::index_state`] sees a matching pair.
+    pub delta: Arc<RwLock<Arc<DeltaSegment>>>,
+    /// Roots of the loaded snapshots, so a snapshot loaded again from the same directory shares
+    /// its [`IndexRoot`] and moves with the others when a reindex retires it.
+    roots: Arc<Mutex<Vec<std::sync::Weak<IndexRoot>>>>,
+}
+
+impl AppState {
+    /// Snapshot of the currently served index.
+    pub fn index(&self) -> Arc<LoadedIndex> { self.index.read().clone() }
+
+    /// `index`, with the root of any snapshot still loaded from the same directory.
+    fn share_root(&self, mut index: LoadedIndex) -> LoadedIndex {
+        let mut roots = self.roots.lock();
+        roots.retain(|root| root.strong_count() > 0);
+        let path = index.root.path();
+        match roots.iter().filter_map(std::sync::Weak::upgrade).find(|root| root.path() == path) {
+            Some(root) => index.root = root,
+            None => roots.push(Arc::downgrade(&index.root)),
+        }
+        index
+    }
+
+    /// Snapshot of the served index together with its buffered delta, for scoring.
+    pub fn index_state(&self) -> IndexState {
+        let index = self.index.read();
+        IndexState::new(index.clone(), self.delta.read().clone())
+    }
 }
 
 pub fn build_app(index_dir: String) -> Result<Router> {
-    // Load index header at startup
-    let index_paths = IndexPaths::new(&index_dir);
-    let (dictionary, df, docs, meta) = load_index_header(&index_paths)?;
-    let admin_token = std::env::var("ADMIN_TOKEN").ok();
-    let app_state = AppState { index_paths_root: PathBuf::from(&index_dir), dictionary, df, docs, num_docs: meta.num_docs, admin_token };
-
-    // CORS: read CORS_ALLOW_ORIGIN (comma-separated) or allow Any by default
-    let cors = match std::env::var("CORS_ALLOW_ORIGIN") {
-        Ok(val) => {
-            let origins: Vec<_> = val
-                .split(',')
-                .filter_map(|s| s.trim().parse().ok())
-                .collect();
-            if origins.is_empty() {
-                CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any)
-            } else {
-                CorsLayer::new().allow_origin(AllowOrigin::list(origins)).allow_methods(Any).allow_headers(Any)
-            }
+    build_app_with_config(index_dir, ServerConfig::from_env())
+}
+
+pub fn build_app_with_config(index_dir: String, config: ServerConfig) -> Result<Router> {
+    Ok(Searcher::with_config(&index_dir, config)?.router())
+}
+
+/// Search an index in-process, without HTTP or JSON. The `/search` handlers are thin
+/// wrappers over it, so embedded and served searches behave identically.
+#[derive(Clone)]
+pub struct Searcher {
+    state: AppState,
+}
+
+impl Searcher {
+    /// Load the index at `index_dir` with settings from the environment, as the server does.
+    pub fn open(index_dir: &str) -> Result<Self> {
+        Self::with_config(index_dir, ServerConfig::from_env())
+    }
+
+    pub fn with_config(index_dir: &str, config: ServerConfig) -> Result<Self> {
+        let loaded = LoadedIndex::load(index_dir, config.load_doc_id_map)?;
+        let feedback = config.feedback_log.as_deref().map(FeedbackLog::open).transpose()?.map(Arc::new);
+        let roots = vec![Arc::downgrade(&loaded.root)];
+        let state = AppState {
+            index: Arc::new(RwLock::new(Arc::new(loaded))),
+            config: Arc::new(config),
+            reindex: Arc::new(Mutex::new(ReindexStatus::default())),
+            commit: Arc::new(Mutex::new(CommitStatus::default())),
+            metrics: Arc::new(Metrics::default()),
+            index_dir: PathBuf::from(index_dir),
+            feedback,
+            delta: Arc::new(RwLock::new(Arc::new(DeltaSegment::default()))),
+            roots: Arc::new(Mutex::new(roots)),
+        };
+        Ok(Self { state })
+    }
+
+    /// The top `k` hits for `query`; the other search options come from `opts` (its `q` and `k` are ignored).
+    pub fn search(&self, query: &str, k: usize, opts: SearchParams) -> Result<SearchResponse, (StatusCode, String)> {
+        self.search_params(SearchParams { q: query.to_string(), k, ..opts })
+    }
+
+    /// Run a search described entirely by `params`, as `POST /search` does.
+    pub fn search_params(&self, params: SearchParams) -> Result<SearchResponse, (StatusCode, String)> {
+        search(&self.state, &self.state.index_state(), params)
+    }
+
+    /// Run several searches against one index snapshot, as `POST /search/batch` does.
+    /// Responses are in the order of `batch`.
+    pub fn search_batch(&self, batch: Vec<SearchParams>) -> Result<Vec<SearchResponse>, (StatusCode, String)> {
+        search_batch(&self.state, &self.state.index_state(), batch)
+    }
+
+    /// Snapshot of the loaded index (document metadata, dictionary, stored texts).
+    pub fn index(&self) -> Arc<LoadedIndex> { self.state.index() }
+
+    /// Load the index at the configured path again and serve it, e.g. after `indexer build
+    /// --swap-symlink` repointed it. Searches already running finish on the old index. Refused while
+    /// a reindex runs or documents are buffered, and a failed load keeps the old index.
+    pub fn reload(&self) -> Result<Arc<LoadedIndex>> {
+        if self.state.reindex.lock().state == ReindexState::Running {
+            anyhow::bail!("a reindex is running; reload once it has finished");
         }
-        Err(_) => CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any),
+        if self.state.commit.lock().state == ReindexState::Running {
+            anyhow::bail!("a commit is running; reload once it has finished");
+        }
+        let index_dir = self.state.index_dir.to_string_lossy();
+        let loaded = Arc::new(self.state.share_root(LoadedIndex::load(&index_dir, self.state.config.load_doc_id_map)?));
+        let mut served = self.state.index.write();
+        let buffered = self.state.delta.read().num_docs;
+        if buffered > 0 {
+            anyhow::bail!("{buffered} buffered document(s) were added against the current index; commit them before reloading");
+        }
+        *served = loaded.clone();
+        Ok(loaded)
+    }
+
+    /// Search, document and admin routes over this searcher's index.
+    pub fn router(self) -> Router {
+        build_router(self.state)
+    }
+}
+
+fn build_router(app_state: AppState) -> Router {
+    let config = &app_state.config;
+    let cors = CorsLayer::new().allow_methods(Any).allow_headers(Any);
+    let cors = if config.cors_allow_origins.is_empty() {
+        cors.allow_origin(Any)
+    } else {
+        cors.allow_origin(AllowOrigin::list(config.cors_allow_origins.iter().cloned()))
+    };
+    // Browsers only show scripts the response headers CORS exposes
+    let exposed: Vec<HeaderName> = [X_INDEX_VERSION, X_INDEX_CREATED_AT].into_iter().chain(config.cors_expose_headers.iter().cloned()).collect();
+    let cors = cors.expose_headers(exposed);
+    let cors = match config.cors_max_age_s {
+        Some(secs) => cors.max_age(std::time::Duration::from_secs(secs)),
+        None => cors,
     };
+    // Responses that already carry a Content-Encoding are passed through untouched by the layer;
+    // tiny ones (/health, 404s) aren't worth the CPU or the extra header bytes.
+    let compression = CompressionLayer::new()
+        .gzip(config.compression)
+        .br(config.compression)
+        .compress_when(SizeAbove::new(config.compression_min_bytes).and(NotForContentType::IMAGES).and(NotForContentType::SSE));
 
-    let app = Router::new()
-        .route("/health", get(|| async { "ok" }))
-        .route("/search", get(search_handler))
-        .route("/doc/:doc_id", get(doc_handler))
+    // Ingest routes carry documents; their own limit overrides the small default set below
+    let ingest = Router::new()
         .route("/index/batch", post(index_batch))
         .route("/index/commit", post(index_commit))
+        .route("/admin/reindex", post(admin_reindex))
+        .layer(DefaultBodyLimit::max(config.max_ingest_body_bytes));
+
+    Router::new()
+        .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/search", get(search_handler).post(search_post_handler))
+        .route("/search/batch", post(search_batch_handler))
+        .route("/doc/:doc_id", get(doc_handler))
+        .route("/doc/by-external/:external_id", get(doc_by_external_handler))
+        .route("/doc/:doc_id/raw", get(doc_raw_handler))
+        .route("/doc/:doc_id/terms", get(doc_terms_handler))
+        .route("/similar/:doc_id", get(similar_handler))
+        .route("/docs", get(list_docs_handler))
+        .route("/feedback", post(feedback_handler))
+        .route("/admin/reindex/status", get(admin_reindex_status))
+        .route("/index/commit/status", get(index_commit_status))
+        .merge(ingest)
+        .fallback(not_found_handler)
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+        .layer(middleware::from_fn_with_state(app_state.clone(), index_version_headers))
         .with_state(app_state)
-        .layer(cors);
-    Ok(app)
+        .layer(compression)
+        .layer(cors)
 }
 
-pub async fn search_handler(State(state): State<AppState>, Query(params): Query<SearchParams>) -> Json<SearchResponse> {
-    let start = std::time::Instant::now();
-    // Tokenize query and build tf map
-    let q_tokens = tokenize(&params.q);
-    let mut tf_q_raw: HashMap<TermId, u32> = HashMap::new();
-    for (term, _pos) in q_tokens {
-        if let Some(&tid) = state.dictionary.get(&term) {
-            *tf_q_raw.entry(tid).or_insert(0) += 1;
+const X_INDEX_VERSION: HeaderName = HeaderName::from_static("x-index-version");
+const X_INDEX_CREATED_AT: HeaderName = HeaderName::from_static("x-index-created-at");
+
+/// Tag every response with the index that served it, so clients can drop cached results when a
+/// restart or reindex serves a new build: `X-Index-Version` (format version) and
+/// `X-Index-Created-At` (RFC 3339 build time, when `meta.json` has one).
+async fn index_version_headers(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let index = state.index();
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(X_INDEX_VERSION, HeaderValue::from(index.version));
+    let created_at = index.created_at
+        .and_then(|t| time::OffsetDateTime::from(t).format(&time::format_description::well_known::Rfc3339).ok())
+        .and_then(|t| HeaderValue::from_str(&t).ok());
+    if let Some(v) = created_at {
+        headers.insert(X_INDEX_CREATED_AT, v);
+    }
+    response
+}
+
+pub async fn search_handler(State(state): State<AppState>, Query(params): Query<SearchParams>) -> Result<Response, (StatusCode, String)> {
+    let headers = search_cache_headers(&state.config, &state.index());
+    let response = Searcher { state }.search_params(params)?;
+    Ok((headers, Json(response)).into_response())
+}
+
+/// `POST /search`: the same search with a JSON body, for queries too long or structured for a URL.
+pub async fn search_post_handler(State(state): State<AppState>, Json(params): Json<SearchParams>) -> Result<Json<SearchResponse>, (StatusCode, String)> {
+    Ok(Json(Searcher { state }.search_params(params)?))
+}
+
+/// `POST /search/batch`: many searches in one request, answered as an array in request order.
+pub async fn search_batch_handler(State(state): State<AppState>, Json(params): Json<BatchSearchParams>) -> Result<Json<Vec<SearchResponse>>, (StatusCode, String)> {
+    // Scoring a batch is CPU-bound and reads postings from disk, so keep it off the async workers
+    let responses = tokio::task::spawn_blocking(move || Searcher { state }.search_batch(params.queries))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("batch search task failed: {e}")))??;
+    Ok(Json(responses))
+}
+
+/// Results are stable until the index is rebuilt, so let caches keep them for a while.
+fn search_cache_headers(config: &ServerConfig, index: &LoadedIndex) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(v) = HeaderValue::from_str(&format!("public, max-age={}", config.search_cache_max_age_s)) {
+        headers.insert(header::CACHE_CONTROL, v);
+    }
+    if let Some(v) = index.created_at.and_then(|t| HeaderValue::from_str(&httpdate::fmt_http_date(t)).ok()) {
+        headers.insert(header::LAST_MODIFIED, v);
+    }
+    headers
+}
+
+/// Query tokens routed to the field channels that score them.
+struct PreparedQuery {
+    /// All query tokens, in query order.
+    q_tokens: Vec<(String, usize)>,
+    field_tokens: Vec<(Field, Vec<(String, usize)>)>,
+    /// Words to highlight in snippets; wildcard words keep their trailing `*`.
+    raw_terms: Vec<String>,
+    /// Wildcard expansions and raw forms: dictionary term -> the `prefix*` word or stem it counts as.
+    expanded_from: HashMap<String, String>,
+    /// Time spent parsing and tokenizing `q`.
+    tokenize_time: std::time::Duration,
+}
+
+impl PreparedQuery {
+    /// Route tokens to fields: `field:term` clauses to that field, the rest to every requested field.
+    /// A word ending in `*` expands to at most `max_expansions` dictionary terms of each field,
+    /// each scored with its own idf.
+    fn parse(q: &str, fields: &[Field], index: &LoadedIndex, max_expansions: usize, case_sensitive: bool) -> Self {
+        let started = std::time::Instant::now();
+        let clauses = parse_query(q);
+        let mut q_tokens: Vec<(String, usize)> = Vec::new();
+        let mut field_tokens: Vec<(Field, Vec<(String, usize)>)> = Vec::new();
+        let mut expanded_from: HashMap<String, String> = HashMap::new();
+        for clause in &clauses {
+            let targets = match clause.field {
+                Some(field) => vec![field],
+                None => fields.to_vec(),
+            };
+            let prefix = clause.text.strip_suffix('*').filter(|p| !p.is_empty());
+            for (i, field) in targets.into_iter().map(|f| f.with_case(case_sensitive)).enumerate() {
+                let tokens: Vec<(String, usize)> = match prefix {
+                    Some(prefix) if field == Field::BodyCase => index.prefix_terms(field, prefix, max_expansions).into_iter().map(|t| (t, 0)).collect(),
+                    Some(prefix) => index.prefix_terms(field, &prefix.to_lowercase(), max_expansions).into_iter().map(|t| (t, 0)).collect(),
+                    None => tokenize_with(&clause.text, &index.field_tokenizer(field)),
+                };
+                // Plain words are the same in every field; expansions differ per field dictionary
+                for (j, (term, pos)) in tokens.iter().enumerate() {
+                    // A raw form follows its stem and only adds to that word's score, even in `mode=and`
+                    if term.starts_with(RAW_FORM_PREFIX) && j > 0 {
+                        expanded_from.insert(term.clone(), tokens[j - 1].0.clone());
+                    }
+                    if prefix.is_some() {
+                        expanded_from.insert(term.clone(), clause.text.clone());
+                        if q_tokens.iter().any(|(t, _)| t == term) { continue; }
+                    } else if i > 0 {
+                        break;
+                    }
+                    q_tokens.push((term.clone(), *pos));
+                }
+                match field_tokens.iter_mut().find(|(f, _)| *f == field) {
+                    Some((_, toks)) => toks.extend(tokens),
+                    None => field_tokens.push((field, tokens)),
+                }
+            }
         }
+        // Raw query words (without field prefixes) for highlighting
+        let raw_terms = clauses.into_iter().map(|c| c.text.to_string()).collect();
+        Self { q_tokens, field_tokens, raw_terms, expanded_from, tokenize_time: started.elapsed() }
     }
-    // Edge case: empty after filtering
-    if tf_q_raw.is_empty() {
-        let elapsed = start.elapsed();
-        return Json(SearchResponse { query: params.q, took_ms: elapsed.as_millis(), took_s: elapsed.as_secs_f64(), total_hits: 0, results: vec![] });
+
+    /// The query word `term` counts towards in `mode=and`: its wildcard, or itself.
+    fn word_of<'a>(&'a self, term: &'a str) -> &'a str {
+        self.expanded_from.get(term).map_or(term, String::as_str)
     }
+}
 
-    // Compute normalized query weights
-    let n = state.num_docs.max(1);
-    let mut q_weights: HashMap<TermId, f32> = HashMap::new();
-    for (tid, tf_raw) in tf_q_raw.iter() {
-        let tf = if *tf_raw > 0 { 1.0 + (*tf_raw as f32).ln() } else { 0.0 };
-        let df_t = *state.df.get(*tid as usize).unwrap_or(&1).max(&1);
-        let idf = ((n as f32) / (df_t as f32)).ln();
-        q_weights.insert(*tid, tf * idf);
+fn search(state: &AppState, index: &IndexState, params: SearchParams) -> Result<SearchResponse, (StatusCode, String)> {
+    let query = prepare_search(state, index, &params)?;
+    execute_search(state, index, params, query, None, None)
+}
+
+/// Check the request's fields and channel, then tokenize its query.
+fn prepare_search(state: &AppState, index: &IndexState, params: &SearchParams) -> Result<PreparedQuery, (StatusCode, String)> {
+    let fields = parse_fields(params.fields.as_deref()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if params.case_sensitive && index.case_channel.is_none() {
+        return Err((StatusCode::BAD_REQUEST, "case_sensitive=true needs an index built with --preserve-case".into()));
     }
-    let mut norm = 0.0f32;
-    for w in q_weights.values() { norm += w * w; }
-    norm = norm.sqrt();
-    if norm == 0.0 { norm = 1.0; }
-    for w in q_weights.values_mut() { *w /= norm; }
+    Ok(PreparedQuery::parse(&params.q, &fields, index, state.config.max_wildcard_expansions, params.case_sensitive))
+}
+
+/// On-disk postings read once for a whole batch, keyed by channel and term id.
+type SharedPostings = HashMap<(Field, TermId), Vec<Posting>>;
 
-    // Aggregate scores from postings
+/// Run `batch` in parallel against one snapshot. Every query is parsed first so that each
+/// distinct on-disk postings list is read once, however many queries use it.
+fn search_batch(state: &AppState, index: &IndexState, batch: Vec<SearchParams>) -> Result<Vec<SearchResponse>, (StatusCode, String)> {
+    if batch.len() > state.config.max_batch_queries {
+        return Err((StatusCode::BAD_REQUEST, format!("at most {} queries per batch", state.config.max_batch_queries)));
+    }
+    let at = |i: usize| move |(status, e): (StatusCode, String)| (status, format!("queries[{i}]: {e}"));
+    let prepared = batch
+        .into_iter()
+        .enumerate()
+        .map(|(i, params)| prepare_search(state, index, &params).map(|query| (i, params, query)).map_err(at(i)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut wanted: HashSet<(Field, TermId)> = HashSet::new();
+    for (_, _, query) in &prepared {
+        for (field, tokens) in query.field_tokens.iter().filter(|(field, _)| index.field_index(*field).is_none()) {
+            let (dictionary, _, _) = index.field_stats(*field);
+            wanted.extend(tokens.iter().filter_map(|(term, _)| dictionary.get(term).map(|&tid| (*field, tid))));
+        }
+    }
+    // A list that fails to load here is retried, counted and reported in `warnings` by the query
+    // that needs it. Both passes share rayon's pool, one thread per core however many batches run.
+    let shared: SharedPostings = wanted
+        .into_par_iter()
+        .filter_map(|(field, tid)| index.read_postings(field, tid, &state.metrics).ok().map(|p| ((field, tid), p.into_owned())))
+        .collect();
+    prepared
+        .into_par_iter()
+        .map(|(i, params, query)| execute_search(state, index, params, query, None, Some(&shared)).map_err(at(i)))
+        .collect()
+}
+
+/// Score `query` and build the response page. `exclude` drops one doc from the hits.
+/// Postings found in `shared` are used instead of reading them again.
+fn execute_search(state: &AppState, index: &IndexState, params: SearchParams, query: PreparedQuery, exclude: Option<DocId>, shared: Option<&SharedPostings>) -> Result<SearchResponse, (StatusCode, String)> {
+    let start = std::time::Instant::now();
+    let _timer = state.metrics.search_latency.start_timer();
+    Metrics::inc(&state.metrics.searches_total);
+    let boosts = parse_boosts(&params.boosts).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let hit_fields = HitFields::parse(params.hit_fields.as_deref()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let snippet_fields = SnippetField::parse_list(params.snippet_fields.as_deref()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    // Body weights of `--store-doc-norms` indexes are raw tf-idf until divided by the doc's norm
+    let body_norms = match (index.doc_norms.as_deref(), params.normalize_docs) {
+        (None, Some(false)) => return Err((StatusCode::BAD_REQUEST, "normalize_docs=false needs an index built with --store-doc-norms".into())),
+        (norms, normalize) => norms.filter(|_| normalize != Some(false)),
+    };
+    let mut doc_debug = match params.debug_doc {
+        Some(doc_id) => {
+            let meta = index.docs.get(&doc_id).ok_or_else(|| (StatusCode::BAD_REQUEST, format!("unknown debug_doc {doc_id}")))?;
+            Some(DocDebug { doc_id, external_id: meta.external_id.clone(), score: 0.0, rank: None, contributions: Vec::new() })
+        }
+        None => None,
+    };
+    let PreparedQuery { q_tokens, field_tokens, raw_terms, .. } = &query;
+    let mut timings = Timings { tokenize_s: query.tokenize_time.as_secs_f64(), ..Default::default() };
+    let mut phase = std::time::Instant::now();
+    // Add the time since the last call to `bucket`
+    let mut lap = |bucket: &mut f64| {
+        let now = std::time::Instant::now();
+        *bucket += (now - phase).as_secs_f64();
+        phase = now;
+    };
+
+    // Aggregate boosted scores from each field channel
     let mut scores: HashMap<DocId, f32> = HashMap::new();
-    let paths = IndexPaths::new(&state.index_paths_root);
-    for (tid, q_w) in q_weights.iter() {
-        if let Ok(postings) = load_postings_for_term(&paths, *tid) {
-            for p in postings {
-                let contrib = p.weight * *q_w; // cosine since doc weights are normalized
-                *scores.entry(p.doc_id).or_insert(0.0) += contrib;
+    let mut matched: HashSet<&str> = HashSet::new();
+    // Distinct query terms each doc matched, for `mode=and`
+    let mut doc_terms: HashMap<DocId, HashSet<&str>> = HashMap::new();
+    // Fields each doc matched in, one `MatchedFields` bit per field, for `matched_fields=true`
+    let mut doc_fields: HashMap<DocId, MatchedFields> = HashMap::new();
+    // Data that couldn't be read; the response is still built from the rest
+    let mut warnings: Vec<String> = Vec::new();
+    let mut parsed = params.debug.then(|| ParsedQuery { raw: params.q.clone(), fields: Vec::new() });
+    // Browsing lists every doc, unscored; filters still apply
+    let browsing = params.browse && params.q.trim().is_empty();
+    if browsing { scores = index.doc_ids.iter().map(|&doc_id| (doc_id, 0.0)).collect(); }
+    lap(&mut timings.scoring_s);
+    for (field, tokens) in field_tokens {
+        // Fields the request's `boosts` don't name count 1.0
+        let boost = boosts.get(&field.named()).copied().unwrap_or(1.0);
+        // Committed plus buffered counts, so idf matches everything a search can see
+        let stats = index.effective_stats(*field);
+        let dictionary = stats.dictionary;
+        let tf_q_raw = query_term_counts(tokens, dictionary, &mut matched);
+        let df: HashMap<TermId, u32> = tokens.iter().filter_map(|(t, _)| stats.df(t)).collect();
+        let q_weights = query_weights(&tf_q_raw, &df, stats.num_docs, index.weighting);
+        let term_of: HashMap<TermId, &str> = tokens.iter().filter_map(|(t, _)| dictionary.get(t).map(|&id| (id, query.word_of(t)))).collect();
+        let norms = body_norms.filter(|_| *field == Field::Body);
+        let doc_weight = |p: &Posting| norms.and_then(|norms| norms.get(p.doc_id as usize)).map_or(p.weight, |norm| p.weight / norm);
+        lap(&mut timings.query_weights_s);
+        if let Some(parsed) = parsed.as_mut() {
+            let mut seen: HashSet<&str> = HashSet::new();
+            let terms = tokens.iter().filter(|(t, _)| seen.insert(t)).map(|(term, _)| {
+                let term_id = dictionary.get(term).copied();
+                ParsedTerm {
+                    term: term.clone(),
+                    term_id,
+                    df: term_id.and_then(|tid| df.get(&tid).copied()),
+                    weight: term_id.and_then(|tid| q_weights.get(&tid).copied()),
+                    expanded_from: query.expanded_from.get(term).cloned(),
+                }
+            });
+            parsed.fields.push(ParsedField { field: field.name().into(), boost, terms: terms.collect() });
+        }
+        for (tid, q_w) in q_weights.iter() {
+            let postings = match shared.and_then(|shared| shared.get(&(*field, *tid))) {
+                Some(postings) => Ok(Cow::Borrowed(postings.as_slice())),
+                None => index.postings(*field, *tid, &state.metrics),
+            };
+            lap(&mut timings.postings_s);
+            let postings = match postings {
+                Ok(postings) => postings,
+                Err(e) => {
+                    push_warning(&mut warnings, postings_warning(term_of[tid], *field, &e));
+                    continue;
+                }
+            };
+            for p in postings.iter() {
+                let contrib = doc_weight(p) * *q_w; // cosine when doc weights are normalized
+                *scores.entry(p.doc_id).or_insert(0.0) += contrib * boost;
+                if params.mode == Mode::And { doc_terms.entry(p.doc_id).or_default().insert(term_of[tid]); }
+                if params.matched_fields { doc_fields.entry(p.doc_id).or_default().insert(*field); }
+            }
+            if let Some(target) = doc_debug.as_mut() {
+                if let Some(p) = postings.iter().find(|p| p.doc_id == target.doc_id) {
+                    let term = tokens.iter().map(|(t, _)| t).find(|t| dictionary.get(*t) == Some(tid)).cloned().unwrap_or_default();
+                    let contribution = doc_weight(p) * *q_w * boost;
+                    target.score += contribution;
+                    target.contributions.push(TermContribution { field: field.name().into(), term, query_weight: *q_w, doc_weight: doc_weight(p), boost, contribution });
+                }
             }
+            lap(&mut timings.scoring_s);
         }
     }
+    // A word no searched field has can't be satisfied, so `and` matches nothing; `or` drops it
+    if params.mode == Mode::And && !browsing {
+        let required: HashSet<&str> = q_tokens.iter().map(|(t, _)| query.word_of(t)).collect();
+        scores.retain(|doc_id, _| doc_terms.get(doc_id).is_some_and(|terms| terms.len() == required.len()));
+    }
+    let filters: Vec<&str> = params.filters.iter().map(String::as_str).chain(params.filter.as_deref()).collect();
+    if !filters.is_empty() {
+        let allowed = filter_docs(state, index, &filters, &mut warnings);
+        scores.retain(|doc_id, _| allowed.as_ref().is_none_or(|ids| ids.contains(doc_id)));
+    }
+    if let Some(doc_id) = exclude { scores.remove(&doc_id); }
+    // Only postings failures so far: the hits may be missing matches
+    let degraded = !warnings.is_empty();
+    let mut response = SearchResponse { degraded, complete: !degraded, warnings, k_clamped: params.k > state.config.max_k, parsed, ..Default::default() };
+    let (hit, miss) = split_matched_terms(q_tokens, &matched);
+    if params.debug {
+        response.corpus_size = Some(index.num_docs);
+        response.matched_terms = Some(hit);
+        response.unmatched_terms = Some(miss);
+    } else if !miss.is_empty() {
+        response.unmatched_terms = Some(miss);
+    }
+    // Edge case: empty after filtering
+    if matched.is_empty() && !browsing {
+        Metrics::inc(&state.metrics.zero_result_searches_total);
+        lap(&mut timings.scoring_s);
+        response.timings = params.profile.then_some(timings);
+        response.doc_debug = doc_debug;
+        let elapsed = start.elapsed();
+        return Ok(SearchResponse { query: params.q, took_ms: elapsed.as_millis(), took_s: elapsed.as_secs_f64(), ..response });
+    }
 
     let mut scored: Vec<(DocId, f32)> = scores.into_iter().collect();
-    let k = params.k.max(1).min(100);
-    // partial sort for top-k
-    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let k = params.k.clamp(1, state.config.max_k);
+    if browsing {
+        // Newest first; undated docs last, in doc_id order
+        scored.sort_by_key(|&(doc_id, _)| (std::cmp::Reverse(index.timestamps.get(&doc_id).copied()), doc_id));
+    } else {
+        // partial sort for top-k
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+        if params.proximity {
+            let boost = params.proximity_boost.unwrap_or(proximity::DEFAULT_BOOST);
+            if !boost.is_finite() || boost < 0.0 { return Err((StatusCode::BAD_REQUEST, "proximity_boost must be a non-negative number".into())); }
+            let word_of: HashMap<String, &str> = q_tokens.iter().map(|(t, _)| (t.clone(), query.word_of(t))).collect();
+            let slop = params.slop.unwrap_or(proximity::DEFAULT_SLOP);
+            proximity::rerank(index, &mut scored, &word_of, slop, boost, params.offset.saturating_add(k), state.config.snippet_max_read_bytes);
+        }
+    }
+    // Relative to the overall best hit, not the page's, so scores stay comparable across pages
+    let score_scale = match scored.first() {
+        Some(&(_, top)) if params.normalize_scores && top > 0.0 => 1.0 / top,
+        _ => 1.0,
+    };
+    let min_score = params.min_score.filter(|_| !browsing);
+    if min_score.is_some_and(|min| !min.is_finite()) { return Err((StatusCode::BAD_REQUEST, "min_score must be a finite number".into())); }
+    // Before paging, so `total_hits` and every page only count hits above the bar
+    if let (Some(min_score), MinScoreMode::BeforePaging) = (min_score, params.min_score_mode) {
+        let cut = scored.partition_point(|&(_, score)| score * score_scale >= min_score);
+        scored.truncate(cut);
+    }
+    if params.dedup_titles {
+        response.titles_collapsed = Some(dedup::collapse_same_titles(index, &mut scored));
+    }
+    if params.dedup {
+        let collapsed = dedup::collapse_duplicates(index, &mut scored, params.offset.saturating_add(k), state.config.snippet_max_read_bytes);
+        response.duplicates_collapsed = Some(collapsed);
+    }
     let total_hits = scored.len();
-    let topk = scored.into_iter().take(k);
+    if total_hits == 0 { Metrics::inc(&state.metrics.zero_result_searches_total); }
+    if let Some(target) = doc_debug.as_mut() {
+        target.rank = scored.iter().position(|&(doc_id, _)| doc_id == target.doc_id).map(|i| i + 1);
+        tracing::info!(doc_id = target.doc_id, score = target.score, rank = ?target.rank, terms = target.contributions.len(), "debug_doc");
+    }
+    response.doc_debug = doc_debug;
+    let page_min = min_score.filter(|_| params.min_score_mode == MinScoreMode::PageOnly);
+    let topk = scored.into_iter().skip(params.offset).take(k).filter(|&(_, score)| page_min.is_none_or(|min| score * score_scale >= min));
+    lap(&mut timings.scoring_s);
 
     // Build results with snippets
     let mut results: Vec<SearchHit> = Vec::new();
-    // Capture raw query terms for highlighting
-    let raw_terms: Vec<String> = params
-        .q
-        .split_whitespace()
-        .map(|s| s.to_string())
-        .collect();
+    let highlight = HighlightOptions::from_params(params.highlight_pre, params.highlight_post, params.whole_word);
     for (doc_id, score) in topk {
-        if let Some(meta) = state.docs.get(&doc_id) {
-            let snippet = meta
-                .text_path
-                .as_ref()
-                .and_then(|rel| snippet_from_file(&state.index_paths_root.join(rel), &raw_terms));
-            results.push(SearchHit { doc_id, score, title: meta.title.clone(), url: meta.url.clone(), snippet });
+        if let Some(meta) = index.docs.get(&doc_id) {
+            // Lean clients that skip snippets skip the text reads too
+            let snippet = if hit_fields.needs_snippet() {
+                // Only read the body when it is a candidate or the fallback may need it
+                let reads_body = snippet_fields.contains(&SnippetField::Body) || params.snippet_fallback != SnippetFallback::None;
+                let text = if reads_body { index.doc_text_head(doc_id, state.config.snippet_max_read_bytes) } else { None };
+                if reads_body && text.is_none() && meta.text_path.is_some() {
+                    push_warning(&mut response.warnings, format!("stored text of doc {doc_id} could not be read; its snippet is missing"));
+                }
+                let description = index.descriptions.get(&doc_id).map(String::as_str);
+                let candidates = snippet_fields.iter().filter_map(|&field| Some((field, match field {
+                    SnippetField::Body => text.as_deref()?,
+                    SnippetField::Title => meta.title.as_str(),
+                    SnippetField::Description => description?,
+                })));
+                let lead = || text.as_deref().and_then(|text| lead_snippet(text, raw_terms, &highlight)).map(|s| (SnippetField::Body, s));
+                best_match_snippet(candidates, raw_terms, &highlight).or_else(|| match params.snippet_fallback {
+                    SnippetFallback::None => None,
+                    SnippetFallback::Lead => lead(),
+                    SnippetFallback::Meta => description.and_then(|d| lead_snippet(d, raw_terms, &highlight)).map(|s| (SnippetField::Description, s)).or_else(lead),
+                })
+            } else {
+                None
+            };
+            let matches = params.highlight_spans.then(|| snippet.as_ref().map(|(_, s)| s.matches.clone()).unwrap_or_default());
+            let snippet_field = params.snippet_fields.as_ref().and(snippet.as_ref()).map(|(field, _)| field.name().into());
+            let snippet = snippet.map(|(_, s)| s.text);
+            let matched_fields = params.matched_fields.then(|| doc_fields.get(&doc_id).map(MatchedFields::names).unwrap_or_default());
+            results.push(SearchHit { doc_id, external_id: meta.external_id.clone(), score: score * score_scale, title: meta.title.clone(), url: meta.url.clone(), snippet, matches, matched_fields, snippet_field, fields: hit_fields });
         }
     }
+    lap(&mut timings.snippets_s);
+    response.complete = response.warnings.is_empty();
+    response.timings = params.profile.then_some(timings);
 
     let elapsed = start.elapsed();
-    Json(SearchResponse { query: params.q, took_ms: elapsed.as_millis(), took_s: elapsed.as_secs_f64(), total_hits, results })
+    Ok(SearchResponse { query: params.q, took_ms: elapsed.as_millis(), took_s: elapsed.as_secs_f64(), total_hits, results, ..response })
+}
+
+/// A `warnings` entry for postings of `term` in `field` that failed to load with `error`.
+fn postings_warning(term: &str, field: Field, error: &anyhow::Error) -> String {
+    let missing = error.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound);
+    if missing {
+        format!("postings for term '{term}' in {} are missing", field.name())
+    } else {
+        format!("postings for term '{term}' in {} are unreadable: {error}", field.name())
+    }
+}
+
+/// Add `warning` unless the response already has it (a term can fail in scoring and in a filter).
+fn push_warning(warnings: &mut Vec<String>, warning: String) {
+    if !warnings.contains(&warning) { warnings.push(warning); }
+}
+
+/// The named fields one document matched in, as a bit set.
+#[derive(Debug, Default, Clone, Copy)]
+struct MatchedFields(u8);
+
+impl MatchedFields {
+    /// Reporting order; the case-sensitive channel reports as `body`.
+    const ORDER: [Field; 3] = [Field::Title, Field::Body, Field::Url];
+
+    fn insert(&mut self, field: Field) {
+        if let Some(i) = Self::ORDER.iter().position(|&f| f == field.named()) { self.0 |= 1 << i; }
+    }
+
+    fn names(&self) -> Vec<Cow<'static, str>> {
+        Self::ORDER.iter().enumerate().filter(|(i, _)| self.0 & (1 << i) != 0).map(|(_, f)| f.name().into()).collect()
+    }
+}
+
+/// "More like this": search the body with the source doc's top tf-idf terms, excluding the doc itself.
+/// Uses the stored term vector when the index has one, otherwise re-tokenizes the stored text.
+pub async fn similar_handler(State(state): State<AppState>, Path(doc_id): Path<u32>, Query(params): Query<SimilarParams>) -> Result<Json<SearchResponse>, JsonError> {
+    let index = state.index_state();
+    if !index.docs.contains_key(&doc_id) {
+        return Err(JsonError(StatusCode::NOT_FOUND, "not found".into()));
+    }
+    let n = params.terms.clamp(1, 50);
+    let seeds = match index.term_vectors.as_ref().and_then(|tv| tv.get(doc_id)) {
+        Some(vector) => index.term_names(vector.iter().take(n).map(|(tid, _)| *tid)).into_iter().map(|(_, term)| term).collect(),
+        None => {
+            let text = index.doc_text(doc_id).ok_or((StatusCode::NOT_FOUND, "document text is not stored".to_string()))?;
+            top_terms(&index, &text, n)
+        }
+    };
+    let q_tokens: Vec<(String, usize)> = seeds.iter().cloned().zip(0..).collect();
+    let query = PreparedQuery { field_tokens: vec![(Field::Body, q_tokens.clone())], q_tokens, raw_terms: Vec::new(), expanded_from: HashMap::new(), tokenize_time: Default::default() };
+    let search_params = SearchParams { q: seeds.join(" "), k: params.k, ..Default::default() };
+    Ok(Json(execute_search(&state, &index, search_params, query, Some(doc_id), None)?))
+}
+
+/// The `n` terms of `text` with the highest tf-idf body weight (under the index's schemes), best first.
+fn top_terms(index: &IndexState, text: &str, n: usize) -> Vec<String> {
+    let mut tf: HashMap<String, u32> = HashMap::new();
+    for (term, _pos) in tokenize_with(text, &index.tokenizer) {
+        *tf.entry(term).or_insert(0) += 1;
+    }
+    let max_tf = tf.values().copied().max().unwrap_or(0);
+    let stats = index.effective_stats(Field::Body);
+    let mut weighted: Vec<(String, f32)> = tf
+        .into_iter()
+        .filter_map(|(term, tf)| {
+            let (_, df) = stats.df(&term)?;
+            Some((term, index.weighting.weight(tf, max_tf, stats.num_docs, df)))
+        })
+        .collect();
+    weighted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+    weighted.into_iter().take(n).map(|(term, _)| term).collect()
 }
 
-pub async fn doc_handler(State(state): State<AppState>, Path(doc_id): Path<u32>) -> Json<serde_json::Value> {
-    if let Some(meta) = state.docs.get(&doc_id) {
-        let mut obj = serde_json::json!({
-            "doc_id": doc_id,
-            "title": meta.title,
-            "url": meta.url,
+/// Docs satisfying every filter (`field:term`, `facet:value` or plain `term` for the body), or
+/// `None` when no filter produced any tokens. Each token of a filter must occur in its field.
+/// Postings that could not be loaded are reported in `warnings` and match nothing.
+fn filter_docs(state: &AppState, index: &LoadedIndex, filters: &[&str], warnings: &mut Vec<String>) -> Option<HashSet<DocId>> {
+    let mut allowed: Option<HashSet<DocId>> = None;
+    let facet = |filter: &str| {
+        let (name, value) = filter.split_once(':')?;
+        let name = name.trim().to_lowercase();
+        index.facet_fields.contains(&name).then(|| (name, value.trim().to_lowercase()))
+    };
+    for (name, value) in filters.iter().filter_map(|f| facet(f)) {
+        let docs: HashSet<DocId> = index.docs.iter()
+            .filter(|(_, m)| m.facets.get(&name).is_some_and(|values| values.iter().any(|v| v.to_lowercase() == value)))
+            .map(|(doc_id, _)| *doc_id)
+            .collect();
+        allowed = Some(match allowed {
+            Some(prev) => prev.intersection(&docs).copied().collect(),
+            None => docs,
         });
-        if let Some(rel) = &meta.text_path {
-            if let Ok(text) = std::fs::read_to_string(state.index_paths_root.join(rel)) {
-                obj["text"] = serde_json::Value::String(text);
+    }
+    for clause in filters.iter().filter(|f| facet(f).is_none()).flat_map(|f| parse_query(f)) {
+        let field = clause.field.unwrap_or(Field::Body);
+        let (dictionary, _, _) = index.field_stats(field);
+        for (term, _pos) in tokenize_with(&clause.text, &index.tokenizer) {
+            let docs: HashSet<DocId> = match dictionary.get(&term).map(|tid| index.postings(field, *tid, &state.metrics)) {
+                Some(Ok(postings)) => postings.iter().map(|p| p.doc_id).collect(),
+                Some(Err(e)) => {
+                    push_warning(warnings, postings_warning(&term, field, &e));
+                    HashSet::new()
+                }
+                None => HashSet::new(),
+            };
+            allowed = Some(match allowed {
+                Some(prev) => prev.intersection(&docs).copied().collect(),
+                None => docs,
+            });
+        }
+    }
+    allowed
+}
+
+/// Count query tokens that exist in `dictionary`, keyed by term id, recording hits in `matched`.
+fn query_term_counts<'q>(q_tokens: &'q [(String, usize)], dictionary: &HashMap<String, TermId>, matched: &mut HashSet<&'q str>) -> HashMap<TermId, u32> {
+    let mut tf_q_raw: HashMap<TermId, u32> = HashMap::new();
+    for (term, _pos) in q_tokens {
+        if let Some(&tid) = dictionary.get(term) {
+            *tf_q_raw.entry(tid).or_insert(0) += 1;
+            matched.insert(term);
+        }
+    }
+    tf_q_raw
+}
+
+/// Split distinct query tokens, in query order, into (matched, unmatched).
+fn split_matched_terms(q_tokens: &[(String, usize)], matched: &HashSet<&str>) -> (Vec<String>, Vec<String>) {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let (mut hit, mut miss) = (Vec::new(), Vec::new());
+    for (term, _pos) in q_tokens {
+        if !seen.insert(term) { continue; }
+        if matched.contains(term.as_str()) { hit.push(term.clone()); } else { miss.push(term.clone()); }
+    }
+    (hit, miss)
+}
+
+/// Compute L2-normalized query weights `tf * idf` with the same schemes as the postings; for
+/// augmented tf the query's most repeated term plays the role of `max_tf`.
+fn query_weights(tf_q_raw: &HashMap<TermId, u32>, df: &HashMap<TermId, u32>, num_docs: u32, weighting: Weighting) -> HashMap<TermId, f32> {
+    let max_tf = tf_q_raw.values().copied().max().unwrap_or(0);
+    let mut q_weights: HashMap<TermId, f32> = HashMap::new();
+    for (tid, tf_raw) in tf_q_raw.iter() {
+        let df_t = df.get(tid).copied().unwrap_or(1);
+        q_weights.insert(*tid, weighting.weight(*tf_raw, max_tf, num_docs, df_t));
+    }
+    let mut norm = 0.0f32;
+    for w in q_weights.values() { norm += w * w; }
+    norm = norm.sqrt();
+    if norm == 0.0 {
+        // Every query term has idf 0 (e.g. occurs in every doc); rank by document weight alone.
+        for (tid, tf_raw) in tf_q_raw.iter() {
+            q_weights.insert(*tid, weighting.tf.weight(*tf_raw, max_tf));
+        }
+        norm = q_weights.values().map(|w| w * w).sum::<f32>().sqrt();
+    }
+    if norm == 0.0 { norm = 1.0; }
+    for w in q_weights.values_mut() { *w /= norm; }
+    q_weights
+}
+
+pub async fn doc_handler(State(state): State<AppState>, Path(doc_id): Path<u32>, Query(params): Query<DocParams>, headers: HeaderMap) -> Response {
+    let _timer = state.metrics.doc_latency.start_timer();
+    let index = state.index();
+    doc_response(&index, doc_id, params, &headers).await
+}
+
+/// `/doc/{id}` for the document indexed under the input id `external_id`.
+pub async fn doc_by_external_handler(State(state): State<AppState>, Path(external_id): Path<String>, Query(params): Query<DocParams>, headers: HeaderMap) -> Response {
+    let _timer = state.metrics.doc_latency.start_timer();
+    let index = state.index();
+    match index.resolve_external_id(&external_id) {
+        Some(doc_id) => doc_response(&index, doc_id, params, &headers).await,
+        None => not_found_handler().await.into_response(),
+    }
+}
+
+async fn doc_response(index: &LoadedIndex, doc_id: DocId, params: DocParams, headers: &HeaderMap) -> Response {
+    if let Some(meta) = index.docs.get(&doc_id) {
+        let mut doc = DocResponse {
+            doc_id,
+            external_id: meta.external_id.clone(),
+            title: meta.title.clone(),
+            url: meta.url.clone(),
+            text_available: false,
+            text: None,
+            truncated: None,
+            // Stored text may be only the head of the indexed body
+            stored_text_limit: index.snippet_store_bytes,
+        };
+        match params.max_chars {
+            Some(max_chars) => {
+                if let Some((text, truncated)) = index.doc_text_prefix(doc_id, max_chars) {
+                    doc.text = Some(text);
+                    doc.truncated = Some(truncated);
+                    doc.text_available = true;
+                }
+            }
+            None => {
+                if let Some(text) = index.doc_text(doc_id) {
+                    doc.text = Some(text.into_owned());
+                    doc.text_available = true;
+                }
             }
         }
-        return Json(obj);
+        // ETag over the exact bytes served, so it changes with the stored content and `max_chars`
+        let body = serde_json::to_vec(&doc).unwrap_or_default();
+        let etag = format!("\"{:016x}\"", fnv1a64(&body));
+        let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()).unwrap_or("");
+        if if_none_match.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*") {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+        return ([(header::ETAG, etag), (header::CONTENT_TYPE, "application/json".to_string())], body).into_response();
     }
-    Json(serde_json::json!({ "error": "not found" }))
+    not_found_handler().await.into_response()
+}
+
+/// JSON 404 for unknown documents and unmatched routes. `GET` routes also answer `HEAD`
+/// with the same status and headers, so `HEAD /doc/{id}` is an existence check.
+async fn not_found_handler() -> JsonError {
+    JsonError(StatusCode::NOT_FOUND, "not found".into())
+}
+
+/// An error answered as `{"error": message}`, for the document routes whose 404s clients
+/// tell apart from unknown routes by body. Converts from the usual `(StatusCode, String)`.
+pub struct JsonError(StatusCode, String);
+
+impl From<(StatusCode, String)> for JsonError {
+    fn from((status, message): (StatusCode, String)) -> Self { JsonError(status, message) }
 }
 
-fn snippet_from_file(path: &PathBuf, raw_terms: &Vec<String>) -> Option<String> {
-    let text = std::fs::read_to_string(path).ok()?;
-    if text.is_empty() { return None; }
-    // find first match (case-insensitive) of any raw term
-    let mut first_idx: Option<usize> = None;
-    for term in raw_terms {
-        if term.trim().is_empty() { continue; }
-        if let Some(pos) = find_case_insensitive(&text, term) { first_idx = Some(pos); break; }
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
     }
-    let snippet = match first_idx {
-        Some(idx) => {
-            let start = idx.saturating_sub(100);
-            let end = (idx + 200).min(text.len());
-            text[start..end].to_string()
+}
+
+/// A document's body terms by stored tf-idf weight, from the index's term vectors.
+pub async fn doc_terms_handler(State(state): State<AppState>, Path(doc_id): Path<u32>, Query(params): Query<DocTermsParams>) -> Result<Json<DocTermsResponse>, JsonError> {
+    let index = state.index();
+    if !index.docs.contains_key(&doc_id) {
+        return Err(JsonError(StatusCode::NOT_FOUND, "not found".into()));
+    }
+    let term_vectors = index.term_vectors.as_ref().ok_or((StatusCode::NOT_FOUND, "term vectors are not stored (build with --store-term-vectors)".to_string()))?;
+    let vector = term_vectors.get(doc_id).ok_or((StatusCode::INTERNAL_SERVER_ERROR, "term vector could not be read".to_string()))?;
+    let top = &vector[..vector.len().min(params.limit.clamp(1, 1000))];
+    let names: HashMap<TermId, String> = index.term_names(top.iter().map(|(tid, _)| *tid)).into_iter().collect();
+    let terms = top.iter().filter_map(|&(term_id, weight)| Some(DocTerm { term: names.get(&term_id)?.clone(), term_id, weight })).collect();
+    Ok(Json(DocTermsResponse { doc_id, total: vector.len(), terms }))
+}
+
+/// 64-bit FNV-1a; stable across builds, unlike `DefaultHasher`.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Read at most `max_chars` characters from the start of `reader`, and whether more remain.
+///
+/// Only reads up to `4 * max_chars + 1` bytes (the UTF-8 worst case), so a small
+/// prefix of a huge file stays cheap.
+fn read_text_prefix(reader: impl std::io::Read, max_chars: usize) -> std::io::Result<(String, bool)> {
+    use std::io::Read;
+    let cap = max_chars.saturating_mul(4);
+    let mut buf = Vec::new();
+    reader.take(cap as u64 + 1).read_to_end(&mut buf)?;
+    let more_bytes = buf.len() > cap;
+    let decoded = String::from_utf8_lossy(&buf);
+    let mut chars = decoded.chars();
+    let text: String = chars.by_ref().take(max_chars).collect();
+    let truncated = more_bytes || chars.next().is_some();
+    Ok((text, truncated))
+}
+
+/// Serve a document's full text as `text/plain`. Per-file texts are streamed without
+/// buffering them in memory; packed texts are copied out of the mapped file.
+pub async fn doc_raw_handler(State(state): State<AppState>, Path(doc_id): Path<u32>) -> Result<impl axum::response::IntoResponse, JsonError> {
+    let index = state.index();
+    let meta = index.docs.get(&doc_id).ok_or((StatusCode::NOT_FOUND, "not found".to_string()))?;
+    if meta.text_path.is_none() {
+        return Err(JsonError(StatusCode::NOT_FOUND, "text not stored for this document".to_string()));
+    }
+    let not_found = || (StatusCode::NOT_FOUND, "document text not found".to_string());
+    let body = match index.text_location(doc_id).ok_or_else(not_found)? {
+        TextLocation::Packed(text) => axum::body::Body::from(text.to_owned()),
+        TextLocation::File(file) => {
+            axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(tokio::fs::File::from_std(file)))
         }
-        None => text.chars().take(200).collect(),
     };
-    Some(highlight_terms(&snippet, raw_terms))
+    Ok(([(axum::http::header::CONTENT_TYPE, "text/plain; charset=utf-8")], body))
 }
 
-fn find_case_insensitive(haystack: &str, needle: &str) -> Option<usize> {
-    let h = haystack.to_lowercase();
-    let n = needle.to_lowercase();
-    h.find(&n)
+/// Liveness plus a spot check that postings files load; 503 when too many of the sample fail.
+async fn health_handler(State(state): State<AppState>) -> Result<&'static str, (StatusCode, String)> {
+    let index = state.index();
+    let (failed, checked) = index.check_postings_sample(state.config.health_sample_terms);
+    if checked > 0 && failed as f64 / checked as f64 > state.config.health_max_failed_fraction {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, format!("degraded: {failed} of {checked} sampled postings files failed to load")));
+    }
+    Ok("ok")
 }
 
-fn highlight_terms(snippet: &str, terms: &Vec<String>) -> String {
-    let mut s = snippet.to_string();
-    for t in terms {
-        if t.trim().is_empty() { continue; }
-        let pat = regex::RegexBuilder::new(&regex::escape(t))
-            .case_insensitive(true)
-            .build()
-            .unwrap();
-        s = pat.replace_all(&s, |caps: &regex::Captures| format!("<em>{}</em>", &caps[0])).to_string();
-    }
-    s
+async fn metrics_handler(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render())
+}
+
+/// Page through all documents in doc_id order.
+pub async fn list_docs_handler(State(state): State<AppState>, Query(params): Query<ListDocsParams>) -> Json<ListDocsResponse> {
+    let index = state.index();
+    let limit = params.limit.clamp(1, 1000);
+    let docs = index
+        .doc_ids
+        .iter()
+        .skip(params.offset)
+        .take(limit)
+        .filter_map(|id| index.docs.get(id).map(|m| DocSummary { doc_id: *id, external_id: m.external_id.clone(), title: m.title.clone(), url: m.url.clone() }))
+        .collect();
+    Json(ListDocsResponse { total: index.doc_ids.len(), offset: params.offset, docs })
+}
+
+// --- Admin endpoints ---
+/// Buffer documents for the next commit. Searches count them in df and `N` right away, but only
+/// match them once committed.
+async fn index_batch(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(docs): Json<Vec<BatchDoc>>) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    authorize(&state, &headers)?;
+    let accepted = docs.len();
+    // Tokenizing a large batch, and checking its ids against an index without an id map, is CPU-bound
+    let buffered = tokio::task::spawn_blocking(move || commit::buffer(&state, docs))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("batch task failed: {e}")))??;
+    Ok(Json(serde_json::json!({ "accepted": accepted, "buffered": buffered })))
+}
+
+/// Append the buffered documents to the served index. With `reweight=true`, the weights this
+/// and earlier commits left stale are rescaled in the background and the answer is 202; see
+/// [`commit`] for the tradeoff.
+async fn index_commit(State(state): State<AppState>, headers: axum::http::HeaderMap, Query(params): Query<CommitParams>) -> Result<(StatusCode, Json<CommitStatus>), (StatusCode, String)> {
+    authorize(&state, &headers)?;
+    let (code, status) = tokio::task::spawn_blocking(move || commit::commit(&state, params))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("commit task failed: {e}")))??;
+    Ok((code, Json(status)))
+}
+
+async fn index_commit_status(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<CommitStatus>, (StatusCode, String)> {
+    authorize(&state, &headers)?;
+    Ok(Json(commit::status(&state)))
 }
 
-// --- Admin endpoints (stubs) ---
-async fn index_batch(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(_docs): Json<serde_json::Value>) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+async fn admin_reindex(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(req): Json<ReindexRequest>) -> Result<(StatusCode, Json<ReindexStatus>), (StatusCode, String)> {
     authorize(&state, &headers)?;
-    Err((StatusCode::NOT_IMPLEMENTED, "Incremental indexing not implemented".into()))
+    let status = reindex::start(&state, req)?;
+    Ok((StatusCode::ACCEPTED, Json(status)))
 }
 
-async fn index_commit(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+async fn admin_reindex_status(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<ReindexStatus>, (StatusCode, String)> {
     authorize(&state, &headers)?;
-    Err((StatusCode::NOT_IMPLEMENTED, "Commit not implemented".into()))
+    Ok(Json(reindex::status(&state)))
 }
 
 fn authorize(state: &AppState, headers: &axum::http::HeaderMap) -> Result<(), (StatusCode, String)> {
-    let required = match &state.admin_token {
+    let required = match &state.config.admin_token {
         Some(t) => t,
         None => return Err((StatusCode::UNAUTHORIZED, "ADMIN_TOKEN not set".into())),
     };
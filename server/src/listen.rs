@@ -0,0 +1,151 @@
+//! Serving accepted connections with the server's connection limits.
+//!
+//! `axum::serve` takes no per-connection settings, so the binary drives hyper itself: each
+//! connection is HTTP/1 with an optional header read timeout and keep-alive idle timeout, and a
+//! semaphore caps how many are open at once. With every limit unset this matches `axum::serve`.
+
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::Request;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::service::TowerToHyperService;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Connection limits (`--keep-alive-timeout-secs`, `--header-read-timeout-secs`,
+/// `--max-connections`). The default has none.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimits {
+    /// Close a keep-alive connection once no request has been in flight for this long;
+    /// zero turns keep-alive off. `None` keeps idle connections open.
+    pub keep_alive_timeout: Option<Duration>,
+    /// Close a connection whose request headers haven't all arrived this long after the server
+    /// started waiting for them. hyper starts the clock when a connection is accepted and again
+    /// after each response, so this also bounds the idle time between keep-alive requests.
+    pub header_read_timeout: Option<Duration>,
+    /// Most connections open at once; further ones wait in the listen backlog until one closes.
+    pub max_connections: Option<usize>,
+}
+
+impl ConnectionLimits {
+    /// Permits for `max_connections`, shared by everything accepting with these limits.
+    pub fn slots(&self) -> Option<Arc<Semaphore>> {
+        self.max_connections.map(|n| Arc::new(Semaphore::new(n)))
+    }
+}
+
+/// Accept TCP connections and serve `app` on each until the listener fails.
+pub async fn serve_tcp(listener: TcpListener, app: Router, limits: ConnectionLimits) -> std::io::Result<()> {
+    let slots = limits.slots();
+    loop {
+        let permit = acquire(&slots).await;
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                accept_failed(e).await;
+                continue;
+            }
+        };
+        tokio::spawn(serve_connection(stream, app.clone(), limits.clone(), permit));
+    }
+}
+
+/// After a failed accept, return when the listener should be polled again.
+pub async fn accept_failed(e: std::io::Error) {
+    // The peer gave up before we got to it
+    if matches!(e.kind(), std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset) { return; }
+    // Out of file descriptors and the like: back off instead of spinning, as `axum::serve` does
+    tracing::error!(error = %e, "accept failed");
+    tokio::time::sleep(Duration::from_secs(1)).await;
+}
+
+/// A connection slot, waiting for one to free up when `slots` are all taken.
+pub async fn acquire(slots: &Option<Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match slots {
+        Some(slots) => Some(slots.clone().acquire_owned().await.expect("the semaphore is never closed")),
+        None => None,
+    }
+}
+
+/// Serve HTTP/1 requests on one connection until it closes; `permit` is held until then.
+pub async fn serve_connection<I>(io: I, app: Router, limits: ConnectionLimits, permit: Option<OwnedSemaphorePermit>)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let _permit = permit;
+    let mut builder = hyper::server::conn::http1::Builder::new();
+    // With a timer hyper defaults to a 30 s header timeout, so always say which one we want
+    builder.timer(TokioTimer::new()).header_read_timeout(limits.header_read_timeout);
+    let idle_timeout = match limits.keep_alive_timeout {
+        Some(timeout) if timeout.is_zero() => {
+            builder.keep_alive(false);
+            None
+        }
+        timeout => timeout,
+    };
+
+    // Requests in flight, and when the last one finished
+    let activity = Arc::new(Mutex::new((0usize, Instant::now())));
+    let service = {
+        let activity = activity.clone();
+        let app = TowerToHyperService::new(app);
+        hyper::service::service_fn(move |req: Request<Incoming>| {
+            let guard = InFlight::start(&activity);
+            let response = hyper::service::Service::call(&app, req);
+            async move {
+                let response = response.await;
+                drop(guard);
+                response
+            }
+        })
+    };
+    let conn = builder.serve_connection(TokioIo::new(io), service);
+    tokio::pin!(conn);
+    let Some(idle_timeout) = idle_timeout else {
+        if let Err(e) = conn.await {
+            tracing::debug!(error = %e, "connection closed with error");
+        }
+        return;
+    };
+    let mut closing = false;
+    loop {
+        let (in_flight, last) = *activity.lock();
+        // A request in flight can't be idle; look again once it could have gone idle
+        let deadline = if in_flight > 0 { Instant::now() + idle_timeout } else { last + idle_timeout };
+        tokio::select! {
+            result = conn.as_mut() => {
+                if let Err(e) = result { tracing::debug!(error = %e, "connection closed with error"); }
+                return;
+            }
+            _ = tokio::time::sleep_until(deadline.into()), if !closing => {
+                // Only idle if nothing started or finished since `last` was read
+                if in_flight == 0 && *activity.lock() == (0, last) {
+                    // Closes the idle connection, or lets a request that just arrived finish first
+                    conn.as_mut().graceful_shutdown();
+                    closing = true;
+                }
+            }
+        }
+    }
+}
+
+/// Counts a request as in flight until dropped.
+struct InFlight(Arc<Mutex<(usize, Instant)>>);
+
+impl InFlight {
+    fn start(activity: &Arc<Mutex<(usize, Instant)>>) -> Self {
+        activity.lock().0 += 1;
+        Self(activity.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut activity = self.0.lock();
+        *activity = (activity.0 - 1, Instant::now());
+    }
+}
@@ -2,13 +2,16 @@ use anyhow::Result;
 use axum::Router;
 use clap::Parser;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use tracing_subscriber::{fmt, EnvFilter};
-use server::build_app;
+use server::listen::{self, ConnectionLimits};
+use server::Searcher;
+use std::time::Duration;
 use tokio::net::TcpListener;
 
 #[derive(Parser)]
 struct Args {
-    /// Index directory path
+    /// Index directory path, or a symlink to one (SIGHUP reloads whatever it then points to)
     #[arg(long, default_value = "./index")]
     index: String,
     /// Host to bind
@@ -17,17 +20,146 @@ struct Args {
     /// Port to bind
     #[arg(long, default_value_t = 8080)]
     port: u16,
+    /// Listen on this Unix domain socket instead of host/port (a stale socket file is replaced)
+    #[arg(long, conflicts_with_all = ["host", "port"])]
+    uds: Option<PathBuf>,
+    /// Async worker threads (default: one per CPU core)
+    #[arg(long)]
+    worker_threads: Option<usize>,
+    /// Max threads for blocking file IO (default: tokio's 512)
+    #[arg(long)]
+    max_blocking_threads: Option<usize>,
+    /// Seconds a keep-alive connection may sit idle between requests before it is closed; 0 turns
+    /// keep-alive off (default: no limit)
+    #[arg(long, env = "KEEP_ALIVE_TIMEOUT_SECS")]
+    keep_alive_timeout_secs: Option<u64>,
+    /// Seconds a client has to send a request's headers, counted from the accept or the previous
+    /// response; guards against slowloris-style stalls (default: no limit)
+    #[arg(long, env = "HEADER_READ_TIMEOUT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    header_read_timeout_secs: Option<u64>,
+    /// Most connections open at once; more wait in the listen backlog (default: no limit)
+    #[arg(long, env = "MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     fmt().with_env_filter(EnvFilter::from_default_env()).init();
     let args = Args::parse();
-    let app: Router = build_app(args.index.clone())?;
+
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(n) = args.worker_threads {
+        anyhow::ensure!(n > 0, "--worker-threads must be at least 1");
+        builder.worker_threads(n);
+    }
+    if let Some(n) = args.max_blocking_threads {
+        anyhow::ensure!(n > 0, "--max-blocking-threads must be at least 1");
+        builder.max_blocking_threads(n);
+    }
+    anyhow::ensure!(args.max_connections != Some(0), "--max-connections must be at least 1");
+    builder.build()?.block_on(serve(args))
+}
+
+async fn serve(args: Args) -> Result<()> {
+    let searcher = Searcher::open(&args.index)?;
+    reload_on_sighup(searcher.clone())?;
+    let app: Router = searcher.router();
+    let limits = ConnectionLimits {
+        keep_alive_timeout: args.keep_alive_timeout_secs.map(Duration::from_secs),
+        header_read_timeout: args.header_read_timeout_secs.map(Duration::from_secs),
+        max_connections: args.max_connections,
+    };
+    if let Some(path) = args.uds {
+        return serve_uds(app, path, limits).await;
+    }
 
     let addr: SocketAddr = format!("{}:{}", args.host, args.port).parse()?;
     let listener = TcpListener::bind(addr).await?;
-    tracing::info!(%addr, "server listening");
-    axum::serve(listener, app).await?;
+    tracing::info!(%addr, ?limits, "server listening");
+    listen::serve_tcp(listener, app, limits).await?;
+    Ok(())
+}
+
+/// Reload the index on SIGHUP, so `indexer build --swap-symlink` can switch it without a restart.
+#[cfg(unix)]
+fn reload_on_sighup(searcher: Searcher) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            let searcher = searcher.clone();
+            match tokio::task::spawn_blocking(move || searcher.reload()).await {
+                Ok(Ok(index)) => tracing::info!(path = %index.root.path().display(), num_docs = index.num_docs, "reloaded index"),
+                Ok(Err(e)) => tracing::error!(error = %format!("{e:#}"), "reload failed; still serving the previous index"),
+                Err(e) => tracing::error!(error = %e, "reload task failed"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reload_on_sighup(_searcher: Searcher) -> Result<()> {
+    Ok(())
+}
+
+/// Serve `app` over HTTP/1 on a Unix socket until Ctrl-C or SIGTERM, then remove the socket file.
+#[cfg(unix)]
+async fn serve_uds(app: Router, path: PathBuf, limits: ConnectionLimits) -> Result<()> {
+    use tokio::net::UnixListener;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    remove_stale_socket(&path).await?;
+    let listener = UnixListener::bind(&path)?;
+    tracing::info!(path = %path.display(), "server listening");
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let slots = limits.slots();
+    loop {
+        let permit = tokio::select! {
+            permit = listen::acquire(&slots) => permit,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+        };
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            // As on TCP, a failed accept skips the connection or backs off; it doesn't stop the server
+            Err(e) => {
+                tokio::select! {
+                    _ = listen::accept_failed(e) => continue,
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = sigterm.recv() => break,
+                }
+            }
+        };
+        tokio::spawn(listen::serve_connection(stream, app.clone(), limits.clone(), permit));
+    }
+    tracing::info!(path = %path.display(), "shutting down; removing socket");
+    std::fs::remove_file(&path).ok();
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn serve_uds(_app: Router, _path: PathBuf, _limits: ConnectionLimits) -> Result<()> {
+    anyhow::bail!("--uds is only supported on Unix")
+}
+
+/// Remove a socket file left behind by a server that didn't shut down cleanly. Refuses to
+/// touch non-socket files or a socket another server is still accepting on.
+#[cfg(unix)]
+async fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    let Ok(meta) = std::fs::symlink_metadata(path) else { return Ok(()) };
+    anyhow::ensure!(meta.file_type().is_socket(), "{} exists and is not a socket", path.display());
+    if tokio::net::UnixStream::connect(path).await.is_ok() {
+        anyhow::bail!("{} is in use by another server", path.display());
+    }
+    tracing::warn!(path = %path.display(), "removing stale socket");
+    std::fs::remove_file(path)?;
     Ok(())
 }
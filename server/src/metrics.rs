@@ -0,0 +1,97 @@
+//! Process-wide counters rendered in the Prometheus text exposition format at `/metrics`.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Cumulative latency histogram with fixed buckets.
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self { buckets: std::array::from_fn(|_| AtomicU64::new(0)), count: AtomicU64::new(0), sum_us: AtomicU64::new(0) }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, secs: f64) {
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            if secs <= *bound { bucket.fetch_add(1, Ordering::Relaxed); }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add((secs * 1e6) as u64, Ordering::Relaxed);
+    }
+
+    /// Returns a guard that observes the elapsed time when dropped.
+    pub fn start_timer(&self) -> HistogramTimer<'_> {
+        HistogramTimer { hist: self, start: Instant::now() }
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {}", bucket.load(Ordering::Relaxed));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum_us.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+pub struct HistogramTimer<'a> {
+    hist: &'a Histogram,
+    start: Instant,
+}
+
+impl Drop for HistogramTimer<'_> {
+    fn drop(&mut self) {
+        self.hist.observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    pub searches_total: AtomicU64,
+    pub zero_result_searches_total: AtomicU64,
+    pub postings_files_read_total: AtomicU64,
+    pub postings_load_errors_total: AtomicU64,
+    pub feedback_dropped_total: AtomicU64,
+    pub search_latency: Histogram,
+    pub doc_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("zapsearch_searches_total", "Search requests served.", &self.searches_total),
+            ("zapsearch_zero_result_searches_total", "Search requests that returned no hits.", &self.zero_result_searches_total),
+            ("zapsearch_postings_files_read_total", "Postings files loaded from disk.", &self.postings_files_read_total),
+            ("zapsearch_postings_load_errors_total", "Postings files that were missing or failed to decode.", &self.postings_load_errors_total),
+            ("zapsearch_feedback_dropped_total", "Feedback events rejected because the writer queue was full.", &self.feedback_dropped_total),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
+        let name = "zapsearch_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Request latency by endpoint.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        self.search_latency.render(&mut out, name, "endpoint=\"search\"");
+        self.doc_latency.render(&mut out, name, "endpoint=\"doc\"");
+        out
+    }
+}
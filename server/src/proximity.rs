@@ -0,0 +1,78 @@
+//! Query-time proximity scoring (`proximity=true`).
+//!
+//! Postings carry no positions, so the best-ranked hits are re-tokenized from their stored text.
+//! A hit whose text holds every query word inside a window of at most `words + slop` positions
+//! has its score multiplied by `1 + boost * words / window`: an exact phrase gets the full
+//! `boost`, looser groupings less, and scattered words nothing.
+
+use crate::LoadedIndex;
+use core::tokenizer::tokenize_iter_with;
+use core::DocId;
+use std::collections::HashMap;
+
+/// Extra positions allowed between the query words by default (`slop`).
+pub const DEFAULT_SLOP: usize = 10;
+/// Score bonus for words that appear as an exact phrase, by default (`proximity_boost`).
+pub const DEFAULT_BOOST: f32 = 0.5;
+/// Hits re-scored per hit wanted; proximity only reorders near the top of the list.
+const RERANK_FACTOR: usize = 10;
+
+/// Boost the best-ranked `want * RERANK_FACTOR` hits of `scored` (sorted by score) by how close
+/// together their stored text holds the query words, then re-sort them. `word_of` maps each query
+/// term (stems, raw forms, wildcard expansions) to the word it stands for; fewer than two distinct
+/// words leaves the ranking alone.
+pub fn rerank(index: &LoadedIndex, scored: &mut [(DocId, f32)], word_of: &HashMap<String, &str>, slop: usize, boost: f32, want: usize, read_bytes: usize) {
+    let mut words: Vec<&str> = word_of.values().copied().collect();
+    words.sort_unstable();
+    words.dedup();
+    if words.len() < 2 { return; }
+    let slot: HashMap<&str, usize> = words.iter().enumerate().map(|(i, &w)| (w, i)).collect();
+    let end = want.saturating_mul(RERANK_FACTOR).min(scored.len());
+    let window = &mut scored[..end];
+    for (doc_id, score) in window.iter_mut() {
+        let Some(text) = index.doc_text_head(*doc_id, read_bytes) else { continue };
+        let hits: Vec<(usize, usize)> = tokenize_iter_with(&text, &index.tokenizer)
+            .filter_map(|(term, pos)| Some((pos, slot[word_of.get(&term)?])))
+            .collect();
+        if let Some(span) = min_window(&hits, words.len()).filter(|&span| span <= words.len() + slop) {
+            *score *= 1.0 + boost * words.len() as f32 / span as f32;
+        }
+    }
+    window.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+}
+
+/// Length in positions of the shortest run of `hits` (position, word slot; in position order)
+/// that contains all `words` slots, if there is one.
+fn min_window(hits: &[(usize, usize)], words: usize) -> Option<usize> {
+    let mut counts = vec![0usize; words];
+    let mut covered = 0;
+    let mut best: Option<usize> = None;
+    let mut left = 0;
+    for &(pos, slot) in hits {
+        if counts[slot] == 0 { covered += 1; }
+        counts[slot] += 1;
+        while covered == words {
+            let (start, first) = hits[left];
+            let span = pos - start + 1;
+            best = Some(best.map_or(span, |b| b.min(span)));
+            counts[first] -= 1;
+            if counts[first] == 0 { covered -= 1; }
+            left += 1;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_window_covers_every_word() {
+        // a . . b a b : the tightest a..b run is positions 4..5
+        assert_eq!(min_window(&[(0, 0), (3, 1), (4, 0), (5, 1)], 2), Some(2));
+        assert_eq!(min_window(&[(0, 0), (1, 0)], 2), None);
+        // Two terms of one word at the same position (a stem and its raw form) don't widen it
+        assert_eq!(min_window(&[(2, 0), (2, 0), (3, 1)], 2), Some(2));
+    }
+}
@@ -0,0 +1,121 @@
+//! Query-string parsing: searchable fields and Lucene-style `field:term` prefixes.
+
+pub use core::api::Mode;
+use std::collections::HashMap;
+
+/// A searchable field. `Body` is served from the on-disk postings; `Title` and `Url`
+/// from in-memory indexes built over `DocMeta` at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Title,
+    Body,
+    Url,
+    /// The body's case-sensitive channel (`--preserve-case`), searched instead of `Body`
+    /// with `case_sensitive=true`. Not addressable by name.
+    BodyCase,
+}
+
+impl Field {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(Field::Title),
+            "body" => Some(Field::Body),
+            "url" => Some(Field::Url),
+            _ => None,
+        }
+    }
+
+    /// Name used in debug output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Body => "body",
+            Field::Url => "url",
+            Field::BodyCase => "body_case",
+        }
+    }
+
+    /// The field as requests name it: `BodyCase` is scored, boosted and filtered as `Body`.
+    pub fn named(self) -> Self {
+        match self {
+            Field::BodyCase => Field::Body,
+            field => field,
+        }
+    }
+
+    /// Swap `Body` for its case-sensitive channel when `case_sensitive` is set.
+    pub fn with_case(self, case_sensitive: bool) -> Self {
+        match self {
+            Field::Body if case_sensitive => Field::BodyCase,
+            field => field,
+        }
+    }
+}
+
+/// Parse the `fields` param (comma-separated field names), defaulting to `body`.
+pub fn parse_fields(spec: Option<&str>) -> Result<Vec<Field>, String> {
+    let spec = match spec {
+        Some(s) if !s.trim().is_empty() => s,
+        _ => return Ok(vec![Field::Body]),
+    };
+    let mut fields = Vec::new();
+    for name in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let field = Field::parse(name).ok_or_else(|| format!("unknown field '{name}' (expected title, body or url)"))?;
+        if !fields.contains(&field) { fields.push(field); }
+    }
+    Ok(fields)
+}
+
+/// Validate a `{"field": boost}` map: known fields, finite and non-negative values.
+pub fn parse_boosts(boosts: &HashMap<String, f32>) -> Result<HashMap<Field, f32>, String> {
+    let mut out = HashMap::new();
+    for (name, &boost) in boosts {
+        let field = Field::parse(name).ok_or_else(|| format!("unknown boost field '{name}' (expected title, body or url)"))?;
+        if !boost.is_finite() || boost < 0.0 {
+            return Err(format!("boost for '{name}' must be a finite, non-negative number"));
+        }
+        out.insert(field, boost);
+    }
+    Ok(out)
+}
+
+/// One whitespace-separated query word, optionally restricted to a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clause {
+    /// `None` searches every requested field.
+    pub field: Option<Field>,
+    pub text: String,
+}
+
+/// Split `q` into clauses. `title:rust` becomes a title-only clause; words with an
+/// unknown prefix (`c++:`, `https://...`) are kept whole as unrestricted text.
+pub fn parse_query(q: &str) -> Vec<Clause> {
+    q.split_whitespace()
+        .map(|word| match word.split_once(':') {
+            Some((name, text)) if !text.is_empty() => match Field::parse(&name.to_lowercase()) {
+                Some(field) => Clause { field: Some(field), text: text.to_string() },
+                None => Clause { field: None, text: word.to_string() },
+            },
+            _ => Clause { field: None, text: word.to_string() },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_prefixed_words_to_fields() {
+        let clauses = parse_query("Title:rust systems url:docs https://x.org");
+        assert_eq!(
+            clauses,
+            vec![
+                Clause { field: Some(Field::Title), text: "rust".into() },
+                Clause { field: None, text: "systems".into() },
+                Clause { field: Some(Field::Url), text: "docs".into() },
+                Clause { field: None, text: "https://x.org".into() },
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Background rebuild of the served index (`POST /admin/reindex`).
+//!
+//! The build runs the same code as `indexer build` into a sibling staging
+//! directory. Once it loads cleanly, the live directory is moved aside, the
+//! staging directory is renamed into its place, and the new [`LoadedIndex`] is
+//! swapped into [`AppState`] while holding the write lock, so new requests see
+//! either the old or the new index. Snapshots of the old index still in use
+//! follow it to where it was moved ([`IndexRoot`]), and it is deleted once the
+//! last of them is dropped.
+
+use crate::{AppState, LoadedIndex};
+use axum::http::StatusCode;
+use core::weighting::{IdfScheme, TfScheme, Weighting};
+use indexer::{build_index_with_progress, BuildOptions, BuildReport, FieldMap};
+use serde::{Deserialize, Serialize};
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The directory a [`LoadedIndex`] reads postings and per-file texts from. Postings are read
+/// lazily, so the directory can only move while no read is opening a file in it: a reindex moves
+/// the old build aside through [`IndexRoot::move_to`], which every snapshot of it sees at once.
+#[derive(Debug)]
+pub struct IndexRoot {
+    path: RwLock<PathBuf>,
+    /// Set once the directory is a retired build, which goes with the last snapshot using it.
+    remove_on_drop: AtomicBool,
+}
+
+impl IndexRoot {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path: RwLock::new(path), remove_on_drop: AtomicBool::new(false) }
+    }
+
+    /// Where the directory is now.
+    pub fn path(&self) -> PathBuf { self.path.read().clone() }
+
+    /// Run `f` with the directory, which stays put until `f` returns. Open files inside `f`
+    /// rather than keeping paths into the directory.
+    pub fn with<T>(&self, f: impl FnOnce(&Path) -> T) -> T { f(&self.path.read()) }
+
+    /// Rename the directory to `to`; with `retire`, it is deleted when this root is dropped.
+    fn move_to(&self, to: &Path, retire: bool) -> std::io::Result<()> {
+        let mut path = self.path.write();
+        fs::rename(&*path, to)?;
+        *path = to.to_path_buf();
+        self.remove_on_drop.store(retire, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for IndexRoot {
+    fn drop(&mut self) {
+        if !*self.remove_on_drop.get_mut() { return; }
+        let path = self.path.get_mut();
+        match fs::remove_dir_all(&*path) {
+            Ok(()) => tracing::info!(path = %path.display(), "removed retired index"),
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to remove retired index"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReindexRequest {
+    /// Input path (JSON/JSONL file or directory), as for `indexer build --input`
+    pub input: String,
+    /// Shorthand for `idf_scheme: "smooth"`
+    #[serde(default)]
+    pub smoothed_idf: bool,
+    /// tf scheme, as for `indexer build --tf-scheme`; defaults to the served index's
+    #[serde(default)]
+    pub tf_scheme: Option<TfScheme>,
+    /// idf scheme, as for `indexer build --idf-scheme`; defaults to the served index's
+    #[serde(default)]
+    pub idf_scheme: Option<IdfScheme>,
+    /// Pivoted length normalization blend (0-1), as for `indexer build --pivot`
+    #[serde(default)]
+    pub pivot: f32,
+    /// Input key renames, as for `indexer build --field-map`
+    #[serde(default)]
+    pub field_map: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReindexState {
+    #[default]
+    Idle,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReindexStatus {
+    pub state: ReindexState,
+    pub input: Option<String>,
+    pub docs_ingested: u32,
+    /// Seconds since the current (or last) rebuild started.
+    pub elapsed_s: Option<f64>,
+    pub report: Option<BuildReport>,
+    pub error: Option<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+/// Current reindex status with `elapsed_s` filled in.
+pub fn status(state: &AppState) -> ReindexStatus {
+    let mut status = state.reindex.lock().clone();
+    status.elapsed_s = status.started.map(|t0| status.finished.unwrap_or_else(Instant::now).duration_since(t0).as_secs_f64());
+    status
+}
+
+/// Start a rebuild on a background thread. Fails with 409 if one is already running.
+pub fn start(state: &AppState, req: ReindexRequest) -> Result<ReindexStatus, (StatusCode, String)> {
+    if !Path::new(&req.input).exists() {
+        return Err((StatusCode::BAD_REQUEST, format!("input path does not exist: {}", req.input)));
+    }
+    if !(0.0..=1.0).contains(&req.pivot) {
+        return Err((StatusCode::BAD_REQUEST, format!("pivot must be between 0 and 1, got {}", req.pivot)));
+    }
+    let field_map = FieldMap::parse(req.field_map.as_deref().unwrap_or("")).map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid field_map: {e}")))?;
+    {
+        // Commit status first, as commits take them: the two never run at once
+        let commit = state.commit.lock();
+        let mut status = state.reindex.lock();
+        if status.state == ReindexState::Running {
+            return Err((StatusCode::CONFLICT, "a reindex is already running".into()));
+        }
+        if commit.state == ReindexState::Running {
+            return Err((StatusCode::CONFLICT, "a commit or reweight pass is running".into()));
+        }
+        *status = ReindexStatus { state: ReindexState::Running, input: Some(req.input.clone()), started: Some(Instant::now()), ..Default::default() };
+    }
+
+    let state_c = state.clone();
+    std::thread::spawn(move || {
+        let result = run(&state_c, &req, field_map);
+        let mut status = state_c.reindex.lock();
+        status.finished = Some(Instant::now());
+        match result {
+            Ok(report) => {
+                tracing::info!(num_docs = report.num_docs, input = %req.input, "reindex complete");
+                status.state = ReindexState::Succeeded;
+                status.report = Some(report);
+            }
+            Err(e) => {
+                tracing::error!(error = %e, input = %req.input, "reindex failed");
+                status.state = ReindexState::Failed;
+                status.error = Some(format!("{e:#}"));
+            }
+        }
+    });
+    Ok(status(state))
+}
+
+fn run(state: &AppState, req: &ReindexRequest, field_map: FieldMap) -> anyhow::Result<BuildReport> {
+    let current = state.index();
+    let live = current.root.path();
+    let staging = sibling(&live, "staging");
+    if staging.exists() { fs::remove_dir_all(&staging)?; }
+
+    // Keep the analyzer, case channel, facets, indexed meta keys and weighting the served index was built with so queries and filters keep matching.
+    let idf = req.idf_scheme.or(req.smoothed_idf.then_some(IdfScheme::Smooth)).unwrap_or(current.weighting.idf);
+    let opts = BuildOptions {
+        weighting: Weighting { tf: req.tf_scheme.unwrap_or(current.weighting.tf), idf },
+        pivot: req.pivot,
+        tokenizer: current.tokenizer.clone(),
+        preserve_case: current.case_channel.is_some(),
+        facet_fields: current.facet_fields.clone(),
+        index_meta: current.index_meta.clone(),
+        store_term_vectors: current.term_vectors.is_some(),
+        snippet_store_bytes: current.snippet_store_bytes,
+        postings_fanout: current.postings_fanout,
+        max_postings_per_term: current.max_postings_per_term,
+        store_doc_norms: current.doc_norms.is_some(),
+        field_map,
+        ..Default::default()
+    };
+    drop(current);
+    let staging_str = staging.to_string_lossy().to_string();
+    let report = build_index_with_progress(&req.input, &staging_str, &opts, &|n| state.reindex.lock().docs_ingested = n)?;
+
+    // Load before touching the live directory so a bad build never replaces a good one.
+    let loaded = LoadedIndex::load(&staging_str, state.config.load_doc_id_map)?;
+    drop(swap_in(state, &staging, loaded)?);
+    Ok(report)
+}
+
+/// Serve `loaded`, loaded from `staging`, in place of the current index: the live directory
+/// is moved aside and `staging` renamed into its place. Returns with the index write lock still
+/// held, so the caller can replace the delta along with it.
+pub(crate) fn swap_in<'a>(state: &'a AppState, staging: &Path, mut loaded: LoadedIndex) -> anyhow::Result<RwLockWriteGuard<'a, Arc<LoadedIndex>>> {
+    let mut served = state.index.write();
+    // Searches holding the old snapshot read from the retired copy from here on, never the new build
+    let old_root = served.root.clone();
+    let live = old_root.path();
+    let retired = sibling(&live, &format!("retired-{}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()));
+    old_root.move_to(&retired, true)?;
+    if let Err(e) = fs::rename(staging, &live) {
+        // Still the served build: it must outlive its snapshots even if it can't be moved back
+        old_root.remove_on_drop.store(false, Ordering::Relaxed);
+        if let Err(back) = old_root.move_to(&live, false) {
+            tracing::error!(retired = %retired.display(), live = %live.display(), error = %back, "couldn't move the served index back; it keeps serving from the retired path, move it back by hand");
+        }
+        return Err(anyhow::Error::from(e).context(format!("moving {} to {}", staging.display(), live.display())));
+    }
+    loaded.root = Arc::new(IndexRoot::new(live));
+    *served = Arc::new(state.share_root(loaded));
+    Ok(served)
+}
+
+/// `index` -> `index.<suffix>` next to the live directory.
+pub(crate) fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| "index".into());
+    dir.with_file_name(format!("{name}.{suffix}"))
+}
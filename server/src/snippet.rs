@@ -0,0 +1,262 @@
+//! Snippet extraction from stored document text and query-term highlighting.
+
+pub use core::api::{MatchSpan, SnippetFallback};
+
+/// How matched terms are found and marked up in snippets.
+#[derive(Debug, Clone)]
+pub struct HighlightOptions {
+    pub pre: String,
+    pub post: String,
+    /// Match terms only as whole words (`cat` not inside `category`), as the tokenizer did.
+    /// A term ending in `*` still matches as a prefix, at the start of a word.
+    pub whole_word: bool,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self { pre: "<em>".into(), post: "</em>".into(), whole_word: true }
+    }
+}
+
+impl HighlightOptions {
+    /// Build from optional request overrides, falling back to `<em>`/`</em>` and whole words.
+    pub fn from_params(pre: Option<String>, post: Option<String>, whole_word: Option<bool>) -> Self {
+        let default = Self::default();
+        Self { pre: pre.unwrap_or(default.pre), post: post.unwrap_or(default.post), whole_word: whole_word.unwrap_or(default.whole_word) }
+    }
+}
+
+/// A stored field a snippet can come from (`snippet_fields`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnippetField {
+    Body,
+    Title,
+    /// The stored meta description.
+    Description,
+}
+
+impl SnippetField {
+    const ALL: [SnippetField; 3] = [SnippetField::Body, SnippetField::Title, SnippetField::Description];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SnippetField::Body => "body",
+            SnippetField::Title => "title",
+            SnippetField::Description => "description",
+        }
+    }
+
+    /// Parse a comma-separated list (`title,body`); blank means the body only.
+    pub fn parse_list(spec: Option<&str>) -> Result<Vec<SnippetField>, String> {
+        let Some(spec) = spec.filter(|s| !s.trim().is_empty()) else { return Ok(vec![SnippetField::Body]) };
+        let mut fields = Vec::new();
+        for name in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let field = Self::ALL.into_iter().find(|f| f.name() == name)
+                .ok_or_else(|| format!("unknown snippet field '{name}' (expected body, title or description)"))?;
+            if !fields.contains(&field) { fields.push(field); }
+        }
+        Ok(fields)
+    }
+}
+
+/// A highlighted snippet and where its highlights are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    pub matches: Vec<MatchSpan>,
+}
+
+/// Window of `text` around the first query-term match (or its start), highlighted.
+pub fn snippet_from_text(text: &str, raw_terms: &[String], highlight: &HighlightOptions) -> Option<Snippet> {
+    match_snippet(text, raw_terms, highlight).or_else(|| lead_snippet(text, raw_terms, highlight))
+}
+
+/// Window of `text` around the first query-term match, highlighted; `None` without a match.
+pub fn match_snippet(text: &str, raw_terms: &[String], highlight: &HighlightOptions) -> Option<Snippet> {
+    Some(highlight_snippet(match_window(text, raw_terms, highlight)?, raw_terms, highlight))
+}
+
+/// The unhighlighted text [`match_snippet`] shows.
+fn match_window<'a>(text: &'a str, raw_terms: &[String], highlight: &HighlightOptions) -> Option<&'a str> {
+    // find first match (case-insensitive) of any raw term
+    let idx = raw_terms.iter().filter_map(|term| term_pattern(term, highlight.whole_word)).find_map(|pat| pat.find(text).map(|m| m.start()))?;
+    let start = floor_char_boundary(text, idx.saturating_sub(100));
+    let end = floor_char_boundary(text, (idx + 200).min(text.len()));
+    Some(&text[start..end])
+}
+
+/// The match snippet from whichever of `candidates` shows the query best: the most distinct
+/// query words in its window, then the most of them per word of window. Ties go to the earlier
+/// candidate. `None` if no query word occurs in any of them.
+pub fn best_match_snippet<'a>(candidates: impl IntoIterator<Item = (SnippetField, &'a str)>, raw_terms: &[String], highlight: &HighlightOptions) -> Option<(SnippetField, Snippet)> {
+    let patterns: Vec<regex::Regex> = raw_terms.iter().filter_map(|term| term_pattern(term, highlight.whole_word)).collect();
+    let mut best: Option<(SnippetField, &str, (usize, f32))> = None;
+    for (field, text) in candidates {
+        let Some(window) = match_window(text, raw_terms, highlight) else { continue };
+        let covered = patterns.iter().filter(|pat| pat.is_match(window)).count();
+        let density = covered as f32 / window.split_whitespace().count().max(1) as f32;
+        if best.as_ref().is_none_or(|(_, _, (c, d))| (covered, density) > (*c, *d)) {
+            best = Some((field, window, (covered, density)));
+        }
+    }
+    best.map(|(field, window, _)| (field, highlight_snippet(window, raw_terms, highlight)))
+}
+
+/// Largest char boundary of `s` at or before byte `i`.
+fn floor_char_boundary(s: &str, mut i: usize) -> usize {
+    while !s.is_char_boundary(i) { i -= 1; }
+    i
+}
+
+/// The first 200 characters of `text`, highlighted.
+pub fn lead_snippet(text: &str, raw_terms: &[String], highlight: &HighlightOptions) -> Option<Snippet> {
+    if text.is_empty() { return None; }
+    let lead: String = text.chars().take(200).collect();
+    Some(highlight_snippet(&lead, raw_terms, highlight))
+}
+
+/// Case-insensitive pattern for a highlight term; `None` for a blank one. A trailing `*` makes
+/// it a prefix. With `whole_word`, word characters at either end must sit on a word boundary
+/// (the end only for non-prefixes). Matching runs on the text itself, since lowercasing can
+/// change byte lengths (`İ` is longer lowercased).
+fn term_pattern(term: &str, whole_word: bool) -> Option<regex::Regex> {
+    let word = term.trim_end_matches('*');
+    let prefix = word.len() < term.len();
+    if word.trim().is_empty() { return None; }
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let mut pattern = regex::escape(word);
+    if whole_word {
+        if is_word_char(word.chars().next()) { pattern.insert_str(0, r"\b"); }
+        if !prefix && is_word_char(word.chars().next_back()) { pattern.push_str(r"\b"); }
+    }
+    regex::RegexBuilder::new(&pattern).case_insensitive(true).build().ok()
+}
+
+/// HTML-escape `snippet` and wrap case-insensitive matches of `terms` in the highlight tags.
+///
+/// Matches are located on the raw text and every segment is escaped on output, so
+/// document content can never inject markup or break out of the highlight tags.
+pub fn highlight_terms(snippet: &str, terms: &[String], highlight: &HighlightOptions) -> String {
+    highlight_snippet(snippet, terms, highlight).text
+}
+
+/// [`highlight_terms`], also reporting the char span of each highlighted region in the output.
+pub fn highlight_snippet(snippet: &str, terms: &[String], highlight: &HighlightOptions) -> Snippet {
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for pat in terms.iter().filter_map(|t| term_pattern(t, highlight.whole_word)) {
+        spans.extend(pat.find_iter(snippet).map(|m| (m.start(), m.end())));
+    }
+    spans.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut out = String::with_capacity(snippet.len() + 16);
+    let mut matches = Vec::with_capacity(merged.len());
+    let (pre_chars, post_chars) = (highlight.pre.chars().count(), highlight.post.chars().count());
+    let mut out_chars = 0;
+    fn push(out: &mut String, s: &str) -> usize {
+        out.push_str(s);
+        s.chars().count()
+    }
+    let mut cursor = 0;
+    for (start, end) in merged {
+        out_chars += push(&mut out, &escape_html(&snippet[cursor..start])) + pre_chars;
+        out.push_str(&highlight.pre);
+        let matched = push(&mut out, &escape_html(&snippet[start..end]));
+        matches.push(MatchSpan { start: out_chars, end: out_chars + matched });
+        out_chars += matched + post_chars;
+        out.push_str(&highlight.post);
+        cursor = end;
+    }
+    out.push_str(&escape_html(&snippet[cursor..]));
+    Snippet { text: out, matches }
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_content_before_highlighting() {
+        let terms = vec!["script".to_string()];
+        let out = highlight_terms("<script>alert(1)</script>", &terms, &HighlightOptions::default());
+        assert_eq!(out, "&lt;<em>script</em>&gt;alert(1)&lt;/<em>script</em>&gt;");
+    }
+
+    #[test]
+    fn custom_tags_and_overlapping_terms() {
+        let terms = vec!["rust".to_string(), "ru".to_string()];
+        let hl = HighlightOptions::from_params(Some("[".into()), Some("]".into()), Some(false));
+        assert_eq!(highlight_terms("Rust & rusty", &terms, &hl), "[Rust] &amp; [rust]y");
+    }
+
+    #[test]
+    fn whole_word_highlighting_skips_words_containing_the_term() {
+        let terms = vec!["cat".to_string()];
+        let text = "A cat, a category and concatenate";
+        assert_eq!(highlight_terms(text, &terms, &HighlightOptions::default()), "A <em>cat</em>, a category and concatenate");
+        let substring = HighlightOptions { whole_word: false, ..Default::default() };
+        assert_eq!(highlight_terms(text, &terms, &substring), "A <em>cat</em>, a <em>cat</em>egory and con<em>cat</em>enate");
+        // Prefix queries still highlight the start of longer words
+        assert_eq!(highlight_terms(text, &["cat*".to_string()], &HighlightOptions::default()), "A <em>cat</em>, a <em>cat</em>egory and concatenate");
+        // The snippet window centers on a whole-word match, not the first substring
+        let text = format!("concatenate {} cat", "x ".repeat(200));
+        assert!(match_snippet(&text, &terms, &HighlightOptions::default()).unwrap().text.ends_with("<em>cat</em>"));
+    }
+
+    #[test]
+    fn snippet_windows_never_split_a_char() {
+        let text = format!("{}rust{}", "é".repeat(99), "ü".repeat(150));
+        let substring = HighlightOptions { whole_word: false, ..Default::default() };
+        let snippet = match_snippet(&text, &["rust".to_string()], &substring).unwrap();
+        assert!(snippet.text.contains("<em>rust</em>"));
+        let text = format!("{}İ rust", "x".repeat(150));
+        assert!(match_snippet(&text, &["rust".to_string()], &HighlightOptions::default()).is_some());
+    }
+
+    #[test]
+    fn match_spans_index_the_output_in_chars() {
+        let terms = vec!["café".to_string()];
+        let out = highlight_snippet("é & café", &terms, &HighlightOptions::default());
+        assert_eq!(out.text, "é &amp; <em>café</em>");
+        let chars: Vec<char> = out.text.chars().collect();
+        let MatchSpan { start, end } = out.matches[0];
+        assert_eq!(chars[start..end].iter().collect::<String>(), "café");
+    }
+
+    #[test]
+    fn best_snippet_field_shows_the_most_query_words() {
+        let terms = vec!["rust".to_string(), "search".to_string()];
+        let hl = HighlightOptions::default();
+        let body = "Notes on rust and many other unrelated things";
+        let (field, snippet) = best_match_snippet([(SnippetField::Body, body), (SnippetField::Title, "Rust search engines")], &terms, &hl).unwrap();
+        assert_eq!((field, snippet.text.as_str()), (SnippetField::Title, "<em>Rust</em> <em>search</em> engines"));
+        // Equal coverage: the denser window wins, and ties keep the earlier field
+        let (field, _) = best_match_snippet([(SnippetField::Body, body), (SnippetField::Title, "Rust")], &terms, &hl).unwrap();
+        assert_eq!(field, SnippetField::Title);
+        let (field, _) = best_match_snippet([(SnippetField::Body, "rust"), (SnippetField::Title, "Rust")], &terms, &hl).unwrap();
+        assert_eq!(field, SnippetField::Body);
+        assert!(best_match_snippet([(SnippetField::Title, "Go")], &terms, &hl).is_none());
+        assert!(SnippetField::parse_list(Some("title,summary")).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! End-to-end tests: JSONL fixtures from `tests/fixtures` go through the real indexer into a
+//! temp dir, and queries go through the app `build_app` serves. Unlike the hand-built index in
+//! `integration_search.rs`, the weights here are whatever `build_index` computes, so these catch
+//! the indexer and the server disagreeing about tokens, idf or normalization.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tempfile::{tempdir, TempDir};
+
+/// An index built from one fixture, and the app serving it.
+struct Harness {
+    _dir: TempDir,
+    fixture: String,
+    app: Router,
+}
+
+impl Harness {
+    fn new(fixture: &str) -> Self {
+        Self::with_options(fixture, indexer::BuildOptions::default())
+    }
+
+    fn with_options(fixture: &str, opts: indexer::BuildOptions) -> Self {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture);
+        let dir = tempdir().unwrap();
+        let index_dir = dir.path().join("index").to_string_lossy().to_string();
+        indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+        let app = server::build_app(index_dir).unwrap();
+        Self { _dir: dir, fixture: std::fs::read_to_string(input).unwrap(), app }
+    }
+
+    /// The fixture's documents as JSON, in input (and so doc id) order.
+    fn docs(&self) -> Vec<Value> {
+        self.fixture.lines().filter(|l| !l.trim().is_empty()).map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    /// `POST /search` with `body`, which must succeed.
+    fn search(&self, body: Value) -> Value {
+        let req = Request::post("/search").header("content-type", "application/json").body(Body::from(body.to_string())).unwrap();
+        // The workspace `core` crate shadows `::core`, which `#[tokio::test]` expands into
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let (status, bytes) = rt.block_on(async {
+            let resp = tower::ServiceExt::oneshot(self.app.clone(), req).await.unwrap();
+            (resp.status(), resp.into_body().collect().await.unwrap().to_bytes())
+        });
+        assert_eq!(status, StatusCode::OK, "{body}: {}", String::from_utf8_lossy(&bytes));
+        serde_json::from_slice(&bytes).unwrap()
+    }
+}
+
+fn external_ids(json: &Value) -> Vec<&str> {
+    json["results"].as_array().unwrap().iter().map(|h| h["external_id"].as_str().unwrap()).collect()
+}
+
+/// Cosine scores of `query` against every fixture body, with the default log tf and standard
+/// idf written out here rather than taken from `core::weighting`.
+fn expected_body_scores(docs: &[Value], query: &str) -> HashMap<String, f32> {
+    let n = docs.len() as f32;
+    let counts: Vec<HashMap<String, u32>> = docs.iter().map(|doc| {
+        let mut tf = HashMap::new();
+        for (term, _) in core::tokenizer::tokenize(doc["body"].as_str().unwrap()) { *tf.entry(term).or_insert(0) += 1; }
+        tf
+    }).collect();
+    let idf = |term: &str| (n / counts.iter().filter(|tf| tf.contains_key(term)).count() as f32).ln();
+    let weight = |tf: u32, term: &str| (1.0 + (tf as f32).ln()) * idf(term);
+
+    let terms: HashSet<String> = core::tokenizer::tokenize(query).into_iter().map(|(t, _)| t).filter(|t| counts.iter().any(|tf| tf.contains_key(t))).collect();
+    let q_norm = terms.iter().map(|t| weight(1, t).powi(2)).sum::<f32>().sqrt();
+    docs.iter().zip(&counts).filter_map(|(doc, tf)| {
+        let d_norm = tf.iter().map(|(t, &c)| weight(c, t).powi(2)).sum::<f32>().sqrt();
+        let dot: f32 = terms.iter().filter_map(|t| tf.get(t).map(|&c| weight(1, t) * weight(c, t))).sum();
+        (dot > 0.0).then(|| (doc["id"].as_str().unwrap().to_string(), dot / (q_norm * d_norm)))
+    }).collect()
+}
+
+#[test]
+fn multi_term_body_scores_match_cosine_over_the_fixture() {
+    let harness = Harness::new("programming.jsonl");
+    for q in ["async rust", "async runtime executor", "rust ownership garbage", "tomatoes frost"] {
+        let expected = expected_body_scores(&harness.docs(), q);
+        let json = harness.search(json!({ "q": q, "k": 10, "fields": "body" }));
+        let hits = json["results"].as_array().unwrap();
+        assert_eq!(hits.len(), expected.len(), "{q}: {json}");
+        for hit in hits {
+            let (id, score) = (hit["external_id"].as_str().unwrap(), hit["score"].as_f64().unwrap() as f32);
+            assert!((expected[id] - score).abs() < 1e-4, "{q}: {id} scored {score}, expected {}", expected[id]);
+        }
+        let scores: Vec<f64> = hits.iter().map(|h| h["score"].as_f64().unwrap()).collect();
+        assert!(scores.windows(2).all(|w| w[0] >= w[1]), "{q}: {scores:?}");
+    }
+}
+
+#[test]
+fn stored_doc_norms_give_the_same_scores() {
+    let baked = Harness::new("programming.jsonl");
+    let stored = Harness::with_options("programming.jsonl", indexer::BuildOptions { store_doc_norms: true, ..Default::default() });
+    let scores = |json: &Value| json["results"].as_array().unwrap().iter().map(|h| (h["external_id"].as_str().unwrap().to_string(), h["score"].as_f64().unwrap())).collect::<Vec<_>>();
+    for q in ["async rust", "the go runtime", "python futures"] {
+        let (want, got) = (scores(&baked.search(json!({ "q": q }))), scores(&stored.search(json!({ "q": q }))));
+        assert_eq!(want.len(), got.len(), "{q}");
+        assert!(want.iter().zip(&got).all(|(w, g)| w.0 == g.0 && (w.1 - g.1).abs() < 1e-5), "{q}: {want:?} vs {got:?}");
+    }
+}
+
+#[test]
+fn multi_term_queries_across_fields() {
+    let harness = Harness::new("programming.jsonl");
+
+    // The page with both words, twice each, comes first
+    let json = harness.search(json!({ "q": "async rust", "k": 10 }));
+    assert_eq!(json["total_hits"], 3);
+    assert_eq!(external_ids(&json)[0], "rust-async");
+    assert!(json["results"][0]["snippet"].as_str().unwrap().contains("<em>"));
+
+    // `and` keeps only documents with every word
+    let json = harness.search(json!({ "q": "async rust", "mode": "and" }));
+    assert_eq!(external_ids(&json), vec!["rust-async"]);
+    let json = harness.search(json!({ "q": "learning ownership", "mode": "and" }));
+    assert_eq!(external_ids(&json), vec!["rust-intro"]);
+
+    // "runtime" is in the go page's title and body but only the async rust body,
+    // which wins on the body alone
+    let json = harness.search(json!({ "q": "runtime" }));
+    assert_eq!(external_ids(&json), vec!["rust-async", "go-runtime"]);
+    let json = harness.search(json!({ "q": "runtime", "fields": "title,body", "boosts": { "title": 3.0 } }));
+    assert_eq!(external_ids(&json), vec!["go-runtime", "rust-async"]);
+    let json = harness.search(json!({ "q": "runtime", "fields": "title" }));
+    assert_eq!(external_ids(&json), vec!["go-runtime"]);
+
+    // Words no document has match nothing and say so under debug
+    let json = harness.search(json!({ "q": "haskell monads", "debug": true }));
+    assert_eq!(json["total_hits"], 0);
+    assert_eq!(json["unmatched_terms"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn fields_left_out_of_boosts_count_one() {
+    let harness = Harness::new("programming.jsonl");
+    let scores = |json: &Value| json["results"].as_array().unwrap().iter().map(|h| (h["external_id"].as_str().unwrap().to_string(), h["score"].as_f64().unwrap())).collect::<Vec<_>>();
+
+    // No boosts, boosts naming other fields and an explicit 1.0 all score the url the same
+    let plain = scores(&harness.search(json!({ "q": "rust", "fields": "url" })));
+    assert_eq!(plain.len(), 2);
+    for boosts in [json!({ "title": 3.0 }), json!({ "url": 1.0 })] {
+        let got = scores(&harness.search(json!({ "q": "rust", "fields": "url", "boosts": boosts })));
+        assert!(plain.iter().zip(&got).all(|(p, g)| p.0 == g.0 && (p.1 - g.1).abs() < 1e-6), "{boosts}: {plain:?} vs {got:?}");
+    }
+    let halved = scores(&harness.search(json!({ "q": "rust", "fields": "url", "boosts": { "url": 0.5 } })));
+    assert!(plain.iter().zip(&halved).all(|(p, h)| (p.1 * 0.5 - h.1).abs() < 1e-6), "{plain:?} vs {halved:?}");
+}
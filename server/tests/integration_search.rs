@@ -1,11 +1,11 @@
-use axum::http::{Request, StatusCode};
+use axum::body::Body;
+use axum::http::{HeaderName, HeaderValue, Request, StatusCode};
 use axum::Router;
-use core::persist::{save_dictionary, save_docs, save_meta, save_postings_for_term, IndexPaths, MetaFile};
+use core::persist::{save_dictionary, save_docs, save_meta, save_postings_for_term, IndexPaths, MetaFile, INDEX_VERSION};
 use core::{DocId, DocMeta, Posting, TermId};
+use server::ServerConfig;
 use http_body_util::BodyExt;
 use hyper::body::Bytes;
-use hyper::body::Incoming as IncomingBody;
-use hyper::Request as HyperRequest;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
@@ -24,8 +24,8 @@ fn build_tiny_index(dir: &std::path::Path) {
 
     // Docs metadata
     let mut docs: HashMap<DocId, DocMeta> = HashMap::new();
-    docs.insert(0, DocMeta { external_id: "doc0".into(), title: "Doc 0".into(), url: None, text_path: Some("texts/0.txt".into()) });
-    docs.insert(1, DocMeta { external_id: "doc1".into(), title: "Doc 1".into(), url: None, text_path: Some("texts/1.txt".into()) });
+    docs.insert(0, DocMeta { external_id: "doc0".into(), title: "Doc 0".into(), url: None, text_path: Some("texts/0.txt".into()), facets: Default::default() });
+    docs.insert(1, DocMeta { external_id: "doc1".into(), title: "Learning Rust".into(), url: None, text_path: Some("texts/1.txt".into()), facets: Default::default() });
     save_docs(&paths, &docs).unwrap();
 
     // Texts
@@ -41,28 +41,33 @@ fn build_tiny_index(dir: &std::path::Path) {
     save_postings_for_term(&paths, 0, &postings).unwrap();
 
     // Meta
-    let meta = MetaFile { num_docs: 2, created_at: "2024-01-01T00:00:00Z".into(), version: 1 };
+    let meta = MetaFile { num_docs: 2, created_at: "2024-01-01T00:00:00Z".into(), version: INDEX_VERSION, ..Default::default() };
     save_meta(&paths, &meta).unwrap();
 }
 
-async fn call(app: Router, uri: &str) -> (StatusCode, Bytes) {
-    let req: HyperRequest<hyper::body::Body> = Request::get(uri).body(hyper::body::Body::empty()).unwrap().map(
-        |b| match b {}
-    );
-    let svc = app.into_service();
-    let resp = tower::ServiceExt::oneshot(svc, req).await.unwrap();
-    let status = resp.status();
-    let body = resp.into_body().collect().await.unwrap().to_bytes();
-    (status, body)
+// The workspace `core` crate shadows `::core`, which `#[tokio::test]` expands into,
+// so requests are driven on a runtime built here instead.
+fn send(app: Router, req: Request<Body>) -> (StatusCode, Bytes) {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        let resp = tower::ServiceExt::oneshot(app, req).await.unwrap();
+        let status = resp.status();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        (status, body)
+    })
+}
+
+fn call(app: Router, uri: &str) -> (StatusCode, Bytes) {
+    send(app, Request::get(uri).body(Body::empty()).unwrap())
 }
 
-#[tokio::test]
-async fn search_returns_ranked_results() {
+#[test]
+fn search_returns_ranked_results() {
     let dir = tempdir().unwrap();
     build_tiny_index(dir.path());
     let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
 
-    let (status, body) = call(app, "/search?q=rust&k=2").await;
+    let (status, body) = call(app, "/search?q=rust&k=2");
     assert_eq!(status, StatusCode::OK);
     let json: Value = serde_json::from_slice(&body).unwrap();
     let arr = json["results"].as_array().unwrap();
@@ -72,3 +77,1892 @@ async fn search_returns_ranked_results() {
     assert_eq!(d0, 0);
     assert_eq!(d1, 1);
 }
+
+#[test]
+fn search_title_field_only_matches_titles() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app.clone(), "/search?q=rust&fields=title");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let arr = json["results"].as_array().unwrap();
+    assert_eq!(arr.len(), 1);
+    assert_eq!(arr[0]["doc_id"].as_u64().unwrap(), 1);
+
+    let (status, _) = call(app, "/search?q=rust&fields=author");
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn field_prefixes_route_terms_to_one_field() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    // `title:` searches titles even though `fields` defaults to body
+    let (_, body) = call(app.clone(), "/search?q=title:learning");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let ids: Vec<u64> = json["results"].as_array().unwrap().iter().map(|h| h["doc_id"].as_u64().unwrap()).collect();
+    assert_eq!(ids, vec![1]);
+    // The prefix is not highlighted as part of the term
+    assert_eq!(json["results"][0]["snippet"], "<em>Learning</em> rust.");
+
+    // Unprefixed terms still search the body
+    let (_, body) = call(app, "/search?q=title:learning%20rust");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["results"].as_array().unwrap().len(), 2);
+    assert_eq!(json["results"][0]["doc_id"], 1);
+}
+
+#[test]
+fn search_debug_reports_term_coverage() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (_, body) = call(app.clone(), "/search?q=rust+zebra&debug=true");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["corpus_size"], 2);
+    assert_eq!(json["matched_terms"], serde_json::json!(["rust"]));
+    assert_eq!(json["unmatched_terms"], serde_json::json!(["zebra"]));
+    assert_eq!(
+        json["parsed"],
+        serde_json::json!({
+            "raw": "rust zebra",
+            "fields": [{
+                "field": "body",
+                "boost": 1.0,
+                "terms": [
+                    { "term": "rust", "term_id": 0, "df": 2, "weight": 1.0 },
+                    { "term": "zebra", "term_id": null, "df": null, "weight": null },
+                ],
+            }],
+        })
+    );
+
+    let (_, body) = call(app.clone(), "/search?q=rust+zebra");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["unmatched_terms"], serde_json::json!(["zebra"]));
+    assert!(json.get("matched_terms").is_none());
+    assert!(json.get("parsed").is_none());
+
+    let (_, body) = call(app, "/search?q=rust");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json.get("unmatched_terms").is_none());
+}
+
+#[test]
+fn out_of_vocabulary_words_are_dropped_by_or_and_fail_and() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, json) = post_search(app.clone(), serde_json::json!({ "q": "rust xyzzyplugh" }));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(hit_ids(&json), vec![0, 1]);
+    assert_eq!(json["unmatched_terms"], serde_json::json!(["xyzzyplugh"]));
+
+    let (status, json) = post_search(app.clone(), serde_json::json!({ "q": "rust xyzzyplugh", "mode": "and" }));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((json["total_hits"].as_u64(), hit_ids(&json)), (Some(0), vec![]));
+    assert_eq!(json["unmatched_terms"], serde_json::json!(["xyzzyplugh"]));
+
+    let (_, json) = post_search(app, serde_json::json!({ "q": "rust", "mode": "and" }));
+    assert_eq!(hit_ids(&json), vec![0, 1]);
+}
+
+#[test]
+fn indexes_from_a_newer_indexer_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let paths = IndexPaths::new(dir.path());
+    let mut meta = core::persist::load_meta(&paths).unwrap();
+    meta.version = INDEX_VERSION + 1;
+    save_meta(&paths, &meta).unwrap();
+    let err = server::LoadedIndex::load(&dir.path().to_string_lossy(), true).err().expect("a newer format must not load");
+    assert!(format!("{err:#}").contains(&format!("format version {}", INDEX_VERSION + 1)), "{err:#}");
+}
+
+#[test]
+fn admin_reindex_swaps_in_new_index() {
+    let dir = tempdir().unwrap();
+    let index_dir = dir.path().join("index");
+    build_tiny_index(&index_dir);
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, r#"{"id":"a","title":"Zebra facts","body":"zebras have stripes"}"#).unwrap();
+    let config = ServerConfig { admin_token: Some("secret".into()), ..Default::default() };
+    let app = server::build_app_with_config(index_dir.to_string_lossy().to_string(), config).unwrap();
+
+    let body = serde_json::json!({ "input": input.to_string_lossy() }).to_string();
+    let unauthorized = Request::post("/admin/reindex").header("content-type", "application/json").body(Body::from(body.clone())).unwrap();
+    assert_eq!(send(app.clone(), unauthorized).0, StatusCode::UNAUTHORIZED);
+
+    assert_eq!(reindex(app.clone(), &input), "succeeded");
+
+    let (_, body) = call(app, "/search?q=zebra");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["results"][0]["title"], "Zebra facts");
+}
+
+/// Start `/admin/reindex` from `input` (admin token "secret") and wait for it; returns the final state.
+fn reindex(app: Router, input: &std::path::Path) -> String {
+    let body = serde_json::json!({ "input": input.to_string_lossy() }).to_string();
+    let req = Request::post("/admin/reindex")
+        .header("content-type", "application/json")
+        .header("X-ADMIN-TOKEN", "secret")
+        .body(Body::from(body))
+        .unwrap();
+    assert_eq!(send(app.clone(), req).0, StatusCode::ACCEPTED);
+
+    let mut state = String::new();
+    for _ in 0..100 {
+        let req = Request::get("/admin/reindex/status").header("X-ADMIN-TOKEN", "secret").body(Body::empty()).unwrap();
+        let (_, body) = send(app.clone(), req);
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        state = json["state"].as_str().unwrap().to_string();
+        if state != "running" { break; }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    state
+}
+
+/// Directories next to `index_dir` that a reindex moved the old build to.
+fn retired_builds(index_dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let name = format!("{}.retired-", index_dir.file_name().unwrap().to_string_lossy());
+    fs::read_dir(index_dir.parent().unwrap()).unwrap().map(|e| e.unwrap().path()).filter(|p| p.file_name().unwrap().to_string_lossy().starts_with(&name)).collect()
+}
+
+#[test]
+fn snapshots_held_across_a_reindex_keep_reading_the_old_build() {
+    let dir = tempdir().unwrap();
+    let index_dir = dir.path().join("index");
+    build_tiny_index(&index_dir);
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Zebra facts","body":"zebras have stripes"}"#, "\n",
+        r#"{"id":"b","title":"Okapi facts","body":"okapis have stripes too"}"#, "\n",
+    )).unwrap();
+    let config = ServerConfig { admin_token: Some("secret".into()), ..Default::default() };
+    let searcher = server::Searcher::with_config(&index_dir.to_string_lossy(), config).unwrap();
+    let app = searcher.clone().router();
+
+    // As a search in flight holds it
+    let old = searcher.index();
+    let old_text = old.doc_text(0).unwrap().into_owned();
+    assert_eq!(reindex(app.clone(), &input), "succeeded");
+    assert_eq!(searcher.index().docs[&0].external_id, "a");
+
+    // The old snapshot reads its own postings and texts, now from where the old build was moved
+    let retired = retired_builds(&index_dir);
+    assert_eq!(retired, vec![old.root.path()]);
+    let postings = old.root.with(|root| core::persist::load_postings_for_term(&IndexPaths::new(root), old.dictionary["rust"])).unwrap();
+    assert_eq!(postings.iter().map(|p| p.doc_id).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(old.doc_text(0).unwrap(), old_text);
+    assert_eq!(old.doc_text_head(1, 7).as_deref(), Some("Learnin"));
+
+    // The new build is served from the original path, and the old one goes with its last snapshot
+    assert_eq!(searcher.index().root.path(), index_dir);
+    let (_, json) = post_search(app, serde_json::json!({ "q": "stripes" }));
+    assert_eq!(json["total_hits"], 2);
+    drop(old);
+    assert!(retired_builds(&index_dir).is_empty());
+}
+
+/// Build an index from JSONL `lines` into `dir` with smoothed idf, so no stored weight is zero.
+fn build_smooth(dir: &std::path::Path, lines: &[&str]) -> server::Searcher {
+    let input = dir.with_extension("jsonl");
+    fs::write(&input, lines.join("\n")).unwrap();
+    let opts = indexer::BuildOptions { weighting: core::weighting::Weighting { idf: core::weighting::IdfScheme::Smooth, ..Default::default() }, ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &dir.to_string_lossy(), &opts).unwrap();
+    let config = ServerConfig { admin_token: Some("secret".into()), ..Default::default() };
+    server::Searcher::with_config(&dir.to_string_lossy(), config).unwrap()
+}
+
+/// POST `body` to an admin endpoint with token "secret".
+fn admin_post(app: Router, uri: &str, body: Value) -> (StatusCode, Bytes) {
+    let req = Request::post(uri).header("content-type", "application/json").header("X-ADMIN-TOKEN", "secret").body(Body::from(body.to_string())).unwrap();
+    send(app, req)
+}
+
+/// Commit the buffered documents and wait for any reweight pass; returns the final status.
+fn commit(app: Router, reweight: bool) -> Value {
+    let (status, _) = admin_post(app.clone(), &format!("/index/commit?reweight={reweight}"), Value::Null);
+    assert!(status == StatusCode::OK || status == StatusCode::ACCEPTED, "{status}");
+    let mut json = Value::Null;
+    for _ in 0..100 {
+        let req = Request::get("/index/commit/status").header("X-ADMIN-TOKEN", "secret").body(Body::empty()).unwrap();
+        json = serde_json::from_slice(&send(app.clone(), req).1).unwrap();
+        if json["state"] != "running" { break; }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    json
+}
+
+/// Every body posting list of `served` matches that of the index at `fresh`, term by term.
+fn assert_same_postings(served: &server::LoadedIndex, fresh: &std::path::Path) {
+    let fresh_paths = IndexPaths::new(fresh);
+    let (fresh_dictionary, fresh_df) = core::persist::load_dictionary(&fresh_paths).unwrap();
+    assert_eq!(served.dictionary.len(), fresh_dictionary.len());
+    for (term, &fresh_tid) in &fresh_dictionary {
+        let tid = served.dictionary[term];
+        assert_eq!(served.df[tid as usize], fresh_df[fresh_tid as usize], "df of {term}");
+        let got = served.root.with(|root| core::persist::load_postings_for_term(&IndexPaths::new(root), tid)).unwrap();
+        let want = core::persist::load_postings_for_term(&fresh_paths, fresh_tid).unwrap();
+        assert_eq!(got.iter().map(|p| p.doc_id).collect::<Vec<_>>(), want.iter().map(|p| p.doc_id).collect::<Vec<_>>(), "docs of {term}");
+        for (g, w) in got.iter().zip(&want) {
+            assert!((g.weight - w.weight).abs() < 1e-5, "{term} in doc {}: {} vs {}", g.doc_id, g.weight, w.weight);
+        }
+    }
+}
+
+const COMMIT_DOCS: [&str; 4] = [
+    r#"{"id":"a","title":"Rust ownership","body":"rust ownership and borrowing"}"#,
+    r#"{"id":"b","title":"Python","body":"python garbage collection"}"#,
+    r#"{"id":"c","title":"Async Rust","body":"async rust with tokio tokio"}"#,
+    r#"{"id":"d","title":"Python async","body":"python async await"}"#,
+];
+
+fn batch_of(lines: &[&str]) -> Value {
+    Value::Array(lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect())
+}
+
+#[test]
+fn commit_without_reweight_appends_and_reports_idf_drift() {
+    let dir = tempdir().unwrap();
+    let index_dir = dir.path().join("index");
+    let searcher = build_smooth(&index_dir, &COMMIT_DOCS[..2]);
+    let app = searcher.clone().router();
+    let rust_before = searcher.index().root.with(|root| core::persist::load_postings_for_term(&IndexPaths::new(root), searcher.index().dictionary["rust"])).unwrap();
+
+    let (status, body) = admin_post(app.clone(), "/index/batch", batch_of(&COMMIT_DOCS[2..3]));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), serde_json::json!({ "accepted": 1, "buffered": 1 }));
+    // Buffered documents count in df but don't match until committed
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "tokio" }));
+    assert_eq!(json["total_hits"], 0);
+    assert_eq!(admin_post(app.clone(), "/index/batch", batch_of(&COMMIT_DOCS[..1])).0, StatusCode::CONFLICT);
+    assert_eq!(admin_post(app.clone(), "/index/batch", batch_of(&COMMIT_DOCS[2..3])).0, StatusCode::CONFLICT);
+
+    let status = commit(app.clone(), false);
+    assert_eq!(status["state"], "succeeded");
+    let report = &status["report"];
+    assert_eq!((report["committed"].as_u64(), report["num_docs"].as_u64(), report["new_terms"].as_u64()), (Some(1), Some(3), Some(2)));
+    assert_eq!(report["stale_docs"], 2);
+    assert!(report["max_idf_drift"].as_f64().unwrap() > 0.0);
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "tokio" }));
+    assert_eq!(hit_ids(&json), vec![2]);
+    let (_, body) = call(app.clone(), "/doc/by-external/c");
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["doc_id"], 2);
+    assert_eq!(searcher.index().doc_text(2).unwrap(), "async rust with tokio tokio");
+    // Earlier documents keep the weights they were built with
+    let index = searcher.index();
+    let rust_after = index.root.with(|root| core::persist::load_postings_for_term(&IndexPaths::new(root), index.dictionary["rust"])).unwrap();
+    assert_eq!(rust_after[0].weight, rust_before[0].weight);
+    assert_eq!(rust_after.iter().map(|p| p.doc_id).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(index.root.path(), index_dir);
+
+    // Nothing buffered: the index stays as it is
+    assert_eq!(commit(app, false)["report"]["committed"], 0);
+}
+
+#[test]
+fn reweight_pass_matches_a_full_rebuild() {
+    let dir = tempdir().unwrap();
+    let index_dir = dir.path().join("index");
+    let searcher = build_smooth(&index_dir, &COMMIT_DOCS[..2]);
+    let app = searcher.clone().router();
+
+    // Two commits leave two sets of stale weights, both fixed by the pass
+    admin_post(app.clone(), "/index/batch", batch_of(&COMMIT_DOCS[2..3]));
+    assert_eq!(commit(app.clone(), false)["report"]["stale_docs"], 2);
+    admin_post(app.clone(), "/index/batch", batch_of(&COMMIT_DOCS[3..]));
+    let status = commit(app.clone(), true);
+    assert_eq!(status["state"], "succeeded", "{status}");
+    assert_eq!(status["report"]["stale_docs"], 3);
+    assert_eq!(status["approximated_postings"], 0);
+    assert!(status["resumed_from"].is_null());
+
+    let rebuilt = dir.path().join("rebuilt");
+    build_smooth(&rebuilt, &COMMIT_DOCS);
+    assert_same_postings(&searcher.index(), &rebuilt);
+    assert!(!index_dir.join("weight_bases.bin").exists());
+    // Nothing is stale any more
+    assert_eq!(commit(app, false)["report"]["stale_docs"], 0);
+}
+
+#[test]
+fn interrupted_reweight_pass_resumes_from_its_checkpoint() {
+    let dir = tempdir().unwrap();
+    let index_dir = dir.path().join("index");
+    let searcher = build_smooth(&index_dir, &COMMIT_DOCS[..2]);
+    let app = searcher.clone().router();
+    admin_post(app.clone(), "/index/batch", batch_of(&COMMIT_DOCS[2..]));
+    commit(app.clone(), false);
+
+    // A postings file that can't be read stops the pass part-way
+    let tid = searcher.index().dictionary["python"];
+    let file = IndexPaths::new(&index_dir).postings_file(tid);
+    let bytes = fs::read(&file).unwrap();
+    fs::remove_file(&file).unwrap();
+    let status = commit(app.clone(), true);
+    assert_eq!(status["state"], "failed");
+    assert_eq!(status["phase"], "norms");
+    assert_eq!(status["terms_done"], tid);
+
+    fs::write(&file, bytes).unwrap();
+    let status = commit(app.clone(), true);
+    assert_eq!(status["state"], "succeeded", "{status}");
+    assert_eq!(status["resumed_from"], serde_json::json!({ "phase": "norms", "next_term": tid }));
+
+    let rebuilt = dir.path().join("rebuilt");
+    build_smooth(&rebuilt, &COMMIT_DOCS);
+    assert_same_postings(&searcher.index(), &rebuilt);
+    assert!(!dir.path().join("index.reweight").exists());
+}
+
+#[test]
+fn indexes_with_unscalable_weights_refuse_batches() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, COMMIT_DOCS[0]).unwrap();
+    let index_dir = dir.path().join("index");
+    indexer::build_index(&input.to_string_lossy(), &index_dir.to_string_lossy(), &indexer::BuildOptions { store_doc_norms: true, ..Default::default() }).unwrap();
+    let config = ServerConfig { admin_token: Some("secret".into()), ..Default::default() };
+    let app = server::build_app_with_config(index_dir.to_string_lossy().to_string(), config).unwrap();
+
+    let (status, body) = admin_post(app, "/index/batch", batch_of(&COMMIT_DOCS[1..2]));
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(String::from_utf8_lossy(&body).contains("--store-doc-norms"));
+}
+
+#[test]
+fn missing_docs_and_unknown_routes_are_json_404s() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app.clone(), "/doc/99");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["error"], "not found");
+    let (status, body) = call(app.clone(), "/no/such/route");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["error"], "not found");
+
+    let head = |uri: &str| Request::builder().method("HEAD").uri(uri).body(Body::empty()).unwrap();
+    let (status, body) = send(app.clone(), head("/doc/0"));
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_empty());
+    let (status, _) = send(app, head("/doc/99"));
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn list_docs_pages_in_doc_id_order() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app, "/docs?offset=1&limit=5");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["total"], 2);
+    let docs = json["docs"].as_array().unwrap();
+    assert_eq!(docs.len(), 1);
+    assert_eq!(docs[0]["doc_id"], 1);
+    assert_eq!(docs[0]["external_id"], "doc1");
+    assert_eq!(docs[0]["title"], "Learning Rust");
+}
+
+#[test]
+fn metrics_count_searches() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    call(app.clone(), "/search?q=rust");
+    call(app.clone(), "/search?q=zebra");
+    let (status, body) = call(app, "/metrics");
+    assert_eq!(status, StatusCode::OK);
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("zapsearch_searches_total 2"));
+    assert!(text.contains("zapsearch_zero_result_searches_total 1"));
+    assert!(text.contains("zapsearch_request_duration_seconds_count{endpoint=\"search\"} 2"));
+}
+
+#[test]
+fn doc_text_can_be_capped_or_streamed() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (_, body) = call(app.clone(), "/doc/0?max_chars=4");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["text"], "Rust");
+    assert_eq!(json["truncated"], true);
+
+    let (_, body) = call(app.clone(), "/doc/1?max_chars=100");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["text"], "Learning rust.");
+    assert_eq!(json["truncated"], false);
+
+    let (status, body) = call(app.clone(), "/doc/0/raw");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[..], b"Rust is great. rust systems programming.");
+    let (status, body) = call(app, "/doc/9/raw");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), serde_json::json!({ "error": "not found" }));
+}
+
+#[test]
+fn docs_without_stored_text_have_no_snippet_or_text() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    // As written by `indexer build --no-store-text`
+    let mut docs: HashMap<DocId, DocMeta> = HashMap::new();
+    docs.insert(0, DocMeta { external_id: "doc0".into(), title: "Doc 0".into(), url: None, text_path: None, facets: Default::default() });
+    docs.insert(1, DocMeta { external_id: "doc1".into(), title: "Learning Rust".into(), url: None, text_path: None, facets: Default::default() });
+    save_docs(&IndexPaths::new(dir.path()), &docs).unwrap();
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app.clone(), "/search?q=rust");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["results"].as_array().unwrap().len(), 2);
+    assert!(json["results"][0]["snippet"].is_null());
+
+    let (_, body) = call(app.clone(), "/doc/0");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["text_available"], false);
+    assert!(json.get("text").is_none());
+    let (status, _) = call(app, "/doc/0/raw");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn control_chars_and_invalid_utf8_still_serve_snippets() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    // A NUL and an escape as JSON escapes, then raw bytes that aren't UTF-8
+    let mut bytes = br#"{"id":"a","title":"Alpha","body":"Rust\u0000 is\u001b safe "#.to_vec();
+    bytes.extend_from_slice(b"\xff\xfe programming\"}\n");
+    bytes.extend_from_slice(b"{\"id\":\"b\",\"title\":\"Beta\",\"body\":\"Gardening tips.\"}\n");
+    fs::write(&input, bytes).unwrap();
+
+    for text_storage in [indexer::TextStorage::Packed, indexer::TextStorage::Files] {
+        let index_dir = dir.path().join(format!("{text_storage:?}")).to_string_lossy().to_string();
+        let opts = indexer::BuildOptions { text_storage, ..Default::default() };
+        let report = indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+        assert_eq!((report.num_docs, report.skipped_records), (2, 0));
+        let app = server::build_app(index_dir).unwrap();
+
+        let (status, json) = post_search(app.clone(), serde_json::json!({ "q": "programming" }));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["results"][0]["snippet"], "Rust is safe \u{FFFD}\u{FFFD} <em>programming</em>");
+        let (_, body) = call(app, "/doc/0");
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["text"], "Rust is safe \u{FFFD}\u{FFFD} programming");
+    }
+
+    // `space` keeps word boundaries; `keep` stores them as they came
+    for (control_chars, want) in [(indexer::ControlChars::Space, "Rust  is  safe"), (indexer::ControlChars::Keep, "Rust\0 is\x1b safe")] {
+        let index_dir = dir.path().join(format!("{control_chars:?}")).to_string_lossy().to_string();
+        indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions { control_chars, ..Default::default() }).unwrap();
+        let (_, body) = call(server::build_app(index_dir).unwrap(), "/doc/0");
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert!(json["text"].as_str().unwrap().starts_with(want), "{control_chars:?}: {}", json["text"]);
+    }
+
+    // External text files aren't sanitized by the indexer, so the server reads them lossily
+    let text_dir = dir.path().join("external");
+    fs::create_dir_all(&text_dir).unwrap();
+    fs::write(text_dir.join("a.txt"), b"Rust \xff programming").unwrap();
+    fs::write(text_dir.join("b.txt"), b"Gardening tips.").unwrap();
+    let index_dir = dir.path().join("ext").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { text_storage: indexer::TextStorage::External(text_dir), ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let (_, json) = post_search(server::build_app(index_dir).unwrap(), serde_json::json!({ "q": "programming" }));
+    assert_eq!(json["results"][0]["snippet"], "Rust \u{FFFD} <em>programming</em>");
+}
+
+#[test]
+fn packed_texts_serve_snippets_and_docs() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Alpha","body":"Rust makes systems programming safe."}"#, "\n",
+        r#"{"id":"b","title":"Beta","body":"Gardening tips for spring."}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index");
+    let (input, index_dir) = (input.to_string_lossy().to_string(), index_dir.to_string_lossy().to_string());
+    indexer::build_index(&input, &index_dir, &indexer::BuildOptions::default()).unwrap();
+    assert!(std::path::Path::new(&index_dir).join("texts.dat").exists());
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, body) = call(app.clone(), "/search?q=rust");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["results"][0]["snippet"], "<em>Rust</em> makes systems programming safe.");
+
+    let (_, body) = call(app.clone(), "/doc/1?max_chars=9");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["text"], "Gardening");
+    assert_eq!(json["truncated"], true);
+
+    let (status, body) = call(app, "/doc/1/raw");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[..], b"Gardening tips for spring.");
+}
+
+#[test]
+fn snippets_scan_only_the_first_bytes_of_long_texts() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, format!(r#"{{"id":"a","title":"A","body":"{} needle"}}"#, "hay ".repeat(20))).unwrap();
+    for per_file_texts in [false, true] {
+        let index_dir = dir.path().join(format!("index-{per_file_texts}")).to_string_lossy().to_string();
+        let text_storage = if per_file_texts { indexer::TextStorage::Files } else { indexer::TextStorage::Packed };
+        let opts = indexer::BuildOptions { text_storage, ..Default::default() };
+        indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+
+        let app = server::build_app_with_config(index_dir.clone(), ServerConfig::default()).unwrap();
+        let (_, json) = post_search(app, serde_json::json!({ "q": "needle" }));
+        assert!(json["results"][0]["snippet"].as_str().unwrap().ends_with("<em>needle</em>"));
+
+        // The match lies past the cap, so the snippet is the capped lead
+        let config = ServerConfig { snippet_max_read_bytes: 12, ..Default::default() };
+        let app = server::build_app_with_config(index_dir, config).unwrap();
+        let (_, json) = post_search(app, serde_json::json!({ "q": "needle" }));
+        assert_eq!(json["results"][0]["snippet"], "hay hay hay ");
+    }
+}
+
+#[test]
+fn snippet_fallback_chooses_lead_meta_or_none() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Gardening","body":"Home | About | Contact","meta":{"description":"Plan a spring garden."}}"#, "\n",
+        r#"{"id":"b","title":"Gardening again","body":"Menu | Login"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    // The query only occurs in titles, so every snippet is a fallback
+    let snippets = |fallback: &str| {
+        let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "title:gardening", "snippet_fallback": fallback }));
+        json["results"].as_array().unwrap().iter().map(|h| (h["doc_id"].as_u64().unwrap(), h["snippet"].clone())).collect::<HashMap<_, _>>()
+    };
+    assert_eq!(snippets("lead")[&0], "Home | About | Contact");
+    assert_eq!(snippets("none")[&0], Value::Null);
+    let meta = snippets("meta");
+    assert_eq!(meta[&0], "Plan a spring garden.");
+    // No description stored: back to the lead
+    assert_eq!(meta[&1], "Menu | Login");
+}
+
+#[test]
+fn browse_lists_docs_newest_first() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"old","title":"Old","body":"rust","timestamp":"2023-01-01T00:00:00Z"}"#, "\n",
+        r#"{"id":"undated","title":"Undated","body":"go"}"#, "\n",
+        r#"{"id":"new","title":"New","body":"rust","timestamp":"2024-06-01T12:00:00Z"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    // Without the flag an empty query still finds nothing
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": " " }));
+    assert_eq!(json["total_hits"], 0);
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "", "browse": true, "mode": "and" }));
+    assert_eq!(json["total_hits"], 3);
+    assert_eq!(hit_ids(&json), vec![2, 0, 1]);
+
+    let (_, json) = post_search(app, serde_json::json!({ "q": "", "browse": true, "filters": ["rust"], "k": 1, "offset": 1 }));
+    assert_eq!(json["total_hits"], 2);
+    assert_eq!(hit_ids(&json), vec![0]);
+}
+
+#[test]
+fn case_sensitive_search_uses_the_preserved_case_channel() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"use a HashMap here"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"the hashmap crate"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { preserve_case: true, ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "HashMap" }));
+    assert_eq!(json["total_hits"], 2);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "HashMap", "case_sensitive": true }));
+    assert_eq!(hit_ids(&json), vec![0]);
+    let (_, json) = post_search(app, serde_json::json!({ "q": "body:hash*", "case_sensitive": true }));
+    assert_eq!(hit_ids(&json), vec![1]);
+
+    // Indexes built without the channel reject the flag
+    let tiny = tempdir().unwrap();
+    build_tiny_index(tiny.path());
+    let app = server::build_app(tiny.path().to_string_lossy().to_string()).unwrap();
+    let (status, _) = post_search(app, serde_json::json!({ "q": "rust", "case_sensitive": true }));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn queries_use_the_analyzer_recorded_at_build_time() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"running shoes"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"run club"}"#, "\n",
+    )).unwrap();
+    let analyzer = dir.path().join("analyzer.toml");
+    fs::write(&analyzer, "stemmer = \"none\"\nstopwords = [\"club\"]\n").unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { tokenizer: indexer::load_analyzer(&analyzer).unwrap(), ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "running" }));
+    assert_eq!(hit_ids(&json), vec![0]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "run" }));
+    assert_eq!(hit_ids(&json), vec![1]);
+    let (_, json) = post_search(app, serde_json::json!({ "q": "club" }));
+    assert_eq!(json["total_hits"], 0);
+}
+
+#[test]
+fn facet_fields_filter_by_meta_values() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"rust guide","meta":{"category":"Rust","author":["Ann","Bo"],"views":10}}"#, "\n",
+        r#"{"id":"b","title":"B","body":"rust news","meta":{"category":"news","author":"Bo"}}"#, "\n",
+        r#"{"id":"c","title":"C","body":"rust misc"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { facet_fields: vec!["category".into(), "author".into()], ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, body) = call(app.clone(), "/search?q=rust&filter=category:rust");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(hit_ids(&json), vec![0]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "filters": ["author:bo"] }));
+    let mut ids = hit_ids(&json);
+    ids.sort();
+    assert_eq!(ids, vec![0, 1]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "filters": ["author:bo", "news"] }));
+    assert_eq!(hit_ids(&json), vec![1]);
+    // Keys that weren't configured aren't facets: `views:10` is body text
+    let (_, json) = post_search(app, serde_json::json!({ "q": "rust", "filters": ["views:10"] }));
+    assert_eq!(json["total_hits"], 0);
+}
+
+#[test]
+fn index_meta_makes_description_and_keywords_searchable() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"garden tools","meta":{"description":"Pruning shears reviewed","keywords":["secateurs","loppers"]}}"#, "\n",
+        r#"{"id":"b","title":"B","body":"garden beds"}"#, "\n",
+    )).unwrap();
+    let plain_dir = dir.path().join("plain").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &plain_dir, &indexer::BuildOptions::default()).unwrap();
+    let (_, json) = post_search(server::build_app(plain_dir).unwrap(), serde_json::json!({ "q": "secateurs" }));
+    assert_eq!(json["total_hits"], 0);
+
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { index_meta: vec!["description".into(), "keywords".into()], ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "secateurs" }));
+    assert_eq!(hit_ids(&json), vec![0]);
+    let (_, json) = post_search(app, serde_json::json!({ "q": "pruning shears", "mode": "and" }));
+    assert_eq!(hit_ids(&json), vec![0]);
+}
+
+#[test]
+fn keep_hyphenated_matches_the_compound_at_query_time() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"real-time search"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"search in real time"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let tokenizer = core::tokenizer::TokenizerConfig { keep_hyphenated: true, ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions { tokenizer, ..Default::default() }).unwrap();
+    // The setting comes from meta.json, so the query is tokenized into the same compound
+    let (_, json) = post_search(server::build_app(index_dir).unwrap(), serde_json::json!({ "q": "real-time" }));
+    assert_eq!(hit_ids(&json), vec![0]);
+}
+
+#[test]
+fn proximity_ranks_adjacent_query_words_first() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"rust search engine internals"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"rust tooling search"}"#, "\n",
+        r#"{"id":"c","title":"C","body":"garden beds"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+    // The shorter doc wins on bag-of-words scores
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust search" }));
+    assert_eq!(hit_ids(&json), vec![1, 0]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust search", "proximity": true, "slop": 0 }));
+    assert_eq!(hit_ids(&json), vec![0, 1]);
+    // With enough slop both count as close, and the order is back to the base scores
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust search", "proximity": true, "slop": 1 }));
+    assert_eq!(hit_ids(&json), vec![1, 0]);
+    let (status, _) = post_search(app, serde_json::json!({ "q": "rust search", "proximity": true, "proximity_boost": -1.0 }));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn snippet_fields_pick_the_field_that_shows_the_query() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Pruning shears","body":"Our garden tools, reviewed. Shears come last.","meta":{"description":"Pruning shears for roses and hedges"}}"#, "\n",
+        r#"{"id":"b","title":"B","body":"garden beds"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+    let q = "pruning shears";
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": q, "fields": "title,body" }));
+    assert_eq!(json["results"][0]["snippet"], "Our garden tools, reviewed. <em>Shears</em> come last.");
+    assert!(json["results"][0].get("snippet_field").is_none());
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": q, "fields": "title,body", "snippet_fields": "body,description" }));
+    assert_eq!(json["results"][0]["snippet_field"], "description");
+    assert_eq!(json["results"][0]["snippet"], "<em>Pruning</em> <em>shears</em> for roses and hedges");
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": q, "fields": "title,body", "snippet_fields": "body,title,description" }));
+    assert_eq!(json["results"][0]["snippet_field"], "title");
+
+    let (status, _) = post_search(app, serde_json::json!({ "q": q, "snippet_fields": "summary" }));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[cfg(unix)]
+#[test]
+fn swap_symlink_then_reload_serves_the_new_build() {
+    let dir = tempdir().unwrap();
+    let build = |name: &str, body: &str| {
+        let input = dir.path().join(format!("{name}.jsonl"));
+        fs::write(&input, format!(r#"{{"id":"{name}","title":"T","body":"{body}"}}"#)).unwrap();
+        let out = dir.path().join(name);
+        indexer::build_index(&input.to_string_lossy(), &out.to_string_lossy(), &indexer::BuildOptions::default()).unwrap();
+        assert!(core::verify::verify_index(&IndexPaths::new(&out)).is_ok());
+        out
+    };
+    let link = dir.path().join("index");
+    let v1 = build("v1", "rust search");
+    assert_eq!(indexer::swap::swap_symlink(&link, &v1).unwrap(), None);
+    let searcher = server::Searcher::open(&link.to_string_lossy()).unwrap();
+    let external = |searcher: &server::Searcher| searcher.search("search", 10, Default::default()).unwrap().results.iter().map(|h| h.external_id.clone()).collect::<Vec<_>>();
+    assert_eq!(external(&searcher), vec!["v1"]);
+
+    let v2 = build("v2", "python search");
+    // Building into the live directory is refused before anything is written
+    assert!(indexer::swap::check_swap_target(&link, &v1).is_err());
+    assert_eq!(indexer::swap::swap_symlink(&link, &v2).unwrap(), Some(fs::canonicalize(&v1).unwrap()));
+    // Still pinned to v1 until reloaded
+    assert_eq!(external(&searcher), vec!["v1"]);
+    searcher.reload().unwrap();
+    assert_eq!(external(&searcher), vec!["v2"]);
+
+    // A damaged build fails verification
+    fs::remove_dir_all(v2.join("postings")).unwrap();
+    assert!(core::verify::verify_index(&IndexPaths::new(&v2)).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn reindex_after_symlink_reloads_moves_every_snapshot_of_the_build() {
+    let dir = tempdir().unwrap();
+    let v1 = dir.path().join("v1");
+    build_tiny_index(&v1);
+    let link = dir.path().join("index");
+    indexer::swap::swap_symlink(&link, &v1).unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, r#"{"id":"a","title":"Zebra facts","body":"zebras have stripes"}"#).unwrap();
+    let config = ServerConfig { admin_token: Some("secret".into()), ..Default::default() };
+    let searcher = server::Searcher::with_config(&link.to_string_lossy(), config).unwrap();
+    let app = searcher.clone().router();
+
+    // Reloading the same target twice leaves two snapshots of one directory, both pinned to it
+    let first = searcher.index();
+    let second = searcher.reload().unwrap();
+    assert!(std::sync::Arc::ptr_eq(&first.root, &second.root));
+    assert_eq!(first.root.path(), fs::canonicalize(&v1).unwrap());
+
+    // The reindex replaces the build the link names; both snapshots follow the old one
+    assert_eq!(reindex(app, &input), "succeeded");
+    assert_eq!(fs::canonicalize(&link).unwrap(), fs::canonicalize(&v1).unwrap());
+    assert_eq!(searcher.index().docs[&0].external_id, "a");
+    assert_eq!(retired_builds(&v1), vec![first.root.path()]);
+    for snapshot in [&first, &second] {
+        assert_eq!(snapshot.doc_text(1).as_deref(), Some("Learning rust."));
+        let postings = snapshot.root.with(|root| core::persist::load_postings_for_term(&IndexPaths::new(root), snapshot.dictionary["rust"])).unwrap();
+        assert_eq!(postings.len(), 2);
+    }
+    drop(first);
+    assert_eq!(retired_builds(&v1).len(), 1);
+    drop(second);
+    assert!(retired_builds(&v1).is_empty());
+
+    // A later reload finds the new build through the link
+    assert_eq!(searcher.reload().unwrap().docs[&0].external_id, "a");
+}
+
+#[test]
+fn max_postings_per_term_keeps_the_highest_weight_docs() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"rust"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"rust tokio serde axum"}"#, "\n",
+        r#"{"id":"c","title":"C","body":"rust rust tokio"}"#, "\n",
+        r#"{"id":"d","title":"D","body":"go"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { max_postings_per_term: Some(2), ..Default::default() };
+    let report = indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    assert_eq!((report.capped_terms, report.dropped_postings), (1, 1));
+    let meta: Value = serde_json::from_slice(&fs::read(dir.path().join("index/meta.json")).unwrap()).unwrap();
+    assert_eq!((meta["max_postings_per_term"].as_u64(), meta["capped_terms"].as_u64()), (Some(2), Some(1)));
+
+    let app = server::build_app(index_dir).unwrap();
+    // "b" has the lowest weight for "rust" (longest doc) and falls past the cap
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust" }));
+    let mut ids = hit_ids(&json);
+    ids.sort();
+    assert_eq!(ids, vec![0, 2]);
+    // Terms under the cap are untouched
+    let (_, json) = post_search(app, serde_json::json!({ "q": "tokio" }));
+    assert_eq!(json["total_hits"], 2);
+}
+
+#[test]
+fn stored_doc_norms_score_like_baked_ones_unless_skipped() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"rust"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"rust tokio serde axum hyper tower"}"#, "\n",
+        r#"{"id":"c","title":"C","body":"rust rust tokio"}"#, "\n",
+        r#"{"id":"d","title":"D","body":"go"}"#, "\n",
+    )).unwrap();
+    let build = |name: &str, store_doc_norms: bool| {
+        let index_dir = dir.path().join(name).to_string_lossy().to_string();
+        let opts = indexer::BuildOptions { store_doc_norms, max_postings_per_term: Some(2), ..Default::default() };
+        indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+        index_dir
+    };
+    let (baked, stored) = (build("baked", false), build("stored", true));
+    assert!(dir.path().join("stored/doc_norms.bin").exists() && !dir.path().join("baked/doc_norms.bin").exists());
+    core::verify::verify_index(&IndexPaths::new(&stored)).unwrap();
+
+    let scores = |json: &Value| json["results"].as_array().unwrap().iter().map(|h| (h["doc_id"].as_u64().unwrap(), h["score"].as_f64().unwrap())).collect::<Vec<_>>();
+    let (baked, stored) = (server::build_app(baked).unwrap(), server::build_app(stored).unwrap());
+    for q in ["rust", "tokio", "rust tokio"] {
+        let (_, want) = post_search(baked.clone(), serde_json::json!({ "q": q }));
+        let (_, got) = post_search(stored.clone(), serde_json::json!({ "q": q }));
+        let (want, got) = (scores(&want), scores(&got));
+        assert_eq!(want.iter().map(|h| h.0).collect::<Vec<_>>(), got.iter().map(|h| h.0).collect::<Vec<_>>(), "{q}");
+        assert!(want.iter().zip(&got).all(|(w, g)| (w.1 - g.1).abs() < 1e-5), "{q}: {want:?} vs {got:?}");
+    }
+
+    // Raw tf-idf no longer divides the long document's weight down
+    let (_, normalized) = post_search(stored.clone(), serde_json::json!({ "q": "tokio" }));
+    let (status, raw) = post_search(stored, serde_json::json!({ "q": "tokio", "normalize_docs": false }));
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(scores(&raw), scores(&normalized));
+    // An index with norms baked in can't skip them
+    let (status, _) = post_search(baked, serde_json::json!({ "q": "tokio", "normalize_docs": false }));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn matched_fields_report_where_query_terms_matched() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Rust book","body":"learn systems programming","url":"https://example.com/book"}"#, "\n",
+        r#"{"id":"b","title":"Cooking","body":"rust removal from pans","url":"https://example.com/rust"}"#, "\n",
+        r#"{"id":"c","title":"Gardening","body":"soil and seeds"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "fields": "title,body,url", "matched_fields": true }));
+    let fields: HashMap<String, Value> = json["results"].as_array().unwrap().iter()
+        .map(|hit| (hit["external_id"].as_str().unwrap().to_string(), hit["matched_fields"].clone()))
+        .collect();
+    assert_eq!(fields["a"], serde_json::json!(["title"]));
+    assert_eq!(fields["b"], serde_json::json!(["body", "url"]));
+    let (_, body) = call(app.clone(), "/search?q=learn+rust&fields=title,body&matched_fields=true");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["results"][0]["matched_fields"], serde_json::json!(["title", "body"]));
+    // Off by default
+    let (_, json) = post_search(app, serde_json::json!({ "q": "rust", "fields": "title,body" }));
+    assert!(json["results"][0].get("matched_fields").is_none());
+}
+
+#[test]
+fn missing_or_partial_index_names_what_to_do() {
+    let dir = tempdir().unwrap();
+    let missing = dir.path().join("nope").to_string_lossy().to_string();
+    let err = format!("{:#}", server::build_app(missing).err().unwrap());
+    assert!(err.contains("does not exist") && err.contains("indexer build"), "{err}");
+
+    build_tiny_index(dir.path());
+    fs::remove_file(dir.path().join("docs.bin")).unwrap();
+    let err = format!("{:#}", server::build_app(dir.path().to_string_lossy().to_string()).err().unwrap());
+    assert!(err.contains("docs.bin (document table)") && err.contains("incomplete"), "{err}");
+}
+
+#[test]
+fn empty_index_serves_empty_results() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, "").unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let report = indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    assert_eq!(report.num_docs, 0);
+    let app = server::build_app(index_dir).unwrap();
+
+    let (status, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "debug": true }));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((json["total_hits"].as_u64(), json["corpus_size"].as_u64()), (Some(0), Some(0)));
+    let (status, json) = post_search(app, serde_json::json!({ "q": "", "browse": true }));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["total_hits"], 0);
+}
+
+#[test]
+fn dedup_collapses_near_identical_hits() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Guide","body":"Rust async runtime guide: tokio tasks and timers"}"#, "\n",
+        r#"{"id":"b","title":"Guide (mirror)","body":"rust ASYNC runtime guide - tokio tasks and timers!"}"#, "\n",
+        r#"{"id":"c","title":"Ownership","body":"rust memory safety through ownership and borrowing"}"#, "\n",
+        r#"{"id":"d","title":"Other","body":"gardening"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust" }));
+    assert_eq!(json["total_hits"], 3);
+    assert!(json.get("duplicates_collapsed").is_none());
+    let (_, json) = post_search(app, serde_json::json!({ "q": "rust tokio", "dedup": true }));
+    assert_eq!((json["total_hits"].as_u64(), json["duplicates_collapsed"].as_u64()), (Some(2), Some(1)));
+    let ids: Vec<&str> = json["results"].as_array().unwrap().iter().map(|h| h["external_id"].as_str().unwrap()).collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&"c") && (ids.contains(&"a") != ids.contains(&"b")), "{ids:?}");
+}
+
+#[test]
+fn dedup_titles_keeps_the_best_hit_per_title() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"Page Not Found","body":"login page missing"}"#, "\n",
+        r#"{"id":"b","title":"page  not found","body":"login page missing, sorry, try the login form again"}"#, "\n",
+        r#"{"id":"c","title":"Login help","body":"login troubleshooting"}"#, "\n",
+        r#"{"id":"d","title":"Other","body":"gardening"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "login" }));
+    assert_eq!(json["total_hits"], 3);
+    assert!(json.get("titles_collapsed").is_none());
+    let best_of_ab = json["results"].as_array().unwrap().iter().map(|h| h["external_id"].as_str().unwrap()).find(|id| *id != "c").unwrap().to_string();
+    let (_, json) = post_search(app, serde_json::json!({ "q": "login", "dedup_titles": true }));
+    assert_eq!((json["total_hits"].as_u64(), json["titles_collapsed"].as_u64()), (Some(2), Some(1)));
+    let ids: Vec<&str> = json["results"].as_array().unwrap().iter().map(|h| h["external_id"].as_str().unwrap()).collect();
+    assert!(ids.contains(&"c") && ids.contains(&best_of_ab.as_str()), "{ids:?}");
+}
+
+#[test]
+fn k_above_max_is_clamped_and_flagged() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let config = ServerConfig { max_k: 1, ..Default::default() };
+    let app = server::build_app_with_config(dir.path().to_string_lossy().to_string(), config).unwrap();
+
+    let (_, body) = call(app.clone(), "/search?q=rust&k=5");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["results"].as_array().unwrap().len(), 1);
+    assert_eq!(json["k_clamped"], true);
+
+    let (_, body) = call(app, "/search?q=rust&k=1");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json.get("k_clamped").is_none());
+}
+
+#[test]
+fn doc_etag_and_search_cache_headers() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let head = |req: Request<Body>| rt.block_on(tower::ServiceExt::oneshot(app.clone(), req)).unwrap();
+
+    let resp = head(Request::get("/doc/0").body(Body::empty()).unwrap());
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()["etag"].clone();
+    let resp = head(Request::get("/doc/0").header("if-none-match", etag.clone()).body(Body::empty()).unwrap());
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    // A different representation gets a different tag
+    let resp = head(Request::get("/doc/0?max_chars=4").header("if-none-match", etag).body(Body::empty()).unwrap());
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = head(Request::get("/search?q=rust").body(Body::empty()).unwrap());
+    assert_eq!(resp.headers()["cache-control"], "public, max-age=60");
+    assert_eq!(resp.headers()["last-modified"], "Mon, 01 Jan 2024 00:00:00 GMT");
+}
+
+#[test]
+fn every_response_names_the_index_that_served_it() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let head = |req: Request<Body>| rt.block_on(tower::ServiceExt::oneshot(app.clone(), req)).unwrap();
+
+    for uri in ["/search?q=rust", "/doc/0", "/health", "/no-such-route"] {
+        let resp = head(Request::get(uri).body(Body::empty()).unwrap());
+        assert_eq!(resp.headers()["x-index-version"], INDEX_VERSION.to_string().as_str(), "{uri}");
+        assert_eq!(resp.headers()["x-index-created-at"], "2024-01-01T00:00:00Z", "{uri}");
+    }
+    // Cross-origin scripts may read them
+    let resp = head(Request::get("/search?q=rust").header("origin", "https://app.example").body(Body::empty()).unwrap());
+    let exposed = resp.headers()["access-control-expose-headers"].to_str().unwrap().to_string();
+    assert!(exposed.contains("x-index-version") && exposed.contains("x-index-created-at"), "{exposed}");
+}
+
+#[test]
+fn cors_preflight_max_age_and_exposed_headers_are_configurable() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let config = ServerConfig {
+        cors_allow_origins: vec![HeaderValue::from_static("https://app.example")],
+        cors_max_age_s: Some(600),
+        cors_expose_headers: vec![HeaderName::from_static("etag")],
+        ..Default::default()
+    };
+    let app = server::build_app_with_config(dir.path().to_string_lossy().to_string(), config).unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let head = |req: Request<Body>| rt.block_on(tower::ServiceExt::oneshot(app.clone(), req)).unwrap();
+
+    let preflight = Request::options("/search")
+        .header("origin", "https://app.example")
+        .header("access-control-request-method", "POST")
+        .body(Body::empty()).unwrap();
+    let resp = head(preflight);
+    assert_eq!(resp.headers()["access-control-allow-origin"], "https://app.example");
+    assert_eq!(resp.headers()["access-control-max-age"], "600");
+
+    let resp = head(Request::get("/search?q=rust").header("origin", "https://app.example").body(Body::empty()).unwrap());
+    let exposed = resp.headers()["access-control-expose-headers"].to_str().unwrap().to_string();
+    assert!(exposed.contains("x-index-version") && exposed.contains("etag"), "{exposed}");
+    // Other origins get no grant
+    let resp = head(Request::get("/search?q=rust").header("origin", "https://other.example").body(Body::empty()).unwrap());
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+#[test]
+fn oversized_bodies_are_rejected_with_413() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let config = ServerConfig { admin_token: Some("secret".into()), max_body_bytes: 256, max_ingest_body_bytes: 4096, ..Default::default() };
+    let app = server::build_app_with_config(dir.path().to_string_lossy().to_string(), config).unwrap();
+    let post = |uri: &str, bytes: usize| {
+        let body = match uri {
+            "/index/batch" => serde_json::json!([{ "id": "pad", "title": "Pad", "body": "x".repeat(bytes) }]).to_string(),
+            _ => serde_json::json!({ "q": "rust", "pad": "x".repeat(bytes) }).to_string(),
+        };
+        let req = Request::post(uri).header("content-type", "application/json").header("x-admin-token", "secret").body(Body::from(body)).unwrap();
+        send(app.clone(), req).0
+    };
+    assert_eq!(post("/search", 10), StatusCode::OK);
+    assert_eq!(post("/search", 1000), StatusCode::PAYLOAD_TOO_LARGE);
+    // Ingest endpoints have their own, larger limit
+    assert_eq!(post("/index/batch", 1000), StatusCode::OK);
+    assert_eq!(post("/index/batch", 5000), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+fn post_search(app: Router, body: Value) -> (StatusCode, Value) {
+    let req = Request::post("/search").header("content-type", "application/json").body(Body::from(body.to_string())).unwrap();
+    let (status, bytes) = send(app, req);
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+fn hit_ids(json: &Value) -> Vec<u64> {
+    json["results"].as_array().unwrap().iter().map(|h| h["doc_id"].as_u64().unwrap()).collect()
+}
+
+#[test]
+fn normalized_scores_are_relative_to_the_top_hit() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (_, raw) = post_search(app.clone(), serde_json::json!({ "q": "rust" }));
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "normalize_scores": true }));
+    assert_eq!(hit_ids(&json), hit_ids(&raw));
+    assert_eq!(json["results"][0]["score"], 1.0);
+    let ratio = raw["results"][1]["score"].as_f64().unwrap() / raw["results"][0]["score"].as_f64().unwrap();
+    assert!((json["results"][1]["score"].as_f64().unwrap() - ratio).abs() < 1e-6);
+
+    // A later page keeps the first page's scale
+    let (_, page2) = post_search(app, serde_json::json!({ "q": "rust", "k": 1, "offset": 1, "normalize_scores": true }));
+    assert_eq!(page2["results"][0]["score"], json["results"][1]["score"]);
+}
+
+#[test]
+fn highlight_spans_locate_matches_in_the_snippet() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "highlight_spans": true, "highlight_pre": "", "highlight_post": "" }));
+    assert_eq!(json["results"][1]["snippet"], "Learning rust.");
+    assert_eq!(json["results"][1]["matches"], serde_json::json!([{ "start": 9, "end": 13 }]));
+
+    // Off by default
+    let (_, json) = post_search(app, serde_json::json!({ "q": "rust" }));
+    assert!(json["results"][0].get("matches").is_none());
+}
+
+#[test]
+fn trailing_wildcard_expands_to_prefixed_terms() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "ru*" }));
+    assert_eq!(hit_ids(&json), vec![0, 1]);
+    assert_eq!(json["results"][1]["snippet"], "Learning <em>ru</em>st.");
+
+    // Both wildcards must match in `and` mode, however many terms each expands to
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "le* ru*", "fields": "title", "mode": "and" }));
+    assert_eq!(hit_ids(&json), vec![1]);
+
+    // Expansions are capped, most frequent terms first
+    let config = ServerConfig { max_wildcard_expansions: 0, ..Default::default() };
+    let capped = server::build_app_with_config(dir.path().to_string_lossy().to_string(), config).unwrap();
+    let (_, json) = post_search(capped, serde_json::json!({ "q": "ru*" }));
+    assert_eq!(json["total_hits"], 0);
+}
+
+#[test]
+fn post_search_supports_offset_mode_filters_and_boosts() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "k": 1, "offset": 1 }));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["total_hits"], 2);
+    assert_eq!(hit_ids(&json), vec![1]);
+
+    // "learning" only occurs in a title, so `and` over the body matches nothing
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust learning", "mode": "and" }));
+    assert_eq!(hit_ids(&json), Vec::<u64>::new());
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust learning", "mode": "and", "fields": "title,body" }));
+    assert_eq!(hit_ids(&json), vec![1]);
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "filters": ["title:learning"] }));
+    assert_eq!(hit_ids(&json), vec![1]);
+
+    let (status, _) = post_search(app, serde_json::json!({ "q": "rust", "boosts": { "title": -1.0 } }));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn doc_terms_list_stored_weights_highest_first() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"rust rust rust tokio common"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"python common"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { store_term_vectors: true, ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (status, body) = call(app.clone(), "/doc/0/terms?limit=2");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["total"], 3);
+    let terms: Vec<&str> = json["terms"].as_array().unwrap().iter().map(|t| t["term"].as_str().unwrap()).collect();
+    assert_eq!(terms, vec!["rust", "tokio"]);
+    // Same weight the postings carry
+    let (_, search) = post_search(app.clone(), serde_json::json!({ "q": "tokio" }));
+    assert_eq!(json["terms"][1]["weight"], search["results"][0]["score"]);
+
+    let (status, body) = call(app.clone(), "/doc/9/terms");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), serde_json::json!({ "error": "not found" }));
+    // "More like this" seeds from the stored vector
+    let (_, body) = call(app, "/similar/1?terms=5");
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(hit_ids(&json), vec![0]);
+
+    let tiny = tempdir().unwrap();
+    build_tiny_index(tiny.path());
+    let app = server::build_app(tiny.path().to_string_lossy().to_string()).unwrap();
+    let (status, body) = call(app, "/doc/0/terms");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["error"].as_str().unwrap().contains("--store-term-vectors"), "{json}");
+}
+
+#[test]
+fn snippet_store_bytes_keeps_the_whole_body_searchable() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"héllo world and then far later zeppelin"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let opts = indexer::BuildOptions { snippet_store_bytes: Some(2), ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "zeppelin" }));
+    assert_eq!(hit_ids(&json), vec![0]);
+    // Two bytes would split "é", so only "h" is kept
+    let (status, body) = call(app, "/doc/0");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["text"], "h");
+    assert_eq!(json["stored_text_limit"], 2);
+}
+
+#[test]
+fn feedback_clicks_are_appended_as_jsonl() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let index_dir = dir.path().to_string_lossy().to_string();
+    let post = |app: Router, body: Value| {
+        let req = Request::post("/feedback").header("content-type", "application/json").body(Body::from(body.to_string())).unwrap();
+        send(app, req).0
+    };
+
+    let app = server::build_app_with_config(index_dir.clone(), ServerConfig::default()).unwrap();
+    assert_eq!(post(app, serde_json::json!({ "q": "rust", "doc_id": 0, "rank": 1 })), StatusCode::NOT_FOUND);
+
+    let log = dir.path().join("feedback.jsonl");
+    let config = ServerConfig { feedback_log: Some(log.clone()), ..Default::default() };
+    let app = server::build_app_with_config(index_dir, config).unwrap();
+    assert_eq!(post(app.clone(), serde_json::json!({ "q": "Learning RUST", "doc_id": 1, "rank": 2 })), StatusCode::ACCEPTED);
+    assert_eq!(post(app.clone(), serde_json::json!({ "q": "rust", "doc_id": 7, "rank": 1 })), StatusCode::BAD_REQUEST);
+    assert_eq!(post(app, serde_json::json!({ "q": " ", "doc_id": 0, "rank": 1 })), StatusCode::BAD_REQUEST);
+
+    // The writer thread appends in the background
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let line = loop {
+        let contents = fs::read_to_string(&log).unwrap_or_default();
+        if contents.ends_with('\n') { break contents; }
+        assert!(std::time::Instant::now() < deadline, "feedback was not written");
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert_eq!(line.lines().count(), 1);
+    let event: Value = serde_json::from_str(line.trim()).unwrap();
+    assert_eq!(event["q"], "Learning RUST");
+    assert_eq!(event["normalized_q"], "learn rust");
+    assert_eq!(event["doc_id"], 1);
+    assert_eq!(event["external_id"], "doc1");
+    assert_eq!(event["rank"], 2);
+    assert!(event["ts_ms"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn raw_forms_rank_exact_spellings_first() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"it runs fast"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"keeps running fast"}"#, "\n",
+        r#"{"id":"c","title":"C","body":"slow"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    let tokenizer = core::tokenizer::TokenizerConfig { raw_forms: true, ..Default::default() };
+    let opts = indexer::BuildOptions { tokenizer, ..Default::default() };
+    let report = indexer::build_index(&input.to_string_lossy(), &index_dir, &opts).unwrap();
+    // "=runs", "=keeps", "=running"
+    assert_eq!(report.raw_form_terms, 3);
+    let app = server::build_app(index_dir).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "running" }));
+    assert_eq!(hit_ids(&json), vec![1, 0]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "runs" }));
+    assert_eq!(hit_ids(&json), vec![0, 1]);
+    // A raw form never makes `and` stricter than its stem
+    let (_, json) = post_search(app, serde_json::json!({ "q": "running fast", "mode": "and" }));
+    assert_eq!(hit_ids(&json), vec![1, 0]);
+}
+
+#[test]
+fn docs_resolve_by_external_id() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"alpha"}"#, "\n",
+        r#"{"id":"pages/b c","title":"B","body":"beta"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index").to_string_lossy().to_string();
+    indexer::build_index(&input.to_string_lossy(), &index_dir, &indexer::BuildOptions::default()).unwrap();
+    let app = server::build_app(index_dir.clone()).unwrap();
+
+    let (status, body) = call(app.clone(), "/doc/by-external/pages%2Fb%20c");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["doc_id"], 1);
+    assert_eq!(json["external_id"], "pages/b c");
+    assert_eq!(json["title"], "B");
+    assert_eq!(json, serde_json::from_slice::<Value>(&call(app.clone(), "/doc/1").1).unwrap());
+    let (status, body) = call(app.clone(), "/doc/by-external/zzz");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["error"], "not found");
+    // Hits carry the input id
+    let (_, json) = post_search(app, serde_json::json!({ "q": "beta" }));
+    assert_eq!(json["results"][0]["external_id"], "pages/b c");
+
+    // Without the map loaded, lookups scan the metadata
+    let config = ServerConfig { load_doc_id_map: false, ..Default::default() };
+    let app = server::build_app_with_config(index_dir, config).unwrap();
+    let (status, body) = call(app, "/doc/by-external/a");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["doc_id"], 0);
+
+    // Indexes without doc_id_map.bin resolve from the stored metadata
+    let tiny = tempdir().unwrap();
+    build_tiny_index(tiny.path());
+    let app = server::build_app(tiny.path().to_string_lossy().to_string()).unwrap();
+    let (status, body) = call(app, "/doc/by-external/doc1");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["doc_id"], 1);
+}
+
+#[test]
+fn hit_fields_project_each_result() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app.clone(), "/search?q=rust&hit_fields=title,score");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let hit = json["results"][0].as_object().unwrap();
+    let mut keys: Vec<&str> = hit.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, vec!["score", "title"]);
+    assert_eq!(hit["title"], "Doc 0");
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "hit_fields": "doc_id,matches", "highlight_spans": true }));
+    assert_eq!(json["results"][0]["doc_id"], 0);
+    assert!(json["results"][0]["matches"].as_array().is_some_and(|m| !m.is_empty()));
+    assert!(json["results"][0].get("snippet").is_none());
+
+    let (status, _) = call(app, "/search?q=rust&hit_fields=title,body");
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn profile_breaks_search_time_into_phases() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust" }));
+    assert!(json.get("timings").is_none());
+    for q in ["rust", "nothing"] {
+        let (status, json) = post_search(app.clone(), serde_json::json!({ "q": q, "profile": true }));
+        assert_eq!(status, StatusCode::OK);
+        let timings = json["timings"].as_object().unwrap();
+        let mut phases: Vec<&str> = timings.keys().map(String::as_str).collect();
+        phases.sort_unstable();
+        assert_eq!(phases, vec!["postings_s", "query_weights_s", "scoring_s", "snippets_s", "tokenize_s"]);
+        assert!(timings.values().all(|v| v.as_f64().is_some_and(|s| s >= 0.0)));
+    }
+}
+
+#[test]
+fn postings_fanout_shards_files_into_subdirectories() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    let docs: String = (0..20).map(|i| format!("{{\"id\":\"d{i}\",\"title\":\"T\",\"body\":\"Word{i} shared\"}}\n")).collect();
+    fs::write(&input, docs).unwrap();
+    let index_dir = dir.path().join("index");
+    let opts = indexer::BuildOptions { postings_fanout: 16, preserve_case: true, ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir.to_string_lossy(), &opts).unwrap();
+
+    let postings = index_dir.join("postings");
+    assert!(postings.join("00/00000000.postings.bin").is_file());
+    assert!(postings.join("15/00000015.postings.bin").is_file());
+    assert!(postings.join("01/00000017.postings.bin").is_file());
+    assert!(fs::read_dir(&postings).unwrap().all(|e| e.unwrap().file_type().unwrap().is_dir()));
+    assert!(index_dir.join("case/postings/00").is_dir());
+
+    let app = server::build_app(index_dir.to_string_lossy().to_string()).unwrap();
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "word17" }));
+    assert_eq!(hit_ids(&json), vec![17]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "Word3", "case_sensitive": true }));
+    assert_eq!(hit_ids(&json), vec![3]);
+    let (_, json) = post_search(app, serde_json::json!({ "q": "shared", "k": 100 }));
+    assert_eq!(json["total_hits"], 20);
+}
+
+#[test]
+fn searcher_runs_the_same_search_without_http() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let index_dir = dir.path().to_string_lossy().to_string();
+    let searcher = server::Searcher::with_config(&index_dir, ServerConfig::default()).unwrap();
+
+    let response = searcher.search("rust", 1, Default::default()).unwrap();
+    assert_eq!(response.total_hits, 2);
+    assert_eq!(response.results.len(), 1);
+    assert_eq!(response.results[0].doc_id, 0);
+    assert_eq!(response.results[0].external_id, "doc0");
+    let opts = server::SearchParams { offset: 1, ..Default::default() };
+    assert_eq!(searcher.search("rust", 5, opts).unwrap().results[0].doc_id, 1);
+    let bad = server::SearchParams { fields: Some("nope".into()), ..Default::default() };
+    assert_eq!(searcher.search("rust", 5, bad).unwrap_err().0, StatusCode::BAD_REQUEST);
+    assert_eq!(searcher.index().num_docs, 2);
+
+    // Same hits as over HTTP
+    let (_, json) = post_search(searcher.router(), serde_json::json!({ "q": "rust", "k": 1 }));
+    assert_eq!(hit_ids(&json), vec![0]);
+}
+
+#[test]
+fn similar_excludes_the_source_doc() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app.clone(), "/similar/0?k=5&terms=3");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["query"], "rust");
+    assert_eq!(hit_ids(&json), vec![1]);
+
+    let (status, body) = call(app, "/similar/9");
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), serde_json::json!({ "error": "not found" }));
+}
+
+#[test]
+fn pivoted_normalization_lifts_long_docs() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    let long = "Rust ownership explained. Rust borrowing, lifetimes, traits, generics, closures, iterators, \
+                modules, crates, testing, macros and error handling. Rust async runtimes and tooling.";
+    let docs = [("short", "Rust"), ("long", long), ("other", "Gardening in spring"), ("more", "Baking bread at home")];
+    let lines: Vec<String> = docs.iter().map(|(id, body)| serde_json::json!({ "id": id, "title": id, "body": body }).to_string()).collect();
+    fs::write(&input, lines.join("\n")).unwrap();
+
+    let top_title = |pivot: f32| {
+        let out = dir.path().join(format!("index-{pivot}"));
+        let opts = indexer::BuildOptions { pivot, ..Default::default() };
+        indexer::build_index(&input.to_string_lossy(), &out.to_string_lossy(), &opts).unwrap();
+        let app = server::build_app(out.to_string_lossy().to_string()).unwrap();
+        let (_, body) = call(app, "/search?q=rust");
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        json["results"][0]["title"].as_str().unwrap().to_string()
+    };
+    assert_eq!(top_title(0.0), "short");
+    assert_eq!(top_title(0.75), "long");
+}
+
+#[test]
+fn missing_postings_mark_search_degraded_and_fail_health() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+    let (status, _) = call(app.clone(), "/health");
+    assert_eq!(status, StatusCode::OK);
+
+    fs::remove_file(dir.path().join("postings/00000000.postings.bin")).unwrap();
+    let (status, _) = call(app.clone(), "/health");
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    // Health checks sample postings without counting toward the postings metrics
+    let (_, metrics) = call(app.clone(), "/metrics");
+    let metrics = String::from_utf8(metrics.to_vec()).unwrap();
+    assert!(metrics.contains("zapsearch_postings_files_read_total 0"), "{metrics}");
+    assert!(metrics.contains("zapsearch_postings_load_errors_total 0"), "{metrics}");
+
+    let (status, body) = call(app.clone(), "/search?q=rust");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["degraded"], true);
+    let (_, metrics) = call(app.clone(), "/metrics");
+    assert!(String::from_utf8(metrics.to_vec()).unwrap().contains("zapsearch_postings_load_errors_total 1"));
+
+    // A batch's shared read of the list fails uncounted; only the query's own retry counts
+    let body = serde_json::json!({ "queries": [{ "q": "rust" }] });
+    let req = Request::post("/search/batch").header("content-type", "application/json").body(Body::from(body.to_string())).unwrap();
+    let (status, _) = send(app.clone(), req);
+    assert_eq!(status, StatusCode::OK);
+    let (_, metrics) = call(app, "/metrics");
+    assert!(String::from_utf8(metrics.to_vec()).unwrap().contains("zapsearch_postings_load_errors_total 2"));
+}
+
+#[test]
+fn unreadable_data_gives_partial_results_with_warnings() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    fs::write(&input, concat!(
+        r#"{"id":"a","title":"A","body":"rust tokio"}"#, "\n",
+        r#"{"id":"b","title":"B","body":"rust serde"}"#, "\n",
+        r#"{"id":"c","title":"C","body":"go"}"#, "\n",
+    )).unwrap();
+    let index_dir = dir.path().join("index");
+    let opts = indexer::BuildOptions { text_storage: indexer::TextStorage::Files, ..Default::default() };
+    indexer::build_index(&input.to_string_lossy(), &index_dir.to_string_lossy(), &opts).unwrap();
+    let app = server::build_app(index_dir.to_string_lossy().to_string()).unwrap();
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust tokio serde", "debug": true }));
+    assert_eq!((json["complete"].as_bool(), json.get("warnings")), (Some(true), None));
+    // Terms are stemmed, so look them up by position in the query
+    let term_ids: Vec<u64> = json["parsed"]["fields"][0]["terms"].as_array().unwrap().iter().map(|t| t["term_id"].as_u64().unwrap()).collect();
+    let postings_file = |word: &str| {
+        let position = ["rust", "tokio", "serde"].iter().position(|w| *w == word).unwrap();
+        index_dir.join(format!("postings/{:08}.postings.bin", term_ids[position]))
+    };
+
+    // A missing term drops its matches; the other terms still rank
+    fs::remove_file(postings_file("tokio")).unwrap();
+    let (status, json) = post_search(app.clone(), serde_json::json!({ "q": "rust tokio" }));
+    assert_eq!(status, StatusCode::OK);
+    let mut ids = hit_ids(&json);
+    ids.sort();
+    assert_eq!(ids, vec![0, 1]);
+    assert_eq!((json["complete"].as_bool(), json["degraded"].as_bool()), (Some(false), Some(true)));
+    assert_eq!(json["warnings"], serde_json::json!(["postings for term 'tokio' in body are missing"]));
+
+    // A corrupt file says why, naming the indexed (stemmed) term; a filter on a failed term
+    // matches nothing but is reported once
+    fs::write(postings_file("serde"), b"not bincode").unwrap();
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust serde", "filter": "serde" }));
+    assert_eq!(hit_ids(&json), Vec::<u64>::new());
+    let warnings = json["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].as_str().unwrap().starts_with("postings for term 'serd' in body are unreadable: "), "{warnings:?}");
+
+    // Unreadable stored text costs the hit its snippet, not the hit
+    fs::remove_file(index_dir.join("texts/1.txt")).unwrap();
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust" }));
+    assert_eq!(json["total_hits"], 2);
+    assert_eq!((json["complete"].as_bool(), json.get("degraded")), (Some(false), None));
+    assert_eq!(json["warnings"], serde_json::json!(["stored text of doc 1 could not be read; its snippet is missing"]));
+
+    let (_, json) = post_search(app, serde_json::json!({ "q": "go" }));
+    assert_eq!((json["complete"].as_bool(), json.get("warnings")), (Some(true), None));
+}
+
+#[test]
+fn indexer_and_queries_follow_the_recorded_weighting() {
+    use core::weighting::{IdfScheme, TfScheme, Weighting};
+    let bodies = ["zebra zebra zebra lynx", "zebra otter", "otter otter lynx", "puma"];
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    let docs: String = bodies.iter().enumerate().map(|(i, b)| format!("{{\"id\":\"d{i}\",\"title\":\"T\",\"body\":\"{b}\"}}\n")).collect();
+    fs::write(&input, docs).unwrap();
+    for tf in [TfScheme::Log, TfScheme::Raw, TfScheme::Boolean, TfScheme::Augmented] {
+        for idf in [IdfScheme::Standard, IdfScheme::Smooth, IdfScheme::Probabilistic, IdfScheme::None] {
+            let weighting = Weighting { tf, idf };
+            let index_dir = dir.path().join(format!("index-{tf:?}-{idf:?}"));
+            let opts = indexer::BuildOptions { weighting, ..Default::default() };
+            indexer::build_index(&input.to_string_lossy(), &index_dir.to_string_lossy(), &opts).unwrap();
+
+            let paths = IndexPaths::new(&index_dir);
+            assert_eq!(core::persist::load_meta(&paths).unwrap().weighting, weighting);
+            let reference = core::InvertedIndex::from_texts(bodies.iter().enumerate().map(|(i, b)| (i as DocId, *b)), &opts.tokenizer, weighting);
+            let (dictionary, _) = core::persist::load_dictionary(&paths).unwrap();
+            for (term, tid) in &dictionary {
+                let built = core::persist::load_postings_for_term(&paths, *tid).unwrap();
+                let want = &reference.postings[&reference.dictionary[term]];
+                assert_eq!(built.len(), want.len(), "{weighting:?} {term}");
+                for (b, w) in built.iter().zip(want) {
+                    assert_eq!(b.doc_id, w.doc_id);
+                    assert!((b.weight - w.weight).abs() < 1e-5, "{weighting:?} {term}: {} vs {}", b.weight, w.weight);
+                }
+            }
+
+            // A single-term query has unit weight, so each score is the doc's stored weight.
+            let app = server::build_app(index_dir.to_string_lossy().to_string()).unwrap();
+            let (status, json) = post_search(app, serde_json::json!({ "q": "otter", "k": 10 }));
+            assert_eq!(status, StatusCode::OK);
+            let otter = &reference.postings[&reference.dictionary["otter"]];
+            let mut expected: Vec<(u64, f32)> = otter.iter().map(|p| (p.doc_id as u64, p.weight)).collect();
+            expected.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+            assert_eq!(hit_ids(&json), expected.iter().map(|(id, _)| *id).collect::<Vec<_>>(), "{weighting:?}");
+        }
+    }
+}
+
+#[test]
+fn df_out_of_step_with_the_dictionary_fails_to_load() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let paths = IndexPaths::new(dir.path());
+    let dict: HashMap<String, TermId> = [("rust".to_string(), 0), ("async".to_string(), 1)].into();
+    save_dictionary(&paths, &(dict, vec![2u32])).unwrap();
+
+    let err = server::build_app(dir.path().to_string_lossy().to_string()).expect_err("stale df must not load");
+    assert!(format!("{err:#}").contains("dictionary has 2 terms but df has 1 entries"), "{err:#}");
+}
+
+#[test]
+fn batch_search_answers_in_order_and_reads_shared_postings_once() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+    let (_, single) = post_search(app.clone(), serde_json::json!({ "q": "rust", "k": 1, "offset": 1 }));
+
+    let body = serde_json::json!({ "queries": [
+        { "q": "rust" },
+        { "q": "zebra" },
+        { "q": "rust", "k": 1, "offset": 1 },
+        { "q": "learning rust", "mode": "and" },
+    ] });
+    let req = Request::post("/search/batch").header("content-type", "application/json").body(Body::from(body.to_string())).unwrap();
+    let (status, bytes) = send(app.clone(), req);
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&bytes).unwrap();
+    let responses = json.as_array().unwrap();
+    assert_eq!(responses.len(), 4);
+    assert_eq!(hit_ids(&responses[0]), vec![0, 1]);
+    assert_eq!(responses[1]["total_hits"], 0);
+    assert_eq!(hit_ids(&responses[2]), hit_ids(&single));
+    assert_eq!(responses[3]["query"], "learning rust");
+
+    // "rust" is read once for the single search and once for the whole batch
+    let (_, metrics) = call(app.clone(), "/metrics");
+    assert!(String::from_utf8(metrics.to_vec()).unwrap().contains("zapsearch_postings_files_read_total 2"));
+
+    let bad = serde_json::json!({ "queries": [{ "q": "rust" }, { "q": "rust", "fields": "nope" }] });
+    let req = Request::post("/search/batch").header("content-type", "application/json").body(Body::from(bad.to_string())).unwrap();
+    let (status, bytes) = send(app, req);
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(bytes.to_vec()).unwrap().starts_with("queries[1]: "));
+}
+
+#[test]
+fn large_responses_are_compressed_when_accepted() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    fs::write(dir.path().join("texts/0.txt"), "Rust is great. ".repeat(500)).unwrap();
+    let index_dir = dir.path().to_string_lossy().to_string();
+    let get = |app: Router, uri: &str, encoding: &str| {
+        let req = Request::get(uri).header("accept-encoding", encoding).body(Body::empty()).unwrap();
+        let res = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(tower::ServiceExt::oneshot(app, req)).unwrap();
+        res.headers().get("content-encoding").map(|v| v.to_str().unwrap().to_string())
+    };
+
+    let app = server::build_app_with_config(index_dir.clone(), ServerConfig::default()).unwrap();
+    assert_eq!(get(app.clone(), "/doc/0", "gzip").as_deref(), Some("gzip"));
+    assert_eq!(get(app.clone(), "/doc/0", "br;q=1, gzip;q=0.5").as_deref(), Some("br"));
+    assert_eq!(get(app.clone(), "/doc/0", "identity"), None);
+    assert_eq!(get(app, "/health", "gzip"), None);
+
+    let config = ServerConfig { compression: false, ..Default::default() };
+    let app = server::build_app_with_config(index_dir, config).unwrap();
+    assert_eq!(get(app, "/doc/0", "gzip"), None);
+}
+
+#[test]
+fn pretokenized_documents_index_their_supplied_terms() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("docs.jsonl");
+    let docs = [
+        r#"{"id":"a","title":"A","body":"Happy dogs were running","tokens":["dog","run","","ENTITY:Lassie"]}"#,
+        r#"{"id":"b","title":"B","body":"Happy cats"}"#,
+    ];
+    fs::write(&input, docs.join("\n")).unwrap();
+    let index_dir = dir.path().join("index");
+    let report = indexer::build_index(&input.to_string_lossy(), &index_dir.to_string_lossy(), &Default::default()).unwrap();
+    assert_eq!(report.pretokenized_docs, 1);
+
+    let app = server::build_app(index_dir.to_string_lossy().to_string()).unwrap();
+    // Query words still go through the tokenizer, so they match supplied terms only when both agree
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "running dogs" }));
+    assert_eq!(hit_ids(&json), vec![0]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "happy" }));
+    assert_eq!(hit_ids(&json), vec![1]);
+    let (_, json) = post_search(app, serde_json::json!({ "q": "lassie" }));
+    assert_eq!(json["total_hits"], 0);
+    let (dictionary, _) = core::persist::load_dictionary(&IndexPaths::new(&index_dir)).unwrap();
+    assert!(dictionary.contains_key("ENTITY:Lassie"));
+}
+
+#[test]
+fn debug_doc_explains_a_doc_outside_the_page() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    let (status, body) = call(app.clone(), "/search?q=rust&k=1&debug_doc=1");
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(hit_ids(&json), vec![0]);
+    let target = &json["doc_debug"];
+    assert_eq!(target["external_id"], "doc1");
+    assert_eq!(target["rank"], 2);
+    assert!((target["score"].as_f64().unwrap() - 0.6).abs() < 1e-6);
+    let contributions = target["contributions"].as_array().unwrap();
+    assert_eq!(contributions.len(), 1);
+    assert_eq!(contributions[0]["field"], "body");
+    assert_eq!(contributions[0]["term"], "rust");
+    assert!((contributions[0]["query_weight"].as_f64().unwrap() - 1.0).abs() < 1e-6);
+
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "zebra", "debug_doc": 0 }));
+    assert_eq!(json["doc_debug"]["rank"], Value::Null);
+    assert_eq!(json["doc_debug"]["score"], 0.0);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust" }));
+    assert!(json.get("doc_debug").is_none());
+    let (status, _) = post_search(app, serde_json::json!({ "q": "rust", "debug_doc": 7 }));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn min_score_drops_weak_hits_before_paging() {
+    let dir = tempdir().unwrap();
+    build_tiny_index(dir.path());
+    let app = server::build_app(dir.path().to_string_lossy().to_string()).unwrap();
+
+    // Raw cosine scores are 0.8 (doc0) and 0.6 (doc1)
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "min_score": 0.7 }));
+    assert_eq!(hit_ids(&json), vec![0]);
+    assert_eq!(json["total_hits"], 1);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "min_score": 0.7, "offset": 1 }));
+    assert!(hit_ids(&json).is_empty());
+    // With normalize_scores the bar is relative to the top hit: doc1 scores 0.75
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "min_score": 0.7, "normalize_scores": true }));
+    assert_eq!(hit_ids(&json), vec![0, 1]);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "min_score": 0.8, "normalize_scores": true }));
+    assert_eq!(hit_ids(&json), vec![0]);
+
+    // page_only cuts just the returned page: total_hits and offsets still count every match
+    let (_, json) = call(app.clone(), "/search?q=rust&min_score=0.7&min_score_mode=page_only&k=1&offset=1");
+    let json: Value = serde_json::from_slice(&json).unwrap();
+    assert!(hit_ids(&json).is_empty());
+    assert_eq!(json["total_hits"], 2);
+    let (_, json) = post_search(app.clone(), serde_json::json!({ "q": "rust", "min_score": 0.5, "min_score_mode": "page_only", "k": 1, "offset": 1 }));
+    assert_eq!((hit_ids(&json), &json["total_hits"]), (vec![1], &serde_json::json!(2)));
+    let (status, _) = call(app.clone(), "/search?q=rust&min_score=0.5&min_score_mode=sometimes");
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(app, "/search?q=rust&min_score=NaN");
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// Serve a tiny index over TCP with `limits` on a free local port.
+async fn spawn_with_limits(dir: &std::path::Path, limits: server::listen::ConnectionLimits) -> std::net::SocketAddr {
+    build_tiny_index(dir);
+    let app = server::build_app(dir.to_string_lossy().to_string()).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server::listen::serve_tcp(listener, app, limits));
+    addr
+}
+
+/// Send a keep-alive `GET /health` and return the response head.
+async fn get_health(stream: &mut tokio::net::TcpStream) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    stream.write_all(b"GET /health HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+    let mut buf = vec![0u8; 4096];
+    let n = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf)).await.expect("no response").unwrap();
+    String::from_utf8_lossy(&buf[..n]).to_string()
+}
+
+/// Whether the server closes `stream` within `wait`, discarding anything else it sends.
+async fn closed_within(stream: &mut tokio::net::TcpStream, wait: std::time::Duration) -> bool {
+    use tokio::io::AsyncReadExt;
+    let mut buf = [0u8; 1024];
+    tokio::time::timeout(wait, async {
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 { break; }
+        }
+    }).await.is_ok()
+}
+
+#[test]
+fn connection_limits_close_stalled_and_idle_connections() {
+    use std::time::Duration;
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        let dir = tempdir().unwrap();
+        // No limits: an idle keep-alive connection stays open and serves another request
+        let addr = spawn_with_limits(&dir.path().join("default"), Default::default()).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(get_health(&mut stream).await.starts_with("HTTP/1.1 200"));
+        assert!(!closed_within(&mut stream, Duration::from_millis(1500)).await);
+        assert!(get_health(&mut stream).await.starts_with("HTTP/1.1 200"));
+
+        // A client that never finishes its headers is dropped
+        let limits = server::listen::ConnectionLimits { header_read_timeout: Some(Duration::from_secs(1)), ..Default::default() };
+        let addr = spawn_with_limits(&dir.path().join("header"), limits).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, b"GET /health HTTP/1.1\r\nHost: te").await.unwrap();
+        assert!(closed_within(&mut stream, Duration::from_secs(3)).await);
+
+        // An idle keep-alive connection is closed after the timeout; 0 closes after each response
+        let limits = server::listen::ConnectionLimits { keep_alive_timeout: Some(Duration::from_secs(1)), ..Default::default() };
+        let addr = spawn_with_limits(&dir.path().join("idle"), limits).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(get_health(&mut stream).await.starts_with("HTTP/1.1 200"));
+        assert!(closed_within(&mut stream, Duration::from_secs(3)).await);
+        let limits = server::listen::ConnectionLimits { keep_alive_timeout: Some(Duration::ZERO), ..Default::default() };
+        let addr = spawn_with_limits(&dir.path().join("no-keep-alive"), limits).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(get_health(&mut stream).await.to_lowercase().contains("connection: close"));
+        assert!(closed_within(&mut stream, Duration::from_secs(1)).await);
+    });
+}
+
+#[test]
+fn max_connections_holds_new_connections_until_one_closes() {
+    use std::time::Duration;
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        let dir = tempdir().unwrap();
+        let limits = server::listen::ConnectionLimits { max_connections: Some(1), ..Default::default() };
+        let addr = spawn_with_limits(dir.path(), limits).await;
+        let mut first = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(get_health(&mut first).await.starts_with("HTTP/1.1 200"));
+
+        // The second connection sits in the backlog: its request gets no answer yet
+        let mut second = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let waiting = tokio::spawn(async move { get_health(&mut second).await });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert!(waiting.await.unwrap().starts_with("HTTP/1.1 200"));
+    });
+}